@@ -0,0 +1,107 @@
+use std::fmt;
+
+use failure::bail;
+use positioned_io::ReadAt;
+
+use crate::{Inode, Reader, Result, SuperBlock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AclTag {
+    UserObj,
+    User(u32),
+    GroupObj,
+    Group(u32),
+    Mask,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AclEntry {
+    pub tag: AclTag,
+    /// rwx bits, 4 = read, 2 = write, 1 = execute
+    pub perm: u16,
+}
+
+/// A POSIX ACL as stored in `system.posix_acl_access` or
+/// `system.posix_acl_default`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Acl {
+    pub entries: Vec<AclEntry>,
+}
+
+impl Acl {
+    // ext4 keeps its own on-disk version, not the one of the xattr API
+    const VERSION: u32 = 1;
+    const NAME_INDEX_ACCESS: u8 = 2;
+    const NAME_INDEX_DEFAULT: u8 = 3;
+
+    pub fn parse(value: &[u8]) -> Result<Self> {
+        let r = Reader::new(value);
+        let version = r.u32(0)?;
+        if version != Self::VERSION {
+            bail!("unsupported acl version: {}", version);
+        }
+        let mut entries = Vec::new();
+        let mut offset = 4;
+        while offset < value.len() as u64 {
+            let tag = r.u16(offset)?;
+            let perm = r.u16(offset + 2)?;
+            // entries without a qualifier are stored without e_id
+            let (tag, len) = match tag {
+                0x01 => (AclTag::UserObj, 4),
+                0x02 => (AclTag::User(r.u32(offset + 4)?), 8),
+                0x04 => (AclTag::GroupObj, 4),
+                0x08 => (AclTag::Group(r.u32(offset + 4)?), 8),
+                0x10 => (AclTag::Mask, 4),
+                0x20 => (AclTag::Other, 4),
+                x => bail!("unknown acl tag: 0x{:X}", x),
+            };
+            entries.push(AclEntry { tag, perm });
+            offset += len;
+        }
+        Ok(Self { entries })
+    }
+}
+
+impl fmt::Display for Acl {
+    /// one `getfacl` style line per entry
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            match entry.tag {
+                AclTag::UserObj => write!(f, "user::")?,
+                AclTag::User(id) => write!(f, "user:{id}:")?,
+                AclTag::GroupObj => write!(f, "group::")?,
+                AclTag::Group(id) => write!(f, "group:{id}:")?,
+                AclTag::Mask => write!(f, "mask::")?,
+                AclTag::Other => write!(f, "other::")?,
+            }
+            for (bit, c) in [(4, 'r'), (2, 'w'), (1, 'x')] {
+                write!(f, "{}", if entry.perm & bit != 0 { c } else { '-' })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Inode {
+    /// The access ACL, `None` if permissions are just the mode bits.
+    pub fn acl(&self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Option<Acl>> {
+        self.acl_by_index(sb, dev, Acl::NAME_INDEX_ACCESS)
+    }
+
+    /// The ACL new entries of a directory inherit.
+    pub fn default_acl(&self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Option<Acl>> {
+        self.acl_by_index(sb, dev, Acl::NAME_INDEX_DEFAULT)
+    }
+
+    fn acl_by_index(&self, sb: &SuperBlock, dev: &dyn ReadAt, index: u8) -> Result<Option<Acl>> {
+        self.xattrs(sb, dev)?
+            .into_iter()
+            .find(|x| x.name_index == index && x.name.is_empty())
+            .map(|x| Acl::parse(&x.value))
+            .transpose()
+    }
+}
@@ -0,0 +1,2811 @@
+mod progress;
+mod shell;
+
+use std::cmp::Ordering;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use failure::{bail, format_err};
+use positioned_io::{ReadAt, Slice};
+use read_file_block_way::builder::ImageBuilder;
+use read_file_block_way::diff::DiffMode;
+use read_file_block_way::events::{Anomaly, Level};
+use read_file_block_way::export::Identity;
+use read_file_block_way::extract::{ExtractOptions, Overwrite};
+use read_file_block_way::gzip::GzipWriter;
+use read_file_block_way::journal::Journal;
+use read_file_block_way::mmap::{Mapped, Mmap};
+use read_file_block_way::progress::ProgressTracker;
+use read_file_block_way::quoting::QuotingStyle;
+use read_file_block_way::zstd::ZstdWriter;
+use read_file_block_way::*;
+
+use crate::progress::ProgressLine;
+
+const USAGE: &str = "\
+usage: ext4cat [options] <image> <command> [args...]
+
+options:
+    -o, --offset <bytes>      the filesystem starts this far into the image
+    -b, --block-size <bytes>  read with this block size, whatever the
+                              superblock says
+    -v, --verbose             report damage met on the way, repeat for
+                              lesser anomalies and the causes of errors
+    --replay                  read the image with the committed journal
+                              transactions replayed, in memory, as after
+                              recovery
+    --as-of <n>               read the image as it was n committed
+                              transactions before the newest one found in
+                              the journal area, experimental: unjournaled
+                              data reads as it is now
+    --timeout <seconds>       give up on the image, failing every read,
+                              this long after starting
+    --read-timeout <ms>       fail any single read of the image taking
+                              longer than this, for slow backends
+    -h, --help                print this help
+    -V, --version             print the version
+
+commands:
+    info                      print the superblock summary
+    ls [-l] [-a] [-i] [path]  list a directory, with -l the mode, links,
+                              owners, size and mtime of each entry as ls
+                              -ln does, with -a names starting with a
+                              dot too, with -i inode numbers
+    cat [--offset x] [--length y] <path>... | -i <inode>
+                              write files out one after the other, or
+                              length bytes from offset into each
+    stat [-c format] <path> | -i <inode>
+                              print everything about an inode, or with -c
+                              what format says as stat(1) does: %n %i %s
+                              %a %A %f %F %u %g %h %b %B %o %t %T %N, %x
+                              %y %z %w for times and %X %Y %Z %W in
+                              seconds
+    find [filters] [pattern]  print the paths matching a glob such as
+                              '/var/log/**/*.gz', every path without one,
+                              and the filters as find(1) has them: -type
+                              f,d,l,b,c,p,s, -size [+-]N[ckMG], -mtime
+                              [+-]days, -uid [+-]N and -regex, matching
+                              the whole path
+    tree [-a] [-s] [-L depth] [path]
+                              draw a directory and everything below, -L
+                              levels deep at most, with -s file sizes
+    df                        blocks and inodes used and free, counted in
+                              the bitmaps, with the label and uuid
+    dump-super                every superblock field, then every group
+                              descriptor, like dumpe2fs
+    shell [--session file] [--script file]
+                              read commands interactively, debugfs style,
+                              resuming the session saved in file, or run
+                              the command file script, with set, if and
+                              variables, as the shell's source does
+    extract [-n] [--overwrite replace|skip|error] [-p] <path> <dest>
+                              copy a file or directory tree out to dest,
+                              replacing what is there unless -n skips it
+                              or error refuses to, with -p restoring
+                              permissions and times
+    tar [-z] [-f file] [path]
+                              write a tar archive of a file or directory
+                              tree, / without a path, to stdout or to
+                              file, with -z gzip compressed
+    cpio [-z] [-f file] [path]
+                              the same as a newc cpio archive, as
+                              initramfs images are
+    export [--format tar|cpio] [--compress gzip|zstd[:level]] [-f file]
+           [path]             the same, tar without a format, compressed
+                              with gzip or zstd at level, 6 and 3 without
+                              one, on other threads than reading the image
+    export --metadata-only [-a algorithms] [--compress ...] [-f file] [path]
+                              the tree without its content instead, as JSON
+                              lines: types, modes, owners, sizes, times,
+                              xattrs and file digests, sha256 or those of
+                              -a as hash takes them
+    trim [--punch-holes]      print the unallocated byte ranges as offset
+                              and length lines, or with --punch-holes
+                              deallocate them in the image file itself,
+                              trusting the block bitmaps, without
+                              writing to the filesystem
+    image-copy <dest>         copy the filesystem to dest as a sparse raw
+                              image of its allocated blocks only, still
+                              mountable
+    repack [--block-size n] [--keep-going] <path> <dest>
+                              write the tree below directory path to dest
+                              as a new image, with the builder's features:
+                              no journal, checksum or xattr, hard links
+                              copied and device nodes, fifos and sockets
+                              left out; the block size is the image's, at
+                              most 4096
+    xxd (--block n | --inode n | --file path) [--offset x] [--len y]
+                              hexdump a block, an inode as stored in the
+                              inode table, or the content of a file, len
+                              bytes from offset into it
+    icheck <block>...         print the inode using each block and its
+                              path, debugfs style
+    ncheck <inode>...         print every path of each inode
+    locate [--all-blocks] <substring>
+                              print the directory entries whose name
+                              contains substring, read out of directory
+                              blocks without walking the tree, deleted
+                              ones left in their slack space too; with
+                              --all-blocks searching every block that
+                              looks like a directory block
+    grep [-rivnlcFa] <pattern> [path...]
+                              print the lines of files matching an
+                              extended regular expression, -r searching
+                              directories, / without a path; -F for a
+                              fixed string, -a to search binary files as
+                              text
+    hash [-r] [-a algorithms] <path>...
+                              print file digests as sha256sum does, -r
+                              for every file below directories, -a for
+                              others of md5, sha1, sha256, sha512, blake3
+                              and xxh3, comma separated
+    diff [--metadata-only | --content-only] [--other-offset bytes]
+         <image> [path]
+                              print the paths added (A), removed (D) and
+                              modified (M) in image against this one,
+                              with what changed of each: metadata and
+                              file content unless told otherwise
+    undelete [--min-score n] [-d dir] [inode...]
+                              list deleted inodes, most recoverable
+                              first, with -d writing what is left of
+                              each, or of the inodes given, to dir as
+                              inode-N
+    carve [--max-size bytes] [-d dir]
+                              list the files found by their signature in
+                              unallocated blocks, with -d writing them to
+                              dir as carved-OFFSET.FORMAT
+    extents <path>            print the extents of a file as filefrag -v
+                              does, with how fragmented it is
+    xattr list [--dump] <path> | xattr get <path> <name>
+                              list the extended attributes of a file, with
+                              --dump their values too, or print one, as
+                              getfattr does; -e text, hex or base64 for
+                              values, --only-values for the raw bytes
+    journal [--block n [-d dir] | --inode n]
+                              list the transactions found anywhere in the
+                              journal area, oldest first, or the versions
+                              of block n they logged, with -d written to
+                              dir as block-N.SEQUENCE, or those of inode n
+                              decoded from its inode table block
+    timeline [--body] [path]  print the times of every file below path, /
+                              without one, as a mactime CSV timeline, or
+                              with --body the body file mactime reads
+
+info, ls, stat, find, tree, df, dump-super, icheck, ncheck, locate and
+diff take --json for JSON output, one object per line for listings
+
+find, extract, tar, cpio, export, grep, hash and timeline stop at the
+first entry they can't read, unless given --keep-going to report each
+and go on, failing at the end
+
+extract, tar, cpio and export take --redact file, leaving out entries,
+replacing file content and dropping extended attributes as the skip,
+placeholder and strip-xattr rules of file say, one per line
+";
+
+/// The image, as it is or seen through its journal.
+enum Device {
+    /// read as it is from the start of the file, and mapped
+    Mapped(Mmap),
+    Read(Box<dyn ReadAt>),
+}
+
+impl ReadAt for Device {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Mapped(x) => x.read_at(pos, buf),
+            Self::Read(x) => x.read_at(pos, buf),
+        }
+    }
+}
+
+impl Mapped for Device {
+    fn image(&self) -> Option<&[u8]> {
+        match self {
+            Self::Mapped(x) => x.image(),
+            Self::Read(_) => None,
+        }
+    }
+}
+
+struct Options {
+    image: PathBuf,
+    offset: u64,
+    block_size: Option<u64>,
+    replay: bool,
+    /// committed transactions to go back
+    as_of: Option<u64>,
+    /// 1 by default, one more per `--verbose`
+    verbosity: u8,
+    /// for the whole session
+    timeout: Option<Duration>,
+    /// for each read of the image
+    read_timeout: Option<Duration>,
+}
+
+/// Commands and the flags they take, see `Args::parse`.
+const COMMANDS: &[(&str, &[&str])] = &[
+    ("info", &["--json"]),
+    ("ls", &["-l", "-a", "-i", "--json"]),
+    ("cat", &["--offset=", "--length=", "-i=", "--inode="]),
+    ("stat", &["-i=", "--inode=", "--json", "-c=", "--format="]),
+    (
+        "find",
+        &[
+            "--json",
+            "-type=",
+            "-size=",
+            "-mtime=",
+            "-uid=",
+            "-regex=",
+            "--keep-going",
+        ],
+    ),
+    ("tree", &["-a", "-s", "-L=", "--json"]),
+    ("df", &["--json"]),
+    ("dump-super", &["--json"]),
+    ("shell", &["--session=", "--script="]),
+    (
+        "extract",
+        &[
+            "-n",
+            "--overwrite=",
+            "-p",
+            "--preserve",
+            "--keep-going",
+            "--redact=",
+        ],
+    ),
+    (
+        "tar",
+        &[
+            "-z",
+            "--gzip",
+            "-f=",
+            "--file=",
+            "--keep-going",
+            "--redact=",
+        ],
+    ),
+    (
+        "cpio",
+        &[
+            "-z",
+            "--gzip",
+            "-f=",
+            "--file=",
+            "--keep-going",
+            "--redact=",
+        ],
+    ),
+    (
+        "export",
+        &[
+            "--format=",
+            "--compress=",
+            "-f=",
+            "--file=",
+            "--keep-going",
+            "--redact=",
+            "--metadata-only",
+            "-a=",
+        ],
+    ),
+    ("trim", &["--punch-holes"]),
+    ("image-copy", &[]),
+    ("repack", &["--block-size=", "--keep-going"]),
+    (
+        "xxd",
+        &["--block=", "--inode=", "--file=", "--offset=", "--len="],
+    ),
+    ("icheck", &["--json"]),
+    ("ncheck", &["--json"]),
+    ("locate", &["--all-blocks", "--json"]),
+    (
+        "grep",
+        &[
+            "-r",
+            "-i",
+            "-v",
+            "-n",
+            "-l",
+            "-c",
+            "-F",
+            "-a",
+            "-e=",
+            "--keep-going",
+        ],
+    ),
+    ("hash", &["-r", "-a=", "--keep-going"]),
+    (
+        "diff",
+        &[
+            "--metadata-only",
+            "--content-only",
+            "--other-offset=",
+            "--json",
+        ],
+    ),
+    ("undelete", &["--min-score=", "-d=", "--dest="]),
+    ("carve", &["--max-size=", "-d=", "--dest="]),
+    ("journal", &["--block=", "--inode=", "-d=", "--dest="]),
+    ("timeline", &["--body", "--keep-going"]),
+    ("extents", &[]),
+    ("xattr", &["--dump", "-e=", "--encoding=", "--only-values"]),
+];
+
+fn find_command(name: &str) -> Option<(&'static str, &'static [&'static str])> {
+    COMMANDS.iter().find(|(x, _)| *x == name).copied()
+}
+
+fn main() {
+    let mut args = std::env::args_os().skip(1);
+    let parsed = parse_args(&mut args);
+    let verbosity = parsed.as_ref().map_or(1, |(o, _)| o.verbosity);
+    let result = parsed.and_then(|(options, args)| run(&options, &args));
+    if let Err(e) = result {
+        // the reader went away, as with `| head`
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            if e.kind() == io::ErrorKind::BrokenPipe {
+                return;
+            }
+        }
+        eprintln!("ext4cat: {e}");
+        if verbosity > 1 {
+            for cause in e.iter_causes() {
+                eprintln!("  caused by: {cause}");
+            }
+        }
+        exit(1);
+    }
+}
+
+/// Parse `[options] <image> <command> [args...]`.
+fn parse_args(args: &mut dyn Iterator<Item = OsString>) -> Result<(Options, Args)> {
+    let mut offset = 0;
+    let mut block_size = None;
+    let mut replay = false;
+    let mut as_of = None;
+    let mut verbosity = 1u8;
+    let mut timeout = None;
+    let mut read_timeout = None;
+    let image = loop {
+        let arg = args
+            .next()
+            .ok_or_else(|| format_err!("no image given\n{}", USAGE))?;
+        let Some(flag) = arg.to_str().filter(|x| x.starts_with('-') && x.len() > 1) else {
+            break PathBuf::from(arg);
+        };
+        let (flag, inline) = match flag.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (flag, None),
+        };
+        let mut value = || -> Result<u64> {
+            let value = match &inline {
+                Some(x) => x.clone(),
+                None => args
+                    .next()
+                    .and_then(|x| x.into_string().ok())
+                    .ok_or_else(|| format_err!("{} needs a value", flag))?,
+            };
+            value
+                .parse()
+                .map_err(|_| format_err!("{}: bad number {:?}", flag, value))
+        };
+        match flag {
+            "-o" | "--offset" => offset = value()?,
+            "-b" | "--block-size" => block_size = Some(value()?),
+            "--replay" => replay = true,
+            "--as-of" => as_of = Some(value()?),
+            "--timeout" => timeout = Some(Duration::from_secs(value()?)),
+            "--read-timeout" => read_timeout = Some(Duration::from_millis(value()?)),
+            "-v" | "--verbose" => verbosity = verbosity.saturating_add(1),
+            "-vv" => verbosity = verbosity.saturating_add(2),
+            "-h" | "--help" => {
+                print!("{USAGE}");
+                exit(0);
+            }
+            "-V" | "--version" => {
+                println!("ext4cat {}", env!("CARGO_PKG_VERSION"));
+                exit(0);
+            }
+            _ => bail!("unknown option {}\n{}", flag, USAGE),
+        }
+    };
+    let command = args
+        .next()
+        .ok_or_else(|| format_err!("no command given\n{}", USAGE))?;
+    let (name, spec) = command
+        .to_str()
+        .and_then(find_command)
+        .ok_or_else(|| format_err!("unknown command {:?}\n{}", command, USAGE))?;
+    if replay && as_of.is_some() {
+        bail!("--replay and --as-of are two views, one at most");
+    }
+    let options = Options {
+        image,
+        offset,
+        block_size,
+        replay,
+        as_of,
+        verbosity,
+        timeout,
+        read_timeout,
+    };
+    Ok((options, Args::parse(name, spec, args)?))
+}
+
+/// A command's flags, with their values, and its operands.
+struct Args {
+    command: &'static str,
+    flags: Vec<(&'static str, Option<String>)>,
+    operands: Vec<OsString>,
+}
+
+impl Args {
+    /// Split `args` by `spec`, the flags the command knows. Names ending
+    /// in `=` take a value, single letter flags can be bundled as `-la`.
+    fn parse(
+        command: &'static str,
+        spec: &[&'static str],
+        args: &mut dyn Iterator<Item = OsString>,
+    ) -> Result<Self> {
+        let known = |name: &str| {
+            spec.iter()
+                .find(|x| x.trim_end_matches('=') == name)
+                .map(|x| (x.trim_end_matches('='), x.ends_with('=')))
+        };
+        let mut parsed = Self {
+            command,
+            flags: Vec::new(),
+            operands: Vec::new(),
+        };
+        let mut only_operands = false;
+        while let Some(arg) = args.next() {
+            let flag = match arg.to_str() {
+                Some("--") if !only_operands => {
+                    only_operands = true;
+                    continue;
+                }
+                Some(x) if !only_operands && x.starts_with('-') && x.len() > 1 => x,
+                _ => {
+                    parsed.operands.push(arg);
+                    continue;
+                }
+            };
+            let (flag, inline) = match flag.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (flag, None),
+            };
+            let bundle: Vec<String> = match known(flag) {
+                Some(_) => vec![flag.to_string()],
+                None if !flag.starts_with("--") && inline.is_none() => {
+                    flag[1..].chars().map(|c| format!("-{c}")).collect()
+                }
+                None => vec![flag.to_string()],
+            };
+            for flag in &bundle {
+                let (name, takes_value) = known(flag)
+                    .ok_or_else(|| format_err!("{}: unknown option {}", command, flag))?;
+                let value = match (takes_value, &inline) {
+                    (false, None) => None,
+                    (false, Some(_)) => bail!("{}: {} takes no value", command, flag),
+                    (true, Some(x)) if bundle.len() == 1 => Some(x.clone()),
+                    (true, _) if bundle.len() > 1 => {
+                        bail!("{}: {} takes a value, it can't be bundled", command, flag)
+                    }
+                    (true, _) => Some(
+                        args.next()
+                            .and_then(|x| x.into_string().ok())
+                            .ok_or_else(|| format_err!("{}: {} needs a value", command, flag))?,
+                    ),
+                };
+                parsed.flags.push((name, value));
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// Whether any of `names` was given.
+    fn has(&self, names: &[&str]) -> bool {
+        self.flags.iter().any(|(x, _)| names.contains(x))
+    }
+
+    /// The value of the last of `names` given.
+    fn value(&self, names: &[&str]) -> Option<&str> {
+        self.flags
+            .iter()
+            .rev()
+            .find(|(x, _)| names.contains(x))
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    /// The value of the last of `names` given as a number, decimal or
+    /// hexadecimal after `0x`.
+    fn number(&self, names: &[&str]) -> Result<Option<u64>> {
+        self.value(names)
+            .map(|x| {
+                let n = match x.strip_prefix("0x") {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => x.parse(),
+                };
+                n.map_err(|_| {
+                    format_err!("{}: {} wants a number, not {:?}", self.command, names[0], x)
+                })
+            })
+            .transpose()
+    }
+
+    /// The operand at `i`, an image path.
+    fn path(&self, i: usize) -> Result<Option<&str>> {
+        self.operands
+            .get(i)
+            .map(|x| {
+                x.to_str()
+                    .ok_or_else(|| format_err!("{}: path {:?} isn't UTF-8", self.command, x))
+            })
+            .transpose()
+    }
+
+    fn at_most(&self, n: usize) -> Result<()> {
+        if let Some(arg) = self.operands.get(n) {
+            bail!("{}: unexpected argument {:?}", self.command, arg);
+        }
+        Ok(())
+    }
+}
+
+fn open(options: &Options) -> Result<Ext4Fs<Device>> {
+    let file = File::open(&options.image)
+        .map_err(|e| format_err!("{}: {}", options.image.display(), e))?;
+    // reads of a mapping can't be timed out
+    let map = match (options.offset, options.timeout, options.read_timeout) {
+        (0, None, None) => Mmap::open(&file).ok(),
+        _ => None,
+    };
+    let mut file = Deadline::new(file);
+    if let Some(x) = options.timeout {
+        file = file.total(x);
+    }
+    if let Some(x) = options.read_timeout {
+        file = file.per_read(x);
+    }
+    let dev = Slice::new(file, options.offset, None);
+    // anything parsed out of a non-ext4 image would be garbage
+    let mut magic = [0u8; 2];
+    match dev.read_exact_at(1024 + 0x38, &mut magic) {
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+            bail!("{}: {}", options.image.display(), e)
+        }
+        _ => (),
+    }
+    if u16::from_le_bytes(magic) != SuperBlock::MAGIC {
+        bail!(
+            "{}: no ext4 superblock at offset {}",
+            options.image.display(),
+            options.offset
+        );
+    }
+    let open = |dev: Device| match options.block_size {
+        Some(size) => Ext4Fs::with_block_size(dev, size),
+        None => Ext4Fs::new(dev),
+    };
+    let mut fs = open(match map {
+        Some(map) => Device::Mapped(map),
+        None => Device::Read(Box::new(dev)),
+    })?;
+    if options.replay || options.as_of.is_some() {
+        let view = match options.as_of {
+            Some(n) => fs.as_of(n as usize)?,
+            None => fs.replayed()?,
+        };
+        let stale = view.dev.stale_blocks().len();
+        if stale > 0 && options.verbosity > 1 {
+            eprintln!("ext4cat: {stale} blocks first logged later read as they are now");
+        }
+        // the superblock as replayed too
+        fs = open(Device::Read(Box::new(view.dev)))?;
+    }
+    if options.verbosity > 1 {
+        let level = match options.verbosity {
+            2 => Level::Warning,
+            _ => Level::Info,
+        };
+        fs.set_event_hook(move |anomaly: &Anomaly| {
+            if anomaly.level() >= level {
+                eprintln!("ext4cat: {anomaly:?}");
+            }
+        });
+    }
+    Ok(fs)
+}
+
+fn run(options: &Options, args: &Args) -> Result<()> {
+    let fs = open(options)?;
+    match args.command {
+        "shell" => return shell::shell(options, &fs, args),
+        "trim" if args.has(&["--punch-holes"]) => return punch_holes(options, &fs, args),
+        _ => {}
+    }
+    let mut out = io::stdout().lock();
+    dispatch(&fs, args, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
+
+/// Run every command but `shell`.
+fn dispatch(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    match args.command {
+        "info" => {
+            args.at_most(0)?;
+            match args.has(&["--json"]) {
+                true => {
+                    let mut object = json::Object::new();
+                    add_fields(&mut object, &superblock_fields(fs)?);
+                    writeln!(out, "{object}")?;
+                }
+                false => writeln!(out, "{}", fs.sb)?,
+            }
+        }
+        "ls" => ls(fs, args, out)?,
+        "cat" => cat(fs, args, out)?,
+        "stat" => stat(fs, args, out)?,
+        "find" => find(fs, args, out)?,
+        "tree" => tree(fs, args, out)?,
+        "df" => df(fs, args, out)?,
+        "dump-super" => dump_super(fs, args, out)?,
+        "extract" => extract(fs, args)?,
+        "tar" | "cpio" | "export" => archive(fs, args, out)?,
+        "trim" => {
+            args.at_most(0)?;
+            if args.has(&["--punch-holes"]) {
+                bail!("trim: --punch-holes only from the command line, not the shell");
+            }
+            write!(out, "{}", fs.trim_report()?)?;
+        }
+        "image-copy" => image_copy(fs, args, out)?,
+        "repack" => repack(fs, args, out)?,
+        "xxd" => xxd(fs, args, out)?,
+        "icheck" => icheck(fs, args, out)?,
+        "ncheck" => ncheck(fs, args, out)?,
+        "locate" => locate(fs, args, out)?,
+        "grep" => grep(fs, args, out)?,
+        "hash" => hash(fs, args, out)?,
+        "diff" => diff(fs, args, out)?,
+        "undelete" => undelete(fs, args, out)?,
+        "carve" => carve(fs, args, out)?,
+        "journal" => journal(fs, args, out)?,
+        "timeline" => timeline(fs, args, out)?,
+        "extents" => extents(fs, args, out)?,
+        "xattr" => xattr(fs, args, out)?,
+        _ => unreachable!("command {} without a handler", args.command),
+    }
+    Ok(())
+}
+
+/// `hexdump -C` style lines of `bytes`, numbered from `base`. Runs of
+/// identical lines collapse into a `*`.
+fn hexdump(out: &mut dyn Write, base: u64, bytes: &[u8]) -> io::Result<()> {
+    let mut previous: Option<&[u8]> = None;
+    let mut collapsed = false;
+    for (i, line) in bytes.chunks(16).enumerate() {
+        if previous == Some(line) {
+            if !collapsed {
+                writeln!(out, "*")?;
+                collapsed = true;
+            }
+            continue;
+        }
+        previous = Some(line);
+        collapsed = false;
+        let mut hex = String::new();
+        for (j, b) in line.iter().enumerate() {
+            hex += &format!("{}{:02x}", if j == 8 { "  " } else { " " }, b);
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7E => b as char,
+                _ => '.',
+            })
+            .collect();
+        writeln!(out, "{:08x} {:<49}  |{}|", base + i as u64 * 16, hex, ascii)?;
+    }
+    writeln!(out, "{:08x}", base + bytes.len() as u64)
+}
+
+/// The entries of a directory by name, or a file itself. Names starting
+/// with a dot are hidden without `-a`.
+fn ls(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(1)?;
+    let path = args.path(0)?.unwrap_or("/");
+    let n = fs
+        .resolve(path)?
+        .ok_or_else(|| format_err!("{}: no such file or directory", path))?;
+    let inode = TypedInode::new(fs.inode(n)?);
+    let mut entries = match &inode {
+        TypedInode::Dir(dir) => fs
+            .dir_entries(n, dir)?
+            .into_iter()
+            .filter(|x| args.has(&["-a"]) || !x.name_bytes.starts_with(b"."))
+            .map(|x| (x.name_bytes, x.inode))
+            .collect(),
+        _ => vec![(path.as_bytes().to_vec(), n)],
+    };
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    if args.has(&["-l"]) && !args.has(&["--json"]) {
+        let listing_dir = matches!(inode, TypedInode::Dir(_));
+        return ls_long(fs, &entries, listing_dir, args.has(&["-i"]), out);
+    }
+    for (name, n) in entries {
+        if args.has(&["--json"]) {
+            let inode = fs.inode(n)?;
+            let mut object = inode_json(n, &inode);
+            object.string("name", &String::from_utf8_lossy(&name));
+            writeln!(out, "{object}")?;
+            continue;
+        }
+        let name = QuotingStyle::default().render(&name);
+        match args.has(&["-i"]) {
+            true => writeln!(out, "{} {name}", n.0)?,
+            false => writeln!(out, "{name}")?,
+        }
+    }
+    Ok(())
+}
+
+/// `ls -ln` as coreutils lays it out: columns as wide as their widest
+/// entry, owners as numbers, times in UTC, and a total of the 1 KiB
+/// blocks allocated first when listing a directory.
+fn ls_long(
+    fs: &Ext4Fs<Device>,
+    entries: &[(Vec<u8>, InodeNumber)],
+    listing_dir: bool,
+    with_inode: bool,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs() as i64);
+    let mut rows = Vec::new();
+    let mut total = 0;
+    for (name, n) in entries {
+        let inode = fs.inode(*n)?;
+        total += inode.allocated_bytes(&fs.sb).div_ceil(1024);
+        let mut name = QuotingStyle::default().render(name);
+        if inode.file_type() == FileType::SymbolicLink {
+            let target = TypedInode::new(inode.clone())
+                .into_symlink()?
+                .target(&fs.sb, &fs.dev)?;
+            name += &format!(" -> {}", QuotingStyle::default().render(target.as_bytes()));
+        }
+        let size = match inode.device() {
+            Some((major, minor)) => format!("{major}, {minor}"),
+            None => inode.size.to_string(),
+        };
+        rows.push([
+            n.0.to_string(),
+            inode.mode_string(),
+            inode.links_count.to_string(),
+            inode.uid.to_string(),
+            inode.gid.to_string(),
+            size,
+            ls_time(inode.mtime, now),
+            name,
+        ]);
+    }
+    let width = |i: usize| {
+        rows.iter()
+            .map(|x: &[String; 8]| x[i].len())
+            .max()
+            .unwrap_or(0)
+    };
+    let widths: Vec<usize> = (0..8).map(width).collect();
+    if listing_dir {
+        writeln!(out, "total {total}")?;
+    }
+    for [inode, mode, links, uid, gid, size, time, name] in &rows {
+        if with_inode {
+            write!(out, "{inode:>w$} ", w = widths[0])?;
+        }
+        writeln!(
+            out,
+            "{mode} {links:>lw$} {uid:>uw$} {gid:>gw$} {size:>sw$} {time} {name}",
+            lw = widths[2],
+            uw = widths[3],
+            gw = widths[4],
+            sw = widths[5],
+        )?;
+    }
+    Ok(())
+}
+
+/// `Mon DD HH:MM` for times in the last six months, `Mon DD  YYYY` for
+/// others, as ls prints them.
+fn ls_time(time: Timestamp, now: i64) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    // YYYY-MM-DD HH:MM:SS.nnnnnnnnn
+    let s = time.to_string();
+    let (date, clock) = s.split_once(' ').unwrap_or_default();
+    let mut parts = date.rsplitn(3, '-');
+    let day: u32 = parts.next().and_then(|x| x.parse().ok()).unwrap_or(0);
+    let month: usize = parts.next().and_then(|x| x.parse().ok()).unwrap_or(1);
+    let year = parts.next().unwrap_or_default();
+    let month = MONTHS[month.clamp(1, 12) - 1];
+    // half of an average Gregorian year
+    let recent = time.seconds <= now && now - time.seconds < 31_556_952 / 2;
+    match recent {
+        true => format!("{month} {day:>2} {}", &clock[..5]),
+        false => format!("{month} {day:>2}  {year:>4}"),
+    }
+}
+
+// files are copied out 1 MiB at a time
+const CHUNK: u64 = 1 << 20;
+
+/// Files written out whole, or the `--length` bytes from `--offset` into
+/// each.
+fn cat(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    let offset = args.number(&["--offset"])?.unwrap_or(0);
+    let length = args.number(&["--length"])?.unwrap_or(u64::MAX);
+    let mut files = Vec::new();
+    if let Some(n) = args.number(&["-i", "--inode"])? {
+        args.at_most(0)?;
+        if n == 0 || n > fs.sb.inodes_count {
+            bail!("cat: inode {} out of range", n);
+        }
+        files.push((format!("<{n}>"), TypedInode::new(fs.inode(InodeNumber(n))?)));
+    }
+    for i in 0..args.operands.len() {
+        let path = args.path(i)?.unwrap();
+        let inode = fs
+            .stat(path)?
+            .ok_or_else(|| format_err!("{}: no such file or directory", path))?;
+        files.push((path.to_string(), inode));
+    }
+    if files.is_empty() {
+        bail!("cat: no file given");
+    }
+    let mut buf = Vec::new();
+    for (label, inode) in files {
+        let TypedInode::File(inode) = inode else {
+            bail!("{}: not a regular file", label);
+        };
+        // borrowing the content from the mapping wherever it can
+        if offset == 0 && length == u64::MAX {
+            fs.cat(&inode, out)?;
+            continue;
+        }
+        let reader = fs.reader(&inode)?;
+        let start = offset.min(inode.size);
+        let end = start + length.min(inode.size - start);
+        buf.resize(CHUNK.min(end - start) as usize, 0);
+        for pos in (start..end).step_by(CHUNK as usize) {
+            let len = CHUNK.min(end - pos) as usize;
+            reader.read_exact_at(pos, &mut buf[..len])?;
+            out.write_all(&buf[..len])?;
+        }
+    }
+    Ok(())
+}
+
+fn stat(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    let (label, n) = match args.value(&["-i", "--inode"]) {
+        Some(n) => {
+            args.at_most(0)?;
+            let n = n
+                .parse()
+                .map_err(|_| format_err!("stat: bad inode number {:?}", n))?;
+            if n == 0 || n > fs.sb.inodes_count {
+                bail!("stat: inode {} out of range", n);
+            }
+            (format!("<{n}>"), InodeNumber(n))
+        }
+        None => {
+            args.at_most(1)?;
+            let path = args
+                .path(0)?
+                .ok_or_else(|| format_err!("stat: no path given"))?;
+            let n = fs
+                .resolve(path)?
+                .ok_or_else(|| format_err!("{}: no such file or directory", path))?;
+            (path.to_string(), n)
+        }
+    };
+    let inode = fs.inode(n)?;
+    if args.has(&["--json"]) {
+        return stat_json(fs, &label, n, &inode, out);
+    }
+    if let Some(format) = args.value(&["-c", "--format"]) {
+        writeln!(out, "{}", stat_format(fs, format, &label, n, &inode)?)?;
+        return Ok(());
+    }
+    let file_type = inode.file_type();
+    writeln!(out, "{:<24}{}", "file:", label)?;
+    writeln!(out, "{:<24}{}", "inode:", n.0)?;
+    if let Some(special) = n.special_label() {
+        writeln!(out, "{:<24}{}", "special:", special)?;
+    }
+    writeln!(out, "{:<24}{:?}", "type:", file_type)?;
+    writeln!(out, "{:<24}{:04o}", "mode:", inode.mode & 0o7777)?;
+    writeln!(out, "{:<24}{}", "uid:", inode.uid)?;
+    writeln!(out, "{:<24}{}", "gid:", inode.gid)?;
+    if fs.sb.has_project() {
+        writeln!(out, "{:<24}{}", "project:", inode.projid.unwrap_or(0))?;
+    }
+    writeln!(out, "{:<24}{}", "size:", inode.size)?;
+    let allocated = inode.allocated_bytes(&fs.sb);
+    writeln!(
+        out,
+        "{:<24}{} ({})",
+        "blocks:",
+        allocated / fs.sb.block_size,
+        human_bytes(allocated)
+    )?;
+    writeln!(out, "{:<24}{}", "links:", inode.links_count)?;
+    writeln!(out, "{:<24}{}", "generation:", inode.generation)?;
+    let flags = inode.flags.to_string();
+    writeln!(
+        out,
+        "{:<24}{}",
+        "flags:",
+        if flags.is_empty() { "none" } else { &flags }
+    )?;
+    writeln!(out, "{:<24}{}", "atime:", inode.atime)?;
+    writeln!(out, "{:<24}{}", "ctime:", inode.ctime)?;
+    writeln!(out, "{:<24}{}", "mtime:", inode.mtime)?;
+    if let Some(crtime) = inode.crtime {
+        writeln!(out, "{:<24}{}", "crtime:", crtime)?;
+    }
+    if inode.dtime != 0 {
+        writeln!(out, "{:<24}{}", "dtime:", inode.dtime)?;
+    }
+    if inode.file_acl != 0 {
+        writeln!(out, "{:<24}{}", "xattr block:", inode.file_acl)?;
+    }
+    if let Some((major, minor)) = inode.device() {
+        writeln!(out, "{:<24}{},{}", "device:", major, minor)?;
+    }
+    if file_type == FileType::SymbolicLink {
+        let target = TypedInode::new(inode.clone())
+            .into_symlink()?
+            .target(&fs.sb, &fs.dev)?;
+        writeln!(out, "{:<24}{}", "target:", target)?;
+    }
+    writeln!(out, "{:<24}{:?}", "block map:", inode.block_map_kind())?;
+    let extents = inode.extents(&fs.sb, &fs.dev)?;
+    if !extents.is_empty() {
+        let blocks: u64 = extents.iter().map(|x| x.len).sum();
+        writeln!(
+            out,
+            "{:<24}{} ({} blocks)",
+            "extents:",
+            extents.len(),
+            blocks
+        )?;
+        // debugfs style, logical range then physical range
+        for extent in extents {
+            let last = extent.len - 1;
+            writeln!(
+                out,
+                "  ({}-{}):{}-{}{}",
+                extent.block,
+                extent.block + last,
+                extent.start,
+                extent.start + last,
+                if extent.uninit { " uninit" } else { "" }
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// `format` with each `%` directive replaced by what it stands for, as
+/// in stat(1). Times are UTC.
+fn stat_format(
+    fs: &Ext4Fs<Device>,
+    format: &str,
+    label: &str,
+    n: InodeNumber,
+    inode: &Inode,
+) -> Result<String> {
+    let human = |t: Timestamp| format!("{t} +0000");
+    let target = || -> Result<String> {
+        TypedInode::new(inode.clone())
+            .into_symlink()?
+            .target(&fs.sb, &fs.dev)
+    };
+    let (major, minor) = inode.device().unwrap_or_default();
+    let mut result = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            result.push(c);
+            continue;
+        }
+        let field = match chars.next() {
+            Some('%') => "%".to_string(),
+            Some('n') => label.to_string(),
+            Some('N') => match inode.file_type() {
+                FileType::SymbolicLink => format!("'{}' -> '{}'", label, target()?),
+                _ => format!("'{label}'"),
+            },
+            Some('i') => n.0.to_string(),
+            Some('s') => inode.size.to_string(),
+            Some('a') => format!("{:o}", inode.mode & 0o7777),
+            Some('A') => inode.mode_string(),
+            Some('f') => format!("{:x}", inode.mode),
+            Some('F') => match inode.file_type() {
+                FileType::Regular if inode.size == 0 => "regular empty file",
+                FileType::Regular => "regular file",
+                FileType::Directory => "directory",
+                FileType::SymbolicLink => "symbolic link",
+                FileType::Fifo => "fifo",
+                FileType::CharacterDevice => "character special file",
+                FileType::BlockDevice => "block special file",
+                FileType::Socket => "socket",
+                FileType::Unknown => "weird file",
+            }
+            .to_string(),
+            Some('u') => inode.uid.to_string(),
+            Some('g') => inode.gid.to_string(),
+            Some('h') => inode.links_count.to_string(),
+            // in the 512 byte units of %B
+            Some('b') => (inode.allocated_bytes(&fs.sb) / 512).to_string(),
+            Some('B') => "512".to_string(),
+            Some('o') => fs.sb.block_size.to_string(),
+            Some('t') => format!("{major:x}"),
+            Some('T') => format!("{minor:x}"),
+            Some('x') => human(inode.atime),
+            Some('y') => human(inode.mtime),
+            Some('z') => human(inode.ctime),
+            Some('w') => inode.crtime.map_or("-".to_string(), human),
+            Some('X') => inode.atime.seconds.to_string(),
+            Some('Y') => inode.mtime.seconds.to_string(),
+            Some('Z') => inode.ctime.seconds.to_string(),
+            Some('W') => inode.crtime.map_or(0, |x| x.seconds).to_string(),
+            Some(x) => bail!("stat: unknown directive %{}", x),
+            None => bail!("stat: format ends with %"),
+        };
+        result += &field;
+    }
+    Ok(result)
+}
+
+/// What `stat` prints, as one object.
+fn stat_json(
+    fs: &Ext4Fs<Device>,
+    label: &str,
+    n: InodeNumber,
+    inode: &Inode,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let mut object = inode_json(n, inode);
+    object.string("file", label);
+    if let Some(special) = n.special_label() {
+        object.string("special", special);
+    }
+    if fs.sb.has_project() {
+        object.number("project", inode.projid.unwrap_or(0));
+    }
+    object.number("links", inode.links_count);
+    object.number("generation", inode.generation);
+    object.number("allocated", inode.allocated_bytes(&fs.sb));
+    let flags: Vec<_> = inode
+        .flags
+        .names()
+        .iter()
+        .map(|x| json::string(x))
+        .collect();
+    object.field("flags", &format!("[{}]", flags.join(",")));
+    object.time("atime", inode.atime);
+    object.time("ctime", inode.ctime);
+    if let Some(crtime) = inode.crtime {
+        object.time("crtime", crtime);
+    }
+    if inode.dtime != 0 {
+        object.number("dtime", inode.dtime);
+    }
+    if inode.file_acl != 0 {
+        object.number("xattr_block", inode.file_acl);
+    }
+    if let Some((major, minor)) = inode.device() {
+        object.field("device", &format!("[{major},{minor}]"));
+    }
+    if inode.file_type() == FileType::SymbolicLink {
+        let target = TypedInode::new(inode.clone())
+            .into_symlink()?
+            .target(&fs.sb, &fs.dev)?;
+        object.string("target", &target);
+    }
+    object.string("block_map", &format!("{:?}", inode.block_map_kind()));
+    let extents: Vec<_> = inode
+        .extents(&fs.sb, &fs.dev)?
+        .iter()
+        .map(|extent| {
+            let mut x = json::Object::new();
+            x.number("block", extent.block)
+                .number("len", extent.len)
+                .number("start", extent.start)
+                .bool("uninit", extent.uninit);
+            x.to_string()
+        })
+        .collect();
+    object.field("extents", &format!("[{}]", extents.join(",")));
+    writeln!(out, "{object}")?;
+    Ok(())
+}
+
+/// The fields of an inode listings show, as JSON.
+fn inode_json(n: InodeNumber, inode: &Inode) -> json::Object {
+    let mut object = json::Object::new();
+    object
+        .number("inode", n.0)
+        .string("type", inode.file_type().name())
+        .string("mode", &format!("{:04o}", inode.mode & 0o7777))
+        .number("uid", inode.uid)
+        .number("gid", inode.gid)
+        .number("size", inode.size)
+        .time("mtime", inode.mtime);
+    object
+}
+
+/// The tests of `find` besides the glob, all of which an entry passes.
+/// Numbers are compared as find(1) does: `+N` more than N, `-N` less and
+/// `N` exactly.
+#[derive(Default)]
+struct FindFilters {
+    types: Option<Vec<FileType>>,
+    /// in units of the second, rounded up
+    size: Option<(Ordering, u64, u64)>,
+    /// in whole days before `now`
+    mtime: Option<(Ordering, u64)>,
+    now: i64,
+    uid: Option<(Ordering, u64)>,
+    regex: Option<regex::Regex>,
+}
+
+impl FindFilters {
+    fn new(args: &Args) -> Result<Self> {
+        let mut filters = FindFilters::default();
+        if let Some(x) = args.value(&["-type"]) {
+            let types = x.split(',').map(|c| {
+                Ok(match c {
+                    "f" => FileType::Regular,
+                    "d" => FileType::Directory,
+                    "l" => FileType::SymbolicLink,
+                    "b" => FileType::BlockDevice,
+                    "c" => FileType::CharacterDevice,
+                    "p" => FileType::Fifo,
+                    "s" => FileType::Socket,
+                    _ => bail!("find: -type wants f, d, l, b, c, p or s, not {:?}", c),
+                })
+            });
+            filters.types = Some(types.collect::<Result<_>>()?);
+        }
+        if let Some(x) = args.value(&["-size"]) {
+            let (unit, digits) = match x.char_indices().last() {
+                Some((i, c)) if c.is_ascii_alphabetic() => (c, &x[..i]),
+                _ => ('b', x),
+            };
+            let unit = match unit {
+                'c' => 1,
+                'w' => 2,
+                'b' => 512,
+                'k' => 1 << 10,
+                'M' => 1 << 20,
+                'G' => 1 << 30,
+                _ => bail!(
+                    "find: -size wants a unit of c, w, b, k, M or G, not {:?}",
+                    x
+                ),
+            };
+            let (ordering, n) = find_number("-size", digits)?;
+            filters.size = Some((ordering, n, unit));
+        }
+        if let Some(x) = args.value(&["-mtime"]) {
+            filters.mtime = Some(find_number("-mtime", x)?);
+            filters.now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |x| x.as_secs() as i64);
+        }
+        if let Some(x) = args.value(&["-uid"]) {
+            filters.uid = Some(find_number("-uid", x)?);
+        }
+        if let Some(x) = args.value(&["-regex"]) {
+            filters.regex = Some(regex::Regex::new(&format!("^({x})$"))?);
+        }
+        Ok(filters)
+    }
+
+    fn matches(&self, path: &str, inode: &Inode) -> bool {
+        let test = |filter: Option<(Ordering, u64)>, value: u64| {
+            filter.is_none_or(|(ordering, n)| value.cmp(&n) == ordering)
+        };
+        let age = (self.now - inode.mtime.seconds).max(0) as u64 / 86400;
+        self.types
+            .as_ref()
+            .is_none_or(|x| x.contains(&inode.file_type()))
+            && self.size.is_none_or(|(ordering, n, unit)| {
+                test(Some((ordering, n)), inode.size.div_ceil(unit))
+            })
+            && test(self.mtime, age)
+            && test(self.uid, inode.uid as u64)
+            && self
+                .regex
+                .as_ref()
+                .is_none_or(|x| x.is_match(path.as_bytes()))
+    }
+}
+
+/// A number of find(1), with `+` or `-` before it for more or less than.
+fn find_number(flag: &str, x: &str) -> Result<(Ordering, u64)> {
+    let (ordering, digits) = match x.as_bytes().first() {
+        Some(b'+') => (Ordering::Greater, &x[1..]),
+        Some(b'-') => (Ordering::Less, &x[1..]),
+        _ => (Ordering::Equal, x),
+    };
+    let n = digits
+        .parse()
+        .map_err(|_| format_err!("find: {} wants a number, not {:?}", flag, x))?;
+    Ok((ordering, n))
+}
+
+/// Print the paths matching a glob and the filters given, walking only
+/// the directories below its literal prefix. Entries which can't be read
+/// end the walk, or with `--keep-going` are reported and it goes on.
+fn find(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(1)?;
+    let pattern = args.path(0)?.unwrap_or("/**");
+    let filters = FindFilters::new(args)?;
+    let start = glob::literal_prefix(pattern);
+    if fs.resolve(start)?.is_none() {
+        return Ok(());
+    }
+    let json = args.has(&["--json"]);
+    // kept apart from the errors reading entries, which the walk records
+    let mut write_error = None;
+    let summary = fs.walk(start, args.has(&["--keep-going"]), |entry| {
+        if write_error.is_none()
+            && glob::path_matches(pattern, &entry.path)
+            && filters.matches(&entry.path, &entry.inode)
+        {
+            write_error = match json {
+                true => {
+                    let mut object = inode_json(entry.inode_number, &entry.inode);
+                    object.string("path", &entry.path);
+                    writeln!(out, "{object}")
+                }
+                false => writeln!(out, "{}", entry.path),
+            }
+            .err();
+        }
+        Ok(())
+    })?;
+    if let Some(e) = write_error {
+        return Err(e.into());
+    }
+    for error in &summary.errors {
+        eprintln!("ext4cat: {}: {}", error.path, error.error);
+    }
+    if !summary.errors.is_empty() {
+        bail!("find: {} entries couldn't be read", summary.errors.len());
+    }
+    Ok(())
+}
+
+struct TreeOptions {
+    all: bool,
+    sizes: bool,
+    max_depth: Option<usize>,
+    json: bool,
+}
+
+/// Draw a directory like `tree(1)`, entries sorted by name. With
+/// `--json`, one object per entry in the same order instead.
+fn tree(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(1)?;
+    let path = args.path(0)?.unwrap_or("/");
+    let n = fs
+        .resolve(path)?
+        .ok_or_else(|| format_err!("{}: no such file or directory", path))?;
+    let max_depth = match args.value(&["-L"]) {
+        Some(x) => match x.parse() {
+            Ok(depth) if depth > 0 => Some(depth),
+            _ => bail!("tree: -L wants a depth from 1, not {:?}", x),
+        },
+        None => None,
+    };
+    let mut tree = Tree {
+        fs,
+        options: TreeOptions {
+            all: args.has(&["-a"]),
+            sizes: args.has(&["-s"]),
+            max_depth,
+            json: args.has(&["--json"]),
+        },
+        counts: (0, 0),
+        out,
+    };
+    if !tree.options.json {
+        writeln!(tree.out, "{path}")?;
+    }
+    tree.below(n, path.trim_end_matches('/'), "", 1)?;
+    if tree.options.json {
+        return Ok(());
+    }
+    let (dirs, files) = tree.counts;
+    writeln!(
+        tree.out,
+        "\n{} director{}, {} file{}",
+        dirs,
+        if dirs == 1 { "y" } else { "ies" },
+        files,
+        if files == 1 { "" } else { "s" }
+    )?;
+    Ok(())
+}
+
+struct Tree<'a> {
+    fs: &'a Ext4Fs<Device>,
+    options: TreeOptions,
+    /// directories and files drawn
+    counts: (u64, u64),
+    out: &'a mut dyn Write,
+}
+
+impl Tree<'_> {
+    fn below(&mut self, dir: InodeNumber, path: &str, prefix: &str, depth: usize) -> Result<()> {
+        let fs = self.fs;
+        let TypedInode::Dir(inode) = TypedInode::new(fs.inode(dir)?) else {
+            return Ok(());
+        };
+        let mut entries: Vec<_> = fs
+            .dir_entries(dir, &inode)?
+            .into_iter()
+            .filter(|x| x.name != "." && x.name != "..")
+            .filter(|x| self.options.all || !x.name_bytes.starts_with(b"."))
+            .collect();
+        entries.sort_by(|a, b| a.name_bytes.cmp(&b.name_bytes));
+        for (i, entry) in entries.iter().enumerate() {
+            let last = i + 1 == entries.len();
+            let inode = fs.inode(entry.inode)?;
+            let entry_path = format!("{}/{}", path, entry.name);
+            let target = match inode.file_type() {
+                FileType::SymbolicLink => Some(
+                    TypedInode::new(inode.clone())
+                        .into_symlink()?
+                        .target(&fs.sb, &fs.dev)?,
+                ),
+                _ => None,
+            };
+            match inode.file_type() {
+                FileType::Directory => self.counts.0 += 1,
+                _ => self.counts.1 += 1,
+            }
+            if self.options.json {
+                let mut object = inode_json(entry.inode, &inode);
+                object.string("path", &entry_path);
+                object.number("depth", depth as u64);
+                if let Some(target) = &target {
+                    object.string("target", target);
+                }
+                writeln!(self.out, "{object}")?;
+            } else {
+                let mut line = format!("{}{}", prefix, if last { "└── " } else { "├── " });
+                if self.options.sizes {
+                    line += &format!("[{:>11}]  ", inode.size);
+                }
+                line += &QuotingStyle::default().render(&entry.name_bytes);
+                if let Some(target) = &target {
+                    line += &format!(" -> {target}");
+                }
+                writeln!(self.out, "{line}")?;
+            }
+            if self.options.max_depth.is_none_or(|max| depth < max) {
+                let prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                self.below(entry.inode, &entry_path, &prefix, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn df(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(0)?;
+    let sb = &fs.sb;
+    if args.has(&["--json"]) {
+        let free = fs.free_space()?;
+        let mut object = json::Object::new();
+        object
+            .string("label", &sb.volume_name)
+            .string("uuid", &sb.uuid_string())
+            .number("block_size", sb.block_size)
+            .number("blocks", sb.blocks_count)
+            .number("free_blocks", free.free_blocks())
+            .number("reserved_blocks", sb.r_blocks_count)
+            .number("inodes", free.inodes_count)
+            .number("free_inodes", free.free_inodes())
+            .number("superblock_free_blocks", sb.free_blocks_count)
+            .number("superblock_free_inodes", sb.free_inodes_count);
+        writeln!(out, "{object}")?;
+        return Ok(());
+    }
+    let label = match sb.volume_name.as_str() {
+        "" => "<none>",
+        x => x,
+    };
+    writeln!(out, "{:<24}{}", "label:", label)?;
+    writeln!(out, "{:<24}{}", "uuid:", sb.uuid_string())?;
+    writeln!(out, "{:<24}{}", "block size:", human_bytes(sb.block_size))?;
+    let free = fs.free_space()?;
+    let total = sb.blocks_count * sb.block_size;
+    let available = free.free_blocks() * sb.block_size;
+    writeln!(
+        out,
+        "{:<24}{} total, {} used, {} free",
+        "size:",
+        human_bytes(total),
+        human_bytes(total - available.min(total)),
+        human_bytes(available)
+    )?;
+    writeln!(
+        out,
+        "{:<24}{} ({})",
+        "reserved blocks:",
+        sb.r_blocks_count,
+        human_bytes(sb.r_blocks_count * sb.block_size)
+    )?;
+    writeln!(out)?;
+    write!(out, "{free}")?;
+    // the kernel only brings the counters up to date now and then
+    if (sb.free_blocks_count, sb.free_inodes_count) != (free.free_blocks(), free.free_inodes()) {
+        writeln!(
+            out,
+            "\nthe superblock counts {} free blocks and {} free inodes",
+            sb.free_blocks_count, sb.free_inodes_count
+        )?;
+    }
+    Ok(())
+}
+
+/// A field of `info --json` and `dump-super`, a number in JSON when it
+/// is one.
+enum Value {
+    Number(u64),
+    Text(String),
+    /// flag names, `none` when there are none
+    Names(Vec<String>),
+}
+
+macro_rules! number_value {
+    ($($t:ty),*) => {
+        $(impl From<$t> for Value {
+            fn from(x: $t) -> Self {
+                Value::Number(x.into())
+            }
+        })*
+    };
+}
+
+number_value!(u8, u16, u32, u64);
+
+impl From<String> for Value {
+    fn from(x: String) -> Self {
+        Value::Text(x)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(x) => write!(f, "{x}"),
+            Value::Text(x) => f.write_str(x),
+            Value::Names(x) if x.is_empty() => f.write_str("none"),
+            Value::Names(x) => f.write_str(&x.join(" ")),
+        }
+    }
+}
+
+/// Add `fields` to `object`, their names in snake case.
+fn add_fields(object: &mut json::Object, fields: &[(&str, Value)]) {
+    for (name, value) in fields {
+        let key = name.replace(' ', "_");
+        match value {
+            Value::Number(x) => object.number(&key, *x),
+            Value::Text(x) => object.string(&key, x),
+            Value::Names(x) => {
+                let names: Vec<_> = x.iter().map(|x| json::string(x)).collect();
+                object.field(&key, &format!("[{}]", names.join(",")))
+            }
+        };
+    }
+}
+
+/// Every field parsed from the superblock.
+fn superblock_fields(fs: &Ext4Fs<Device>) -> Result<Vec<(&'static str, Value)>> {
+    let sb = &fs.sb;
+    let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    let hash_seed: Vec<u8> = sb.hash_seed.iter().flat_map(|x| x.to_le_bytes()).collect();
+    let hash_version = dirhash::HashVersion::new(sb.def_hash_version, sb)
+        .map(|x| x.name())
+        .unwrap_or("unknown");
+    let checksum = match (sb.has_metadata_csum(), sb.checksum_valid) {
+        (false, _) => "none".to_string(),
+        (true, true) => format!("0x{:08x}", sb.checksum),
+        (true, false) => format!(
+            "0x{:08x}, computed 0x{:08x}",
+            sb.checksum,
+            sb.computed_checksum(&fs.dev)?
+        ),
+    };
+    Ok(vec![
+        ("magic", format!("0x{:04X}", sb.magic).into()),
+        ("uuid", sb.uuid_string().into()),
+        ("volume name", sb.volume_name.clone().into()),
+        ("revision", sb.rev_level.into()),
+        (
+            "compat features",
+            Value::Names(features::names(sb.feature_compat, features::COMPAT)),
+        ),
+        (
+            "incompat features",
+            Value::Names(features::names(sb.feature_incompat, features::INCOMPAT)),
+        ),
+        (
+            "ro_compat features",
+            Value::Names(features::names(sb.feature_ro_compat, features::RO_COMPAT)),
+        ),
+        ("flags", format!("0x{:x}", sb.flags).into()),
+        ("inode count", sb.inodes_count.into()),
+        ("block count", sb.blocks_count.into()),
+        ("reserved block count", sb.r_blocks_count.into()),
+        ("free blocks", sb.free_blocks_count.into()),
+        ("free inodes", sb.free_inodes_count.into()),
+        ("first data block", sb.first_data_block.into()),
+        ("block size", sb.block_size.into()),
+        ("cluster size", sb.cluster_size.into()),
+        ("blocks per group", sb.block_per_group.into()),
+        ("clusters per group", sb.cluster_per_group.into()),
+        ("inodes per group", sb.inode_per_group.into()),
+        ("group count", sb.group_count().into()),
+        ("inode size", sb.inode_size.into()),
+        ("first inode", sb.first_ino.into()),
+        ("descriptor size", sb.desc_size.into()),
+        ("reserved gdt blocks", sb.reserved_gdt_blocks.into()),
+        ("first meta bg", sb.first_meta_bg.into()),
+        ("groups per flex", sb.groups_per_flex().into()),
+        ("journal inode", sb.journal_inum.into()),
+        ("first orphan inode", sb.last_orphan.into()),
+        ("mmp block", sb.mmp_block.into()),
+        ("mmp update interval", sb.mmp_update_interval.into()),
+        ("user quota inode", sb.usr_quota_inum.into()),
+        ("group quota inode", sb.grp_quota_inum.into()),
+        ("project quota inode", sb.prj_quota_inum.into()),
+        ("filename encoding", sb.encoding.to_string().into()),
+        (
+            "encoding flags",
+            format!("0x{:x}", sb.encoding_flags).into(),
+        ),
+        ("hash seed", hex(&hash_seed).into()),
+        (
+            "default hash",
+            format!("{} ({})", hash_version, sb.def_hash_version).into(),
+        ),
+        (
+            "checksum seed",
+            format!("0x{:08x}", sb.checksum_seed).into(),
+        ),
+        ("checksum", checksum.into()),
+    ])
+}
+
+/// Everything parsed from the superblock and the group descriptors, one
+/// `name: value` line each, so dumps of two images can be diffed. With
+/// `--json`, the superblock object then one object per group.
+fn dump_super(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(0)?;
+    let sb = &fs.sb;
+    let json = args.has(&["--json"]);
+    let fields = superblock_fields(fs)?;
+    if json {
+        let mut object = json::Object::new();
+        add_fields(&mut object, &fields);
+        writeln!(out, "{object}")?;
+    } else {
+        for (name, value) in fields {
+            writeln!(out, "{:<24}{}", format!("{name}:"), value)?;
+        }
+    }
+    for group in 0..sb.group_count() {
+        let number = BlockGroupNumber(group);
+        let bgd = number.block_group_descriptor(sb, &fs.dev)?;
+        let first = sb.group_first_block(group);
+        let last = (first + sb.block_per_group).min(sb.blocks_count) - 1;
+        let flags = bgd.flag_names().iter().map(|x| x.to_string()).collect();
+        let checksum = match number.descriptor_checksum(sb, &fs.dev)? {
+            None => "none".to_string(),
+            Some(x) if x == bgd.checksum => format!("0x{x:04x}"),
+            Some(x) => format!("0x{:04x}, computed 0x{:04x}", bgd.checksum, x),
+        };
+        let fields: Vec<(&str, Value)> = vec![
+            ("flags", Value::Names(flags)),
+            ("checksum", checksum.into()),
+            ("block bitmap", bgd.block_bitmap.into()),
+            ("inode bitmap", bgd.inode_bitmap.into()),
+            ("inode table", bgd.inode_table.into()),
+            ("free blocks", bgd.free_blocks_count.into()),
+            ("free inodes", bgd.free_inodes_count.into()),
+            ("unused inodes", bgd.itable_unused.into()),
+        ];
+        if json {
+            let mut object = json::Object::new();
+            object
+                .number("group", group)
+                .number("first_block", first)
+                .number("last_block", last)
+                .bool("superblock_copy", sb.group_has_super(group));
+            add_fields(&mut object, &fields);
+            writeln!(out, "{object}")?;
+            continue;
+        }
+        writeln!(out, "\ngroup {group}: blocks {first}-{last}")?;
+        if sb.group_has_super(group) {
+            writeln!(out, "  {:<22}yes", "superblock copy:")?;
+        }
+        for (name, value) in fields {
+            writeln!(out, "  {:<22}{}", format!("{name}:"), value)?;
+        }
+    }
+    Ok(())
+}
+
+/// The policy of the redaction rules file given with `--redact`, one
+/// redacting nothing without.
+fn redaction(args: &Args) -> Result<redact::RedactionPolicy> {
+    let Some(file) = args.value(&["--redact"]) else {
+        return Ok(redact::RedactionPolicy::new());
+    };
+    let text = std::fs::read_to_string(file).map_err(|e| format_err!("{}: {}", file, e))?;
+    redact::RedactionPolicy::parse(&text).map_err(|e| format_err!("{}: {}", file, e))
+}
+
+fn extract(fs: &Ext4Fs<Device>, args: &Args) -> Result<()> {
+    args.at_most(2)?;
+    let (Some(path), Some(dest)) = (args.path(0)?, args.operands.get(1)) else {
+        bail!("extract: a path and a destination are needed");
+    };
+    let overwrite = match args.value(&["--overwrite"]) {
+        _ if args.has(&["-n"]) => Overwrite::Skip,
+        None | Some("replace") => Overwrite::Replace,
+        Some("skip") => Overwrite::Skip,
+        Some("error") => Overwrite::Refuse,
+        Some(x) => bail!(
+            "extract: --overwrite wants replace, skip or error, not {:?}",
+            x
+        ),
+    };
+    let redaction = redaction(args)?;
+    let options = ExtractOptions {
+        overwrite,
+        preserve: args.has(&["-p", "--preserve"]),
+        keep_going: args.has(&["--keep-going"]),
+    };
+    // a single file goes into the destination directory
+    std::fs::create_dir_all(dest)
+        .map_err(|e| format_err!("{}: {}", Path::new(dest).display(), e))?;
+    let summary = fs.extract(
+        path,
+        Path::new(dest),
+        &Identity,
+        &redaction,
+        &options,
+        &mut ProgressLine::new(false),
+    )?;
+    for error in &summary.errors {
+        eprintln!("ext4cat: {}: {}", error.path, error.error);
+    }
+    if !summary.errors.is_empty() {
+        bail!(
+            "extract: {} entries couldn't be extracted",
+            summary.errors.len()
+        );
+    }
+    Ok(())
+}
+
+/// `tar`, `cpio` and `export`, archives or with `--metadata-only` the
+/// manifest of a tree.
+fn archive(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(1)?;
+    let path = args.path(0)?.unwrap_or("/");
+    let redaction = &redaction(args)?;
+    let format = match args.command {
+        "export" if args.has(&["--metadata-only"]) => match args.value(&["--format"]) {
+            Some(_) => bail!("export: --metadata-only has a format of its own"),
+            None => "manifest",
+        },
+        "export" => match args.value(&["--format"]).unwrap_or("tar") {
+            x @ ("tar" | "cpio") => x,
+            x => bail!("export: --format wants tar or cpio, not {:?}", x),
+        },
+        x => x,
+    };
+    let algorithms = match format {
+        "manifest" => digest::parse_algorithms(args.value(&["-a"]).unwrap_or("sha256"))?,
+        _ if args.value(&["-a"]).is_some() => bail!("export: -a goes with --metadata-only"),
+        _ => Vec::new(),
+    };
+    let compression = match args.value(&["--compress"]) {
+        _ if args.has(&["-z", "--gzip"]) => Some(("gzip", 6)),
+        None | Some("none") => None,
+        Some(x) => {
+            let (name, level) = x.split_once(':').unwrap_or((x, ""));
+            let (default, max) = match name {
+                "gzip" => (6, 9),
+                "zstd" => (3, 19),
+                _ => bail!("export: --compress wants gzip or zstd, not {:?}", x),
+            };
+            let level = match level {
+                "" => default,
+                level => match level.parse() {
+                    Ok(n) if (1..=max).contains(&n) => n,
+                    _ => bail!(
+                        "export: {} levels go from 1 to {}, not {:?}",
+                        name,
+                        max,
+                        level
+                    ),
+                },
+            };
+            Some((name, level))
+        }
+    };
+    let mut file;
+    let out = match args.value(&["-f", "--file"]) {
+        Some(name) if name != "-" => {
+            file = BufWriter::new(File::create(name).map_err(|e| format_err!("{}: {}", name, e))?);
+            &mut file as &mut dyn Write
+        }
+        _ => out,
+    };
+    let identity = &Identity;
+    let keep_going = args.has(&["--keep-going"]);
+    let write = |out: &mut dyn Write| match format {
+        "tar" => fs.write_tar(path, keep_going, identity, redaction, out),
+        "cpio" => fs.write_cpio(path, keep_going, identity, redaction, out),
+        _ => fs.write_manifest(path, keep_going, identity, redaction, &algorithms, out),
+    };
+    let summary = match compression {
+        Some(("gzip", level)) => {
+            let mut gzip = GzipWriter::new(out, level);
+            let summary = write(&mut gzip)?;
+            gzip.finish()?;
+            summary
+        }
+        Some((_, level)) => {
+            let mut zstd = ZstdWriter::new(out, level);
+            let summary = write(&mut zstd)?;
+            zstd.finish()?;
+            summary
+        }
+        None => write(out)?,
+    };
+    for error in &summary.errors {
+        eprintln!("ext4cat: {}: {}", error.path, error.error);
+    }
+    if !summary.errors.is_empty() {
+        bail!(
+            "{}: {} entries couldn't be archived",
+            args.command,
+            summary.errors.len()
+        );
+    }
+    Ok(())
+}
+
+/// Hexdump raw bytes: a block, an inode record, or file content, each
+/// numbered by where it is, on the device or in the file.
+fn xxd(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(0)?;
+    let number = |name: &str| args.number(&[name]);
+    let (block, inode, file) = (
+        number("--block")?,
+        number("--inode")?,
+        args.value(&["--file"]),
+    );
+    // what to dump: where it starts on the device or in the file, where
+    // in what it is read from, and its size
+    let (base, start, size, reader): (u64, u64, u64, Box<dyn ReadAt + '_>) =
+        match (block, inode, file) {
+            (Some(n), None, None) => {
+                if n >= fs.sb.blocks_count {
+                    bail!("xxd: block {} past the last block", n);
+                }
+                let base = n * fs.sb.block_size;
+                (base, base, fs.sb.block_size, Box::new(&fs.dev))
+            }
+            (None, Some(n), None) => {
+                if n == 0 || n > fs.sb.inodes_count {
+                    bail!("xxd: inode {} out of range", n);
+                }
+                let (block, offset) = InodeNumber(n).inode_location(&fs.sb, &fs.dev)?;
+                let base = block * fs.sb.block_size + offset;
+                (base, base, fs.sb.inode_size, Box::new(&fs.dev))
+            }
+            (None, None, Some(path)) => {
+                let inode = fs
+                    .stat(path)?
+                    .ok_or_else(|| format_err!("{}: no such file or directory", path))?
+                    .into_file()?;
+                (0, 0, inode.size, Box::new(fs.reader(&inode)?))
+            }
+            _ => bail!("xxd: one of --block, --inode and --file is needed"),
+        };
+    let offset = number("--offset")?.unwrap_or(0);
+    if offset > size {
+        bail!("xxd: offset {} past the end, at {}", offset, size);
+    }
+    let len = number("--len")?.unwrap_or(u64::MAX).min(size - offset);
+    let mut buf = vec![0u8; len as usize];
+    reader.read_exact_at(start + offset, &mut buf)?;
+    hexdump(out, base + offset, &buf)?;
+    Ok(())
+}
+
+/// Punch the unallocated ranges out of the image file, the one place
+/// ext4cat writes to the image, through a handle of its own.
+fn punch_holes(options: &Options, fs: &Ext4Fs<Device>, args: &Args) -> Result<()> {
+    args.at_most(0)?;
+    let image = OpenOptions::new()
+        .write(true)
+        .open(&options.image)
+        .map_err(|e| format_err!("{}: {}", options.image.display(), e))?;
+    let punched = fs.trim_report()?.punch_holes(&image, options.offset)?;
+    println!("{} punched", human_bytes(punched));
+    Ok(())
+}
+
+/// Copy the filesystem into a new sparse image, writing only the blocks
+/// in use.
+fn image_copy(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(1)?;
+    let dest = args
+        .operands
+        .first()
+        .ok_or_else(|| format_err!("image-copy: no destination given"))?;
+    let file =
+        File::create(dest).map_err(|e| format_err!("{}: {}", Path::new(dest).display(), e))?;
+    let written = fs.sparse_copy(&file)?;
+    writeln!(
+        out,
+        "{} written of {}",
+        human_bytes(written),
+        human_bytes(fs.sb.blocks_count * fs.sb.block_size)
+    )?;
+    Ok(())
+}
+
+/// A directory written out as the root of a new image by the builder.
+fn repack(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(2)?;
+    let (Some(path), Some(dest)) = (args.path(0)?, args.operands.get(1)) else {
+        bail!("repack: a directory and a destination are needed");
+    };
+    let block_size = args
+        .number(&["--block-size"])?
+        .unwrap_or(fs.sb.block_size.min(4096));
+    let mut builder = ImageBuilder::new()
+        .block_size(block_size)
+        .label(&fs.sb.volume_name)
+        .uuid(fs.sb.uuid);
+    let summary = fs.repack(path, args.has(&["--keep-going"]), &mut builder)?;
+    for error in &summary.errors {
+        eprintln!("ext4cat: {}: {}", error.path, error.error);
+    }
+    let mut file =
+        File::create(dest).map_err(|e| format_err!("{}: {}", Path::new(dest).display(), e))?;
+    let written = builder.build(&mut file)?;
+    writeln!(out, "{} written", human_bytes(written))?;
+    if !summary.errors.is_empty() {
+        bail!(
+            "repack: {} entries couldn't be repacked",
+            summary.errors.len()
+        );
+    }
+    Ok(())
+}
+
+/// The inode using each block given, with its path when it has one.
+fn icheck(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    if args.operands.is_empty() {
+        bail!("icheck: no block given");
+    }
+    let blocks = args
+        .operands
+        .iter()
+        .map(|x| {
+            let x = x.to_string_lossy();
+            match x.parse::<u64>() {
+                Ok(n) if n < fs.sb.blocks_count => Ok(n),
+                Ok(n) => Err(format_err!("icheck: block {} past the last block", n)),
+                Err(_) => Err(format_err!("icheck: bad block number {:?}", x)),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let owners = fs.block_owners(&blocks)?;
+    let paths = match owners.iter().any(Option::is_some) {
+        true => fs.path_table()?,
+        false => PathTable::default(),
+    };
+    let json = args.has(&["--json"]);
+    if !json {
+        writeln!(out, "{:<12}{:<12}path", "block", "inode")?;
+    }
+    for (block, owner) in blocks.into_iter().zip(owners) {
+        let path = owner.and_then(|n| paths.path(n));
+        if json {
+            let mut object = json::Object::new();
+            object.number("block", block);
+            match owner {
+                Some(n) => object.number("inode", n.0),
+                None => object.field("inode", "null"),
+            };
+            if let Some(path) = path {
+                object.string("path", path);
+            }
+            if let Some(special) = owner.and_then(InodeNumber::special_label) {
+                object.string("special", special);
+            }
+            object.bool("allocated", fs.is_block_allocated(block)?);
+            writeln!(out, "{object}")?;
+            continue;
+        }
+        match owner {
+            Some(n) => match (path, n.special_label()) {
+                (Some(path), _) => writeln!(out, "{:<12}{:<12}{}", block, n.0, path)?,
+                // the journal, resize inode and the like have no path
+                (None, Some(special)) => writeln!(out, "{:<12}{:<12}<{}>", block, n.0, special)?,
+                (None, None) => writeln!(out, "{:<12}{:<12}-", block, n.0)?,
+            },
+            // allocated blocks of no inode are filesystem metadata, or lost
+            None if fs.is_block_allocated(block)? => writeln!(out, "{block:<12}<block not found>")?,
+            None => writeln!(out, "{block:<12}<block not found, free>")?,
+        }
+    }
+    Ok(())
+}
+
+/// Every path of each inode given, hard links included.
+fn ncheck(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    if args.operands.is_empty() {
+        bail!("ncheck: no inode given");
+    }
+    let inodes = args
+        .operands
+        .iter()
+        .map(|x| {
+            let x = x.to_string_lossy();
+            match x.parse::<u64>() {
+                Ok(n) if n > 0 && n <= fs.sb.inodes_count => Ok(InodeNumber(n)),
+                Ok(n) => Err(format_err!("ncheck: inode {} out of range", n)),
+                Err(_) => Err(format_err!("ncheck: bad inode number {:?}", x)),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let paths = fs.path_table()?;
+    let json = args.has(&["--json"]);
+    if !json {
+        writeln!(out, "{:<12}path", "inode")?;
+    }
+    for n in inodes {
+        let paths = paths.paths(n);
+        if json {
+            let paths: Vec<_> = paths.iter().map(|x| json::string(x)).collect();
+            let mut object = json::Object::new();
+            object
+                .number("inode", n.0)
+                .field("paths", &format!("[{}]", paths.join(",")));
+            if let Some(special) = n.special_label() {
+                object.string("special", special);
+            }
+            writeln!(out, "{object}")?;
+            continue;
+        }
+        if paths.is_empty() {
+            let special = n.special_label().unwrap_or("no path");
+            writeln!(out, "{:<12}<{}>", n.0, special)?;
+        }
+        for path in paths {
+            writeln!(out, "{:<12}{}", n.0, path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Directory entries whose name contains a substring, as found in the
+/// blocks of directories, with the path of their directory when it has
+/// one. Entries found in slack space are marked deleted.
+fn locate(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(1)?;
+    let pattern = args
+        .operands
+        .first()
+        .ok_or_else(|| format_err!("locate: no substring given"))?
+        .to_string_lossy();
+    let hits = fs.locate(&pattern, args.has(&["--all-blocks"]))?;
+    let paths = match hits.iter().any(|x| x.dir.is_some()) {
+        true => fs.path_table()?,
+        false => PathTable::default(),
+    };
+    let json = args.has(&["--json"]);
+    if !json {
+        writeln!(out, "{:<12}{:<12}path", "block", "inode")?;
+    }
+    for hit in hits {
+        let path = match hit.dir.and_then(|n| paths.path(n)) {
+            Some("/") => format!("/{}", hit.name),
+            Some(dir) => format!("{}/{}", dir, hit.name),
+            None => hit.name.clone(),
+        };
+        if json {
+            let mut object = json::Object::new();
+            object
+                .number("block", hit.block)
+                .number("inode", hit.inode.0);
+            match hit.dir {
+                Some(n) => object.number("dir", n.0),
+                None => object.field("dir", "null"),
+            };
+            object
+                .string("name", &hit.name)
+                .string("path", &path)
+                .bool("deleted", hit.deleted);
+            writeln!(out, "{object}")?;
+            continue;
+        }
+        let deleted = if hit.deleted { " (deleted)" } else { "" };
+        writeln!(
+            out,
+            "{:<12}{:<12}{}{}",
+            hit.block, hit.inode.0, path, deleted
+        )?;
+    }
+    Ok(())
+}
+
+struct GrepOptions {
+    regex: regex::Regex,
+    invert: bool,
+    line_numbers: bool,
+    /// print the names of matching files only
+    names_only: bool,
+    count: bool,
+    /// search binary files as text, instead of only saying they match
+    text: bool,
+    /// prefix lines with the file name
+    with_names: bool,
+}
+
+/// Search files line by line, GNU grep style. Entries which can't be
+/// read end the search, or with `--keep-going` are reported and it goes
+/// on.
+fn grep(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    let (pattern, first_path) = match args.value(&["-e"]) {
+        Some(pattern) => (pattern.to_string(), 0),
+        None => {
+            let pattern = args
+                .operands
+                .first()
+                .ok_or_else(|| format_err!("grep: no pattern given"))?;
+            (pattern.to_string_lossy().into_owned(), 1)
+        }
+    };
+    let recursive = args.has(&["-r"]);
+    let mut paths = (first_path..args.operands.len())
+        .map(|i| args.path(i).map(Option::unwrap))
+        .collect::<Result<Vec<_>>>()?;
+    if paths.is_empty() {
+        if !recursive {
+            bail!("grep: no file given");
+        }
+        paths.push("/");
+    }
+    let pattern = match args.has(&["-F"]) {
+        true => regex::escape(&pattern),
+        false => pattern,
+    };
+    let options = GrepOptions {
+        regex: match args.has(&["-i"]) {
+            true => regex::Regex::case_insensitive(&pattern)?,
+            false => regex::Regex::new(&pattern)?,
+        },
+        invert: args.has(&["-v"]),
+        line_numbers: args.has(&["-n"]),
+        names_only: args.has(&["-l"]),
+        count: args.has(&["-c"]),
+        text: args.has(&["-a"]),
+        with_names: recursive || paths.len() > 1,
+    };
+    let mut errors = 0;
+    for path in paths {
+        let inode = match fs.stat(path)? {
+            Some(x) => x,
+            None => {
+                eprintln!("ext4cat: grep: {path}: no such file or directory");
+                errors += 1;
+                continue;
+            }
+        };
+        if inode.file_type() != FileType::Directory {
+            grep_file(fs, inode.as_file()?, path, &options, out)??;
+            continue;
+        }
+        if !recursive {
+            eprintln!("ext4cat: grep: {path}: is a directory");
+            errors += 1;
+            continue;
+        }
+        // kept apart from the errors reading entries, which the walk records
+        let mut write_error = None;
+        let summary = fs.walk(path, args.has(&["--keep-going"]), |entry| {
+            if write_error.is_some() || entry.inode.file_type() != FileType::Regular {
+                return Ok(());
+            }
+            let file = TypedInode::new(entry.inode.clone()).into_file()?;
+            match grep_file(fs, &file, &entry.path, &options, out) {
+                Ok(result) => result,
+                Err(e) => {
+                    write_error = Some(e);
+                    Ok(())
+                }
+            }
+        })?;
+        if let Some(e) = write_error {
+            return Err(e.into());
+        }
+        for error in &summary.errors {
+            eprintln!("ext4cat: {}: {}", error.path, error.error);
+        }
+        errors += summary.errors.len();
+    }
+    if errors > 0 {
+        bail!("grep: {} entries couldn't be searched", errors);
+    }
+    Ok(())
+}
+
+/// Search one file, streaming its content. Failing to write is the
+/// outer error, failing to read the inner one.
+fn grep_file(
+    fs: &Ext4Fs<Device>,
+    inode: &FileInode,
+    path: &str,
+    options: &GrepOptions,
+    out: &mut dyn Write,
+) -> io::Result<Result<()>> {
+    let reader = match fs.reader(inode) {
+        Ok(x) => x,
+        Err(e) => return Ok(Err(e)),
+    };
+    let prefix = match options.with_names {
+        true => format!("{path}:"),
+        false => String::new(),
+    };
+    let (mut line_number, mut count) = (0u64, 0u64);
+    // handle a line, true once the file needs no more searching
+    let mut line = |line: &[u8], binary: bool| -> io::Result<bool> {
+        line_number += 1;
+        if options.regex.is_match(line) == options.invert {
+            return Ok(false);
+        }
+        count += 1;
+        if options.names_only {
+            writeln!(out, "{path}")?;
+            return Ok(true);
+        }
+        if options.count {
+            return Ok(false);
+        }
+        if binary {
+            writeln!(out, "{path}: binary file matches")?;
+            return Ok(true);
+        }
+        write!(out, "{prefix}")?;
+        if options.line_numbers {
+            write!(out, "{line_number}:")?;
+        }
+        out.write_all(line)?;
+        writeln!(out)?;
+        Ok(false)
+    };
+    let mut buf = vec![0u8; CHUNK.min(inode.size) as usize];
+    // the start of a line continuing in the next chunk
+    let mut partial = Vec::new();
+    let mut binary = false;
+    for pos in (0..inode.size).step_by(CHUNK as usize) {
+        let len = CHUNK.min(inode.size - pos) as usize;
+        if let Err(e) = reader.read_exact_at(pos, &mut buf[..len]) {
+            return Ok(Err(e.into()));
+        }
+        let mut rest = &buf[..len];
+        binary |= !options.text && rest.contains(&0);
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            let done = if partial.is_empty() {
+                line(&rest[..end], binary)?
+            } else {
+                partial.extend_from_slice(&rest[..end]);
+                let done = line(&partial, binary)?;
+                partial.clear();
+                done
+            };
+            if done {
+                return Ok(Ok(()));
+            }
+            rest = &rest[end + 1..];
+        }
+        partial.extend_from_slice(rest);
+    }
+    // a last line without a newline
+    if !partial.is_empty() {
+        line(&partial, binary)?;
+    }
+    if options.count {
+        writeln!(out, "{prefix}{count}")?;
+    }
+    Ok(Ok(()))
+}
+
+/// Digests of files in `sha256sum` format, or with several algorithms
+/// tagged BSD style, which `sha256sum -c` reads as well.
+fn hash(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    if args.operands.is_empty() {
+        bail!("hash: no file given");
+    }
+    let algorithms = digest::parse_algorithms(args.value(&["-a"]).unwrap_or("sha256"))?;
+    let write = |path: &str, digests: Vec<digest::Digest>, out: &mut dyn Write| {
+        // names with a newline or a backslash are escaped, the line
+        // marked by a leading backslash
+        let escaped = path.replace('\\', "\\\\").replace('\n', "\\n");
+        let mark = if escaped.len() != path.len() {
+            "\\"
+        } else {
+            ""
+        };
+        for digest in digests {
+            match algorithms.len() {
+                1 => writeln!(out, "{mark}{digest}  {escaped}")?,
+                _ => writeln!(
+                    out,
+                    "{mark}{} ({escaped}) = {digest}",
+                    digest.algorithm.name().to_uppercase()
+                )?,
+            }
+        }
+        io::Result::Ok(())
+    };
+    let mut line = ProgressLine::new(true);
+    let mut tracker = ProgressTracker::new(&mut line, 0);
+    let mut errors = 0;
+    for i in 0..args.operands.len() {
+        let path = args.path(i)?.unwrap();
+        let Some(inode) = fs.stat(path)? else {
+            eprintln!("ext4cat: hash: {path}: no such file or directory");
+            errors += 1;
+            continue;
+        };
+        match inode.file_type() {
+            FileType::Regular => {
+                match fs.digests_with_progress(&inode, &algorithms, path, &mut tracker) {
+                    Ok(digests) => {
+                        tracker.entry(path);
+                        write(path, digests, out)?
+                    }
+                    Err(e) => {
+                        eprintln!("ext4cat: {path}: {e}");
+                        errors += 1;
+                    }
+                }
+            }
+            FileType::Directory if args.has(&["-r"]) => {
+                // kept apart from the errors reading entries, which the
+                // walk records
+                let mut write_error = None;
+                let summary = fs.walk(path, args.has(&["--keep-going"]), |entry| {
+                    if write_error.is_some() || entry.inode.file_type() != FileType::Regular {
+                        return Ok(());
+                    }
+                    let digests = fs.digests_with_progress(
+                        &entry.inode,
+                        &algorithms,
+                        &entry.path,
+                        &mut tracker,
+                    )?;
+                    tracker.entry(&entry.path);
+                    write_error = write(&entry.path, digests, out).err();
+                    Ok(())
+                })?;
+                if let Some(e) = write_error {
+                    return Err(e.into());
+                }
+                for error in &summary.errors {
+                    eprintln!("ext4cat: {}: {}", error.path, error.error);
+                }
+                errors += summary.errors.len();
+            }
+            FileType::Directory => {
+                eprintln!("ext4cat: hash: {path}: is a directory");
+                errors += 1;
+            }
+            _ => {
+                eprintln!("ext4cat: hash: {path}: not a regular file");
+                errors += 1;
+            }
+        }
+    }
+    if errors > 0 {
+        bail!("hash: {} entries couldn't be hashed", errors);
+    }
+    Ok(())
+}
+
+/// The changes from this image to another, `git diff --name-status`
+/// style.
+fn diff(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(2)?;
+    let Some(image) = args.operands.first() else {
+        bail!("diff: no image to compare with given");
+    };
+    let offset = match args.value(&["--other-offset"]) {
+        Some(x) => x
+            .parse()
+            .map_err(|_| format_err!("diff: --other-offset: bad number {:?}", x))?,
+        None => 0,
+    };
+    let other = open(&Options {
+        image: PathBuf::from(image),
+        offset,
+        block_size: None,
+        replay: false,
+        as_of: None,
+        verbosity: 1,
+        timeout: None,
+        read_timeout: None,
+    })?;
+    let mode = match (
+        args.has(&["--metadata-only"]),
+        args.has(&["--content-only"]),
+    ) {
+        (true, true) => bail!("diff: --metadata-only and --content-only are exclusive"),
+        (true, false) => DiffMode::Metadata,
+        (false, true) => DiffMode::Content,
+        (false, false) => DiffMode::Both,
+    };
+    let path = args.path(1)?.unwrap_or("/");
+    let diff = fs.diff(&other, path, mode)?;
+    for change in &diff.changes {
+        if args.has(&["--json"]) {
+            let fields: Vec<String> = change.fields.iter().map(|x| json::string(x)).collect();
+            let mut object = json::Object::new();
+            object
+                .string("path", &change.path)
+                .string("change", change.kind.name())
+                .field("fields", &format!("[{}]", fields.join(",")));
+            writeln!(out, "{object}")?;
+        } else if change.fields.is_empty() {
+            writeln!(out, "{} {}", change.kind.letter(), change.path)?;
+        } else {
+            let fields = change.fields.join(", ");
+            writeln!(out, "{} {} ({fields})", change.kind.letter(), change.path)?;
+        }
+    }
+    for error in &diff.old.errors {
+        eprintln!("ext4cat: {}: {}", error.path, error.error);
+    }
+    // those of the other image say which it is
+    for error in &diff.new.errors {
+        let image = image.to_string_lossy();
+        eprintln!("ext4cat: {image}: {}: {}", error.path, error.error);
+    }
+    let errors = diff.old.errors.len() + diff.new.errors.len();
+    if errors > 0 {
+        bail!("diff: {} entries couldn't be compared", errors);
+    }
+    Ok(())
+}
+
+/// Deleted inodes whose content may still be there, scored, and that
+/// content written out.
+fn undelete(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    let min_score: u32 = match args.value(&["--min-score"]) {
+        Some(x) => x
+            .parse()
+            .map_err(|_| format_err!("undelete: --min-score: bad number {:?}", x))?,
+        None => 0,
+    };
+    let wanted = args
+        .operands
+        .iter()
+        .map(|x| {
+            x.to_str()
+                .and_then(|x| x.parse().ok())
+                .map(InodeNumber)
+                .ok_or_else(|| format_err!("undelete: bad inode number {:?}", x))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mut candidates = fs.recovery_candidates()?;
+    for n in &wanted {
+        if !candidates.iter().any(|x| x.inode_number == *n) {
+            bail!("undelete: {}: not a deleted inode", n.0);
+        }
+    }
+    candidates.retain(|x| {
+        x.score >= min_score && (wanted.is_empty() || wanted.contains(&x.inode_number))
+    });
+    let Some(dest) = args.value(&["-d", "--dest"]) else {
+        for candidate in &candidates {
+            // to the second, as ls -l
+            let dtime = Timestamp::decode(candidate.inode.dtime, None).to_string();
+            writeln!(
+                out,
+                "{:>8} {:>3} {:>12} {} {}",
+                candidate.inode_number.0,
+                candidate.score,
+                candidate.inode.size,
+                &dtime[..dtime.len() - 10],
+                candidate.reasons.join(", ")
+            )?;
+        }
+        return Ok(());
+    };
+    std::fs::create_dir_all(dest).map_err(|e| format_err!("{}: {}", dest, e))?;
+    let mut errors = 0;
+    for candidate in &candidates {
+        let path = Path::new(dest).join(format!("inode-{}", candidate.inode_number.0));
+        let result = (|| -> Result<u64> {
+            let mut file = BufWriter::new(File::create(&path)?);
+            let written = fs.recover_content(candidate, &mut file)?;
+            file.flush()?;
+            Ok(written)
+        })();
+        match result {
+            Ok(written) => writeln!(out, "{}: {} bytes", path.display(), written)?,
+            Err(e) => {
+                eprintln!("ext4cat: {}: {}", path.display(), e);
+                errors += 1;
+            }
+        }
+    }
+    if errors > 0 {
+        bail!("undelete: {} inodes couldn't be recovered", errors);
+    }
+    Ok(())
+}
+
+/// What the journal area holds: every transaction still there, or the
+/// versions of a block or an inode they logged.
+fn journal(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(0)?;
+    let journal = Journal::open(fs)?;
+    let state = |committed: bool| match committed {
+        true => "committed",
+        false => "uncommitted",
+    };
+    if let Some(n) = args.number(&["--inode"])? {
+        if n == 0 || n > fs.sb.inodes_count {
+            bail!("journal: inode {} out of range", n);
+        }
+        writeln!(
+            out,
+            "{:<12}{:<13}{:<8}{:<7}{:<12}mtime",
+            "sequence", "state", "mode", "links", "size"
+        )?;
+        for (version, inode) in journal.inode_history(InodeNumber(n))? {
+            writeln!(
+                out,
+                "{:<12}{:<13}{:<8o}{:<7}{:<12}{}",
+                version.sequence,
+                state(version.committed),
+                inode.mode,
+                inode.links_count,
+                inode.size,
+                inode.mtime
+            )?;
+        }
+        return Ok(());
+    }
+    let Some(block) = args.number(&["--block"])? else {
+        if args.value(&["-d", "--dest"]).is_some() {
+            bail!("journal: -d writes the versions of a --block");
+        }
+        writeln!(
+            out,
+            "{:<12}{:<13}{:<8}revoked",
+            "sequence", "state", "blocks"
+        )?;
+        for transaction in journal.history()? {
+            writeln!(
+                out,
+                "{:<12}{:<13}{:<8}{}",
+                transaction.sequence,
+                state(transaction.committed),
+                transaction.tags.len(),
+                transaction.revoked.len()
+            )?;
+        }
+        return Ok(());
+    };
+    let versions = journal.block_history(block)?;
+    if let Some(dest) = args.value(&["-d", "--dest"]) {
+        std::fs::create_dir_all(dest).map_err(|e| format_err!("{}: {}", dest, e))?;
+        for version in &versions {
+            let path = Path::new(dest).join(format!("block-{block}.{}", version.sequence));
+            std::fs::write(&path, journal.logged_block(&version.tag)?)
+                .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+            writeln!(out, "{}", path.display())?;
+        }
+        return Ok(());
+    }
+    writeln!(out, "{:<12}{:<13}journal block", "sequence", "state")?;
+    for version in versions {
+        writeln!(
+            out,
+            "{:<12}{:<13}{}",
+            version.sequence,
+            state(version.committed),
+            version.tag.journal_block
+        )?;
+    }
+    Ok(())
+}
+
+/// Files whose inodes are gone, found in free space by their signature.
+fn carve(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(0)?;
+    let max_len = match args.value(&["--max-size"]) {
+        Some(x) => x
+            .parse()
+            .map_err(|_| format_err!("carve: --max-size: bad number {:?}", x))?,
+        // larger files are cut
+        None => 64 << 20,
+    };
+    let files = fs.carve(max_len, &mut ProgressLine::new(true))?;
+    let Some(dest) = args.value(&["-d", "--dest"]) else {
+        for file in &files {
+            let incomplete = if file.complete { "" } else { " incomplete" };
+            writeln!(
+                out,
+                "{:>12} {:>12} {}{}",
+                file.offset, file.len, file.format, incomplete
+            )?;
+        }
+        return Ok(());
+    };
+    std::fs::create_dir_all(dest).map_err(|e| format_err!("{}: {}", dest, e))?;
+    for file in &files {
+        let path = Path::new(dest).join(format!("carved-{}.{}", file.offset, file.format));
+        let mut writer = BufWriter::new(
+            File::create(&path).map_err(|e| format_err!("{}: {}", path.display(), e))?,
+        );
+        fs.write_carved(file, &mut writer)?;
+        writer.flush()?;
+        writeln!(out, "{}: {} bytes", path.display(), file.len)?;
+    }
+    Ok(())
+}
+
+fn timeline(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(1)?;
+    let path = args.path(0)?.unwrap_or("/");
+    let keep_going = args.has(&["--keep-going"]);
+    let summary = match args.has(&["--body"]) {
+        true => fs.write_bodyfile(path, keep_going, out)?,
+        false => fs.write_timeline(path, keep_going, out)?,
+    };
+    for error in &summary.errors {
+        eprintln!("ext4cat: {}: {}", error.path, error.error);
+    }
+    if !summary.errors.is_empty() {
+        bail!(
+            "timeline: {} entries couldn't be read",
+            summary.errors.len()
+        );
+    }
+    Ok(())
+}
+
+/// The extent map of a file, `filefrag -v` style. Fragmentation is the
+/// share of the boundaries between its blocks where the next block isn't
+/// the one following on the device.
+fn extents(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    args.at_most(1)?;
+    let path = args
+        .path(0)?
+        .ok_or_else(|| format_err!("extents: no path given"))?;
+    let inode = fs
+        .stat(path)?
+        .ok_or_else(|| format_err!("{}: no such file or directory", path))?;
+    let block_size = fs.sb.block_size;
+    let extents = inode.extents(&fs.sb, &fs.dev)?;
+    let last_block = inode.size.div_ceil(block_size).saturating_sub(1);
+    writeln!(out, "Filesystem type is: {:x}", fs.sb.magic)?;
+    writeln!(
+        out,
+        "File size of {} is {} ({} blocks of {} bytes)",
+        path,
+        inode.size,
+        inode.size.div_ceil(block_size),
+        block_size
+    )?;
+    writeln!(
+        out,
+        " ext:     logical_offset:        physical_offset: length:   expected: flags:"
+    )?;
+    let mut breaks = 0;
+    let mut next = None;
+    for (i, extent) in extents.iter().enumerate() {
+        let end = extent.len - 1;
+        // where the extent would be had the file stayed contiguous,
+        // holes included
+        let expected = next
+            .map(|(block, start)| start + (extent.block - block))
+            .filter(|&x| x != extent.start);
+        if expected.is_some() {
+            breaks += 1;
+        }
+        next = Some((extent.block, extent.start));
+        let mut flags = Vec::new();
+        if extent.uninit {
+            flags.push("unwritten");
+        }
+        if i + 1 == extents.len() {
+            flags.push("last");
+        }
+        if (extent.block..=extent.block + end).contains(&last_block) {
+            flags.push("eof");
+        }
+        let line = format!(
+            "{:>4}: {:>10}..{:>10}: {:>10}..{:>10}: {:>6}: {:>10} {}",
+            i,
+            extent.block,
+            extent.block + end,
+            extent.start,
+            extent.start + end,
+            extent.len,
+            expected.map(|x| format!("{x}:")).unwrap_or_default(),
+            flags.join(",")
+        );
+        writeln!(out, "{}", line.trim_end())?;
+    }
+    let blocks: u64 = extents.iter().map(|x| x.len).sum();
+    let fragmentation = match blocks {
+        0 | 1 => 0.0,
+        _ => breaks as f64 * 100.0 / (blocks - 1) as f64,
+    };
+    writeln!(
+        out,
+        "{}: {} extent{} found, {} discontinuit{}, {:.2}% fragmented",
+        path,
+        extents.len(),
+        if extents.len() == 1 { "" } else { "s" },
+        breaks,
+        if breaks == 1 { "y" } else { "ies" },
+        fragmentation
+    )?;
+    Ok(())
+}
+
+/// Extended attributes, `getfattr` style.
+fn xattr(fs: &Ext4Fs<Device>, args: &Args, out: &mut dyn Write) -> Result<()> {
+    let action = args
+        .path(0)?
+        .ok_or_else(|| format_err!("xattr: list or get needed"))?;
+    let path = args
+        .path(1)?
+        .ok_or_else(|| format_err!("xattr: no path given"))?;
+    let encoding = args.value(&["-e", "--encoding"]);
+    if let Some(x) = encoding.filter(|x| !["text", "hex", "base64"].contains(x)) {
+        bail!("xattr: -e wants text, hex or base64, not {:?}", x);
+    }
+    let inode = fs
+        .stat(path)?
+        .ok_or_else(|| format_err!("{}: no such file or directory", path))?;
+    let xattrs = inode.xattrs(&fs.sb, &fs.dev)?;
+    let dump = |xattrs: &[Xattr], out: &mut dyn Write| -> io::Result<()> {
+        for xattr in xattrs {
+            match args.has(&["--only-values"]) {
+                true => out.write_all(&xattr.value)?,
+                false => writeln!(
+                    out,
+                    "{}={}",
+                    xattr.full_name(),
+                    xattr_value(&xattr.value, encoding)
+                )?,
+            }
+        }
+        Ok(())
+    };
+    match action {
+        "list" => {
+            args.at_most(2)?;
+            match args.has(&["--dump"]) {
+                true => dump(&xattrs, out)?,
+                false => {
+                    for xattr in &xattrs {
+                        writeln!(out, "{}", xattr.full_name())?;
+                    }
+                }
+            }
+        }
+        "get" => {
+            args.at_most(3)?;
+            let name = args
+                .path(2)?
+                .ok_or_else(|| format_err!("xattr: get needs a name"))?;
+            let xattr = xattrs
+                .iter()
+                .find(|x| x.full_name() == name)
+                .ok_or_else(|| format_err!("{}: {}: no such attribute", path, name))?;
+            dump(std::slice::from_ref(xattr), out)?;
+        }
+        x => bail!("xattr: {:?} isn't list or get", x),
+    }
+    Ok(())
+}
+
+/// An attribute value as getfattr prints it: quoted text with octal
+/// escapes, `0x` and hex, or `0s` and base64, text when it is printable
+/// but for a final NUL and base64 otherwise if not told which.
+fn xattr_value(value: &[u8], encoding: Option<&str>) -> String {
+    let text = value.strip_suffix(b"\0").unwrap_or(value);
+    let printable = text
+        .iter()
+        .all(|&b| b.is_ascii_graphic() || b.is_ascii_whitespace());
+    match encoding {
+        Some("text") => {}
+        Some("hex") => {
+            return format!(
+                "0x{}",
+                value.iter().map(|b| format!("{b:02x}")).collect::<String>()
+            )
+        }
+        Some(_) => return format!("0s{}", base64(value)),
+        None if printable => {}
+        None => return format!("0s{}", base64(value)),
+    }
+    let mut quoted = String::from("\"");
+    for &b in text {
+        match b {
+            b'"' | b'\\' => quoted += &format!("\\{}", b as char),
+            b if b.is_ascii_graphic() || b == b' ' => quoted.push(b as char),
+            b => quoted += &format!("\\{b:03o}"),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut s = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => s.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => s.push('='),
+            }
+        }
+    }
+    s
+}
@@ -0,0 +1,70 @@
+//! A progress line on stderr for the commands that can run for hours on
+//! large images.
+
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use read_file_block_way::human_bytes;
+use read_file_block_way::progress::{Progress, ProgressSink};
+
+// redrawn at most this often
+const INTERVAL: Duration = Duration::from_millis(200);
+
+/// Redraws one line of stderr, cleared when dropped. Only drawn when
+/// stderr is a terminal, and not sharing it with the output of the
+/// command.
+pub(crate) struct ProgressLine {
+    enabled: bool,
+    last: Option<Instant>,
+}
+
+impl ProgressLine {
+    /// For a command writing to stdout when `writes_stdout`.
+    pub(crate) fn new(writes_stdout: bool) -> Self {
+        ProgressLine {
+            enabled: io::stderr().is_terminal() && !(writes_stdout && io::stdout().is_terminal()),
+            last: None,
+        }
+    }
+}
+
+impl ProgressSink for ProgressLine {
+    fn progress(&mut self, progress: &Progress<'_>) {
+        if !self.enabled || self.last.is_some_and(|x| x.elapsed() < INTERVAL) {
+            return;
+        }
+        self.last = Some(Instant::now());
+        let mut line = format!(
+            "{} entries, {}",
+            progress.entries_done,
+            human_bytes(progress.bytes_done)
+        );
+        if let Some(percent) = (progress.bytes_done * 100).checked_div(progress.bytes_total) {
+            line += &format!(" of {} ({percent}%)", human_bytes(progress.bytes_total));
+        }
+        line += &format!(", {}/s", human_bytes(progress.throughput() as u64));
+        if let Some(eta) = progress.eta() {
+            line += &format!(", {}s left", eta.as_secs());
+        }
+        if !progress.current_path.is_empty() {
+            // the end of long paths, where the name is
+            let path = progress.current_path;
+            let start = path.len().saturating_sub(40);
+            let start = (start..path.len())
+                .find(|&i| path.is_char_boundary(i))
+                .unwrap_or(0);
+            line += &format!(" {}", &path[start..]);
+        }
+        let mut err = io::stderr().lock();
+        let _ = write!(err, "\r\x1b[K{line}");
+        let _ = err.flush();
+    }
+}
+
+impl Drop for ProgressLine {
+    fn drop(&mut self) {
+        if self.last.is_some() {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
@@ -0,0 +1,350 @@
+//! `ext4cat <image> shell`: commands read one line at a time, debugfs
+//! style, or run from a command file with `--script`, with a working
+//! directory that relative paths start from. Every other command of the
+//! CLI is available too.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, BufReader, IsTerminal, Write};
+
+use failure::{bail, format_err};
+use read_file_block_way::script::{Script, ScriptHost};
+use read_file_block_way::session::Session;
+use read_file_block_way::*;
+
+use super::{dispatch, find_command, Args, Device, Options};
+
+const HELP: &str = "\
+shell commands:
+    cd [path]                 change the working directory, / without a path
+    pwd                       print the working directory
+    inode <n>                 print everything about inode n
+    block <n>                 hexdump block n
+    bookmark [name]           list the bookmarks, or bookmark the working
+                              directory as name
+    go <name>                 change to a bookmarked directory
+    source <file>             run a command file of the host, with set, if
+                              and variables
+    help                      print this help
+    quit                      leave, saving the session with --session
+
+and every command of ext4cat --help but shell, with paths relative to the
+working directory, which commands given no path work on
+";
+
+/// Commands and how many of their first operands are image paths.
+const PATH_OPERANDS: &[(&str, usize)] = &[
+    ("ls", usize::MAX),
+    ("cat", usize::MAX),
+    ("stat", usize::MAX),
+    ("tree", usize::MAX),
+    ("extract", 1),
+    ("repack", 1),
+    ("tar", 1),
+    ("cpio", 1),
+    ("export", 1),
+    ("grep", usize::MAX),
+    ("hash", usize::MAX),
+    ("diff", 2),
+    ("timeline", 1),
+    ("extents", 1),
+    ("xattr", 2),
+];
+
+/// Flags of commands whose value is an image path.
+const PATH_FLAGS: &[(&str, &str)] = &[("xxd", "--file")];
+
+struct Shell<'a> {
+    fs: &'a Ext4Fs<Device>,
+    /// the working directory and bookmarks
+    session: Session,
+    /// variables of command files, kept from one to the next
+    vars: HashMap<String, String>,
+    done: bool,
+}
+
+pub(crate) fn shell(options: &Options, fs: &Ext4Fs<Device>, args: &Args) -> Result<()> {
+    args.at_most(0)?;
+    let session_file = args.value(&["--session"]);
+    let session = match session_file {
+        Some(file) if fs::metadata(file).is_ok() => {
+            let session = Session::load(&mut BufReader::new(fs::File::open(file)?))
+                .map_err(|e| format_err!("{}: {}", file, e))?;
+            session.check(fs)?;
+            session
+        }
+        _ => Session::new(options.image.clone(), fs),
+    };
+    let mut shell = Shell {
+        fs,
+        session,
+        vars: HashMap::new(),
+        done: false,
+    };
+    match args.value(&["--script"]) {
+        Some(file) => shell.source(file)?,
+        None => shell.interact()?,
+    }
+    if let Some(file) = session_file {
+        let mut out = Vec::new();
+        shell.session.save(&mut out)?;
+        fs::write(file, out).map_err(|e| format_err!("{}: {}", file, e))?;
+    }
+    Ok(())
+}
+
+impl Shell<'_> {
+    /// Read commands from stdin until it ends or `quit`, prompting for
+    /// them on a terminal.
+    fn interact(&mut self) -> Result<()> {
+        let interactive = io::stdin().is_terminal();
+        let mut lines = io::stdin().lock().lines();
+        while !self.done {
+            if interactive {
+                print!("ext4cat:{}> ", self.session.cwd_path);
+                io::stdout().flush()?;
+            }
+            let Some(line) = lines.next() else {
+                break;
+            };
+            if let Err(e) = self.execute(&line?) {
+                if let Some(e) = e.downcast_ref::<io::Error>() {
+                    if e.kind() == io::ErrorKind::BrokenPipe {
+                        break;
+                    }
+                }
+                eprintln!("ext4cat: {e}");
+            }
+        }
+        if interactive && !self.done {
+            println!();
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, line: &str) -> Result<()> {
+        let words = split_words(line)?;
+        let Some((command, rest)) = words.split_first() else {
+            return Ok(());
+        };
+        let mut out = io::stdout().lock();
+        let arity = |n: usize| -> Result<()> {
+            if rest.len() != n {
+                bail!(
+                    "{}: {} arguments expected, {} given",
+                    command,
+                    n,
+                    rest.len()
+                );
+            }
+            Ok(())
+        };
+        match command.as_str() {
+            "cd" => {
+                if rest.len() > 1 {
+                    bail!("cd: one directory at most");
+                }
+                let path = rest.first().map_or("/", |x| x.as_str());
+                self.cd(&self.absolute(path))?;
+            }
+            "pwd" => {
+                arity(0)?;
+                writeln!(out, "{}", self.session.cwd_path)?;
+            }
+            "inode" => {
+                arity(1)?;
+                let flag = ["-i".to_string(), rest[0].clone()];
+                self.run("stat", &flag, &mut out)?;
+            }
+            "block" => {
+                arity(1)?;
+                let flag = ["--block".to_string(), rest[0].clone()];
+                self.run("xxd", &flag, &mut out)?;
+            }
+            "bookmark" => match rest {
+                [] => {
+                    for (name, path) in &self.session.bookmarks {
+                        writeln!(out, "{name:<16}{path}")?;
+                    }
+                }
+                [name] => {
+                    let path = self.session.cwd_path.clone();
+                    self.session.bookmarks.insert(name.clone(), path);
+                }
+                _ => bail!("bookmark: one name at most"),
+            },
+            "go" => {
+                arity(1)?;
+                let path = self
+                    .session
+                    .bookmarks
+                    .get(&rest[0])
+                    .ok_or_else(|| format_err!("go: no bookmark {:?}", rest[0]))?
+                    .clone();
+                self.cd(&path)?;
+            }
+            "source" => {
+                arity(1)?;
+                drop(out);
+                return self.source(&rest[0]);
+            }
+            "help" => {
+                arity(0)?;
+                write!(out, "{HELP}")?;
+            }
+            "quit" | "exit" => self.done = true,
+            "shell" => bail!("shell: already in the shell"),
+            name => self.run(name, rest, &mut out)?,
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Run a command of the CLI, its path operands made absolute.
+    fn run(&self, name: &str, words: &[String], out: &mut dyn Write) -> Result<()> {
+        let (name, spec) = find_command(name)
+            .ok_or_else(|| format_err!("unknown command {:?}, try help", name))?;
+        let mut args = Args::parse(name, spec, &mut words.iter().map(OsString::from))?;
+        let paths = PATH_OPERANDS
+            .iter()
+            .find(|(x, _)| *x == name)
+            .map_or(0, |(_, n)| *n);
+        // the pattern of grep comes first, unless given with -e, the
+        // other image of diff, and list or get for xattr
+        let skip =
+            usize::from(name == "grep" && !args.has(&["-e"]) || name == "diff" || name == "xattr");
+        for (i, operand) in args.operands.iter_mut().enumerate() {
+            let word = operand.to_string_lossy();
+            let absolute = if i >= skip && i < paths {
+                self.absolute(&word)
+            } else if name == "find" {
+                // a bare name pattern matches anywhere below, as it does
+                // below the root outside the shell
+                match word.contains('/') {
+                    true if word.starts_with('/') => continue,
+                    true => format!("{}/{}", self.session.cwd_path.trim_end_matches('/'), word),
+                    false => format!(
+                        "{}/**/{}",
+                        self.session.cwd_path.trim_end_matches('/'),
+                        word
+                    ),
+                }
+            } else {
+                continue;
+            };
+            *operand = absolute.into();
+        }
+        // without a path, the working directory; grep reads its input
+        // from files only, unless recursive
+        let below_cwd = paths > 0 && (name != "grep" || args.has(&["-r"]));
+        if below_cwd && args.operands.len() == skip {
+            args.operands.push(self.session.cwd_path.clone().into());
+        }
+        for (flag, value) in &mut args.flags {
+            if PATH_FLAGS.contains(&(name, *flag)) {
+                if let Some(path) = value {
+                    *path = self.absolute(path);
+                }
+            }
+        }
+        dispatch(self.fs, &args, out)
+    }
+
+    /// Run a command file of the host, its variables kept for the next.
+    fn source(&mut self, file: &str) -> Result<()> {
+        let text = fs::read_to_string(file).map_err(|e| format_err!("{}: {}", file, e))?;
+        let script = Script::parse(&text)?;
+        let mut vars = std::mem::take(&mut self.vars);
+        let result = script.run(self, &mut vars);
+        self.vars = vars;
+        result
+    }
+
+    fn cd(&mut self, path: &str) -> Result<()> {
+        let n = self
+            .fs
+            .resolve(path)?
+            .ok_or_else(|| format_err!("cd: {}: no such file or directory", path))?;
+        if self.fs.inode(n)?.file_type() != FileType::Directory {
+            bail!("cd: {}: not a directory", path);
+        }
+        self.session.cwd = n;
+        self.session.cwd_path = path.to_string();
+        Ok(())
+    }
+
+    /// `path` from the working directory, `.` and `..` folded away.
+    fn absolute(&self, path: &str) -> String {
+        let joined = match path.starts_with('/') {
+            true => path.to_string(),
+            false => format!("{}/{}", self.session.cwd_path, path),
+        };
+        let mut parts = Vec::new();
+        for part in joined.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    parts.pop();
+                }
+                x => parts.push(x),
+            }
+        }
+        format!("/{}", parts.join("/"))
+    }
+}
+
+impl ScriptHost for Shell<'_> {
+    fn command(&mut self, line: &str) -> Result<()> {
+        self.execute(line)
+    }
+
+    fn exists(&mut self, path: &str) -> Result<bool> {
+        Ok(self.fs.resolve(&self.absolute(path))?.is_some())
+    }
+}
+
+/// Split a command line into words at whitespace. Single quotes keep
+/// everything as is, double quotes and bare backslashes escape the next
+/// character.
+fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated ' in {:?}", line),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => word.push(c),
+                            None => bail!("unterminated \" in {:?}", line),
+                        },
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated \" in {:?}", line),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => bail!("trailing \\ in {:?}", line),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
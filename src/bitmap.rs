@@ -0,0 +1,226 @@
+//! Block and inode allocation bitmaps, one block of each per group.
+
+use std::fmt;
+
+use failure::bail;
+use positioned_io::ReadAt;
+
+use crate::{BlockGroupDescriptor, BlockGroupNumber, Ext4Fs, InodeNumber, Result, SuperBlock};
+
+/// Free blocks and inodes of one group, counted in its bitmaps.
+#[derive(Debug, Clone, Copy)]
+pub struct GroupFree {
+    pub group: BlockGroupNumber,
+    pub free_blocks: u64,
+    pub free_inodes: u64,
+}
+
+/// Free space of the whole image, per group. The counters kept in the
+/// superblock and descriptors are only updated lazily by the kernel, the
+/// bitmaps are authoritative.
+#[derive(Debug, Clone)]
+pub struct FreeSpace {
+    pub block_size: u64,
+    pub blocks_count: u64,
+    pub inodes_count: u64,
+    pub groups: Vec<GroupFree>,
+}
+
+impl FreeSpace {
+    pub fn free_blocks(&self) -> u64 {
+        self.groups.iter().map(|x| x.free_blocks).sum()
+    }
+
+    pub fn free_inodes(&self) -> u64 {
+        self.groups.iter().map(|x| x.free_inodes).sum()
+    }
+}
+
+/// `df`-style summary of blocks and inodes.
+impl fmt::Display for FreeSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<8} {:>12} {:>12} {:>12} {:>5}",
+            "", "total", "used", "free", "use%"
+        )?;
+        for (title, total, free) in [
+            ("blocks", self.blocks_count, self.free_blocks()),
+            ("inodes", self.inodes_count, self.free_inodes()),
+        ] {
+            let used = total - free.min(total);
+            let percent = (used * 100).checked_div(total).unwrap_or(0);
+            writeln!(
+                f,
+                "{title:<8} {total:>12} {used:>12} {free:>12} {percent:>4}%"
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// per group counts are at most a bitmap block's bits, checked when the
+// superblock is parsed
+fn bit(bitmap: &[u8], index: u64) -> bool {
+    bitmap
+        .get((index / 8) as usize)
+        .is_some_and(|x| x & (1 << (index % 8)) != 0)
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// The block bitmap of `group`, one bit per cluster, rebuilt from the
+    /// group layout if it was never initialized.
+    pub fn block_bitmap(&self, group: BlockGroupNumber) -> Result<Vec<u8>> {
+        let sb = &self.sb;
+        let bgd = group.block_group_descriptor(sb, &self.dev)?;
+        if sb.has_group_desc_csum() && bgd.flags & BlockGroupDescriptor::BLOCK_UNINIT != 0 {
+            let end = (sb.group_first_block(group.0) + sb.block_per_group).min(sb.blocks_count);
+            return Ok(uninit_block_bitmap(sb, group.0, &bgd, end));
+        }
+        let mut bitmap = vec![0u8; sb.block_size as usize];
+        self.dev
+            .read_exact_at(bgd.block_bitmap * sb.block_size, &mut bitmap)?;
+        Ok(bitmap)
+    }
+
+    /// The inode bitmap of `group`, all clear if it was never initialized.
+    /// Uninit flags are only trusted when descriptors are checksummed.
+    pub fn inode_bitmap(&self, group: BlockGroupNumber) -> Result<Vec<u8>> {
+        let sb = &self.sb;
+        let bgd = group.block_group_descriptor(sb, &self.dev)?;
+        let mut bitmap = vec![0u8; sb.block_size as usize];
+        if !sb.has_group_desc_csum() || bgd.flags & BlockGroupDescriptor::INODE_UNINIT == 0 {
+            self.dev
+                .read_exact_at(bgd.inode_bitmap * sb.block_size, &mut bitmap)?;
+        }
+        Ok(bitmap)
+    }
+
+    /// Whether the cluster holding `block` is allocated. Blocks before the
+    /// first group, the boot block of 1 KiB block images, always are.
+    pub fn is_block_allocated(&self, block: u64) -> Result<bool> {
+        let sb = &self.sb;
+        if block >= sb.blocks_count {
+            bail!("block {} out of range", block);
+        }
+        if block < sb.first_data_block {
+            return Ok(true);
+        }
+        let group = sb.block_group_of(block);
+        let index = (block - sb.group_first_block(group.0)) / sb.blocks_per_cluster();
+        Ok(bit(&self.block_bitmap(group)?, index))
+    }
+
+    pub fn is_inode_allocated(&self, n: InodeNumber) -> Result<bool> {
+        if n.0 == 0 || n.0 > self.sb.inodes_count {
+            bail!("inode number {} out of range", n.0);
+        }
+        let group = n.block_group_number(&self.sb);
+        let index = (n.0 - 1) % self.sb.inode_per_group;
+        Ok(bit(&self.inode_bitmap(group)?, index))
+    }
+
+    /// Every inode whose slot in the inode table was ever initialized, in
+    /// order. Groups flagged INODE_UNINIT and the unused tail of each
+    /// inode table are skipped: whatever they hold is garbage from before
+    /// the filesystem was created.
+    pub fn initialized_inodes(&self) -> Result<impl Iterator<Item = InodeNumber>> {
+        let sb = &self.sb;
+        let mut ranges = Vec::new();
+        for group in 0..sb.group_count() {
+            let bgd = BlockGroupNumber(group).block_group_descriptor(sb, &self.dev)?;
+            let used = if !sb.has_group_desc_csum() {
+                sb.inode_per_group
+            } else if bgd.flags & BlockGroupDescriptor::INODE_UNINIT != 0 {
+                0
+            } else {
+                sb.inode_per_group.saturating_sub(bgd.itable_unused)
+            };
+            let first = group * sb.inode_per_group + 1;
+            ranges.push(first..(first + used).min(sb.inodes_count + 1));
+        }
+        Ok(ranges.into_iter().flatten().map(InodeNumber))
+    }
+
+    /// Count the free blocks and inodes of every group.
+    pub fn free_space(&self) -> Result<FreeSpace> {
+        let sb = &self.sb;
+        let mut groups = Vec::new();
+        for group in 0..sb.group_count() {
+            let group = BlockGroupNumber(group);
+            let first = sb.group_first_block(group.0);
+            let end = (first + sb.block_per_group).min(sb.blocks_count);
+            let blocks = self.block_bitmap(group)?;
+            let step = sb.blocks_per_cluster();
+            let free_blocks = (0..sb.cluster_per_group)
+                .map(|cluster| (cluster, first + cluster * step))
+                .take_while(|(_, block)| *block < end)
+                .filter(|(cluster, _)| !bit(&blocks, *cluster))
+                .map(|(_, block)| step.min(end - block))
+                .sum();
+            let inodes = self.inode_bitmap(group)?;
+            let free_inodes = (0..sb.inode_per_group)
+                .filter(|x| !bit(&inodes, *x))
+                .count() as u64;
+            groups.push(GroupFree {
+                group,
+                free_blocks,
+                free_inodes,
+            });
+        }
+        Ok(FreeSpace {
+            block_size: sb.block_size,
+            blocks_count: sb.blocks_count,
+            inodes_count: sb.inodes_count,
+            groups,
+        })
+    }
+}
+
+/// Rebuild the bitmap of a group whose bitmap was never initialized, in
+/// which only the superblock backup, descriptors and the group's own
+/// bitmaps and inode table can be in use.
+fn uninit_block_bitmap(
+    sb: &SuperBlock,
+    group: u64,
+    bgd: &BlockGroupDescriptor,
+    end: u64,
+) -> Vec<u8> {
+    let first = sb.group_first_block(group);
+    let mut bitmap = vec![0u8; sb.block_size as usize];
+    // descriptors can point anywhere, only what falls in the group is
+    // marked
+    let mut mark = |block: u64, count: u64| {
+        for block in block.max(first)..block.saturating_add(count).min(end) {
+            let cluster = (block - first) / sb.blocks_per_cluster();
+            if let Some(byte) = bitmap.get_mut((cluster / 8) as usize) {
+                *byte |= 1 << (cluster % 8);
+            }
+        }
+    };
+    let per_block = sb.descriptors_per_block();
+    if sb.group_has_super(group) {
+        let gdt_blocks = if sb.has_meta_bg() {
+            sb.first_meta_bg
+        } else {
+            sb.group_count().div_ceil(per_block)
+        };
+        mark(
+            first,
+            (1 + gdt_blocks).saturating_add(sb.reserved_gdt_blocks),
+        );
+    }
+    // meta_bg keeps descriptor copies in the first, second and last group
+    // of each meta group
+    if sb.has_meta_bg() && group / per_block >= sb.first_meta_bg {
+        let index = group % per_block;
+        if index == 0 || index == 1 || index == per_block - 1 {
+            mark(first + sb.group_has_super(group) as u64, 1);
+        }
+    }
+    let inode_table_blocks = (sb.inode_per_group * sb.inode_size).div_ceil(sb.block_size);
+    mark(bgd.block_bitmap, 1);
+    mark(bgd.inode_bitmap, 1);
+    mark(bgd.inode_table, inode_table_blocks);
+    bitmap
+}
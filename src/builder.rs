@@ -0,0 +1,787 @@
+//! Writing new images: a small ext4 built from a tree held in memory, for
+//! test fixtures and for repacking a tree without mke2fs.
+//!
+//! The feature set is fixed: extents, sparse superblock backups, 256
+//! bytes inodes and linear directories. There is no journal, no checksum
+//! and no extended attribute.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use failure::bail;
+use positioned_io::{ReadAt, WriteAt};
+
+use crate::digest::sha256;
+use crate::{Ext4Fs, FileType, InodeFlags, Result, SuperBlock, Timestamp, TypedInode, WalkSummary};
+
+/// What a node of the tree is.
+#[derive(Debug, Clone)]
+pub enum NodeKind {
+    File(Vec<u8>),
+    Dir,
+    Symlink(String),
+}
+
+impl NodeKind {
+    fn file_type(&self) -> FileType {
+        match self {
+            Self::File(_) => FileType::Regular,
+            Self::Dir => FileType::Directory,
+            Self::Symlink(_) => FileType::SymbolicLink,
+        }
+    }
+}
+
+/// Ownership, permissions and modification time of a node.
+#[derive(Debug, Clone, Copy)]
+pub struct NodeMeta {
+    /// permission bits, the file type comes from the node's kind
+    pub permissions: u16,
+    pub uid: u32,
+    pub gid: u32,
+    /// `None` for the image's timestamp
+    pub mtime: Option<Timestamp>,
+}
+
+impl NodeMeta {
+    pub fn new(permissions: u16) -> Self {
+        Self {
+            permissions,
+            uid: 0,
+            gid: 0,
+            mtime: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Node {
+    kind: NodeKind,
+    meta: NodeMeta,
+    children: BTreeMap<String, usize>,
+}
+
+/// A tree to write out as an image. It starts with the root directory
+/// and `/lost+found`, whose metadata can be changed by adding them again.
+#[derive(Debug)]
+pub struct ImageBuilder {
+    nodes: Vec<Node>,
+    block_size: u64,
+    free_blocks: u64,
+    free_inodes: u64,
+    label: String,
+    uuid: [u8; 16],
+    timestamp: Timestamp,
+}
+
+const INODE_SIZE: u64 = 256;
+// fields of the large inode in use, up to the creation time
+const EXTRA_ISIZE: u16 = 32;
+const DESC_SIZE: u64 = 32;
+const ROOT_INO: u64 = 2;
+const LOST_FOUND_INO: u64 = 11;
+const FIRST_INO: u64 = 11;
+const MAX_EXTENT_LEN: u64 = 32768;
+// targets shorter than this are kept in i_block
+const FAST_SYMLINK_MAX: usize = 60;
+// link counts above this are stored as 1 with dir_nlink
+const MAX_LINKS: u64 = 65000;
+const MAX_NAME_LEN: usize = 255;
+
+impl Default for ImageBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ImageBuilder {
+    pub fn new() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut uuid: [u8; 16] = sha256(&now.as_nanos().to_le_bytes())[..16]
+            .try_into()
+            .unwrap();
+        // a random (version 4) UUID
+        uuid[6] = uuid[6] & 0x0F | 0x40;
+        uuid[8] = uuid[8] & 0x3F | 0x80;
+        let dir = |permissions| Node {
+            kind: NodeKind::Dir,
+            meta: NodeMeta::new(permissions),
+            children: BTreeMap::new(),
+        };
+        let mut root = dir(0o755);
+        root.children.insert("lost+found".to_string(), 1);
+        Self {
+            nodes: vec![root, dir(0o700)],
+            block_size: 4096,
+            free_blocks: 0,
+            free_inodes: 0,
+            label: String::new(),
+            uuid,
+            timestamp: Timestamp {
+                seconds: now.as_secs() as i64,
+                nanos: 0,
+            },
+        }
+    }
+
+    /// 1024, 2048 or 4096, 4096 by default.
+    pub fn block_size(mut self, block_size: u64) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Blocks left free past those the tree needs.
+    pub fn free_blocks(mut self, blocks: u64) -> Self {
+        self.free_blocks = blocks;
+        self
+    }
+
+    /// Inodes left free past those the tree needs.
+    pub fn free_inodes(mut self, inodes: u64) -> Self {
+        self.free_inodes = inodes;
+        self
+    }
+
+    /// Volume label, at most 16 bytes.
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+
+    pub fn uuid(mut self, uuid: [u8; 16]) -> Self {
+        self.uuid = uuid;
+        self
+    }
+
+    /// Time recorded in the superblock and of nodes without an mtime,
+    /// now by default.
+    pub fn timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    /// Add a node at absolute `path`, whose parent must already be there.
+    /// Adding a directory again only changes its metadata.
+    pub fn add(&mut self, path: &str, kind: NodeKind, meta: NodeMeta) -> Result<()> {
+        let path = path.trim_matches('/');
+        let (parent_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() {
+            if !matches!(kind, NodeKind::Dir) {
+                bail!("the root must be a directory");
+            }
+            self.nodes[0].meta = meta;
+            return Ok(());
+        }
+        if name == "." || name == ".." || name.len() > MAX_NAME_LEN {
+            bail!("bad name {:?}", name);
+        }
+        let mut parent = 0;
+        for component in parent_path.split('/').filter(|x| !x.is_empty()) {
+            match self.nodes[parent].children.get(component) {
+                Some(&child) if matches!(self.nodes[child].kind, NodeKind::Dir) => parent = child,
+                _ => bail!("no directory /{}", parent_path),
+            }
+        }
+        if let Some(&existing) = self.nodes[parent].children.get(name) {
+            if matches!(
+                (&self.nodes[existing].kind, &kind),
+                (NodeKind::Dir, NodeKind::Dir)
+            ) {
+                self.nodes[existing].meta = meta;
+                return Ok(());
+            }
+            bail!("/{} already exists", path);
+        }
+        self.nodes.push(Node {
+            kind,
+            meta,
+            children: BTreeMap::new(),
+        });
+        let index = self.nodes.len() - 1;
+        self.nodes[parent].children.insert(name.to_string(), index);
+        Ok(())
+    }
+
+    /// Write the image to `out`, which must read as zeros, a new file or
+    /// an empty `Vec`. Returns the size of the image in bytes.
+    pub fn build<W: WriteAt>(&self, out: &mut W) -> Result<u64> {
+        let bs = self.block_size;
+        if ![1024, 2048, 4096].contains(&bs) {
+            bail!("unsupported block size {}", bs);
+        }
+        if self.label.len() > 16 {
+            bail!("label {:?} longer than 16 bytes", self.label);
+        }
+        let inos = self.inode_numbers();
+        let mut parents = vec![0; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate() {
+            for &child in node.children.values() {
+                parents[child] = i;
+            }
+        }
+        // content of each node, empty for fast symlinks
+        let contents: Vec<Cow<[u8]>> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| match &node.kind {
+                NodeKind::File(data) => Cow::Borrowed(data.as_slice()),
+                NodeKind::Symlink(target) if target.len() < FAST_SYMLINK_MAX => {
+                    Cow::Borrowed(&[][..])
+                }
+                NodeKind::Symlink(target) => Cow::Borrowed(target.as_bytes()),
+                NodeKind::Dir => Cow::Owned(self.dir_blocks(i, inos[i], inos[parents[i]], &inos)),
+            })
+            .collect();
+
+        let data_blocks: u64 = contents.iter().map(|x| (x.len() as u64).div_ceil(bs)).sum();
+        let used_inodes = FIRST_INO - 1 + self.nodes.len() as u64 - 1;
+        let geometry = Geometry::new(
+            bs,
+            data_blocks + self.leaf_slack(&contents) + self.free_blocks,
+            used_inodes + self.free_inodes,
+        );
+
+        let mut allocator = Allocator::new(&geometry);
+        let mut inodes = vec![None; (geometry.ipg * geometry.groups) as usize];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let content = &contents[i];
+            let blocks = (content.len() as u64).div_ceil(bs);
+            let runs = allocator.alloc(blocks)?;
+            let mut logical = 0;
+            for &(start, len) in &runs {
+                let from = (logical * bs) as usize;
+                let to = content.len().min(((logical + len) * bs) as usize);
+                let mut run = content[from..to].to_vec();
+                // zeros to the end of the last block, which may end the image
+                run.resize((len * bs) as usize, 0);
+                out.write_all_at(start * bs, &run)?;
+                logical += len;
+            }
+            let (block, leaves) = match &node.kind {
+                NodeKind::Symlink(target) if content.is_empty() => {
+                    let mut block = [0u8; 60];
+                    block[..target.len()].copy_from_slice(target.as_bytes());
+                    (block, 0)
+                }
+                _ => self.write_extents(&runs, &mut allocator, out)?,
+            };
+            let links = match node.kind {
+                NodeKind::Dir => {
+                    let subdirs = node
+                        .children
+                        .values()
+                        .filter(|&&x| matches!(self.nodes[x].kind, NodeKind::Dir))
+                        .count() as u64;
+                    if 2 + subdirs > MAX_LINKS {
+                        1
+                    } else {
+                        2 + subdirs
+                    }
+                }
+                _ => 1,
+            };
+            let size = match &node.kind {
+                NodeKind::Symlink(target) => target.len(),
+                _ => content.len(),
+            };
+            let raw = self.raw_inode(node, size as u64, blocks + leaves, links, block);
+            out.write_all_at(geometry.inode_offset(inos[i]), &raw)?;
+            inodes[(inos[i] - 1) as usize] = Some(matches!(node.kind, NodeKind::Dir));
+        }
+        // the reserved inodes are in use, if empty
+        for inode in inodes.iter_mut().take((FIRST_INO - 1) as usize) {
+            inode.get_or_insert(false);
+        }
+        self.write_metadata(&geometry, &allocator, &inodes, out)?;
+        Ok(geometry.blocks_count * bs)
+    }
+
+    /// Inode numbers by node: the root and lost+found have fixed ones,
+    /// the rest are numbered breadth first.
+    fn inode_numbers(&self) -> Vec<u64> {
+        let mut inos = vec![0; self.nodes.len()];
+        inos[0] = ROOT_INO;
+        inos[1] = LOST_FOUND_INO;
+        let mut next = FIRST_INO + 1;
+        let mut queue = std::collections::VecDeque::from([0]);
+        while let Some(i) = queue.pop_front() {
+            for &child in self.nodes[i].children.values() {
+                if inos[child] == 0 {
+                    inos[child] = next;
+                    next += 1;
+                }
+                queue.push_back(child);
+            }
+        }
+        inos
+    }
+
+    /// Linear directory blocks of node `i`: `.` and `..`, then its
+    /// children by name, the last record of each block stretched to its
+    /// end.
+    fn dir_blocks(&self, i: usize, ino: u64, parent: u64, inos: &[u64]) -> Vec<u8> {
+        let bs = self.block_size as usize;
+        let mut records = vec![
+            (ino, ".", FileType::Directory),
+            (parent, "..", FileType::Directory),
+        ];
+        for (name, &child) in &self.nodes[i].children {
+            records.push((inos[child], name, self.nodes[child].kind.file_type()));
+        }
+        let mut out: Vec<u8> = Vec::new();
+        let mut block_start = 0;
+        let mut last = 0;
+        let stretch = |out: &mut Vec<u8>, last: usize, block_start: usize| {
+            let rec_len = (block_start + bs - last) as u16;
+            out[last + 4..last + 6].copy_from_slice(&rec_len.to_le_bytes());
+            out.resize(block_start + bs, 0);
+        };
+        for (ino, name, file_type) in records {
+            let rec_len = (8 + name.len() + 3) & !3;
+            if out.len() + rec_len > block_start + bs {
+                stretch(&mut out, last, block_start);
+                block_start += bs;
+            }
+            last = out.len();
+            out.extend_from_slice(&(ino as u32).to_le_bytes());
+            out.extend_from_slice(&(rec_len as u16).to_le_bytes());
+            out.push(name.len() as u8);
+            out.push(file_type.dirent());
+            out.extend_from_slice(name.as_bytes());
+            out.resize(last + rec_len, 0);
+        }
+        stretch(&mut out, last, block_start);
+        out
+    }
+
+    /// Blocks to set aside for extent tree leaves, a guess erring on the
+    /// generous side: files are split at every group's metadata.
+    fn leaf_slack(&self, contents: &[Cow<[u8]>]) -> u64 {
+        let bs = self.block_size;
+        let per_leaf = (bs - 12) / 12;
+        contents
+            .iter()
+            .map(|x| {
+                let blocks = (x.len() as u64).div_ceil(bs);
+                let extents = blocks.div_ceil(MAX_EXTENT_LEN) + blocks.div_ceil(bs * 4) + 2;
+                if extents > 4 {
+                    extents.div_ceil(per_leaf)
+                } else {
+                    0
+                }
+            })
+            .sum()
+    }
+
+    /// Write the leaves of the extent tree mapping `runs`, returning
+    /// i_block and the number of leaf blocks.
+    fn write_extents<W: WriteAt>(
+        &self,
+        runs: &[(u64, u64)],
+        allocator: &mut Allocator,
+        out: &mut W,
+    ) -> Result<([u8; 60], u64)> {
+        let bs = self.block_size;
+        let mut extents = Vec::new();
+        let mut logical = 0;
+        for &(start, len) in runs {
+            for done in (0..len).step_by(MAX_EXTENT_LEN as usize) {
+                let mut e = [0u8; 12];
+                let len = MAX_EXTENT_LEN.min(len - done);
+                let start = start + done;
+                e[0..4].copy_from_slice(&((logical + done) as u32).to_le_bytes());
+                e[4..6].copy_from_slice(&(len as u16).to_le_bytes());
+                e[6..8].copy_from_slice(&((start >> 32) as u16).to_le_bytes());
+                e[8..12].copy_from_slice(&(start as u32).to_le_bytes());
+                extents.push(e);
+            }
+            logical += len;
+        }
+        let node = |entries: &[[u8; 12]], max: u64, depth: u16, size: usize| {
+            let mut node = vec![0u8; size];
+            node[0..2].copy_from_slice(&0xF30Au16.to_le_bytes());
+            node[2..4].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+            node[4..6].copy_from_slice(&(max as u16).to_le_bytes());
+            node[6..8].copy_from_slice(&depth.to_le_bytes());
+            for (i, e) in entries.iter().enumerate() {
+                node[12 + i * 12..24 + i * 12].copy_from_slice(e);
+            }
+            node
+        };
+        let mut leaves = 0;
+        let (entries, depth) = if extents.len() <= 4 {
+            (extents, 0)
+        } else {
+            let per_leaf = (bs - 12) / 12;
+            let mut index = Vec::new();
+            for chunk in extents.chunks(per_leaf as usize) {
+                let Some((leaf, _)) = allocator.alloc(1)?.pop() else {
+                    unreachable!()
+                };
+                out.write_all_at(leaf * bs, &node(chunk, per_leaf, 0, bs as usize))?;
+                let mut e = [0u8; 12];
+                e[0..4].copy_from_slice(&chunk[0][0..4]);
+                e[4..8].copy_from_slice(&(leaf as u32).to_le_bytes());
+                e[8..10].copy_from_slice(&((leaf >> 32) as u16).to_le_bytes());
+                index.push(e);
+                leaves += 1;
+            }
+            if index.len() > 4 {
+                bail!(
+                    "file of {} extents, deeper trees aren't written",
+                    extents.len()
+                );
+            }
+            (index, 1)
+        };
+        let block = node(&entries, 4, depth, 60).try_into().unwrap();
+        Ok((block, leaves))
+    }
+
+    fn raw_inode(
+        &self,
+        node: &Node,
+        size: u64,
+        blocks: u64,
+        links: u64,
+        block: [u8; 60],
+    ) -> Vec<u8> {
+        let mut raw = vec![0u8; INODE_SIZE as usize];
+        let mut put = |at: usize, bytes: &[u8]| raw[at..at + bytes.len()].copy_from_slice(bytes);
+        let meta = &node.meta;
+        let mode = node.kind.file_type() as u16 | meta.permissions & 0o7777;
+        let (seconds, extra) = meta.mtime.unwrap_or(self.timestamp).encode();
+        put(0x0, &mode.to_le_bytes());
+        put(0x2, &(meta.uid as u16).to_le_bytes());
+        put(0x4, &(size as u32).to_le_bytes());
+        for at in [0x8, 0xC, 0x10, 0x90] {
+            put(at, &seconds.to_le_bytes());
+        }
+        for at in [0x84, 0x88, 0x8C, 0x94] {
+            put(at, &extra.to_le_bytes());
+        }
+        put(0x18, &(meta.gid as u16).to_le_bytes());
+        put(0x1A, &(links as u16).to_le_bytes());
+        // 512 bytes sectors
+        put(
+            0x1C,
+            &((blocks * self.block_size / 512) as u32).to_le_bytes(),
+        );
+        let fast_symlink = matches!(&node.kind, NodeKind::Symlink(x) if x.len() < FAST_SYMLINK_MAX);
+        if !fast_symlink {
+            put(0x20, &InodeFlags::EXTENTS.to_le_bytes());
+        }
+        put(0x28, &block);
+        put(0x6C, &((size >> 32) as u32).to_le_bytes());
+        put(0x78, &((meta.uid >> 16) as u16).to_le_bytes());
+        put(0x7A, &((meta.gid >> 16) as u16).to_le_bytes());
+        put(0x80, &EXTRA_ISIZE.to_le_bytes());
+        raw
+    }
+
+    /// Bitmaps, group descriptors and superblocks, once every block and
+    /// inode in use is known. `inodes` holds, for each inode in use,
+    /// whether it is a directory.
+    fn write_metadata<W: WriteAt>(
+        &self,
+        geometry: &Geometry,
+        allocator: &Allocator,
+        inodes: &[Option<bool>],
+        out: &mut W,
+    ) -> Result<()> {
+        let bs = self.block_size;
+        let mut descriptors = vec![0u8; (geometry.gdt_blocks * bs) as usize];
+        let mut free_blocks = 0;
+        let mut free_inodes = 0;
+        for g in 0..geometry.groups {
+            let start = geometry.group_start(g);
+            let len = geometry.group_end(g) - start;
+            let mut bitmap = vec![0u8; bs as usize];
+            let set = |bitmap: &mut [u8], bit: u64| bitmap[(bit / 8) as usize] |= 1 << (bit % 8);
+            // blocks past the end of the filesystem are marked in use
+            for bit in (0..geometry.overhead(g)).chain(len..bs * 8) {
+                set(&mut bitmap, bit);
+            }
+            for &(run, run_len) in &allocator.used {
+                let from = run.max(start);
+                let to = (run + run_len).min(start + len);
+                for block in from..to.max(from) {
+                    set(&mut bitmap, block - start);
+                }
+            }
+            let used: u64 = bitmap.iter().map(|x| x.count_ones() as u64).sum();
+            let group_free_blocks = bs * 8 - used;
+            out.write_all_at(geometry.block_bitmap(g) * bs, &bitmap)?;
+
+            let mut bitmap = vec![0u8; bs as usize];
+            let group_inodes =
+                &inodes[(g * geometry.ipg) as usize..((g + 1) * geometry.ipg) as usize];
+            for (i, _) in group_inodes.iter().enumerate().filter(|(_, x)| x.is_some()) {
+                set(&mut bitmap, i as u64);
+            }
+            for bit in geometry.ipg..bs * 8 {
+                set(&mut bitmap, bit);
+            }
+            let group_free_inodes = group_inodes.iter().filter(|x| x.is_none()).count() as u64;
+            let dirs = group_inodes.iter().filter(|x| **x == Some(true)).count() as u64;
+            out.write_all_at(geometry.inode_bitmap(g) * bs, &bitmap)?;
+
+            let d = &mut descriptors[(g * DESC_SIZE) as usize..((g + 1) * DESC_SIZE) as usize];
+            d[0x0..0x4].copy_from_slice(&(geometry.block_bitmap(g) as u32).to_le_bytes());
+            d[0x4..0x8].copy_from_slice(&(geometry.inode_bitmap(g) as u32).to_le_bytes());
+            d[0x8..0xC].copy_from_slice(&(geometry.inode_table(g) as u32).to_le_bytes());
+            d[0xC..0xE].copy_from_slice(&(group_free_blocks as u16).to_le_bytes());
+            d[0xE..0x10].copy_from_slice(&(group_free_inodes as u16).to_le_bytes());
+            d[0x10..0x12].copy_from_slice(&(dirs as u16).to_le_bytes());
+            free_blocks += group_free_blocks;
+            free_inodes += group_free_inodes;
+        }
+        for g in (0..geometry.groups).filter(|&g| SuperBlock::sparse_has_super(g)) {
+            let start = geometry.group_start(g);
+            let sb = self.raw_superblock(geometry, free_blocks, free_inodes, g);
+            // the primary superblock is 1024 bytes in, whatever the block size
+            let at = if g == 0 { 1024 } else { start * bs };
+            out.write_all_at(at, &sb)?;
+            out.write_all_at((start + 1) * bs, &descriptors)?;
+        }
+        // the image is as long as the filesystem, even if it ends in
+        // free blocks
+        let end = geometry.blocks_count;
+        if !allocator
+            .used
+            .iter()
+            .any(|&(start, len)| start + len == end)
+        {
+            out.write_all_at(end * bs - 1, &[0])?;
+        }
+        Ok(())
+    }
+
+    fn raw_superblock(
+        &self,
+        geometry: &Geometry,
+        free_blocks: u64,
+        free_inodes: u64,
+        group: u64,
+    ) -> Vec<u8> {
+        let mut raw = vec![0u8; 1024];
+        let mut put = |at: usize, bytes: &[u8]| raw[at..at + bytes.len()].copy_from_slice(bytes);
+        let log_block_size = (self.block_size / 1024).trailing_zeros();
+        let time = (self.timestamp.seconds as u32).to_le_bytes();
+        put(
+            0x0,
+            &((geometry.ipg * geometry.groups) as u32).to_le_bytes(),
+        );
+        put(0x4, &(geometry.blocks_count as u32).to_le_bytes());
+        put(0xC, &(free_blocks as u32).to_le_bytes());
+        put(0x10, &(free_inodes as u32).to_le_bytes());
+        put(0x14, &(geometry.first_data_block as u32).to_le_bytes());
+        put(0x18, &log_block_size.to_le_bytes());
+        put(0x1C, &log_block_size.to_le_bytes());
+        put(0x20, &(geometry.bpg as u32).to_le_bytes());
+        put(0x24, &(geometry.bpg as u32).to_le_bytes());
+        put(0x28, &(geometry.ipg as u32).to_le_bytes());
+        put(0x30, &time);
+        put(0x36, &u16::MAX.to_le_bytes());
+        put(0x38, &SuperBlock::MAGIC.to_le_bytes());
+        // clean, continue on errors
+        put(0x3A, &1u16.to_le_bytes());
+        put(0x3C, &1u16.to_le_bytes());
+        put(0x40, &time);
+        put(0x4C, &1u32.to_le_bytes());
+        put(0x54, &(FIRST_INO as u32).to_le_bytes());
+        put(0x58, &(INODE_SIZE as u16).to_le_bytes());
+        put(0x5A, &(group as u16).to_le_bytes());
+        let incompat = SuperBlock::INCOMPAT_FILETYPE | SuperBlock::INCOMPAT_EXTENTS;
+        put(0x60, &incompat.to_le_bytes());
+        let ro_compat = SuperBlock::RO_COMPAT_SPARSE_SUPER
+            | SuperBlock::RO_COMPAT_LARGE_FILE
+            | SuperBlock::RO_COMPAT_DIR_NLINK
+            | SuperBlock::RO_COMPAT_EXTRA_ISIZE;
+        put(0x64, &ro_compat.to_le_bytes());
+        put(0x68, &self.uuid);
+        put(0x78, self.label.as_bytes());
+        put(0x108, &time);
+        put(0x15C, &EXTRA_ISIZE.to_le_bytes());
+        put(0x15E, &EXTRA_ISIZE.to_le_bytes());
+        raw
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Add the tree below directory `path` to `builder` as its root, with
+    /// the ownership, permissions and mtime of each node. Hard links
+    /// become copies. Device nodes, fifos and sockets have no place in
+    /// built images and are left out. Entries that can't be read or added
+    /// are recorded in the summary with `keep_going`, or end the repack
+    /// with an error.
+    pub fn repack(
+        &self,
+        path: &str,
+        keep_going: bool,
+        builder: &mut ImageBuilder,
+    ) -> Result<WalkSummary> {
+        let root = format!("/{}", path.trim_matches('/'));
+        self.walk(&root, keep_going, |entry| {
+            let inode = &entry.inode;
+            if entry.depth == 0 && inode.file_type() != FileType::Directory {
+                bail!("not a directory");
+            }
+            let path = &entry.path[root.trim_end_matches('/').len()..];
+            let kind = match TypedInode::new(inode.clone()) {
+                TypedInode::Dir(_) => NodeKind::Dir,
+                TypedInode::File(_) => {
+                    let mut content = vec![0; inode.size as usize];
+                    inode
+                        .reader(&self.sb, &self.dev)?
+                        .read_exact_at(0, &mut content)?;
+                    NodeKind::File(content)
+                }
+                TypedInode::Symlink(x) => NodeKind::Symlink(x.target(&self.sb, &self.dev)?),
+                TypedInode::Other(_) => return Ok(()),
+            };
+            let meta = NodeMeta {
+                permissions: inode.mode & 0o7777,
+                uid: inode.uid,
+                gid: inode.gid,
+                mtime: Some(inode.mtime),
+            };
+            builder.add(path, kind, meta)
+        })
+    }
+}
+
+/// Where groups and their metadata lie: each group starts with a
+/// superblock and descriptor copy if sparse_super keeps one there, then
+/// its bitmaps and inode table.
+#[derive(Debug)]
+struct Geometry {
+    block_size: u64,
+    first_data_block: u64,
+    bpg: u64,
+    ipg: u64,
+    groups: u64,
+    gdt_blocks: u64,
+    blocks_count: u64,
+}
+
+impl Geometry {
+    /// The smallest layout with room for `data_blocks` and `inodes`.
+    fn new(block_size: u64, data_blocks: u64, inodes: u64) -> Self {
+        let bpg = block_size * 8;
+        // inode tables fill whole blocks, bitmaps whole bytes
+        let ipg_align = 8.max(block_size / INODE_SIZE);
+        let mut geometry = Self {
+            block_size,
+            first_data_block: (block_size == 1024) as u64,
+            bpg,
+            ipg: 0,
+            groups: 1,
+            gdt_blocks: 0,
+            blocks_count: 0,
+        };
+        loop {
+            let g = &mut geometry;
+            g.ipg = inodes.div_ceil(g.groups).div_ceil(ipg_align).max(1) * ipg_align;
+            g.gdt_blocks = (g.groups * DESC_SIZE).div_ceil(block_size);
+            let overhead: u64 = (0..g.groups).map(|x| g.overhead(x)).sum();
+            g.blocks_count = g.first_data_block + overhead + data_blocks;
+            let groups = (g.blocks_count - g.first_data_block).div_ceil(bpg);
+            if groups <= g.groups && g.ipg <= bpg {
+                break;
+            }
+            g.groups = groups.max(g.groups + 1);
+        }
+        // the last group holds at least its own metadata
+        let last = geometry.groups - 1;
+        let last_len = geometry.blocks_count - geometry.group_start(last);
+        geometry.blocks_count += geometry.overhead(last).saturating_sub(last_len);
+        geometry
+    }
+
+    fn itable_blocks(&self) -> u64 {
+        self.ipg * INODE_SIZE / self.block_size
+    }
+
+    fn group_start(&self, group: u64) -> u64 {
+        self.first_data_block + group * self.bpg
+    }
+
+    fn group_end(&self, group: u64) -> u64 {
+        (self.group_start(group) + self.bpg).min(self.blocks_count)
+    }
+
+    fn block_bitmap(&self, group: u64) -> u64 {
+        let backup = SuperBlock::sparse_has_super(group) as u64 * (1 + self.gdt_blocks);
+        self.group_start(group) + backup
+    }
+
+    fn inode_bitmap(&self, group: u64) -> u64 {
+        self.block_bitmap(group) + 1
+    }
+
+    fn inode_table(&self, group: u64) -> u64 {
+        self.block_bitmap(group) + 2
+    }
+
+    /// Metadata blocks at the start of the group.
+    fn overhead(&self, group: u64) -> u64 {
+        self.inode_table(group) + self.itable_blocks() - self.group_start(group)
+    }
+
+    fn inode_offset(&self, ino: u64) -> u64 {
+        let group = (ino - 1) / self.ipg;
+        let index = (ino - 1) % self.ipg;
+        self.inode_table(group) * self.block_size + index * INODE_SIZE
+    }
+}
+
+/// Hands out the blocks of each group past its metadata, in order.
+struct Allocator {
+    /// free ranges, `[start, end)`
+    free: Vec<(u64, u64)>,
+    /// runs handed out, `(start, len)`
+    used: Vec<(u64, u64)>,
+}
+
+impl Allocator {
+    fn new(geometry: &Geometry) -> Self {
+        let free = (0..geometry.groups)
+            .map(|g| {
+                (
+                    geometry.group_start(g) + geometry.overhead(g),
+                    geometry.group_end(g),
+                )
+            })
+            .filter(|(start, end)| start < end)
+            .rev()
+            .collect();
+        Self {
+            free,
+            used: Vec::new(),
+        }
+    }
+
+    /// `blocks` blocks as runs of contiguous blocks.
+    fn alloc(&mut self, mut blocks: u64) -> Result<Vec<(u64, u64)>> {
+        let mut runs = Vec::new();
+        while blocks > 0 {
+            let Some((start, end)) = self.free.last_mut() else {
+                bail!("image layout too small, {} blocks short", blocks);
+            };
+            let len = blocks.min(*end - *start);
+            runs.push((*start, len));
+            *start += len;
+            blocks -= len;
+            if start == end {
+                self.free.pop();
+            }
+        }
+        self.used.extend(&runs);
+        Ok(runs)
+    }
+}
@@ -0,0 +1,217 @@
+//! File carving: files found by their signature in unallocated blocks,
+//! for when the inodes that pointed at them are gone.
+
+use std::io::Write;
+
+use positioned_io::ReadAt;
+
+use crate::progress::{ProgressSink, ProgressTracker};
+use crate::recover::sniff;
+use crate::{Ext4Fs, Result};
+
+// free space is read 1 MiB at a time
+const CHUNK: u64 = 1 << 20;
+
+/// A file found in free space, its content the `len` bytes from `offset`
+/// on the device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CarvedFile {
+    /// one of the formats of `recover::SIGNATURES`
+    pub format: &'static str,
+    pub offset: u64,
+    pub len: u64,
+    /// whether the format says where the file ends and it ended there,
+    /// rather than at a block of zeros, the end of the free range or the
+    /// size limit
+    pub complete: bool,
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Scan the unallocated blocks for the start of a known file format
+    /// and find where each file found ends, `max_len` bytes further at
+    /// most. Files are assumed to start at a block and to lie in one run
+    /// of free blocks, a fragmented file is cut at the end of its first
+    /// run. Every block is looked at, in files found too: the end a
+    /// format gives may take in blocks of other files when the one found
+    /// was sparse or fragmented. The free space scanned is counted to
+    /// `progress`, each file found as an entry.
+    pub fn carve(&self, max_len: u64, progress: &mut dyn ProgressSink) -> Result<Vec<CarvedFile>> {
+        let block_size = self.sb.block_size;
+        let report = self.trim_report()?;
+        let mut tracker = ProgressTracker::new(progress, report.free_blocks() * block_size);
+        let mut found = Vec::new();
+        for range in report.ranges {
+            let start = range.start * block_size;
+            let end = (range.start + range.blocks) * block_size;
+            let mut buf = Vec::new();
+            let mut buf_offset = 0;
+            let mut pos = start;
+            while pos < end {
+                if pos < buf_offset || pos + 16 > buf_offset + buf.len() as u64 {
+                    let len = CHUNK.min(end - pos) as usize;
+                    buf.resize(len, 0);
+                    self.dev.read_exact_at(pos, &mut buf)?;
+                    buf_offset = pos;
+                    tracker.advance("", len as u64);
+                }
+                let at = (pos - buf_offset) as usize;
+                let Some(format) = sniff(&buf[at..]) else {
+                    pos += block_size;
+                    continue;
+                };
+                let mut window = vec![0u8; max_len.min(end - pos) as usize];
+                self.dev.read_exact_at(pos, &mut window)?;
+                let file = match file_len(format, &window) {
+                    Some(len) if len <= window.len() => CarvedFile {
+                        format,
+                        offset: pos,
+                        len: len as u64,
+                        complete: true,
+                    },
+                    _ => CarvedFile {
+                        format,
+                        offset: pos,
+                        len: up_to_zeros(&window, block_size as usize) as u64,
+                        complete: false,
+                    },
+                };
+                tracker.entry("");
+                found.push(file);
+                pos += block_size;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Write the content of a carved file to `out`.
+    pub fn write_carved(&self, file: &CarvedFile, out: &mut dyn Write) -> Result<()> {
+        let mut buf = vec![0u8; CHUNK.min(file.len) as usize];
+        for pos in (0..file.len).step_by(CHUNK as usize) {
+            let len = CHUNK.min(file.len - pos) as usize;
+            self.dev.read_exact_at(file.offset + pos, &mut buf[..len])?;
+            out.write_all(&buf[..len])?;
+        }
+        Ok(())
+    }
+}
+
+/// `data` up to its first block of zeros, where a file whose end isn't
+/// known most likely ends.
+fn up_to_zeros(data: &[u8], block_size: usize) -> usize {
+    data.chunks(block_size)
+        .position(|x| x.iter().all(|&b| b == 0))
+        .map_or(data.len(), |i| i * block_size)
+}
+
+/// The length of the file of `format` starting `data`, from its own
+/// structure, None if the format doesn't tell or the end isn't in
+/// `data`. It may be past the end of `data` when the format records its
+/// size.
+fn file_len(format: &str, data: &[u8]) -> Option<usize> {
+    match format {
+        "png" => png_len(data),
+        "jpeg" => jpeg_len(data),
+        "gif" => find(data, b"\x00\x3b", 6).map(|i| i + 2),
+        "pdf" => {
+            let i = find(data, b"%%EOF", 5)? + 5;
+            let eol = data[i..]
+                .iter()
+                .take(2)
+                .take_while(|&&b| b == b'\r' || b == b'\n');
+            Some(i + eol.count())
+        }
+        "zip" => {
+            // the end of central directory record, 22 bytes and a comment
+            let i = find(data, b"PK\x05\x06", 4)?;
+            let comment = u16::from_le_bytes(data.get(i + 20..i + 22)?.try_into().ok()?);
+            Some(i + 22 + comment as usize)
+        }
+        "sqlite" => {
+            let page_size = match u16::from_be_bytes(data.get(16..18)?.try_into().ok()?) {
+                1 => 65536,
+                x => x as usize,
+            };
+            let pages = u32::from_be_bytes(data.get(28..32)?.try_into().ok()?) as usize;
+            (pages > 0).then_some(page_size * pages)
+        }
+        "elf" => elf_len(data),
+        _ => None,
+    }
+}
+
+/// Where `needle` first is in `data`, looking from `from`.
+fn find(data: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(needle.len())
+        .position(|x| x == needle)
+        .map(|i| i + from)
+}
+
+/// Chunk after chunk, up to the end of IEND: each is a length, a type,
+/// the data and a crc.
+fn png_len(data: &[u8]) -> Option<usize> {
+    let mut pos = 8;
+    loop {
+        let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind = data.get(pos + 4..pos + 8)?;
+        pos += 12 + len;
+        if kind == b"IEND" {
+            return Some(pos);
+        }
+    }
+}
+
+/// Segment after segment, then through the entropy coded data of a scan
+/// to the marker after it: the end of image, or the next segment of a
+/// progressive image.
+fn jpeg_len(data: &[u8]) -> Option<usize> {
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        match marker {
+            // fill bytes
+            0xFF => pos += 1,
+            0xD9 => return Some(pos + 2),
+            // markers without a segment
+            0x01 | 0xD0..=0xD8 => pos += 2,
+            _ => {
+                let len = u16::from_be_bytes(data.get(pos + 2..pos + 4)?.try_into().ok()?);
+                pos += 2 + len as usize;
+                if marker == 0xDA {
+                    // stuffed zeros and restart markers belong to the data
+                    while *data.get(pos)? != 0xFF
+                        || matches!(data.get(pos + 1)?, 0x00 | 0xD0..=0xD7)
+                    {
+                        pos += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The end of the section header table, or of the program header table
+/// if that comes later: ELF files end with one of them.
+fn elf_len(data: &[u8]) -> Option<usize> {
+    let big_endian = *data.get(5)? == 2;
+    let n = |at: usize, size: usize| -> Option<u64> {
+        let bytes = data.get(at..at + size)?;
+        let fold = |value: u64, b: &u8| value << 8 | *b as u64;
+        Some(match big_endian {
+            true => bytes.iter().fold(0, fold),
+            false => bytes.iter().rev().fold(0, fold),
+        })
+    };
+    // offsets of the header fields, 32 then 64 bits
+    let (phoff, shoff, word) = match data.get(4)? {
+        1 => (n(0x1C, 4)?, n(0x20, 4)?, 0),
+        2 => (n(0x20, 8)?, n(0x28, 8)?, 12),
+        _ => return None,
+    };
+    let ph = phoff + n(0x2A + word, 2)? * n(0x2C + word, 2)?;
+    let sh = shoff + n(0x2E + word, 2)? * n(0x30 + word, 2)?;
+    usize::try_from(ph.max(sh)).ok()
+}
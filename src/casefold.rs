@@ -0,0 +1,52 @@
+//! Name comparison for casefolded directories.
+
+use failure::bail;
+
+use crate::{Inode, InodeFlags, Result, SuperBlock};
+
+impl SuperBlock {
+    // s_encoding value of utf8, the only encoding ext4 knows
+    pub const ENCODING_UTF8: u16 = 1;
+}
+
+impl Inode {
+    /// Whether names in this directory compare case-insensitively.
+    pub fn is_casefolded(&self, sb: &SuperBlock) -> bool {
+        self.flags.contains(InodeFlags::CASEFOLD) && sb.encoding == SuperBlock::ENCODING_UTF8
+    }
+}
+
+/// Fold `name` the way the kernel does before comparing names of a
+/// casefolded directory. Full case folding is approximated by lowercasing
+/// plus the few foldings which differ from it. Names are not normalized
+/// to NFD as the kernel does, so composed and decomposed spellings of a
+/// name differ: folded names matching is an answer, not matching is only
+/// one for ASCII names, see `check_missing`.
+pub(crate) fn fold(name: &str) -> String {
+    name.chars()
+        .flat_map(|c| match c {
+            'ß' | 'ẞ' => "ss".chars().collect::<Vec<_>>(),
+            'ς' => vec!['σ'],
+            c => c.to_lowercase().collect(),
+        })
+        .collect()
+}
+
+/// Whether `a` and `b` name the same entry, in a directory casefolded or
+/// not.
+pub(crate) fn same_name(casefolded: bool, a: &str, b: &str) -> bool {
+    a == b || casefolded && fold(a) == fold(b)
+}
+
+/// An error for `name` not found in a directory casefolded or not, when
+/// it may still be there under another normalization.
+pub(crate) fn check_missing(casefolded: bool, name: &str) -> Result<()> {
+    if casefolded && !name.is_ascii() {
+        bail!(
+            "{}: names outside of ASCII can't be told missing from casefolded directories, \
+             Unicode normalization isn't supported",
+            name
+        );
+    }
+    Ok(())
+}
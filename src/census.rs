@@ -0,0 +1,121 @@
+//! Which features, geometries and directory hashes a set of images uses,
+//! read from their superblocks alone so that large fleets are cheap to
+//! survey.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+
+use failure::bail;
+use positioned_io::ReadAt;
+
+use crate::dirhash::HashVersion;
+use crate::features::{self, COMPAT, INCOMPAT, RO_COMPAT};
+use crate::{human_bytes, Result, SuperBlock};
+
+/// Number of images using each value, keyed by value.
+#[derive(Debug, Default)]
+pub struct Census {
+    pub images: u64,
+    pub compat: BTreeMap<String, u64>,
+    pub incompat: BTreeMap<String, u64>,
+    pub ro_compat: BTreeMap<String, u64>,
+    pub block_sizes: BTreeMap<u64, u64>,
+    pub inode_sizes: BTreeMap<u64, u64>,
+    pub hash_versions: BTreeMap<String, u64>,
+    /// images which couldn't be read, with the reason
+    pub failed: Vec<(String, String)>,
+}
+
+impl Census {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count the superblock of one image.
+    pub fn add(&mut self, sb: &SuperBlock) {
+        self.images += 1;
+        for (counts, flags, table) in [
+            (&mut self.compat, sb.feature_compat, COMPAT),
+            (&mut self.incompat, sb.feature_incompat, INCOMPAT),
+            (&mut self.ro_compat, sb.feature_ro_compat, RO_COMPAT),
+        ] {
+            for name in features::names(flags, table) {
+                *counts.entry(name).or_default() += 1;
+            }
+        }
+        *self.block_sizes.entry(sb.block_size).or_default() += 1;
+        *self.inode_sizes.entry(sb.inode_size).or_default() += 1;
+        let hash = match HashVersion::new(sb.def_hash_version, sb) {
+            Ok(version) => version.name().to_string(),
+            Err(_) => format!("unknown ({})", sb.def_hash_version),
+        };
+        *self.hash_versions.entry(hash).or_default() += 1;
+    }
+
+    /// Read the superblock of the image at `path` and count it, or
+    /// record why it couldn't be read.
+    pub fn add_image(&mut self, path: &Path) {
+        if let Err(e) = Self::read_superblock(path).map(|sb| self.add(&sb)) {
+            self.failed
+                .push((path.display().to_string(), e.to_string()));
+        }
+    }
+
+    fn read_superblock(path: &Path) -> Result<SuperBlock> {
+        let file = File::open(path)?;
+        // anything parsed out of a non-ext4 file would be garbage
+        let mut magic = [0u8; 2];
+        file.read_exact_at(1024 + 0x38, &mut magic)?;
+        if u16::from_le_bytes(magic) != SuperBlock::MAGIC {
+            bail!("no ext2/3/4 superblock");
+        }
+        SuperBlock::new(&file)
+    }
+}
+
+impl fmt::Display for Census {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let images = self.images.max(1) as f64;
+        let row = |f: &mut fmt::Formatter<'_>, value: &str, count: u64| {
+            writeln!(
+                f,
+                "  {:<24} {:>8} {:>6.1}%",
+                value,
+                count,
+                count as f64 * 100.0 / images
+            )
+        };
+        writeln!(
+            f,
+            "{} images, {} unreadable",
+            self.images,
+            self.failed.len()
+        )?;
+        for (title, counts) in [
+            ("compat features", &self.compat),
+            ("incompat features", &self.incompat),
+            ("ro_compat features", &self.ro_compat),
+            ("directory hashes", &self.hash_versions),
+        ] {
+            writeln!(f, "*** {title}")?;
+            for (value, &count) in counts {
+                row(f, value, count)?;
+            }
+        }
+        for (title, counts) in [
+            ("block sizes", &self.block_sizes),
+            ("inode sizes", &self.inode_sizes),
+        ] {
+            writeln!(f, "*** {title}")?;
+            for (&value, &count) in counts {
+                row(f, &human_bytes(value), count)?;
+            }
+        }
+        for (path, reason) in &self.failed {
+            writeln!(f, "unreadable {path}: {reason}")?;
+        }
+        Ok(())
+    }
+}
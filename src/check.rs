@@ -0,0 +1,79 @@
+use positioned_io::ReadAt;
+
+use crate::events::Anomaly;
+use crate::mmp::MmpState;
+use crate::{BlockMapKind, Ext4Fs, FileType, Result};
+
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub inodes_checked: u64,
+    pub findings: Vec<Anomaly>,
+}
+
+impl CheckReport {
+    fn add<T: ReadAt>(&mut self, fs: &Ext4Fs<T>, anomaly: Anomaly) {
+        fs.report(anomaly.clone());
+        self.findings.push(anomaly);
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Walk every in-use inode and collect anything inconsistent instead
+    /// of failing on the first bad inode. Findings are also passed to the
+    /// event hook as they are found.
+    pub fn check(&self) -> Result<CheckReport> {
+        let mut report = CheckReport::default();
+        if !self.sb.checksum_valid {
+            let anomaly = Anomaly::BadChecksum {
+                structure: "superblock",
+                offset: 1024,
+                stored: self.sb.checksum,
+                computed: self.sb.computed_checksum(&self.dev)?,
+            };
+            report.add(self, anomaly);
+        }
+        if let Some(mmp) = self.mmp()? {
+            if let MmpState::InUse(sequence) = mmp.state {
+                let anomaly = Anomaly::MountedElsewhere {
+                    node: mmp.node_name,
+                    sequence,
+                };
+                report.add(self, anomaly);
+            }
+        }
+        for n in self.orphans()? {
+            report.add(self, Anomaly::Orphan(n));
+        }
+        for n in self.initialized_inodes()? {
+            let inode = match self.inode(n) {
+                Ok(inode) => inode,
+                Err(e) => {
+                    let error = e.to_string();
+                    report.add(self, Anomaly::UnreadableInode { inode: n, error });
+                    continue;
+                }
+            };
+            if inode.mode == 0 || inode.links_count == 0 {
+                continue;
+            }
+            report.inodes_checked += 1;
+            let kind = inode.block_map_kind();
+            if matches!(
+                kind,
+                BlockMapKind::MissingExtentHeader | BlockMapKind::UnflaggedExtentHeader
+            ) {
+                report.add(self, Anomaly::ExtentsFlagMismatch { inode: n, kind });
+            }
+            if matches!(inode.file_type(), FileType::Directory) {
+                let problems = match self.htree(&inode) {
+                    Ok(htree) => htree.map(|x| x.problems).unwrap_or_default(),
+                    Err(e) => vec![e.to_string()],
+                };
+                for problem in problems {
+                    report.add(self, Anomaly::DamagedHtree { dir: n, problem });
+                }
+            }
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+use positioned_io::ReadAt;
+
+use crate::{human_bytes, Ext4Fs, FileType, Result, WalkSummary};
+
+// samples taken per file, spread evenly over its content
+const SAMPLES: u64 = 4;
+// fast deflate, enough to tell compressible data from already compressed
+const LEVEL: u8 = 1;
+
+/// Sampled compressibility of the files below a directory,
+/// subdirectories included.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Compressibility {
+    pub files: u64,
+    pub bytes: u64,
+    pub sampled: u64,
+    /// size of the samples once deflated
+    pub compressed: u64,
+}
+
+impl Compressibility {
+    /// compressed size over original size, 1.0 when nothing was sampled
+    pub fn ratio(&self) -> f64 {
+        if self.sampled == 0 {
+            1.0
+        } else {
+            self.compressed as f64 / self.sampled as f64
+        }
+    }
+
+    /// size of the files once compressed, extrapolated from the samples
+    pub fn estimated_bytes(&self) -> u64 {
+        (self.bytes as f64 * self.ratio()) as u64
+    }
+}
+
+/// Compressibility per directory, keyed by path.
+#[derive(Debug, Default)]
+pub struct CompressionReport {
+    pub dirs: BTreeMap<String, Compressibility>,
+    pub summary: WalkSummary,
+}
+
+impl fmt::Display for CompressionReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>12} {:>12} {:>6} {:>8}  path",
+            "size", "estimated", "ratio", "files"
+        )?;
+        for (path, x) in &self.dirs {
+            writeln!(
+                f,
+                "{:>12} {:>12} {:>5.0}% {:>8}  {}",
+                human_bytes(x.bytes),
+                human_bytes(x.estimated_bytes()),
+                x.ratio() * 100.0,
+                x.files,
+                path
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Estimate how well the files below `path` compress by deflating up
+    /// to `sample_bytes` of each, taken from a few places spread over the
+    /// file. Files with several links are counted once.
+    pub fn compression_report(&self, path: &str, sample_bytes: u64) -> Result<CompressionReport> {
+        let mut report = CompressionReport::default();
+        let mut seen = HashSet::new();
+        report.summary = self.walk(path, true, |entry| {
+            let inode = &entry.inode;
+            if inode.file_type() == FileType::Directory {
+                report.dirs.entry(entry.path.clone()).or_default();
+            }
+            if inode.file_type() != FileType::Regular
+                || inode.links_count > 1 && !seen.insert(entry.inode_number)
+            {
+                return Ok(());
+            }
+            let reader = self.reader_of(inode)?;
+            let chunk = (sample_bytes / SAMPLES).min(inode.size);
+            let mut sampled = 0;
+            let mut compressed = 0;
+            if chunk > 0 {
+                let mut buf = vec![0u8; chunk as usize];
+                let stride = (inode.size - chunk) / (SAMPLES - 1);
+                let mut offsets: Vec<u64> = (0..SAMPLES).map(|i| i * stride).collect();
+                // small files are sampled once
+                offsets.dedup();
+                for offset in offsets {
+                    reader.read_exact_at(offset, &mut buf)?;
+                    sampled += chunk;
+                    compressed += miniz_oxide::deflate::compress_to_vec(&buf, LEVEL).len() as u64;
+                }
+            }
+            // charge the file to every directory above it
+            let mut dir = entry.path.as_str();
+            while let Some(i) = dir.rfind('/') {
+                dir = if i == 0 { "/" } else { &dir[..i] };
+                if dir.len() < path.trim_end_matches('/').len() {
+                    break;
+                }
+                let x = report.dirs.entry(dir.to_string()).or_default();
+                x.files += 1;
+                x.bytes += inode.size;
+                x.sampled += sampled;
+                x.compressed += compressed;
+                if dir == "/" {
+                    break;
+                }
+            }
+            Ok(())
+        })?;
+        Ok(report)
+    }
+}
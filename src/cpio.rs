@@ -0,0 +1,170 @@
+//! Export as a cpio archive in the newc format, the one initramfs images
+//! are made of.
+
+use std::io::Write;
+
+use failure::bail;
+use positioned_io::ReadAt;
+
+use crate::export::NameTransform;
+use crate::redact::RedactionPolicy;
+use crate::tar::{copy_content, Output};
+use crate::{Ext4Fs, FileType, HardLinks, Result, TypedInode, WalkSummary};
+
+const MAGIC: &str = "070701";
+const TRAILER: &str = "TRAILER!!!";
+
+/// The fields of a newc header, each written as 8 hex digits.
+#[derive(Default)]
+struct Header {
+    ino: u32,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    nlink: u32,
+    mtime: u32,
+    size: u32,
+    rdev: (u32, u32),
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Write `path` and everything below it to `out` as a newc cpio
+    /// archive, with modes, owners, modification times, symlinks, hard
+    /// links between files and special files. Names go through
+    /// `transform`, entries it drops are left out, as are those
+    /// `redaction` skips. Files of 4 GiB and more don't fit the format
+    /// and are errors like entries that can't be read, recorded in the
+    /// summary with `keep_going`, or ending the archive as a failure to
+    /// write does.
+    pub fn write_cpio(
+        &self,
+        path: &str,
+        keep_going: bool,
+        transform: &dyn NameTransform,
+        redaction: &RedactionPolicy,
+        out: &mut dyn Write,
+    ) -> Result<WalkSummary> {
+        let root = format!("/{}", path.trim_matches('/'));
+        let mut links = HardLinks::default();
+        let mut out = Output::new(out);
+        let summary = self.walk(&root, keep_going, |entry| {
+            if out.failed.is_some() || redaction.skips(&entry.path) {
+                return Ok(());
+            }
+            let relative = entry.path[root.len()..].trim_start_matches('/');
+            // a single file is archived under its own name
+            let relative = match relative {
+                "" if entry.inode.file_type() != FileType::Directory => {
+                    root.rsplit('/').next().unwrap_or_default()
+                }
+                x => x,
+            };
+            let Some(name) = transform.transform(relative) else {
+                return Ok(());
+            };
+            let name = if name.is_empty() { "." } else { &name };
+            let inode = &entry.inode;
+            let mut header = Header {
+                ino: entry.inode_number.0 as u32,
+                mode: inode.mode as u32,
+                uid: inode.uid,
+                gid: inode.gid,
+                nlink: inode.links_count as u32,
+                // times before the epoch don't fit
+                mtime: inode.mtime.seconds.clamp(0, u32::MAX as i64) as u32,
+                size: 0,
+                rdev: inode.device().unwrap_or_default(),
+            };
+            let placeholder = match inode.file_type() {
+                FileType::Regular => redaction.placeholder(&entry.path),
+                _ => None,
+            };
+            if let Some(text) = placeholder {
+                // not linked to the real content under another name
+                header.nlink = 1;
+                header.size = text.len() as u32;
+                write_header(&mut out, &header, name)?;
+                out.write_all(text)?;
+                out.write_all(&[0; 3][..padding(text.len() as u64)])?;
+                return Ok(());
+            }
+            // the content goes with the first name only, further names
+            // are empty entries sharing its inode number
+            if links.add(entry).is_some() {
+                write_header(&mut out, &header, name)?;
+                return Ok(());
+            }
+            match TypedInode::new(inode.clone()) {
+                TypedInode::File(file) => {
+                    if inode.size > u32::MAX as u64 {
+                        bail!("{} bytes is too large for cpio", inode.size);
+                    }
+                    let reader = self.reader(&file)?;
+                    header.size = inode.size as u32;
+                    write_header(&mut out, &header, name)?;
+                    let result = copy_content(&reader, inode.size, &mut out);
+                    out.write_all(&[0; 3][..padding(inode.size)])?;
+                    result
+                }
+                TypedInode::Symlink(x) => {
+                    let target = x.target(&self.sb, &self.dev)?;
+                    header.size = target.len() as u32;
+                    write_header(&mut out, &header, name)?;
+                    out.write_all(target.as_bytes())?;
+                    out.write_all(&[0; 3][..padding(target.len() as u64)])?;
+                    Ok(())
+                }
+                TypedInode::Dir(_) | TypedInode::Other(_) => {
+                    write_header(&mut out, &header, name)?;
+                    Ok(())
+                }
+            }
+        })?;
+        if let Some(error) = out.failed {
+            return Err(error.into());
+        }
+        let trailer = Header {
+            nlink: 1,
+            ..Header::default()
+        };
+        write_header(&mut out, &trailer, TRAILER)?;
+        out.flush()?;
+        Ok(summary)
+    }
+}
+
+/// Zeros needed after `len` bytes to get back to a multiple of 4.
+fn padding(len: u64) -> usize {
+    ((4 - len % 4) % 4) as usize
+}
+
+/// Write a header and the name following it, padded so what comes next
+/// starts at a multiple of 4.
+fn write_header(out: &mut dyn Write, header: &Header, name: &str) -> std::io::Result<()> {
+    let fields = [
+        header.ino,
+        header.mode,
+        header.uid,
+        header.gid,
+        header.nlink,
+        header.mtime,
+        header.size,
+        // the device the files come from, the same for all of them
+        0,
+        0,
+        header.rdev.0,
+        header.rdev.1,
+        // the name size counts its NUL
+        name.len() as u32 + 1,
+        // checksum, only for the 070702 variant
+        0,
+    ];
+    write!(out, "{MAGIC}")?;
+    for field in fields {
+        write!(out, "{field:08x}")?;
+    }
+    out.write_all(name.as_bytes())?;
+    // the 110 bytes of header and the name with its NUL
+    let len = 110 + name.len() as u64 + 1;
+    out.write_all(&[0; 4][..1 + padding(len)])
+}
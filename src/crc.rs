@@ -0,0 +1,57 @@
+// crc32c (Castagnoli), reflected polynomial
+const POLY: u32 = 0x82F63B78;
+// crc32 (IEEE), reflected polynomial
+const POLY_IEEE: u32 = 0xEDB88320;
+
+const TABLE: [u32; 256] = table(POLY);
+const TABLE_IEEE: [u32; 256] = table(POLY_IEEE);
+
+const fn table(poly: u32) -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ poly
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// crc32c without the final inversion, the way ext4 and jbd2 chain
+/// checksums: pass the previous result as `crc` to continue.
+pub(crate) fn crc32c(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &b| {
+        TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ crc >> 8
+    })
+}
+
+/// crc32 (IEEE) as gzip and zip store it, inversions included: pass 0
+/// to start and the previous result to continue.
+pub(crate) fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &b| {
+        TABLE_IEEE[((crc ^ b as u32) & 0xFF) as usize] ^ crc >> 8
+    })
+}
+
+/// crc16 (ANSI, reflected polynomial 0xA001), which checksums group
+/// descriptors under uninit_bg.
+pub(crate) fn crc16(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |crc, &b| {
+        (0..8).fold(crc ^ b as u16, |crc, _| {
+            if crc & 1 != 0 {
+                crc >> 1 ^ 0xA001
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
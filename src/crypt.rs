@@ -0,0 +1,456 @@
+//! Just enough cryptography to read fscrypt v2 encrypted files: AES-256
+//! in XTS mode for contents and CBC with ciphertext stealing for names,
+//! with keys derived through HKDF-SHA512.
+
+use failure::bail;
+
+use crate::digest::sha512;
+use crate::Result;
+
+// multiplication in AES's GF(2^8)
+const fn gmul(mut a: u8, mut b: u8) -> u8 {
+    let mut p = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            p ^= a;
+        }
+        a = if a & 0x80 != 0 { a << 1 ^ 0x1B } else { a << 1 };
+        b >>= 1;
+    }
+    p
+}
+
+const SBOX: [u8; 256] = {
+    let mut sbox = [0u8; 256];
+    let mut x = 0;
+    while x < 256 {
+        // multiplicative inverse, 0 for 0
+        let mut inv = 0u8;
+        let mut y = 1;
+        while y < 256 {
+            if gmul(x as u8, y as u8) == 1 {
+                inv = y as u8;
+            }
+            y += 1;
+        }
+        sbox[x] = inv
+            ^ inv.rotate_left(1)
+            ^ inv.rotate_left(2)
+            ^ inv.rotate_left(3)
+            ^ inv.rotate_left(4)
+            ^ 0x63;
+        x += 1;
+    }
+    sbox
+};
+
+const INV_SBOX: [u8; 256] = {
+    let mut inv = [0u8; 256];
+    let mut x = 0;
+    while x < 256 {
+        inv[SBOX[x] as usize] = x as u8;
+        x += 1;
+    }
+    inv
+};
+
+/// AES with a 256 bits key.
+#[derive(Clone)]
+pub(crate) struct Aes256 {
+    round_keys: [[u8; 16]; 15],
+}
+
+impl Aes256 {
+    pub(crate) fn new(key: &[u8; 32]) -> Self {
+        let mut words = [[0u8; 4]; 60];
+        for (i, word) in key.chunks_exact(4).enumerate() {
+            words[i].copy_from_slice(word);
+        }
+        let mut rcon = 1u8;
+        for i in 8..60 {
+            let mut t = words[i - 1];
+            if i % 8 == 0 {
+                t = [
+                    SBOX[t[1] as usize] ^ rcon,
+                    SBOX[t[2] as usize],
+                    SBOX[t[3] as usize],
+                    SBOX[t[0] as usize],
+                ];
+                rcon = gmul(rcon, 2);
+            } else if i % 8 == 4 {
+                t = t.map(|b| SBOX[b as usize]);
+            }
+            for j in 0..4 {
+                words[i][j] = words[i - 8][j] ^ t[j];
+            }
+        }
+        let mut round_keys = [[0u8; 16]; 15];
+        for (i, round_key) in round_keys.iter_mut().enumerate() {
+            for j in 0..4 {
+                round_key[j * 4..j * 4 + 4].copy_from_slice(&words[i * 4 + j]);
+            }
+        }
+        Self { round_keys }
+    }
+
+    fn add_round_key(state: &mut [u8; 16], key: &[u8; 16]) {
+        state.iter_mut().zip(key).for_each(|(s, k)| *s ^= k);
+    }
+
+    // the state is column major: byte `c * 4 + r` is row r of column c
+    fn shift_rows(state: &mut [u8; 16], inverse: bool) {
+        let old = *state;
+        for r in 1..4 {
+            for c in 0..4 {
+                let from = if inverse {
+                    (c + 4 - r) % 4
+                } else {
+                    (c + r) % 4
+                };
+                state[c * 4 + r] = old[from * 4 + r];
+            }
+        }
+    }
+
+    fn mix_columns(state: &mut [u8; 16], m: [u8; 4]) {
+        for c in 0..4 {
+            let col = [
+                state[c * 4],
+                state[c * 4 + 1],
+                state[c * 4 + 2],
+                state[c * 4 + 3],
+            ];
+            for r in 0..4 {
+                state[c * 4 + r] = (0..4).fold(0, |acc, i| acc ^ gmul(m[(i + 4 - r) % 4], col[i]));
+            }
+        }
+    }
+
+    pub(crate) fn encrypt(&self, block: &mut [u8; 16]) {
+        Self::add_round_key(block, &self.round_keys[0]);
+        for round in 1..15 {
+            block.iter_mut().for_each(|b| *b = SBOX[*b as usize]);
+            Self::shift_rows(block, false);
+            if round != 14 {
+                Self::mix_columns(block, [2, 3, 1, 1]);
+            }
+            Self::add_round_key(block, &self.round_keys[round]);
+        }
+    }
+
+    pub(crate) fn decrypt(&self, block: &mut [u8; 16]) {
+        Self::add_round_key(block, &self.round_keys[14]);
+        for round in (0..14).rev() {
+            Self::shift_rows(block, true);
+            block.iter_mut().for_each(|b| *b = INV_SBOX[*b as usize]);
+            Self::add_round_key(block, &self.round_keys[round]);
+            if round != 0 {
+                Self::mix_columns(block, [14, 11, 13, 9]);
+            }
+        }
+    }
+}
+
+fn hmac_sha512(key: &[u8], data: &[u8]) -> [u8; 64] {
+    const BLOCK: usize = 128;
+    let mut padded = [0u8; BLOCK];
+    if key.len() > BLOCK {
+        padded[..64].copy_from_slice(&sha512(key));
+    } else {
+        padded[..key.len()].copy_from_slice(key);
+    }
+    let mut inner: Vec<u8> = padded.iter().map(|b| b ^ 0x36).collect();
+    inner.extend(data);
+    let mut outer: Vec<u8> = padded.iter().map(|b| b ^ 0x5C).collect();
+    outer.extend(sha512(&inner));
+    sha512(&outer)
+}
+
+// info contexts fscrypt prepends to what it derives
+const HKDF_CONTEXT_KEY_IDENTIFIER: u8 = 1;
+const HKDF_CONTEXT_PER_FILE_ENC_KEY: u8 = 2;
+
+/// A v2 policy master key, as given to FS_IOC_ADD_ENCRYPTION_KEY.
+#[derive(Clone)]
+pub(crate) struct MasterKey {
+    // HKDF-Extract of the raw key with an all zero salt
+    prk: [u8; 64],
+    pub(crate) identifier: [u8; 16],
+}
+
+impl MasterKey {
+    pub(crate) fn new(raw: &[u8]) -> Self {
+        let prk = hmac_sha512(&[0u8; 64], raw);
+        let mut key = Self {
+            prk,
+            identifier: [0; 16],
+        };
+        let identifier = key.derive(HKDF_CONTEXT_KEY_IDENTIFIER, &[], 16);
+        key.identifier.copy_from_slice(&identifier);
+        key
+    }
+
+    // HKDF-Expand with fscrypt's "fscrypt\0" + context prefix
+    fn derive(&self, context: u8, info: &[u8], len: usize) -> Vec<u8> {
+        let mut full_info = b"fscrypt\0".to_vec();
+        full_info.push(context);
+        full_info.extend(info);
+        hkdf_expand(&self.prk, &full_info, len)
+    }
+}
+
+fn hkdf_expand(prk: &[u8], info: &[u8], len: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut previous = Vec::new();
+    let mut counter = 1u8;
+    while out.len() < len {
+        let mut data = previous;
+        data.extend(info);
+        data.push(counter);
+        previous = hmac_sha512(prk, &data).to_vec();
+        out.extend(&previous);
+        counter += 1;
+    }
+    out.truncate(len);
+    out
+}
+
+/// The encryption context of an inode, stored in its "c" xattr of the
+/// encryption index.
+#[derive(Debug, Clone)]
+pub(crate) struct EncryptionContext {
+    pub(crate) contents_mode: u8,
+    pub(crate) filenames_mode: u8,
+    pub(crate) flags: u8,
+    pub(crate) master_key_identifier: [u8; 16],
+    pub(crate) nonce: [u8; 16],
+}
+
+impl EncryptionContext {
+    pub(crate) const XATTR_INDEX: u8 = 9;
+    pub(crate) const XATTR_NAME: &'static str = "c";
+
+    const MODE_AES_256_XTS: u8 = 1;
+    const MODE_AES_256_CTS: u8 = 4;
+    // other flags change how IVs and keys are derived
+    const FLAGS_PAD_MASK: u8 = 0x3;
+
+    pub(crate) fn parse(raw: &[u8]) -> Result<Self> {
+        if raw.first() != Some(&2) {
+            bail!("unsupported fscrypt policy version {:?}", raw.first());
+        }
+        if raw.len() < 40 {
+            bail!("fscrypt context too short: {} bytes", raw.len());
+        }
+        let context = Self {
+            contents_mode: raw[1],
+            filenames_mode: raw[2],
+            flags: raw[3],
+            master_key_identifier: raw[8..24].try_into().unwrap(),
+            nonce: raw[24..40].try_into().unwrap(),
+        };
+        if context.contents_mode != Self::MODE_AES_256_XTS
+            || context.filenames_mode != Self::MODE_AES_256_CTS
+        {
+            bail!(
+                "unsupported fscrypt modes: contents {}, filenames {}",
+                context.contents_mode,
+                context.filenames_mode
+            );
+        }
+        if context.flags & !Self::FLAGS_PAD_MASK != 0 {
+            bail!("unsupported fscrypt policy flags: 0x{:X}", context.flags);
+        }
+        Ok(context)
+    }
+
+    /// The AES-256-XTS key for the contents of the inode.
+    pub(crate) fn contents_key(&self, master: &MasterKey) -> ContentsKey {
+        let key = master.derive(HKDF_CONTEXT_PER_FILE_ENC_KEY, &self.nonce, 64);
+        ContentsKey {
+            data: Aes256::new(key[..32].try_into().unwrap()),
+            tweak: Aes256::new(key[32..].try_into().unwrap()),
+        }
+    }
+
+    /// The AES-256-CTS key for the names in the directory.
+    pub(crate) fn filenames_key(&self, master: &MasterKey) -> FilenamesKey {
+        let key = master.derive(HKDF_CONTEXT_PER_FILE_ENC_KEY, &self.nonce, 32);
+        FilenamesKey(Aes256::new(key[..].try_into().unwrap()))
+    }
+}
+
+/// AES-256-XTS, one data unit per filesystem block.
+#[derive(Clone)]
+pub(crate) struct ContentsKey {
+    data: Aes256,
+    tweak: Aes256,
+}
+
+impl ContentsKey {
+    /// Decrypt logical block `block` of the file in place.
+    pub(crate) fn decrypt(&self, block: u64, buf: &mut [u8]) {
+        let mut tweak = [0u8; 16];
+        tweak[..8].copy_from_slice(&block.to_le_bytes());
+        self.tweak.encrypt(&mut tweak);
+        for chunk in buf.chunks_exact_mut(16) {
+            let mut x: [u8; 16] = chunk.try_into().unwrap();
+            x.iter_mut().zip(&tweak).for_each(|(x, t)| *x ^= t);
+            self.data.decrypt(&mut x);
+            x.iter_mut().zip(&tweak).for_each(|(x, t)| *x ^= t);
+            chunk.copy_from_slice(&x);
+            // multiply the tweak by x in GF(2^128)
+            let carry = tweak[15] >> 7;
+            for i in (1..16).rev() {
+                tweak[i] = tweak[i] << 1 | tweak[i - 1] >> 7;
+            }
+            tweak[0] = (tweak[0] << 1) ^ (carry * 0x87);
+        }
+    }
+}
+
+/// AES-256 in CBC mode with ciphertext stealing (CS3), zero IV.
+pub(crate) struct FilenamesKey(Aes256);
+
+impl FilenamesKey {
+    /// Decrypt an encrypted name, dropping the null padding.
+    pub(crate) fn decrypt(&self, name: &[u8]) -> Result<Vec<u8>> {
+        if name.len() < 16 {
+            bail!("encrypted name too short: {} bytes", name.len());
+        }
+        let blocks = name.len().div_ceil(16);
+        let mut plain = Vec::with_capacity(name.len());
+        let mut previous = [0u8; 16];
+        let cbc_blocks = if blocks == 1 { 1 } else { blocks - 2 };
+        for chunk in name[..cbc_blocks * 16].chunks_exact(16) {
+            let mut x: [u8; 16] = chunk.try_into().unwrap();
+            self.0.decrypt(&mut x);
+            x.iter_mut().zip(&previous).for_each(|(x, p)| *x ^= p);
+            plain.extend(x);
+            previous = chunk.try_into().unwrap();
+        }
+        if blocks > 1 {
+            // the last two blocks are swapped, the last one truncated
+            let tail = &name[cbc_blocks * 16..];
+            let last_len = tail.len() - 16;
+            let mut z: [u8; 16] = tail[..16].try_into().unwrap();
+            self.0.decrypt(&mut z);
+            let mut second_last = z;
+            second_last[..last_len].copy_from_slice(&tail[16..]);
+            let last: Vec<u8> = (0..last_len).map(|i| z[i] ^ tail[16 + i]).collect();
+            self.0.decrypt(&mut second_last);
+            second_last
+                .iter_mut()
+                .zip(&previous)
+                .for_each(|(x, p)| *x ^= p);
+            plain.extend(second_last);
+            plain.extend(last);
+        }
+        while plain.last() == Some(&0) {
+            plain.pop();
+        }
+        Ok(plain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    // FIPS-197 appendix C.3
+    #[test]
+    fn aes256_known_answer() {
+        let key: [u8; 32] = std::array::from_fn(|i| i as u8);
+        let aes = Aes256::new(&key);
+        let mut block: [u8; 16] = hex("00112233445566778899aabbccddeeff").try_into().unwrap();
+        aes.encrypt(&mut block);
+        assert_eq!(block[..], hex("8ea2b7ca516745bfeafc49904b496089"));
+        aes.decrypt(&mut block);
+        assert_eq!(block[..], hex("00112233445566778899aabbccddeeff"));
+    }
+
+    // IEEE 1619-2007 XTS-AES-256 vector 10, a 512 byte data unit
+    #[test]
+    fn xts_known_answer() {
+        let key = |s| Aes256::new(&hex(s).try_into().unwrap());
+        let contents = ContentsKey {
+            data: key("2718281828459045235360287471352662497757247093699959574966967627"),
+            tweak: key("3141592653589793238462643383279502884197169399375105820974944592"),
+        };
+        let mut buf = hex(concat!(
+            "1c3b3a102f770386e4836c99e370cf9bea00803f5e482357a4ae12d414a3e63b",
+            "5d31e276f8fe4a8d66b317f9ac683f44680a86ac35adfc3345befecb4bb188fd",
+            "5776926c49a3095eb108fd1098baec70aaa66999a72a82f27d848b21d4a741b0",
+            "c5cd4d5fff9dac89aeba122961d03a757123e9870f8acf1000020887891429ca",
+            "2a3e7a7d7df7b10355165c8b9a6d0a7de8b062c4500dc4cd120c0f7418dae3d0",
+            "b5781c34803fa75421c790dfe1de1834f280d7667b327f6c8cd7557e12ac3a0f",
+            "93ec05c52e0493ef31a12d3d9260f79a289d6a379bc70c50841473d1a8cc81ec",
+            "583e9645e07b8d9670655ba5bbcfecc6dc3966380ad8fecb17b6ba02469a020a",
+            "84e18e8f84252070c13e9f1f289be54fbc481457778f616015e1327a02b140f1",
+            "505eb309326d68378f8374595c849d84f4c333ec4423885143cb47bd71c5edae",
+            "9be69a2ffeceb1bec9de244fbe15992b11b77c040f12bd8f6a975a44a0f90c29",
+            "a9abc3d4d893927284c58754cce294529f8614dcd2aba991925fedc4ae74ffac",
+            "6e333b93eb4aff0479da9a410e4450e0dd7ae4c6e2910900575da401fc07059f",
+            "645e8b7e9bfdef33943054ff84011493c27b3429eaedb4ed5376441a77ed4385",
+            "1ad77f16f541dfd269d50d6a5f14fb0aab1cbb4c1550be97f7ab4066193c4caa",
+            "773dad38014bd2092fa755c824bb5e54c4f36ffda9fcea70b9c6e693e148c151",
+        ));
+        contents.decrypt(0xff, &mut buf);
+        let plain: Vec<u8> = (0..512).map(|i| i as u8).collect();
+        assert_eq!(buf, plain);
+    }
+
+    // RFC 4231 test cases 2 and 6, the latter with a key hashed first
+    #[test]
+    fn hmac_sha512_known_answer() {
+        assert_eq!(
+            hmac_sha512(b"Jefe", b"what do ya want for nothing?")[..],
+            hex(concat!(
+                "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea250554",
+                "9758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737",
+            ))
+        );
+        assert_eq!(
+            hmac_sha512(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )[..],
+            hex(concat!(
+                "80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f352",
+                "6b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598",
+            ))
+        );
+    }
+
+    // the inputs of RFC 5869 test cases 1 and 3, which only has SHA-256
+    // outputs, through SHA-512
+    #[test]
+    fn hkdf_sha512_known_answer() {
+        let ikm = [0x0b; 22];
+        let salt: Vec<u8> = (0x00..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+        let prk = hmac_sha512(&salt, &ikm);
+        assert_eq!(
+            hkdf_expand(&prk, &info, 42),
+            hex(concat!(
+                "832390086cda71fb47625bb5ceb168e4c8e26a1a16ed34d9fc7fe92c14815793",
+                "38da362cb8d9f925d7cb",
+            ))
+        );
+        // no salt is a zero one, as fscrypt extracts its master keys
+        assert_eq!(
+            hkdf_expand(&MasterKey::new(&ikm).prk, &[], 42),
+            hex(concat!(
+                "f5fa02b18298a72a8c23898a8703472c6eb179dc204c03425c970e3b164bf90f",
+                "ff22d04836d0e2343bac",
+            ))
+        );
+    }
+}
@@ -0,0 +1,155 @@
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use positioned_io::ReadAt;
+
+/// Wraps a device with an overall deadline and a per read time budget,
+/// so a session over a slow backend fails with `TimedOut` instead of
+/// stalling a pipeline. With either set, each read runs on a thread of
+/// its own and is given up on, left to finish into a buffer nobody reads,
+/// once its budget or the deadline runs out.
+pub struct Deadline<R> {
+    inner: Arc<R>,
+    deadline: Option<Instant>,
+    op_timeout: Option<Duration>,
+}
+
+impl<R: ReadAt> Deadline<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            deadline: None,
+            op_timeout: None,
+        }
+    }
+
+    /// fail every read not done `total` from now
+    pub fn total(mut self, total: Duration) -> Self {
+        self.deadline = Some(Instant::now() + total);
+        self
+    }
+
+    /// fail any single read taking longer than `timeout`
+    pub fn per_read(mut self, timeout: Duration) -> Self {
+        self.op_timeout = Some(timeout);
+        self
+    }
+
+    /// The device, unless reads given up on still hold it.
+    pub fn into_inner(self) -> Option<R> {
+        Arc::into_inner(self.inner)
+    }
+}
+
+impl<R: ReadAt + Send + Sync + 'static> ReadAt for Deadline<R> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let start = Instant::now();
+        let left = self.deadline.map(|x| x.saturating_duration_since(start));
+        if left == Some(Duration::ZERO) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "deadline exceeded"));
+        }
+        let budget = match (left, self.op_timeout) {
+            (Some(a), Some(b)) => a.min(b),
+            (Some(x), None) | (None, Some(x)) => x,
+            (None, None) => return self.inner.read_at(pos, buf),
+        };
+        let (tx, rx) = mpsc::channel();
+        let inner = Arc::clone(&self.inner);
+        let len = buf.len();
+        thread::spawn(move || {
+            let mut data = vec![0; len];
+            let result = inner.read_at(pos, &mut data).map(|n| {
+                data.truncate(n);
+                data
+            });
+            // let go of the device before the caller can see the read done
+            drop(inner);
+            // the reader may have given up already
+            let _ = tx.send(result);
+        });
+        match rx.recv_timeout(budget) {
+            Ok(result) => {
+                let data = result?;
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            Err(RecvTimeoutError::Timeout) if Some(budget) == left => {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "deadline exceeded"))
+            }
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("read of {len} bytes at {pos} timed out"),
+            )),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::other(format!(
+                "read of {len} bytes at {pos} panicked"
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes equal to their offset, `delay` after being asked for.
+    struct Slow {
+        delay: Duration,
+    }
+
+    impl ReadAt for Slow {
+        fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(self.delay);
+            for (i, x) in buf.iter_mut().enumerate() {
+                *x = (pos as usize + i) as u8;
+            }
+            Ok(buf.len())
+        }
+    }
+
+    fn slow(millis: u64) -> Slow {
+        Slow {
+            delay: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn reads_within_budget() {
+        let dev = Deadline::new(slow(0))
+            .total(Duration::from_secs(60))
+            .per_read(Duration::from_secs(60));
+        let mut buf = [0; 4];
+        assert_eq!(dev.read_at(3, &mut buf).unwrap(), 4);
+        assert_eq!(buf, [3, 4, 5, 6]);
+        assert!(dev.into_inner().is_some());
+    }
+
+    #[test]
+    fn slow_reads_are_given_up_on() {
+        let dev = Deadline::new(slow(10_000)).per_read(Duration::from_millis(50));
+        let start = Instant::now();
+        let e = dev.read_at(0, &mut [0; 4]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(e.to_string(), "read of 4 bytes at 0 timed out");
+        // not waiting for the read to return
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // still held by the read given up on
+        assert!(dev.into_inner().is_none());
+    }
+
+    #[test]
+    fn deadline_cuts_reads_short() {
+        let dev = Deadline::new(slow(10_000))
+            .total(Duration::from_millis(50))
+            .per_read(Duration::from_secs(60));
+        let start = Instant::now();
+        let e = dev.read_at(0, &mut [0; 4]).unwrap_err();
+        assert_eq!(e.to_string(), "deadline exceeded");
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // and every read after it
+        let e = dev.read_at(0, &mut [0; 4]).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::TimedOut);
+    }
+}
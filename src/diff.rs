@@ -0,0 +1,233 @@
+//! What changed between two images of the same tree, before and after
+//! snapshots say: the paths added, removed and modified.
+
+use std::collections::{BTreeMap, HashMap};
+
+use positioned_io::ReadAt;
+
+use crate::digest::{Digest, HashAlgorithm};
+use crate::{Ext4Fs, FileType, Inode, InodeNumber, Result, TypedInode, WalkError, WalkSummary};
+
+/// What is compared of the paths both images have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DiffMode {
+    /// mode, owners, size, times and link counts, no content is read
+    Metadata,
+    /// the digests of file content, whatever their metadata
+    Content,
+    #[default]
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+impl ChangeKind {
+    /// The letter of `git diff --name-status`.
+    pub fn letter(self) -> char {
+        match self {
+            Self::Added => 'A',
+            Self::Removed => 'D',
+            Self::Modified => 'M',
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Modified => "modified",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub path: String,
+    pub kind: ChangeKind,
+    /// what differs of a modified path: type, mode, uid, gid, size,
+    /// mtime, links, device, target or content
+    pub fields: Vec<&'static str>,
+}
+
+#[derive(Debug, Default)]
+pub struct Diff {
+    /// in path order
+    pub changes: Vec<Change>,
+    /// the walk of the old image, with the files whose content couldn't
+    /// be read among its errors
+    pub old: WalkSummary,
+    pub new: WalkSummary,
+}
+
+/// The entries of one image by path, and the digests of its files by
+/// inode so hard links are read once.
+struct Side<'a, T: ReadAt> {
+    fs: &'a Ext4Fs<T>,
+    entries: BTreeMap<String, (InodeNumber, Inode)>,
+    summary: WalkSummary,
+    digests: HashMap<InodeNumber, Vec<Digest>>,
+}
+
+impl<'a, T: ReadAt> Side<'a, T> {
+    fn new(fs: &'a Ext4Fs<T>, path: &str) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        let summary = fs.walk(path, true, |entry| {
+            entries.insert(
+                entry.path.clone(),
+                (entry.inode_number, entry.inode.clone()),
+            );
+            Ok(())
+        })?;
+        Ok(Side {
+            fs,
+            entries,
+            summary,
+            digests: HashMap::new(),
+        })
+    }
+
+    /// The sha256 of a file, None if it couldn't be read, the failure
+    /// then recorded.
+    fn digest(&mut self, path: &str, n: InodeNumber, inode: &Inode) -> Option<&[Digest]> {
+        if !self.digests.contains_key(&n) {
+            match self.fs.digests(inode, &[HashAlgorithm::Sha256]) {
+                Ok(x) => {
+                    self.digests.insert(n, x);
+                }
+                Err(error) => {
+                    self.summary.errors.push(WalkError {
+                        path: path.to_string(),
+                        error,
+                    });
+                    return None;
+                }
+            }
+        }
+        self.digests.get(&n).map(|x| x.as_slice())
+    }
+
+    /// Whether `path` is below, or is, a path the walk failed at: what is
+    /// missing there is unknown rather than removed.
+    fn failed_at(&self, path: &str) -> bool {
+        self.summary.errors.iter().any(|x| {
+            path.strip_prefix(x.path.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Compare `path` and everything below it in this image, the old one,
+    /// with the same path in `other`, the new one. Entries that can't be
+    /// read on either side are recorded in the summaries and left out of
+    /// the changes, along with everything below them.
+    pub fn diff<U: ReadAt>(&self, other: &Ext4Fs<U>, path: &str, mode: DiffMode) -> Result<Diff> {
+        let root = format!("/{}", path.trim_matches('/'));
+        let mut old = Side::new(self, &root)?;
+        let mut new = Side::new(other, &root)?;
+        // the entries are taken out so both sides can be read while
+        // going through them
+        let old_entries = std::mem::take(&mut old.entries);
+        let new_entries = std::mem::take(&mut new.entries);
+        let mut changes = Vec::new();
+        for (path, (n, inode)) in &old_entries {
+            let change = match new_entries.get(path) {
+                Some((m, other)) => {
+                    let fields = compare(&mut old, &mut new, path, (*n, inode), (*m, other), mode);
+                    if fields.is_empty() {
+                        continue;
+                    }
+                    (ChangeKind::Modified, fields)
+                }
+                None if new.failed_at(path) => continue,
+                None => (ChangeKind::Removed, Vec::new()),
+            };
+            changes.push(Change {
+                path: path.clone(),
+                kind: change.0,
+                fields: change.1,
+            });
+        }
+        for path in new_entries.keys() {
+            if !old_entries.contains_key(path) && !old.failed_at(path) {
+                changes.push(Change {
+                    path: path.clone(),
+                    kind: ChangeKind::Added,
+                    fields: Vec::new(),
+                });
+            }
+        }
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(Diff {
+            changes,
+            old: old.summary,
+            new: new.summary,
+        })
+    }
+}
+
+/// The fields that differ between the old and new inode at `path`. A
+/// change of type says it all.
+fn compare<T: ReadAt, U: ReadAt>(
+    old: &mut Side<T>,
+    new: &mut Side<U>,
+    path: &str,
+    (n, a): (InodeNumber, &Inode),
+    (m, b): (InodeNumber, &Inode),
+    mode: DiffMode,
+) -> Vec<&'static str> {
+    if a.file_type() != b.file_type() {
+        return vec!["type"];
+    }
+    let mut fields = Vec::new();
+    if mode != DiffMode::Content {
+        for (name, differs) in [
+            ("mode", a.mode != b.mode),
+            ("uid", a.uid != b.uid),
+            ("gid", a.gid != b.gid),
+            ("size", a.size != b.size),
+            ("mtime", a.mtime != b.mtime),
+            ("links", a.links_count != b.links_count),
+            ("device", a.device() != b.device()),
+        ] {
+            if differs {
+                fields.push(name);
+            }
+        }
+    }
+    match (TypedInode::new(a.clone()), TypedInode::new(b.clone())) {
+        (TypedInode::Symlink(x), TypedInode::Symlink(y)) => {
+            let x = x.target(&old.fs.sb, &old.fs.dev);
+            let y = y.target(&new.fs.sb, &new.fs.dev);
+            if let (Ok(x), Ok(y)) = (&x, &y) {
+                if x != y {
+                    fields.push("target");
+                }
+            }
+            for (summary, result) in [(&mut old.summary, x), (&mut new.summary, y)] {
+                if let Err(error) = result {
+                    let path = path.to_string();
+                    summary.errors.push(WalkError { path, error });
+                }
+            }
+        }
+        _ if mode == DiffMode::Metadata || a.file_type() != FileType::Regular => {}
+        // content of another length differs without reading it
+        _ if a.size != b.size => fields.push("content"),
+        _ => {
+            let x = old.digest(path, n, a).map(|x| x.to_vec());
+            let y = new.digest(path, m, b);
+            if let (Some(x), Some(y)) = (x, y) {
+                if x != y {
+                    fields.push("content");
+                }
+            }
+        }
+    }
+    fields
+}
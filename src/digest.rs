@@ -0,0 +1,1086 @@
+//! Digests of file content for hash sets and manifests. Every algorithm
+//! is streaming, so that several of them can be fed from a single read
+//! of the data.
+
+use std::fmt;
+use std::str::FromStr;
+
+use failure::{bail, Error};
+use positioned_io::ReadAt;
+
+use crate::progress::{NoProgress, ProgressTracker};
+use crate::{Ext4Fs, Inode, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    Blake3,
+    /// XXH3 64 bits, not cryptographic but fast
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [Self; 6] = [
+        Self::Md5,
+        Self::Sha1,
+        Self::Sha256,
+        Self::Sha512,
+        Self::Blake3,
+        Self::Xxh3,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Md5 => "md5",
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+            Self::Blake3 => "blake3",
+            Self::Xxh3 => "xxh3",
+        }
+    }
+
+    pub fn hasher(self) -> Box<dyn Hasher> {
+        match self {
+            Self::Md5 => Box::new(Md::new(Md5::INIT)),
+            Self::Sha1 => Box::new(Md::new(Sha1::INIT)),
+            Self::Sha256 => Box::new(Md::new(Sha256::INIT)),
+            Self::Sha512 => Box::new(Md::new(Sha512::INIT)),
+            Self::Blake3 => Box::new(Blake3::new()),
+            Self::Xxh3 => Box::new(Xxh3::new()),
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match Self::ALL.into_iter().find(|x| x.name() == s) {
+            Some(algorithm) => Ok(algorithm),
+            None => bail!("unknown hash algorithm: {}", s),
+        }
+    }
+}
+
+/// Parse a comma separated list of algorithm names, e.g. `sha256,md5`.
+pub fn parse_algorithms(list: &str) -> Result<Vec<HashAlgorithm>> {
+    let mut algorithms = Vec::new();
+    for name in list.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+        let algorithm = name.parse()?;
+        if !algorithms.contains(&algorithm) {
+            algorithms.push(algorithm);
+        }
+    }
+    if algorithms.is_empty() {
+        bail!("no hash algorithm given");
+    }
+    Ok(algorithms)
+}
+
+pub trait Hasher {
+    fn update(&mut self, data: &[u8]);
+    fn finish(self: Box<Self>) -> Vec<u8>;
+}
+
+/// A digest of some content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digest {
+    pub algorithm: HashAlgorithm,
+    pub bytes: Vec<u8>,
+}
+
+impl fmt::Display for Digest {
+    /// Lowercase hex, as `sha256sum` and friends print it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for b in &self.bytes {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Feeds the same data to several hashers.
+pub struct MultiHasher {
+    hashers: Vec<(HashAlgorithm, Box<dyn Hasher>)>,
+}
+
+impl MultiHasher {
+    pub fn new(algorithms: &[HashAlgorithm]) -> Self {
+        Self {
+            hashers: algorithms.iter().map(|&x| (x, x.hasher())).collect(),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for (_, hasher) in &mut self.hashers {
+            hasher.update(data);
+        }
+    }
+
+    /// One digest per algorithm, in the order they were given.
+    pub fn finish(self) -> Vec<Digest> {
+        self.hashers
+            .into_iter()
+            .map(|(algorithm, hasher)| Digest {
+                algorithm,
+                bytes: hasher.finish(),
+            })
+            .collect()
+    }
+}
+
+// content is hashed 1 MiB at a time
+const CHUNK: u64 = 1 << 20;
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Digests of the content of `inode` with each of `algorithms`,
+    /// reading the content once.
+    pub fn digests(&self, inode: &Inode, algorithms: &[HashAlgorithm]) -> Result<Vec<Digest>> {
+        let mut sink = NoProgress;
+        let mut tracker = ProgressTracker::new(&mut sink, inode.size);
+        self.digests_with_progress(inode, algorithms, "", &mut tracker)
+    }
+
+    /// `digests`, adding the bytes read to `tracker` as they are, with
+    /// `path` as the one being read.
+    pub fn digests_with_progress(
+        &self,
+        inode: &Inode,
+        algorithms: &[HashAlgorithm],
+        path: &str,
+        tracker: &mut ProgressTracker,
+    ) -> Result<Vec<Digest>> {
+        let reader = self.reader_of(inode)?;
+        let mut hasher = MultiHasher::new(algorithms);
+        let mut buf = vec![0u8; CHUNK.min(inode.size) as usize];
+        for pos in (0..inode.size).step_by(CHUNK as usize) {
+            let len = CHUNK.min(inode.size - pos) as usize;
+            reader.read_exact_at(pos, &mut buf[..len])?;
+            hasher.update(&buf[..len]);
+            tracker.advance(path, len as u64);
+        }
+        Ok(hasher.finish())
+    }
+}
+
+/// sha256 of `data` in one go.
+pub(crate) fn sha256(data: &[u8]) -> [u8; 32] {
+    let mut hasher = Box::new(Md::new(Sha256::INIT));
+    hasher.update(data);
+    hasher.finish().try_into().unwrap()
+}
+
+/// sha512 of `data` in one go.
+pub(crate) fn sha512(data: &[u8]) -> [u8; 64] {
+    let mut hasher = Box::new(Md::new(Sha512::INIT));
+    hasher.update(data);
+    hasher.finish().try_into().unwrap()
+}
+
+/// Compression function of a Merkle–Damgård hash: MD5 and the SHA-1/2
+/// family, which only differ in block size, word order and rounds.
+trait Compress: Copy + 'static {
+    const BLOCK: usize;
+    /// bytes of the message length closing the padding
+    const LENGTH_BYTES: usize;
+    const LITTLE_ENDIAN: bool;
+    fn compress(&mut self, block: &[u8]);
+    fn output(&self) -> Vec<u8>;
+}
+
+struct Md<C: Compress> {
+    state: C,
+    buf: Vec<u8>,
+    len: u128,
+}
+
+impl<C: Compress> Md<C> {
+    fn new(state: C) -> Self {
+        Self {
+            state,
+            buf: Vec::with_capacity(C::BLOCK),
+            len: 0,
+        }
+    }
+}
+
+impl<C: Compress> Hasher for Md<C> {
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u128;
+        if !self.buf.is_empty() {
+            let take = (C::BLOCK - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buf.len() < C::BLOCK {
+                return;
+            }
+            let block = std::mem::take(&mut self.buf);
+            self.state.compress(&block);
+            self.buf = block;
+            self.buf.clear();
+        }
+        let mut blocks = data.chunks_exact(C::BLOCK);
+        for block in &mut blocks {
+            self.state.compress(block);
+        }
+        self.buf.extend_from_slice(blocks.remainder());
+    }
+
+    fn finish(mut self: Box<Self>) -> Vec<u8> {
+        let bits = self.len * 8;
+        let mut tail = std::mem::take(&mut self.buf);
+        tail.push(0x80);
+        while tail.len() % C::BLOCK != C::BLOCK - C::LENGTH_BYTES {
+            tail.push(0);
+        }
+        if C::LITTLE_ENDIAN {
+            tail.extend(&bits.to_le_bytes()[..C::LENGTH_BYTES]);
+        } else {
+            tail.extend(&bits.to_be_bytes()[16 - C::LENGTH_BYTES..]);
+        }
+        for block in tail.chunks_exact(C::BLOCK) {
+            self.state.compress(block);
+        }
+        self.state.output()
+    }
+}
+
+// floor(abs(sin(i + 1)) * 2^32)
+static MD5_K: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed, 0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+static SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+static SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+#[derive(Clone, Copy)]
+struct Md5([u32; 4]);
+
+static MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+impl Md5 {
+    const INIT: Self = Self([0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476]);
+}
+
+impl Compress for Md5 {
+    const BLOCK: usize = 64;
+    const LENGTH_BYTES: usize = 8;
+    const LITTLE_ENDIAN: bool = true;
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut m = [0u32; 16];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            m[i] = u32::from_le_bytes(word.try_into().unwrap());
+        }
+        let [mut a, mut b, mut c, mut d] = self.0;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => (b & c | !b & d, i),
+                1 => (d & b | !d & c, (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let f = f.wrapping_add(a).wrapping_add(MD5_K[i]).wrapping_add(m[g]);
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(MD5_SHIFTS[i / 16 * 4 + i % 4]));
+        }
+        for (x, y) in self.0.iter_mut().zip([a, b, c, d]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+
+    fn output(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sha1([u32; 5]);
+
+impl Sha1 {
+    const INIT: Self = Self([0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0]);
+}
+
+impl Compress for Sha1 {
+    const BLOCK: usize = 64;
+    const LENGTH_BYTES: usize = 8;
+    const LITTLE_ENDIAN: bool = false;
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = self.0;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i / 20 {
+                0 => (b & c | !b & d, 0x5a827999),
+                1 => (b ^ c ^ d, 0x6ed9eba1),
+                2 => (b & c | b & d | c & d, 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+        for (x, y) in self.0.iter_mut().zip([a, b, c, d, e]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+
+    fn output(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|x| x.to_be_bytes()).collect()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sha256([u32; 8]);
+
+impl Sha256 {
+    const INIT: Self = Self([
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ]);
+}
+
+impl Compress for Sha256 {
+    const BLOCK: usize = 64;
+    const LENGTH_BYTES: usize = 8;
+    const LITTLE_ENDIAN: bool = false;
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ w[i - 15] >> 3;
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ w[i - 2] >> 10;
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.0;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = e & f ^ !e & g;
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA256_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = a & b ^ a & c ^ b & c;
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (x, y) in self.0.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+
+    fn output(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|x| x.to_be_bytes()).collect()
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Sha512([u64; 8]);
+
+impl Sha512 {
+    const INIT: Self = Self([
+        0x6a09e667f3bcc908,
+        0xbb67ae8584caa73b,
+        0x3c6ef372fe94f82b,
+        0xa54ff53a5f1d36f1,
+        0x510e527fade682d1,
+        0x9b05688c2b3e6c1f,
+        0x1f83d9abfb41bd6b,
+        0x5be0cd19137e2179,
+    ]);
+}
+
+impl Compress for Sha512 {
+    const BLOCK: usize = 128;
+    const LENGTH_BYTES: usize = 16;
+    const LITTLE_ENDIAN: bool = false;
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u64; 80];
+        for (i, word) in block.chunks_exact(8).enumerate() {
+            w[i] = u64::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ w[i - 15] >> 7;
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ w[i - 2] >> 6;
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.0;
+        for i in 0..80 {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = e & f ^ !e & g;
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(SHA512_K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = a & b ^ a & c ^ b & c;
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (x, y) in self.0.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *x = x.wrapping_add(y);
+        }
+    }
+
+    fn output(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|x| x.to_be_bytes()).collect()
+    }
+}
+
+static BLAKE3_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+static BLAKE3_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+// domain separation flags
+const CHUNK_START: u32 = 1;
+const CHUNK_END: u32 = 2;
+const PARENT: u32 = 4;
+const ROOT: u32 = 8;
+const BLAKE3_CHUNK: usize = 1024;
+
+fn blake3_compress(
+    cv: &[u32; 8],
+    block: &[u32; 16],
+    counter: u64,
+    len: u32,
+    flags: u32,
+) -> [u32; 16] {
+    let mut s = [0u32; 16];
+    s[..8].copy_from_slice(cv);
+    s[8..12].copy_from_slice(&BLAKE3_IV[..4]);
+    s[12] = counter as u32;
+    s[13] = (counter >> 32) as u32;
+    s[14] = len;
+    s[15] = flags;
+    let g = |s: &mut [u32; 16], [a, b, c, d]: [usize; 4], x: u32, y: u32| {
+        s[a] = s[a].wrapping_add(s[b]).wrapping_add(x);
+        s[d] = (s[d] ^ s[a]).rotate_right(16);
+        s[c] = s[c].wrapping_add(s[d]);
+        s[b] = (s[b] ^ s[c]).rotate_right(12);
+        s[a] = s[a].wrapping_add(s[b]).wrapping_add(y);
+        s[d] = (s[d] ^ s[a]).rotate_right(8);
+        s[c] = s[c].wrapping_add(s[d]);
+        s[b] = (s[b] ^ s[c]).rotate_right(7);
+    };
+    let mut m = *block;
+    for round in 0..7 {
+        // columns, then diagonals
+        g(&mut s, [0, 4, 8, 12], m[0], m[1]);
+        g(&mut s, [1, 5, 9, 13], m[2], m[3]);
+        g(&mut s, [2, 6, 10, 14], m[4], m[5]);
+        g(&mut s, [3, 7, 11, 15], m[6], m[7]);
+        g(&mut s, [0, 5, 10, 15], m[8], m[9]);
+        g(&mut s, [1, 6, 11, 12], m[10], m[11]);
+        g(&mut s, [2, 7, 8, 13], m[12], m[13]);
+        g(&mut s, [3, 4, 9, 14], m[14], m[15]);
+        if round < 6 {
+            m = BLAKE3_PERMUTATION.map(|i| m[i]);
+        }
+    }
+    for i in 0..8 {
+        s[i] ^= s[i + 8];
+        s[i + 8] ^= cv[i];
+    }
+    s
+}
+
+fn blake3_words(block: &[u8; 64]) -> [u32; 16] {
+    let mut words = [0u32; 16];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        words[i] = u32::from_le_bytes(word.try_into().unwrap());
+    }
+    words
+}
+
+/// The last compression of a chunk or parent, held back until we know
+/// whether it is the root.
+struct Blake3Output {
+    cv: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    len: u32,
+    flags: u32,
+}
+
+impl Blake3Output {
+    fn parent(left: [u32; 8], right: [u32; 8]) -> Self {
+        let mut block = [0u32; 16];
+        block[..8].copy_from_slice(&left);
+        block[8..].copy_from_slice(&right);
+        Self {
+            cv: BLAKE3_IV,
+            block,
+            counter: 0,
+            len: 64,
+            flags: PARENT,
+        }
+    }
+
+    fn chaining_value(&self) -> [u32; 8] {
+        let out = blake3_compress(&self.cv, &self.block, self.counter, self.len, self.flags);
+        out[..8].try_into().unwrap()
+    }
+
+    fn root(&self) -> Vec<u8> {
+        let out = blake3_compress(&self.cv, &self.block, 0, self.len, self.flags | ROOT);
+        out[..8].iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+}
+
+struct Blake3Chunk {
+    cv: [u32; 8],
+    counter: u64,
+    block: [u8; 64],
+    block_len: usize,
+    compressed: usize,
+}
+
+impl Blake3Chunk {
+    fn new(counter: u64) -> Self {
+        Self {
+            cv: BLAKE3_IV,
+            counter,
+            block: [0; 64],
+            block_len: 0,
+            compressed: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        64 * self.compressed + self.block_len
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.compressed == 0 {
+            CHUNK_START
+        } else {
+            0
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // the last block is compressed by `output`
+            if self.block_len == 64 {
+                let words = blake3_words(&self.block);
+                let out = blake3_compress(&self.cv, &words, self.counter, 64, self.start_flag());
+                self.cv = out[..8].try_into().unwrap();
+                self.compressed += 1;
+                self.block = [0; 64];
+                self.block_len = 0;
+            }
+            let take = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+        }
+    }
+
+    fn output(&self) -> Blake3Output {
+        Blake3Output {
+            cv: self.cv,
+            block: blake3_words(&self.block),
+            counter: self.counter,
+            len: self.block_len as u32,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+/// BLAKE3 hashing 1 KiB chunks into a binary tree, kept as the stack of
+/// chaining values of complete subtrees.
+struct Blake3 {
+    chunk: Blake3Chunk,
+    stack: Vec<[u32; 8]>,
+}
+
+impl Blake3 {
+    fn new() -> Self {
+        Self {
+            chunk: Blake3Chunk::new(0),
+            stack: Vec::new(),
+        }
+    }
+}
+
+impl Hasher for Blake3 {
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // a full chunk is only closed once more data follows, the
+            // last one may be the root
+            if self.chunk.len() == BLAKE3_CHUNK {
+                let mut cv = self.chunk.output().chaining_value();
+                let mut chunks = self.chunk.counter + 1;
+                // merge subtrees completed by this chunk
+                while chunks & 1 == 0 {
+                    cv = Blake3Output::parent(self.stack.pop().unwrap(), cv).chaining_value();
+                    chunks >>= 1;
+                }
+                self.stack.push(cv);
+                self.chunk = Blake3Chunk::new(self.chunk.counter + 1);
+            }
+            let take = (BLAKE3_CHUNK - self.chunk.len()).min(data.len());
+            self.chunk.update(&data[..take]);
+            data = &data[take..];
+        }
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        let mut output = self.chunk.output();
+        for &cv in self.stack.iter().rev() {
+            output = Blake3Output::parent(cv, output.chaining_value());
+        }
+        output.root()
+    }
+}
+
+// the default secret keying XXH3
+static XXH3_SECRET: [u8; 192] = [
+    0xb8, 0xfe, 0x6c, 0x39, 0x23, 0xa4, 0x4b, 0xbe, 0x7c, 0x01, 0x81, 0x2c, 0xf7, 0x21, 0xad, 0x1c,
+    0xde, 0xd4, 0x6d, 0xe9, 0x83, 0x90, 0x97, 0xdb, 0x72, 0x40, 0xa4, 0xa4, 0xb7, 0xb3, 0x67, 0x1f,
+    0xcb, 0x79, 0xe6, 0x4e, 0xcc, 0xc0, 0xe5, 0x78, 0x82, 0x5a, 0xd0, 0x7d, 0xcc, 0xff, 0x72, 0x21,
+    0xb8, 0x08, 0x46, 0x74, 0xf7, 0x43, 0x24, 0x8e, 0xe0, 0x35, 0x90, 0xe6, 0x81, 0x3a, 0x26, 0x4c,
+    0x3c, 0x28, 0x52, 0xbb, 0x91, 0xc3, 0x00, 0xcb, 0x88, 0xd0, 0x65, 0x8b, 0x1b, 0x53, 0x2e, 0xa3,
+    0x71, 0x64, 0x48, 0x97, 0xa2, 0x0d, 0xf9, 0x4e, 0x38, 0x19, 0xef, 0x46, 0xa9, 0xde, 0xac, 0xd8,
+    0xa8, 0xfa, 0x76, 0x3f, 0xe3, 0x9c, 0x34, 0x3f, 0xf9, 0xdc, 0xbb, 0xc7, 0xc7, 0x0b, 0x4f, 0x1d,
+    0x8a, 0x51, 0xe0, 0x4b, 0xcd, 0xb4, 0x59, 0x31, 0xc8, 0x9f, 0x7e, 0xc9, 0xd9, 0x78, 0x73, 0x64,
+    0xea, 0xc5, 0xac, 0x83, 0x34, 0xd3, 0xeb, 0xc3, 0xc5, 0x81, 0xa0, 0xff, 0xfa, 0x13, 0x63, 0xeb,
+    0x17, 0x0d, 0xdd, 0x51, 0xb7, 0xf0, 0xda, 0x49, 0xd3, 0x16, 0x55, 0x26, 0x29, 0xd4, 0x68, 0x9e,
+    0x2b, 0x16, 0xbe, 0x58, 0x7d, 0x47, 0xa1, 0xfc, 0x8f, 0xf8, 0xb8, 0xd1, 0x7a, 0xd0, 0x31, 0xce,
+    0x45, 0xcb, 0x3a, 0x8f, 0x95, 0x16, 0x04, 0x28, 0xaf, 0xd7, 0xfb, 0xca, 0xbb, 0x4b, 0x40, 0x7e,
+];
+
+const PRIME32_1: u64 = 0x9e3779b1;
+const PRIME32_2: u64 = 0x85ebca77;
+const PRIME32_3: u64 = 0xc2b2ae3d;
+const PRIME64_1: u64 = 0x9e3779b185ebca87;
+const PRIME64_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME64_3: u64 = 0x165667b19e3779f9;
+const PRIME64_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME64_5: u64 = 0x27d4eb2f165667c5;
+const PRIME_MX1: u64 = 0x165667919e3779f9;
+const PRIME_MX2: u64 = 0x9fb21c651e98df25;
+const XXH3_STRIPE: usize = 64;
+// stripes between scrambles with the default secret
+const XXH3_STRIPES_PER_BLOCK: usize = (XXH3_SECRET.len() - XXH3_STRIPE) / 8;
+const XXH3_BLOCK: usize = XXH3_STRIPE * XXH3_STRIPES_PER_BLOCK;
+// longer inputs take the striped path
+const XXH3_MIDSIZE_MAX: usize = 240;
+
+fn secret(at: usize) -> u64 {
+    u64::from_le_bytes(XXH3_SECRET[at..at + 8].try_into().unwrap())
+}
+
+fn read64(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+fn read32(data: &[u8], at: usize) -> u64 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap()) as u64
+}
+
+fn fold64(a: u64, b: u64) -> u64 {
+    let product = a as u128 * b as u128;
+    product as u64 ^ (product >> 64) as u64
+}
+
+fn xxh64_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 33;
+    h = h.wrapping_mul(PRIME64_2);
+    h ^= h >> 29;
+    h = h.wrapping_mul(PRIME64_3);
+    h ^ h >> 32
+}
+
+fn xxh3_avalanche(mut h: u64) -> u64 {
+    h ^= h >> 37;
+    h = h.wrapping_mul(PRIME_MX1);
+    h ^ h >> 32
+}
+
+fn xxh3_mix16(data: &[u8], at: usize, secret_at: usize) -> u64 {
+    fold64(
+        read64(data, at) ^ secret(secret_at),
+        read64(data, at + 8) ^ secret(secret_at + 8),
+    )
+}
+
+/// XXH3 of inputs up to `XXH3_MIDSIZE_MAX` bytes, each size range having
+/// its own mixing.
+fn xxh3_short(data: &[u8]) -> u64 {
+    let len = data.len();
+    match len {
+        0 => xxh64_avalanche(secret(56) ^ secret(64)),
+        1..=3 => {
+            let combined = (data[0] as u64) << 16
+                | (data[len >> 1] as u64) << 24
+                | data[len - 1] as u64
+                | (len as u64) << 8;
+            xxh64_avalanche(combined ^ (read32(&XXH3_SECRET, 0) ^ read32(&XXH3_SECRET, 4)))
+        }
+        4..=8 => {
+            let input = read32(data, len - 4).wrapping_add(read32(data, 0) << 32);
+            let mut h = input ^ (secret(8) ^ secret(16));
+            h ^= h.rotate_left(49) ^ h.rotate_left(24);
+            h = h.wrapping_mul(PRIME_MX2);
+            h ^= (h >> 35).wrapping_add(len as u64);
+            h = h.wrapping_mul(PRIME_MX2);
+            h ^ h >> 28
+        }
+        9..=16 => {
+            let lo = read64(data, 0) ^ (secret(24) ^ secret(32));
+            let hi = read64(data, len - 8) ^ (secret(40) ^ secret(48));
+            let acc = (len as u64)
+                .wrapping_add(lo.swap_bytes())
+                .wrapping_add(hi)
+                .wrapping_add(fold64(lo, hi));
+            xxh3_avalanche(acc)
+        }
+        17..=128 => {
+            let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+            // pairs of 16 byte lanes from both ends, more pairs for
+            // longer inputs
+            let pairs = (len - 1) / 32;
+            for i in (0..=pairs).rev() {
+                acc = acc
+                    .wrapping_add(xxh3_mix16(data, 16 * i, 32 * i))
+                    .wrapping_add(xxh3_mix16(data, len - 16 * (i + 1), 32 * i + 16));
+            }
+            xxh3_avalanche(acc)
+        }
+        _ => {
+            let mut acc = (len as u64).wrapping_mul(PRIME64_1);
+            for i in 0..8 {
+                acc = acc.wrapping_add(xxh3_mix16(data, 16 * i, 16 * i));
+            }
+            acc = xxh3_avalanche(acc);
+            for i in 8..len / 16 {
+                acc = acc.wrapping_add(xxh3_mix16(data, 16 * i, 16 * (i - 8) + 3));
+            }
+            acc = acc.wrapping_add(xxh3_mix16(data, len - 16, 136 - 17));
+            xxh3_avalanche(acc)
+        }
+    }
+}
+
+fn xxh3_accumulate(acc: &mut [u64; 8], stripe: &[u8], secret_at: usize) {
+    for i in 0..8 {
+        let value = read64(stripe, 8 * i);
+        let key = value ^ secret(secret_at + 8 * i);
+        acc[i ^ 1] = acc[i ^ 1].wrapping_add(value);
+        acc[i] = acc[i].wrapping_add((key & 0xffffffff).wrapping_mul(key >> 32));
+    }
+}
+
+/// XXH3 of longer inputs: 64 byte stripes accumulated into eight lanes,
+/// scrambled after every block. The last stripe always ends the input.
+struct Xxh3 {
+    acc: [u64; 8],
+    /// input not yet accumulated, never a full block: whether a block is
+    /// the last one is only known once more input follows
+    pending: Vec<u8>,
+    /// end of the last accumulated block, for a last stripe reaching
+    /// back into it
+    previous: [u8; XXH3_STRIPE],
+    len: u64,
+}
+
+impl Xxh3 {
+    fn new() -> Self {
+        Self {
+            acc: [
+                PRIME32_3, PRIME64_1, PRIME64_2, PRIME64_3, PRIME64_4, PRIME32_2, PRIME64_5,
+                PRIME32_1,
+            ],
+            pending: Vec::new(),
+            previous: [0; XXH3_STRIPE],
+            len: 0,
+        }
+    }
+}
+
+impl Hasher for Xxh3 {
+    fn update(&mut self, data: &[u8]) {
+        self.len += data.len() as u64;
+        self.pending.extend_from_slice(data);
+        let mut done = 0;
+        while self.pending.len() - done > XXH3_BLOCK {
+            let block = &self.pending[done..done + XXH3_BLOCK];
+            for (i, stripe) in block.chunks_exact(XXH3_STRIPE).enumerate() {
+                xxh3_accumulate(&mut self.acc, stripe, 8 * i);
+            }
+            let last_key = XXH3_SECRET.len() - XXH3_STRIPE;
+            for (i, lane) in self.acc.iter_mut().enumerate() {
+                *lane = (*lane ^ *lane >> 47 ^ secret(last_key + 8 * i)).wrapping_mul(PRIME32_1);
+            }
+            self.previous
+                .copy_from_slice(&block[XXH3_BLOCK - XXH3_STRIPE..]);
+            done += XXH3_BLOCK;
+        }
+        self.pending.drain(..done);
+    }
+
+    fn finish(self: Box<Self>) -> Vec<u8> {
+        let pending = &self.pending;
+        if self.len as usize <= XXH3_MIDSIZE_MAX {
+            return xxh3_short(pending).to_be_bytes().to_vec();
+        }
+        let mut acc = self.acc;
+        let stripes = (pending.len() - 1) / XXH3_STRIPE;
+        for i in 0..stripes {
+            xxh3_accumulate(&mut acc, &pending[XXH3_STRIPE * i..], 8 * i);
+        }
+        let mut last = [0u8; XXH3_STRIPE];
+        if pending.len() >= XXH3_STRIPE {
+            last.copy_from_slice(&pending[pending.len() - XXH3_STRIPE..]);
+        } else {
+            let from_previous = XXH3_STRIPE - pending.len();
+            last[..from_previous].copy_from_slice(&self.previous[pending.len()..]);
+            last[from_previous..].copy_from_slice(pending);
+        }
+        xxh3_accumulate(&mut acc, &last, XXH3_SECRET.len() - XXH3_STRIPE - 7);
+        let mut h = self.len.wrapping_mul(PRIME64_1);
+        for i in 0..4 {
+            h = h.wrapping_add(fold64(
+                acc[2 * i] ^ secret(11 + 16 * i),
+                acc[2 * i + 1] ^ secret(11 + 16 * i + 8),
+            ));
+        }
+        xxh3_avalanche(h).to_be_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(algorithm: HashAlgorithm, pieces: &[&[u8]]) -> String {
+        let mut hasher = MultiHasher::new(&[algorithm]);
+        for piece in pieces {
+            hasher.update(piece);
+        }
+        hasher.finish()[0].to_string()
+    }
+
+    #[test]
+    fn known_answers() {
+        use HashAlgorithm::*;
+        let million = vec![b'a'; 1_000_000];
+        let cases = [
+            (
+                Md5,
+                [
+                    "d41d8cd98f00b204e9800998ecf8427e",
+                    "900150983cd24fb0d6963f7d28e17f72",
+                    "7707d6ae4e027c70eea2a935c2296f21",
+                ],
+            ),
+            (
+                Sha1,
+                [
+                    "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+                    "a9993e364706816aba3e25717850c26c9cd0d89d",
+                    "34aa973cd4c4daa4f61eeb2bdbad27316534016f",
+                ],
+            ),
+            (
+                Sha256,
+                [
+                    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                    "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+                    "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0",
+                ],
+            ),
+            (
+                Sha512,
+                [
+                    concat!(
+                        "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce",
+                        "47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e",
+                    ),
+                    concat!(
+                        "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a",
+                        "2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+                    ),
+                    concat!(
+                        "e718483d0ce769644e2e42c7bc15b4638e1f98b13b2044285632a803afa973eb",
+                        "de0ff244877ea60a4cb0432ce577c31beb009c5c2c49aa2e4eadb217ad8cc09b",
+                    ),
+                ],
+            ),
+            (
+                Blake3,
+                [
+                    "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+                    "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+                    "616f575a1b58d4c9797d4217b9730ae5e6eb319d76edef6549b46f4efe31ff8b",
+                ],
+            ),
+            (
+                Xxh3,
+                ["2d06800538d394c2", "78af5f94892f3950", "b1fd6fae5285c4eb"],
+            ),
+        ];
+        for (algorithm, [empty, abc, a_million]) in cases {
+            assert_eq!(digest(algorithm, &[]), empty, "{}", algorithm.name());
+            assert_eq!(digest(algorithm, &[b"abc"]), abc, "{}", algorithm.name());
+            assert_eq!(
+                digest(algorithm, &[&million]),
+                a_million,
+                "{}",
+                algorithm.name()
+            );
+            // the same, fed in pieces not lining up with any block size
+            let pieces: Vec<&[u8]> = million.chunks(997).collect();
+            assert_eq!(
+                digest(algorithm, &pieces),
+                a_million,
+                "{}",
+                algorithm.name()
+            );
+        }
+    }
+}
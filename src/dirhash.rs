@@ -0,0 +1,183 @@
+//! Hashes of directory entry names, ordering the entries of indexed
+//! directories. Which one a directory uses is recorded in its index root;
+//! whether name bytes are signed depends on the platform that created the
+//! filesystem and is recorded in the superblock flags.
+
+use failure::bail;
+
+use crate::{Result, SuperBlock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashVersion {
+    Legacy,
+    HalfMd4,
+    Tea,
+    LegacyUnsigned,
+    HalfMd4Unsigned,
+    TeaUnsigned,
+}
+
+impl HashVersion {
+    /// The hash of an index root whose hash byte is `version`. Roots only
+    /// record the signed variants, the superblock says which one is meant.
+    pub fn new(version: u8, sb: &SuperBlock) -> Result<Self> {
+        let unsigned = sb.flags & SuperBlock::FLAGS_UNSIGNED_HASH != 0;
+        Ok(match (version, unsigned) {
+            (0, false) => Self::Legacy,
+            (1, false) => Self::HalfMd4,
+            (2, false) => Self::Tea,
+            (0, true) | (3, _) => Self::LegacyUnsigned,
+            (1, true) | (4, _) => Self::HalfMd4Unsigned,
+            (2, true) | (5, _) => Self::TeaUnsigned,
+            _ => bail!("unsupported directory hash version {}", version),
+        })
+    }
+
+    /// Name as printed by dumpe2fs.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Legacy => "legacy",
+            Self::HalfMd4 => "half_md4",
+            Self::Tea => "tea",
+            Self::LegacyUnsigned => "legacy_unsigned",
+            Self::HalfMd4Unsigned => "half_md4_unsigned",
+            Self::TeaUnsigned => "tea_unsigned",
+        }
+    }
+
+    fn is_unsigned(self) -> bool {
+        matches!(
+            self,
+            Self::LegacyUnsigned | Self::HalfMd4Unsigned | Self::TeaUnsigned
+        )
+    }
+}
+
+// used when the superblock seed is all zero
+const DEFAULT_SEED: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+// end of directory marker, never a valid hash
+const EOF_HASH: u32 = 0x7fffffff << 1;
+
+/// Hash `name`, returning the major hash used by the index and the minor
+/// hash. The low bit of the major hash is always clear, the index uses it
+/// to mark hash collisions continuing in the next block.
+pub fn hash(version: HashVersion, name: &[u8], seed: [u32; 4]) -> (u32, u32) {
+    let mut buf = if seed == [0; 4] { DEFAULT_SEED } else { seed };
+    let unsigned = version.is_unsigned();
+    let (major, minor) = match version {
+        HashVersion::Legacy | HashVersion::LegacyUnsigned => (legacy(name, unsigned), 0),
+        HashVersion::HalfMd4 | HashVersion::HalfMd4Unsigned => {
+            for start in (0..name.len()).step_by(32) {
+                half_md4(&mut buf, &words::<8>(&name[start..], unsigned));
+            }
+            (buf[1], buf[2])
+        }
+        HashVersion::Tea | HashVersion::TeaUnsigned => {
+            for start in (0..name.len()).step_by(16) {
+                tea(&mut buf, &words::<4>(&name[start..], unsigned));
+            }
+            (buf[0], buf[1])
+        }
+    };
+    let major = major & !1;
+    if major == EOF_HASH {
+        (EOF_HASH - 2, minor)
+    } else {
+        (major, minor)
+    }
+}
+
+// a name byte, sign extended unless `unsigned`
+fn byte(b: u8, unsigned: bool) -> u32 {
+    if unsigned {
+        b as u32
+    } else {
+        b as i8 as i32 as u32
+    }
+}
+
+fn legacy(name: &[u8], unsigned: bool) -> u32 {
+    let (mut hash0, mut hash1) = (0x12a3fe2du32, 0x37abe8f9u32);
+    for &b in name {
+        let mut hash = hash1.wrapping_add(hash0 ^ byte(b, unsigned).wrapping_mul(7152373));
+        if hash & 0x80000000 != 0 {
+            hash = hash.wrapping_sub(0x7fffffff);
+        }
+        hash1 = hash0;
+        hash0 = hash;
+    }
+    hash0 << 1
+}
+
+/// Pack the start of what's left of the name into `N` words, padded with
+/// a pattern of the length left.
+fn words<const N: usize>(rest: &[u8], unsigned: bool) -> [u32; N] {
+    let len = rest.len() as u32;
+    let pad = (len | len << 8) * 0x10001;
+    let chunk = &rest[..rest.len().min(N * 4)];
+    let mut out = [pad; N];
+    let mut val = pad;
+    for (i, &b) in chunk.iter().enumerate() {
+        val = byte(b, unsigned).wrapping_add(val << 8);
+        if i % 4 == 3 {
+            out[i / 4] = val;
+            val = pad;
+        }
+    }
+    if !chunk.len().is_multiple_of(4) {
+        out[chunk.len() / 4] = val;
+    }
+    out
+}
+
+fn tea(buf: &mut [u32; 4], input: &[u32; 4]) {
+    const DELTA: u32 = 0x9E3779B9;
+    let (mut b0, mut b1) = (buf[0], buf[1]);
+    let [a, b, c, d] = *input;
+    let mut sum = 0u32;
+    for _ in 0..16 {
+        sum = sum.wrapping_add(DELTA);
+        b0 = b0.wrapping_add(
+            (b1 << 4).wrapping_add(a) ^ b1.wrapping_add(sum) ^ (b1 >> 5).wrapping_add(b),
+        );
+        b1 = b1.wrapping_add(
+            (b0 << 4).wrapping_add(c) ^ b0.wrapping_add(sum) ^ (b0 >> 5).wrapping_add(d),
+        );
+    }
+    buf[0] = buf[0].wrapping_add(b0);
+    buf[1] = buf[1].wrapping_add(b1);
+}
+
+/// MD4 reduced to 3 rounds of 8 steps, over 32 bytes of input.
+fn half_md4(buf: &mut [u32; 4], input: &[u32; 8]) {
+    fn f(x: u32, y: u32, z: u32) -> u32 {
+        z ^ (x & (y ^ z))
+    }
+    fn g(x: u32, y: u32, z: u32) -> u32 {
+        (x & y).wrapping_add((x ^ y) & z)
+    }
+    fn h(x: u32, y: u32, z: u32) -> u32 {
+        x ^ y ^ z
+    }
+    type Round = (fn(u32, u32, u32) -> u32, u32, [usize; 8], [u32; 4]);
+    const ROUNDS: [Round; 3] = [
+        (f, 0, [0, 1, 2, 3, 4, 5, 6, 7], [3, 7, 11, 19]),
+        (g, 0x5A827999, [1, 3, 5, 7, 0, 2, 4, 6], [3, 5, 9, 13]),
+        (h, 0x6ED9EBA1, [3, 7, 2, 6, 1, 5, 0, 4], [3, 9, 11, 15]),
+    ];
+    let mut s = *buf;
+    for (func, k, order, shifts) in ROUNDS {
+        for (step, &i) in order.iter().enumerate() {
+            // a, d, c, b take turns being updated
+            let [a, b, c, d] = [0, 1, 2, 3].map(|x| (x + 4 - step % 4) % 4);
+            s[a] = s[a]
+                .wrapping_add(func(s[b], s[c], s[d]))
+                .wrapping_add(input[i].wrapping_add(k))
+                .rotate_left(shifts[step % 4]);
+        }
+    }
+    for (x, y) in buf.iter_mut().zip(s) {
+        *x = x.wrapping_add(y);
+    }
+}
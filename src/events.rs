@@ -0,0 +1,68 @@
+use crate::{BlockMapKind, InodeNumber};
+
+/// Something wrong with the filesystem the parser could work around.
+#[derive(Debug, Clone)]
+pub enum Anomaly {
+    BadChecksum {
+        /// which structure failed, e.g. `superblock`
+        structure: &'static str,
+        /// byte offset of the structure on the device
+        offset: u64,
+        stored: u32,
+        computed: u32,
+    },
+    /// A directory entry length that doesn't fit the entry or its block.
+    /// The rest of the block was skipped.
+    SuspiciousRecLen {
+        dir: InodeNumber,
+        offset: u64,
+        rec_len: u64,
+    },
+    /// An inode on the orphan list, unlinked while still open.
+    Orphan(InodeNumber),
+    ExtentsFlagMismatch {
+        inode: InodeNumber,
+        kind: BlockMapKind,
+    },
+    /// The MMP block says another node has the filesystem mounted, so
+    /// the image may be changing underneath.
+    MountedElsewhere { node: String, sequence: u32 },
+    /// The hash index of a directory is inconsistent with its blocks.
+    /// Listings are unaffected, entries are read from the leaves.
+    DamagedHtree { dir: InodeNumber, problem: String },
+    /// An in-use inode that couldn't be decoded, skipped by the check.
+    UnreadableInode { inode: InodeNumber, error: String },
+    /// Incompat features this crate can't read, the image opened anyway
+    /// under `FeaturePolicy::Warn`.
+    UnsupportedFeatures(Vec<String>),
+}
+
+/// How much an anomaly matters, for hooks to filter on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// normal enough on a live filesystem
+    Info,
+    /// damage, or what is read may be wrong
+    Warning,
+}
+
+impl Anomaly {
+    pub fn level(&self) -> Level {
+        match self {
+            Self::Orphan(_) => Level::Info,
+            _ => Level::Warning,
+        }
+    }
+}
+
+/// Receives anomalies as they are encountered, so embedders can collect
+/// them without parsing log output.
+pub trait EventHook {
+    fn anomaly(&self, anomaly: &Anomaly);
+}
+
+impl<F: Fn(&Anomaly)> EventHook for F {
+    fn anomaly(&self, anomaly: &Anomaly) {
+        self(anomaly)
+    }
+}
@@ -0,0 +1,97 @@
+/// Rewrites the path an entry is exported under, so every export backend
+/// names its output the same way. Paths are relative to the exported root
+/// and use `/` separators. Returning `None` leaves the entry out.
+pub trait NameTransform {
+    fn transform(&self, path: &str) -> Option<String>;
+}
+
+impl<F: Fn(&str) -> Option<String>> NameTransform for F {
+    fn transform(&self, path: &str) -> Option<String> {
+        self(path)
+    }
+}
+
+/// Keep paths as they are.
+pub struct Identity;
+
+impl NameTransform for Identity {
+    fn transform(&self, path: &str) -> Option<String> {
+        Some(path.to_string())
+    }
+}
+
+/// Replace characters Windows can't store in a file name with `_`, and
+/// drop the trailing dots and spaces it silently strips.
+pub struct SanitizeWindows;
+
+impl NameTransform for SanitizeWindows {
+    fn transform(&self, path: &str) -> Option<String> {
+        let components: Vec<String> = path
+            .split('/')
+            .map(|name| {
+                let name: String = name
+                    .chars()
+                    .map(|c| match c {
+                        '<' | '>' | ':' | '"' | '\\' | '|' | '?' | '*' => '_',
+                        c if c.is_control() => '_',
+                        c => c,
+                    })
+                    .collect();
+                let trimmed = name.trim_end_matches(['.', ' ']);
+                if trimmed.is_empty() && !name.is_empty() {
+                    "_".to_string()
+                } else {
+                    trimmed.to_string()
+                }
+            })
+            .collect();
+        Some(components.join("/"))
+    }
+}
+
+/// Strip a leading directory, leaving out anything not below it.
+pub struct StripPrefix(pub String);
+
+impl NameTransform for StripPrefix {
+    fn transform(&self, path: &str) -> Option<String> {
+        let prefix = self.0.trim_matches('/');
+        if prefix.is_empty() {
+            return Some(path.to_string());
+        }
+        let rest = path.strip_prefix(prefix)?;
+        match rest.strip_prefix('/') {
+            Some(rest) => Some(rest.to_string()),
+            None if rest.is_empty() => Some(String::new()),
+            None => None,
+        }
+    }
+}
+
+/// Put every entry directly in the output root, keeping only its name.
+pub struct Flatten;
+
+impl NameTransform for Flatten {
+    fn transform(&self, path: &str) -> Option<String> {
+        Some(path.rsplit('/').next().unwrap_or(path).to_string())
+    }
+}
+
+/// Apply several transforms in order, stopping at the first that drops
+/// the entry.
+#[derive(Default)]
+pub struct Chain(pub Vec<Box<dyn NameTransform>>);
+
+impl Chain {
+    pub fn then(mut self, transform: impl NameTransform + 'static) -> Self {
+        self.0.push(Box::new(transform));
+        self
+    }
+}
+
+impl NameTransform for Chain {
+    fn transform(&self, path: &str) -> Option<String> {
+        self.0
+            .iter()
+            .try_fold(path.to_string(), |path, x| x.transform(&path))
+    }
+}
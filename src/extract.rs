@@ -0,0 +1,306 @@
+//! Copying a tree out of the image onto the host.
+
+use std::collections::HashMap;
+use std::fs::{self, File, FileTimes, OpenOptions, Permissions};
+use std::os::unix::fs::{FileExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+
+use failure::{bail, format_err};
+use positioned_io::ReadAt;
+
+use crate::export::NameTransform;
+use crate::progress::{ProgressSink, ProgressTracker};
+use crate::redact::RedactionPolicy;
+use crate::{
+    Ext4Fs, FileType, HardLinks, Inode, InodeFlags, Result, TypedInode, WalkError, WalkSummary,
+};
+
+// read at most 1 MiB at once
+const MAX_READ_BLOCKS: u64 = 256;
+
+/// What to do about entries already at the destination. Directories are
+/// always merged into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overwrite {
+    /// replace them, symlinks included rather than written through
+    #[default]
+    Replace,
+    /// leave them as they are, and don't extract over them
+    Skip,
+    /// fail for each of them
+    Refuse,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ExtractOptions {
+    pub overwrite: Overwrite,
+    /// restore the permissions and the access and modification times of
+    /// files and directories, not those of symlinks
+    pub preserve: bool,
+    /// record the entries that can't be read or written in the summary
+    /// and go on, rather than stopping at the first
+    pub keep_going: bool,
+}
+
+/// A run of device blocks landing at `offset` of file `file`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlannedRead {
+    pub physical: u64,
+    pub blocks: u64,
+    /// index into the inodes the plan was made for
+    pub file: usize,
+    /// byte offset in the file
+    pub offset: u64,
+}
+
+/// The reads needed to copy out a set of files, in disk order, so that
+/// extracting many small files reads the device sequentially instead of
+/// seeking from file to file.
+#[derive(Debug, Clone, Default)]
+pub struct ReadPlan {
+    pub reads: Vec<PlannedRead>,
+    /// files whose content isn't stored as plain blocks, inline data and
+    /// encrypted files, to be read through their reader instead
+    pub copied: Vec<usize>,
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Plan the reads of the content of `inodes`. Holes and uninitialized
+    /// extents need no reads, the output is expected to be sparse.
+    pub fn read_plan(&self, inodes: &[&Inode]) -> Result<ReadPlan> {
+        let bs = self.sb.block_size;
+        let mut plan = ReadPlan::default();
+        for (file, inode) in inodes.iter().enumerate() {
+            if inode.flags.contains(InodeFlags::INLINE_DATA) || inode.is_encrypted() {
+                plan.copied.push(file);
+                continue;
+            }
+            let blocks = inode.size.div_ceil(bs);
+            for extent in inode.extents(&self.sb, &self.dev)? {
+                if extent.uninit || extent.block >= blocks {
+                    continue;
+                }
+                let len = extent.len.min(blocks - extent.block);
+                for done in (0..len).step_by(MAX_READ_BLOCKS as usize) {
+                    plan.reads.push(PlannedRead {
+                        physical: extent.start + done,
+                        blocks: MAX_READ_BLOCKS.min(len - done),
+                        file,
+                        offset: (extent.block + done) * bs,
+                    });
+                }
+            }
+        }
+        plan.reads.sort_by_key(|x| x.physical);
+        Ok(plan)
+    }
+
+    /// Copy `path` and everything below it into the host directory `dest`,
+    /// recreating directories, regular files, symlinks and hard links
+    /// between files. Names go
+    /// through `transform`, entries it drops are skipped, as are those
+    /// `redaction` skips. File content is
+    /// read in disk order once the tree is created. Ownership is never
+    /// restored, modes and timestamps only as `options` say, as they say
+    /// whether to go on past entries that fail. `progress` counts the
+    /// entries walked, then the bytes of content copied.
+    pub fn extract(
+        &self,
+        path: &str,
+        dest: &Path,
+        transform: &dyn NameTransform,
+        redaction: &RedactionPolicy,
+        options: &ExtractOptions,
+        progress: &mut dyn ProgressSink,
+    ) -> Result<WalkSummary> {
+        let root = format!("/{}", path.trim_matches('/'));
+        let keep_going = options.keep_going;
+        // created empty, opened again as their reads come up so that no
+        // more than one is open at a time
+        let mut files: Vec<(String, PathBuf, Inode)> = Vec::new();
+        // created, in walk order, to restore their metadata from
+        let mut created: Vec<(String, PathBuf, Inode)> = Vec::new();
+        let mut links = HardLinks::default();
+        let mut targets = HashMap::new();
+        let mut summary = self.walk_with_progress(&root, keep_going, progress, |entry| {
+            if redaction.skips(&entry.path) {
+                return Ok(());
+            }
+            let relative = entry.path[root.len()..].trim_start_matches('/');
+            // a single file is extracted under its own name
+            let relative = match relative {
+                "" if entry.inode.file_type() != FileType::Directory => {
+                    root.rsplit('/').next().unwrap_or_default()
+                }
+                x => x,
+            };
+            let Some(name) = transform.transform(relative) else {
+                return Ok(());
+            };
+            // whatever the names and the transform, nothing lands
+            // outside of dest
+            if !Path::new(&name)
+                .components()
+                .all(|x| matches!(x, Component::Normal(_)))
+            {
+                bail!("{:?} would be extracted outside of the destination", name);
+            }
+            let target = dest.join(&name);
+            let placeholder = match entry.inode.file_type() {
+                FileType::Regular => redaction.placeholder(&entry.path),
+                _ => None,
+            };
+            let is_dir = entry.inode.file_type() == FileType::Directory;
+            if !is_dir && !make_room(&target, options.overwrite)? {
+                return Ok(());
+            }
+            // not linked to the real content under another name
+            if let Some(text) = placeholder {
+                fs::write(&target, text)?;
+                created.push((entry.path.clone(), target, entry.inode.clone()));
+                return Ok(());
+            }
+            if links.add(entry).is_some() {
+                if let Some(first) = targets.get(&entry.inode_number) {
+                    fs::hard_link(first, &target)?;
+                    return Ok(());
+                }
+            }
+            if entry.inode.links_count > 1 && entry.inode.file_type() != FileType::Directory {
+                targets
+                    .entry(entry.inode_number)
+                    .or_insert_with(|| target.clone());
+            }
+            match TypedInode::new(entry.inode.clone()) {
+                TypedInode::Dir(_) => {
+                    if fs::symlink_metadata(&target).is_ok_and(|x| !x.is_dir()) {
+                        bail!("{} is in the way", target.display());
+                    }
+                    fs::create_dir_all(&target)?
+                }
+                TypedInode::File(_) => {
+                    File::create(&target)?.set_len(entry.inode.size)?;
+                    files.push((entry.path.clone(), target.clone(), entry.inode.clone()));
+                }
+                TypedInode::Symlink(x) => {
+                    std::os::unix::fs::symlink(x.target(&self.sb, &self.dev)?, &target)?;
+                    return Ok(());
+                }
+                // device nodes, fifos and sockets
+                TypedInode::Other(_) => return Ok(()),
+            }
+            created.push((entry.path.clone(), target, entry.inode.clone()));
+            Ok(())
+        })?;
+
+        let inodes: Vec<&Inode> = files.iter().map(|(_, _, inode)| inode).collect();
+        let plan = self.read_plan(&inodes)?;
+        let total = inodes.iter().map(|x| x.size).sum();
+        let mut tracker = ProgressTracker::new(progress, total);
+        let mut failed = vec![false; files.len()];
+        let mut fail = |file: usize, error: failure::Error, summary: &mut WalkSummary| {
+            if !keep_going {
+                bail!("{}: {}", files[file].0, error);
+            }
+            // report each file once
+            if !std::mem::replace(&mut failed[file], true) {
+                summary.errors.push(WalkError {
+                    path: files[file].0.clone(),
+                    error,
+                });
+            }
+            Ok(())
+        };
+        let bs = self.sb.block_size;
+        let mut open: Option<(usize, File)> = None;
+        for read in &plan.reads {
+            let (path, target, inode) = &files[read.file];
+            let len = (read.blocks * bs).min(inode.size - read.offset);
+            let mut buf = vec![0u8; len as usize];
+            let result = self
+                .dev
+                .read_exact_at(read.physical * bs, &mut buf)
+                .and_then(|_| {
+                    let file = match open.take() {
+                        Some((index, file)) if index == read.file => file,
+                        // closing the previous one
+                        _ => OpenOptions::new().write(true).open(target)?,
+                    };
+                    let file = &open.insert((read.file, file)).1;
+                    file.write_all_at(&buf, read.offset)
+                });
+            match result {
+                Ok(()) => tracker.advance(path, len),
+                Err(error) => fail(read.file, error.into(), &mut summary)?,
+            }
+            // the zeros read past the end of a truncated image are kept,
+            // the file is reported incomplete
+            if self.is_missing(read.physical, read.blocks) {
+                let missing = self.missing_blocks(inode).unwrap_or_default();
+                let error = format_err!("{missing} blocks past the end of the image");
+                fail(read.file, error, &mut summary)?;
+            }
+        }
+        drop(open);
+        for &index in &plan.copied {
+            let (path, target, inode) = &files[index];
+            let result = self.reader_of(inode).and_then(|reader| {
+                let mut buf = vec![0u8; inode.size as usize];
+                reader.read_exact_at(0, &mut buf)?;
+                OpenOptions::new()
+                    .write(true)
+                    .open(target)?
+                    .write_all_at(&buf, 0)?;
+                Ok(())
+            });
+            match result {
+                Ok(()) => tracker.advance(path, inode.size),
+                Err(error) => fail(index, error, &mut summary)?,
+            }
+        }
+        if options.preserve {
+            // children first, writing into a directory changes its times
+            // and its mode may not allow it
+            for (path, target, inode) in created.iter().rev() {
+                if let Err(error) = restore_metadata(target, inode) {
+                    if !keep_going {
+                        bail!("{}: {}", path, error);
+                    }
+                    summary.errors.push(WalkError {
+                        path: path.clone(),
+                        error,
+                    });
+                }
+            }
+        }
+        Ok(summary)
+    }
+}
+
+/// Clear the way for a file or symlink at `target`, false if an entry
+/// there is to be kept instead.
+fn make_room(target: &Path, overwrite: Overwrite) -> Result<bool> {
+    let Ok(existing) = fs::symlink_metadata(target) else {
+        return Ok(true);
+    };
+    if existing.is_dir() {
+        bail!("directory {} is in the way", target.display());
+    }
+    match overwrite {
+        Overwrite::Replace => {
+            fs::remove_file(target)?;
+            Ok(true)
+        }
+        Overwrite::Skip => Ok(false),
+        Overwrite::Refuse => bail!("{} already exists", target.display()),
+    }
+}
+
+fn restore_metadata(target: &Path, inode: &Inode) -> Result<()> {
+    let times = FileTimes::new()
+        .set_accessed(inode.atime.to_system_time())
+        .set_modified(inode.mtime.to_system_time());
+    File::open(target)?.set_times(times)?;
+    fs::set_permissions(target, Permissions::from_mode((inode.mode & 0o7777) as u32))?;
+    Ok(())
+}
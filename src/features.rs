@@ -0,0 +1,97 @@
+//! Names of the superblock feature flags.
+
+pub const COMPAT: &[(u32, &str)] = &[
+    (0x1, "dir_prealloc"),
+    (0x2, "imagic_inodes"),
+    (0x4, "has_journal"),
+    (0x8, "ext_attr"),
+    (0x10, "resize_inode"),
+    (0x20, "dir_index"),
+    (0x40, "lazy_bg"),
+    (0x80, "exclude_inode"),
+    (0x100, "exclude_bitmap"),
+    (0x200, "sparse_super2"),
+    (0x400, "fast_commit"),
+    (0x800, "stable_inodes"),
+    (0x1000, "orphan_file"),
+];
+
+pub const INCOMPAT: &[(u32, &str)] = &[
+    (0x1, "compression"),
+    (0x2, "filetype"),
+    (0x4, "needs_recovery"),
+    (0x8, "journal_dev"),
+    (0x10, "meta_bg"),
+    (0x40, "extent"),
+    (0x80, "64bit"),
+    (0x100, "mmp"),
+    (0x200, "flex_bg"),
+    (0x400, "ea_inode"),
+    (0x1000, "dirdata"),
+    (0x2000, "metadata_csum_seed"),
+    (0x4000, "large_dir"),
+    (0x8000, "inline_data"),
+    (0x10000, "encrypt"),
+    (0x20000, "casefold"),
+];
+
+pub const RO_COMPAT: &[(u32, &str)] = &[
+    (0x1, "sparse_super"),
+    (0x2, "large_file"),
+    (0x4, "btree_dir"),
+    (0x8, "huge_file"),
+    (0x10, "uninit_bg"),
+    (0x20, "dir_nlink"),
+    (0x40, "extra_isize"),
+    (0x80, "has_snapshot"),
+    (0x100, "quota"),
+    (0x200, "bigalloc"),
+    (0x400, "metadata_csum"),
+    (0x800, "replica"),
+    (0x1000, "read-only"),
+    (0x2000, "project"),
+    (0x4000, "shared_blocks"),
+    (0x8000, "verity"),
+    (0x10000, "orphan_present"),
+];
+
+/// Incompat features this crate knows how to read. Anything else changes
+/// the on-disk format in ways the parsers would misread. Read-only access
+/// never needs to understand compat or ro_compat features.
+pub const SUPPORTED_INCOMPAT: u32 = 0x2 // filetype
+    | 0x4 // needs_recovery, read as is without replaying the journal
+    | 0x10 // meta_bg
+    | 0x40 // extent
+    | 0x80 // 64bit
+    | 0x100 // mmp
+    | 0x200 // flex_bg
+    | 0x400 // ea_inode
+    | 0x2000 // metadata_csum_seed
+    | 0x4000 // large_dir
+    | 0x8000 // inline_data
+    | 0x10000 // encrypt, encrypted names and content are reported as such
+    | 0x20000; // casefold
+
+/// Name every bit set in `flags`, unknown ones as hex.
+pub fn names(flags: u32, table: &[(u32, &str)]) -> Vec<String> {
+    (0..32)
+        .map(|bit| 1u32 << bit)
+        .filter(|flag| flags & flag != 0)
+        .map(|flag| match table.iter().find(|(x, _)| *x == flag) {
+            Some((_, name)) => name.to_string(),
+            None => format!("0x{flag:X}"),
+        })
+        .collect()
+}
+
+/// What to do when an image uses incompat features we don't support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FeaturePolicy {
+    /// fail to open the image
+    #[default]
+    Refuse,
+    /// report an `Anomaly::UnsupportedFeatures` and carry on
+    Warn,
+    /// carry on silently
+    Ignore,
+}
@@ -0,0 +1,224 @@
+use std::io;
+
+use failure::bail;
+use positioned_io::{ReadAt, Size};
+
+use crate::crypt::ContentsKey;
+#[cfg(unix)]
+use crate::mmap::Chunk;
+use crate::{Extent, Inode, InodeFlags, Result, SuperBlock};
+
+/// Reads the content of an inode by logical offset, going through its
+/// extent map. Holes and uninitialized extents read as zeros and reads
+/// stop at i_size.
+pub struct FileReader<D: ReadAt> {
+    dev: D,
+    extents: Vec<Extent>,
+    /// content of inline data inodes, which have no blocks
+    inline: Option<Vec<u8>>,
+    /// decrypts the blocks of encrypted inodes
+    key: Option<ContentsKey>,
+    block_size: u64,
+    size: u64,
+}
+
+impl<D: ReadAt> FileReader<D> {
+    pub(crate) fn with_key(self, key: ContentsKey) -> Self {
+        Self {
+            key: Some(key),
+            ..self
+        }
+    }
+
+    /// Let reads go on up to `size`, for metadata kept past i_size.
+    pub(crate) fn with_size(self, size: u64) -> Self {
+        Self { size, ..self }
+    }
+
+    /// The extents of the file, by logical block.
+    pub fn extents(&self) -> &[Extent] {
+        &self.extents
+    }
+
+    /// The content as slices of `image`, the whole device, and runs of
+    /// zeros. `None` for inline data and encrypted content, which can't
+    /// be borrowed, and for extents outside `image`.
+    #[cfg(unix)]
+    pub(crate) fn chunks<'a>(&self, image: &'a [u8]) -> Option<Vec<Chunk<'a>>> {
+        if self.inline.is_some() || self.key.is_some() {
+            return None;
+        }
+        let bs = self.block_size;
+        let mut chunks = Vec::new();
+        let mut pos = 0;
+        for extent in &self.extents {
+            let start = extent.block.saturating_mul(bs).min(self.size);
+            let end = (extent.block.saturating_add(extent.len))
+                .saturating_mul(bs)
+                .min(self.size);
+            if start < pos || start >= end {
+                continue;
+            }
+            if start > pos {
+                chunks.push(Chunk::Zeros(start - pos));
+            }
+            if extent.uninit {
+                chunks.push(Chunk::Zeros(end - start));
+            } else {
+                let physical = usize::try_from(extent.start.checked_mul(bs)?).ok()?;
+                let len = usize::try_from(end - start).ok()?;
+                chunks.push(Chunk::Data(
+                    image.get(physical..physical.checked_add(len)?)?,
+                ));
+            }
+            pos = end;
+        }
+        if pos < self.size {
+            chunks.push(Chunk::Zeros(self.size - pos));
+        }
+        Some(chunks)
+    }
+}
+
+impl<D: ReadAt> ReadAt for FileReader<D> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if pos >= self.size {
+            return Ok(0);
+        }
+        let block = pos / self.block_size;
+        let offset = pos % self.block_size;
+        // stay within one block, and within the file
+        let len = (buf.len() as u64)
+            .min(self.block_size - offset)
+            .min(self.size - pos) as usize;
+        let buf = &mut buf[..len];
+        if let Some(inline) = &self.inline {
+            let start = (pos as usize).min(inline.len());
+            let end = (start + len).min(inline.len());
+            buf[..end - start].copy_from_slice(&inline[start..end]);
+            buf[end - start..].fill(0);
+            return Ok(len);
+        }
+        // the last extent starting at or before the block, if it reaches it
+        let i = self.extents.partition_point(|x| x.block <= block);
+        let extent = i.checked_sub(1).map(|i| &self.extents[i]);
+        match extent.filter(|x| x.map(block).is_some()) {
+            Some(extent) if !extent.uninit => {
+                let physical = extent.map(block).unwrap();
+                match &self.key {
+                    // blocks are encrypted as a whole
+                    Some(key) => {
+                        let mut plain = vec![0u8; self.block_size as usize];
+                        self.dev
+                            .read_exact_at(physical * self.block_size, &mut plain)?;
+                        key.decrypt(block, &mut plain);
+                        buf.copy_from_slice(&plain[offset as usize..offset as usize + len]);
+                    }
+                    None => self
+                        .dev
+                        .read_exact_at(physical * self.block_size + offset, buf)?,
+                }
+            }
+            _ => buf.fill(0),
+        }
+        Ok(len)
+    }
+}
+
+impl<D: ReadAt> Size for FileReader<D> {
+    fn size(&self) -> io::Result<Option<u64>> {
+        Ok(Some(self.size))
+    }
+}
+
+/// A byte range of a file, see [`Inode::segments`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub offset: u64,
+    pub len: u64,
+    /// a hole reads as zeros and needs no blocks
+    pub hole: bool,
+}
+
+impl Inode {
+    /// Split the content into alternating data and hole ranges covering
+    /// `0..size`, like walking it with `SEEK_DATA` and `SEEK_HOLE`.
+    /// Uninitialized extents count as holes, they read as zeros too.
+    pub fn segments(&self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Vec<Segment>> {
+        if self.flags.contains(InodeFlags::INLINE_DATA) {
+            let data = Segment {
+                offset: 0,
+                len: self.size,
+                hole: false,
+            };
+            return Ok([data].into_iter().filter(|x| x.len > 0).collect());
+        }
+        let mut extents = self.extents(sb, dev)?;
+        extents.sort_by_key(|x| x.block);
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut push = |offset: u64, end: u64, hole: bool| match segments.last_mut() {
+            Some(last) if last.hole == hole => last.len = end - last.offset,
+            _ => segments.push(Segment {
+                offset,
+                len: end - offset,
+                hole,
+            }),
+        };
+        let mut pos = 0;
+        for extent in extents {
+            let start = (extent.block * sb.block_size).min(self.size);
+            let end = ((extent.block + extent.len) * sb.block_size).min(self.size);
+            if start < pos || start >= end {
+                continue;
+            }
+            if start > pos {
+                push(pos, start, true);
+            }
+            push(start, end, extent.uninit);
+            pos = end;
+        }
+        if pos < self.size {
+            push(pos, self.size, true);
+        }
+        Ok(segments)
+    }
+
+    /// A reader over the whole content of the inode. Fails for encrypted
+    /// inodes, whose content would only be ciphertext.
+    pub fn reader<D: ReadAt>(&self, sb: &SuperBlock, dev: D) -> Result<FileReader<D>> {
+        if self.is_encrypted() {
+            bail!("content is encrypted, no key");
+        }
+        self.raw_reader(sb, dev)
+    }
+
+    /// Like `reader`, but hands out the ciphertext of encrypted inodes.
+    pub fn raw_reader<D: ReadAt>(&self, sb: &SuperBlock, dev: D) -> Result<FileReader<D>> {
+        let inline = if self.flags.contains(InodeFlags::INLINE_DATA) {
+            // the first 60 bytes are in i_block, the rest in system.data
+            let mut data = self.block.clone();
+            if let Some(x) = self
+                .xattrs(sb, &dev)?
+                .into_iter()
+                .find(|x| x.full_name() == "system.data")
+            {
+                data.extend(x.value);
+            }
+            Some(data)
+        } else {
+            None
+        };
+        // sorted for lookups by logical block, whatever order a damaged
+        // tree had them in
+        let mut extents = self.extents(sb, &dev)?;
+        extents.sort_by_key(|x| x.block);
+        Ok(FileReader {
+            inline,
+            key: None,
+            extents,
+            dev,
+            block_size: sb.block_size,
+            size: self.size,
+        })
+    }
+}
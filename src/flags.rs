@@ -0,0 +1,80 @@
+//! Per inode flags, as stored in i_flags.
+
+use std::fmt;
+
+use crate::features;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct InodeFlags(pub u32);
+
+impl InodeFlags {
+    pub const SECURE_RM: u32 = 0x1;
+    pub const UNRM: u32 = 0x2;
+    pub const COMPR: u32 = 0x4;
+    pub const SYNC: u32 = 0x8;
+    pub const IMMUTABLE: u32 = 0x10;
+    pub const APPEND: u32 = 0x20;
+    pub const NODUMP: u32 = 0x40;
+    pub const NOATIME: u32 = 0x80;
+    pub const ENCRYPT: u32 = 0x800;
+    /// hashed directory (htree)
+    pub const INDEX: u32 = 0x1000;
+    pub const JOURNAL_DATA: u32 = 0x4000;
+    pub const DIRSYNC: u32 = 0x10000;
+    pub const TOPDIR: u32 = 0x20000;
+    pub const HUGE_FILE: u32 = 0x40000;
+    pub const EXTENTS: u32 = 0x80000;
+    pub const VERITY: u32 = 0x100000;
+    /// the inode stores a large xattr value
+    pub const EA_INODE: u32 = 0x200000;
+    pub const DAX: u32 = 0x2000000;
+    pub const INLINE_DATA: u32 = 0x10000000;
+    pub const PROJINHERIT: u32 = 0x20000000;
+    pub const CASEFOLD: u32 = 0x40000000;
+
+    const NAMES: &'static [(u32, &'static str)] = &[
+        (Self::SECURE_RM, "secure_rm"),
+        (Self::UNRM, "undelete"),
+        (Self::COMPR, "compressed"),
+        (Self::SYNC, "sync"),
+        (Self::IMMUTABLE, "immutable"),
+        (Self::APPEND, "append_only"),
+        (Self::NODUMP, "nodump"),
+        (Self::NOATIME, "noatime"),
+        (Self::ENCRYPT, "encrypted"),
+        (Self::INDEX, "indexed"),
+        (Self::JOURNAL_DATA, "journal_data"),
+        (Self::DIRSYNC, "dirsync"),
+        (Self::TOPDIR, "topdir"),
+        (Self::HUGE_FILE, "huge_file"),
+        (Self::EXTENTS, "extents"),
+        (Self::VERITY, "verity"),
+        (Self::EA_INODE, "ea_inode"),
+        (Self::DAX, "dax"),
+        (Self::INLINE_DATA, "inline_data"),
+        (Self::PROJINHERIT, "project_inherit"),
+        (Self::CASEFOLD, "casefold"),
+    ];
+
+    /// Whether every bit of `flags` is set.
+    pub fn contains(self, flags: u32) -> bool {
+        self.0 & flags == flags
+    }
+
+    /// Name every flag set, unknown ones as hex.
+    pub fn names(self) -> Vec<String> {
+        features::names(self.0, Self::NAMES)
+    }
+}
+
+impl fmt::Display for InodeFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.names().join(" "))
+    }
+}
+
+impl fmt::Debug for InodeFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InodeFlags(0x{:X}: {})", self.0, self)
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+
+use failure::bail;
+use positioned_io::ReadAt;
+
+use crate::casefold;
+use crate::crypt::{EncryptionContext, MasterKey};
+use crate::events::{Anomaly, EventHook};
+use crate::features::FeaturePolicy;
+use crate::{
+    DirInode, DirectoryEntry, FileInode, FileReader, FileType, Inode, InodeFlags, InodeNumber,
+    Result, SuperBlock, TypedInode,
+};
+
+/// A filesystem handle bundling the device with its superblock, so callers
+/// don't have to thread both through every lookup.
+pub struct Ext4Fs<T: ReadAt> {
+    pub dev: T,
+    pub sb: SuperBlock,
+    hook: Option<Box<dyn EventHook>>,
+    /// anomalies found before a hook was set, handed to the first one
+    pending: Vec<Anomaly>,
+    keys: Vec<MasterKey>,
+    /// bytes present in a truncated image, see `open_truncated`
+    pub(crate) image_len: Option<u64>,
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    // root `/` has fixed inode position 2
+    pub const ROOT: InodeNumber = InodeNumber(2);
+
+    /// Open the filesystem, refusing images which use incompat features
+    /// this crate can't read.
+    pub fn new(dev: T) -> Result<Self> {
+        Self::with_policy(dev, FeaturePolicy::default())
+    }
+
+    pub fn with_policy(dev: T, policy: FeaturePolicy) -> Result<Self> {
+        let sb = SuperBlock::new(&dev)?;
+        Self::with_superblock(dev, sb, policy)
+    }
+
+    /// Open the filesystem with `size` as its block size, see
+    /// [`SuperBlock::with_block_size`].
+    pub fn with_block_size(dev: T, size: u64) -> Result<Self> {
+        let sb = SuperBlock::with_block_size(&dev, size)?;
+        Self::with_superblock(dev, sb, FeaturePolicy::default())
+    }
+
+    fn with_superblock(dev: T, sb: SuperBlock, policy: FeaturePolicy) -> Result<Self> {
+        let unsupported = sb.unsupported_incompat();
+        let mut pending = Vec::new();
+        if !unsupported.is_empty() {
+            match policy {
+                FeaturePolicy::Refuse => {
+                    bail!("unsupported incompat features: {}", unsupported.join(", "))
+                }
+                FeaturePolicy::Warn => pending.push(Anomaly::UnsupportedFeatures(unsupported)),
+                FeaturePolicy::Ignore => {}
+            }
+        }
+        Ok(Self {
+            dev,
+            sb,
+            hook: None,
+            pending,
+            keys: Vec::new(),
+            image_len: None,
+        })
+    }
+
+    /// Report anomalies found from now on to `hook`, and those found
+    /// opening the image.
+    pub fn set_event_hook(&mut self, hook: impl EventHook + 'static) {
+        for anomaly in self.pending.drain(..) {
+            hook.anomaly(&anomaly);
+        }
+        self.hook = Some(Box::new(hook));
+    }
+
+    pub(crate) fn report(&self, anomaly: Anomaly) {
+        if let Some(hook) = &self.hook {
+            hook.anomaly(&anomaly);
+        }
+    }
+
+    /// Add a fscrypt v2 master key, used to decrypt names and content of
+    /// the inodes it protects. Returns the key identifier.
+    pub fn add_key(&mut self, master_key: &[u8]) -> [u8; 16] {
+        let key = MasterKey::new(master_key);
+        let identifier = key.identifier;
+        self.keys.push(key);
+        identifier
+    }
+
+    /// The encryption context of an encrypted inode and the master key
+    /// protecting it, `None` if we don't have the key.
+    fn encryption(&self, inode: &Inode) -> Result<Option<(EncryptionContext, &MasterKey)>> {
+        let Some(raw) = inode.xattrs(&self.sb, &self.dev)?.into_iter().find(|x| {
+            x.name_index == EncryptionContext::XATTR_INDEX
+                && x.name == EncryptionContext::XATTR_NAME
+        }) else {
+            bail!("encrypted inode without encryption context");
+        };
+        let context = EncryptionContext::parse(&raw.value)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|x| x.identifier == context.master_key_identifier);
+        Ok(key.map(|key| (context, key)))
+    }
+
+    /// Entries of directory `n`, reporting suspicious entries. Names of
+    /// encrypted directories are decrypted if we have the key.
+    pub fn dir_entries(&self, n: InodeNumber, dir: &DirInode) -> Result<Vec<DirectoryEntry>> {
+        self.entries_of(n, dir)
+    }
+
+    /// `dir_entries` for an inode already known to be a directory.
+    pub(crate) fn entries_of(&self, n: InodeNumber, inode: &Inode) -> Result<Vec<DirectoryEntry>> {
+        let mut entries = inode.dir_entries_with(&self.sb, &self.dev, &mut |offset, rec_len| {
+            self.report(Anomaly::SuspiciousRecLen {
+                dir: n,
+                offset,
+                rec_len,
+            })
+        })?;
+        if inode.is_encrypted() {
+            if let Some((context, master)) = self.encryption(inode)? {
+                let key = context.filenames_key(master);
+                for entry in entries.iter_mut().filter(|x| x.encrypted) {
+                    entry.name_bytes = key.decrypt(&entry.name_bytes)?;
+                    entry.name = String::from_utf8_lossy(&entry.name_bytes).into();
+                    entry.encrypted = false;
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// A reader over the content of `file`, decrypted if it is encrypted
+    /// and we have its key.
+    pub fn reader(&self, file: &FileInode) -> Result<FileReader<&T>> {
+        self.reader_of(file)
+    }
+
+    /// `reader` for any inode with content, the journal and quota files
+    /// among the special inodes, and those of a walk.
+    pub(crate) fn reader_of(&self, inode: &Inode) -> Result<FileReader<&T>> {
+        if inode.is_encrypted() {
+            if let Some((context, master)) = self.encryption(inode)? {
+                let reader = inode.raw_reader(&self.sb, &self.dev)?;
+                return Ok(reader.with_key(context.contents_key(master)));
+            }
+        }
+        inode.reader(&self.sb, &self.dev)
+    }
+
+    pub fn inode(&self, n: InodeNumber) -> Result<Inode> {
+        n.inode(&self.sb, &self.dev)
+    }
+
+    /// Resolve an absolute path to its inode number, `None` if any
+    /// component doesn't exist.
+    pub fn resolve(&self, path: &str) -> Result<Option<InodeNumber>> {
+        Resolver::new(self).resolve(path)
+    }
+
+    /// The inode at `path`, by its file type.
+    pub fn stat(&self, path: &str) -> Result<Option<TypedInode>> {
+        Resolver::new(self).stat(path)
+    }
+
+    /// Stat a batch of paths. Directories and inodes read while resolving
+    /// one path are reused for the others, so paths sharing parents only
+    /// cost one read of each parent; indexed directories are looked up
+    /// through their index each time instead.
+    pub fn stat_many(&self, paths: &[&str]) -> Result<Vec<Option<TypedInode>>> {
+        let mut resolver = Resolver::new(self);
+        paths.iter().map(|path| resolver.stat(path)).collect()
+    }
+}
+
+struct Resolver<'a, T: ReadAt> {
+    fs: &'a Ext4Fs<T>,
+    inodes: HashMap<InodeNumber, Inode>,
+    /// entries of each directory read, keyed by folded names in
+    /// casefolded directories
+    dirs: HashMap<InodeNumber, (bool, HashMap<String, InodeNumber>)>,
+}
+
+impl<'a, T: ReadAt> Resolver<'a, T> {
+    fn new(fs: &'a Ext4Fs<T>) -> Self {
+        Self {
+            fs,
+            inodes: HashMap::new(),
+            dirs: HashMap::new(),
+        }
+    }
+
+    fn inode(&mut self, n: InodeNumber) -> Result<&Inode> {
+        if !self.inodes.contains_key(&n) {
+            let inode = self.fs.inode(n)?;
+            self.inodes.insert(n, inode);
+        }
+        Ok(&self.inodes[&n])
+    }
+
+    /// Indexed directories are looked up through their index, others are
+    /// read whole and kept for the next lookups.
+    fn lookup(&mut self, dir: InodeNumber, name: &str) -> Result<Option<InodeNumber>> {
+        if !self.dirs.contains_key(&dir) {
+            let fs = self.fs;
+            let inode = self.inode(dir)?;
+            if !matches!(inode.file_type(), FileType::Directory) {
+                bail!("not a directory: inode {}", dir.0);
+            }
+            // names of encrypted directories are only found decrypted
+            if inode.flags.contains(InodeFlags::INDEX) && !inode.is_encrypted() {
+                return fs.lookup(inode, name);
+            }
+            let casefolded = inode.is_casefolded(&fs.sb);
+            let entries = fs
+                .entries_of(dir, inode)?
+                .into_iter()
+                .map(|x| match casefolded {
+                    true => (casefold::fold(&x.name), x.inode),
+                    false => (x.name, x.inode),
+                })
+                .collect();
+            self.dirs.insert(dir, (casefolded, entries));
+        }
+        let (casefolded, entries) = &self.dirs[&dir];
+        let found = match casefolded {
+            true => entries.get(&casefold::fold(name)).copied(),
+            false => entries.get(name).copied(),
+        };
+        if found.is_none() {
+            casefold::check_missing(*casefolded, name)?;
+        }
+        Ok(found)
+    }
+
+    fn resolve(&mut self, path: &str) -> Result<Option<InodeNumber>> {
+        let mut current = Ext4Fs::<T>::ROOT;
+        for name in path.split('/').filter(|x| !x.is_empty() && *x != ".") {
+            match self.lookup(current, name)? {
+                Some(n) => current = n,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(current))
+    }
+
+    fn stat(&mut self, path: &str) -> Result<Option<TypedInode>> {
+        match self.resolve(path)? {
+            Some(n) => Ok(Some(TypedInode::new(self.inode(n)?.clone()))),
+            None => Ok(None),
+        }
+    }
+}
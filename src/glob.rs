@@ -0,0 +1,46 @@
+//! Shell-like patterns over image paths. `*` and `?` don't match `/`,
+//! `**` does, and `**/` also matches no directory at all. A pattern
+//! without a `/` matches names in any directory.
+
+/// Match the image path `path` against `pattern`, both absolute or both
+/// relative to the image root.
+pub fn path_matches(pattern: &str, path: &str) -> bool {
+    let path = path.trim_matches('/');
+    if pattern.contains('/') {
+        glob(pattern.trim_start_matches('/').as_bytes(), path.as_bytes())
+    } else {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        glob(pattern.as_bytes(), name.as_bytes())
+    }
+}
+
+/// The directories of `pattern` before its first wildcard, where a walk
+/// for its matches can start.
+pub fn literal_prefix(pattern: &str) -> &str {
+    if !pattern.contains('/') {
+        return "/";
+    }
+    let wild = pattern.find(['*', '?']).unwrap_or(pattern.len());
+    match pattern[..wild].rfind('/') {
+        Some(0) | None => "/",
+        Some(i) => &pattern[..i],
+    }
+}
+
+/// Match `text` against `pattern` as a whole.
+pub fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => {
+            // `**/` also matches no directory at all
+            let rest_after_slash = rest.strip_prefix(b"/").unwrap_or(rest);
+            glob(rest_after_slash, text) || (0..=text.len()).any(|i| glob(rest, &text[i..]))
+        }
+        [b'*', rest @ ..] => {
+            let run = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=run).any(|i| glob(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => matches!(text, [c, tail @ ..] if *c != b'/' && glob(rest, tail)),
+        [c, rest @ ..] => matches!(text, [t, tail @ ..] if t == c && glob(rest, tail)),
+    }
+}
@@ -0,0 +1,95 @@
+//! A streaming gzip writer for the archives the image is exported to.
+
+use std::io::{self, Write};
+
+use miniz_oxide::deflate::core::{
+    compress, create_comp_flags_from_zip_params, CompressorOxide, TDEFLFlush, TDEFLStatus,
+};
+
+use crate::crc::crc32;
+
+// deflated output is handed on in pieces of this size
+const OUT_CHUNK: usize = 64 * 1024;
+// no file name, no modification time, compressed on Unix
+const HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 3];
+
+/// Compress everything written to it as a single gzip member written on
+/// to `inner`. [`finish`](Self::finish) must be called to complete it.
+pub struct GzipWriter<W: Write> {
+    inner: W,
+    compressor: Box<CompressorOxide>,
+    buf: Vec<u8>,
+    crc: u32,
+    len: u32,
+    header_written: bool,
+}
+
+impl<W: Write> GzipWriter<W> {
+    /// `level` as gzip's, 1 for fastest to 9 for smallest.
+    pub fn new(inner: W, level: u8) -> Self {
+        // negative window bits for raw deflate, gzip has its own framing
+        let flags = create_comp_flags_from_zip_params(level.min(10) as i32, -15, 0);
+        GzipWriter {
+            inner,
+            compressor: Box::new(CompressorOxide::new(flags)),
+            buf: vec![0; OUT_CHUNK],
+            crc: 0,
+            len: 0,
+            header_written: false,
+        }
+    }
+
+    /// Write the end of the member and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.deflate(&[], TDEFLFlush::Finish)?;
+        let mut trailer = [0u8; 8];
+        trailer[..4].copy_from_slice(&self.crc.to_le_bytes());
+        trailer[4..].copy_from_slice(&self.len.to_le_bytes());
+        self.inner.write_all(&trailer)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Feed `data` to the compressor, writing out what comes of it, until
+    /// all of it is consumed and, when finishing, the stream is done.
+    fn deflate(&mut self, mut data: &[u8], flush: TDEFLFlush) -> io::Result<()> {
+        if !self.header_written {
+            self.inner.write_all(&HEADER)?;
+            self.header_written = true;
+        }
+        loop {
+            let (status, read, written) =
+                compress(&mut self.compressor, data, &mut self.buf, flush);
+            self.inner.write_all(&self.buf[..written])?;
+            data = &data[read..];
+            match status {
+                TDEFLStatus::Done => return Ok(()),
+                // the output buffer had room to spare, nothing is pending
+                TDEFLStatus::Okay
+                    if data.is_empty()
+                        && written < self.buf.len()
+                        && flush != TDEFLFlush::Finish =>
+                {
+                    return Ok(())
+                }
+                TDEFLStatus::Okay => {}
+                _ => return Err(io::Error::other("deflate failed")),
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.deflate(data, TDEFLFlush::None)?;
+        self.crc = crc32(self.crc, data);
+        // the size is stored modulo 2^32
+        self.len = self.len.wrapping_add(data.len() as u32);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflate(&[], TDEFLFlush::Sync)?;
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,136 @@
+//! NFS-style file handles: an inode number and its generation, laid out
+//! as the `FILEID_INO32_GEN` handles Linux hands out for ext4, so that a
+//! handle to an inode freed and reused since reads as stale.
+
+use std::fmt;
+use std::str::FromStr;
+
+use failure::{bail, format_err};
+use positioned_io::ReadAt;
+
+use crate::{Ext4Fs, Inode, InodeNumber, Reader, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileHandle {
+    pub inode: InodeNumber,
+    pub generation: u32,
+    /// the directory the handle was made through, with its generation
+    pub parent: Option<(InodeNumber, u32)>,
+}
+
+impl FileHandle {
+    /// `FILEID_INO32_GEN`
+    pub const TYPE_INO32_GEN: u8 = 1;
+    /// `FILEID_INO32_GEN_PARENT`
+    pub const TYPE_INO32_GEN_PARENT: u8 = 2;
+
+    pub fn handle_type(&self) -> u8 {
+        match self.parent {
+            Some(_) => Self::TYPE_INO32_GEN_PARENT,
+            None => Self::TYPE_INO32_GEN,
+        }
+    }
+
+    /// The handle's `f_handle` bytes, 32-bit little endian words.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut words = vec![self.inode.0 as u32, self.generation];
+        if let Some((parent, generation)) = self.parent {
+            words.extend([parent.0 as u32, generation]);
+        }
+        words.iter().flat_map(|x| x.to_le_bytes()).collect()
+    }
+
+    pub fn from_bytes(handle_type: u8, bytes: &[u8]) -> Result<Self> {
+        let expected = match handle_type {
+            Self::TYPE_INO32_GEN => 8,
+            Self::TYPE_INO32_GEN_PARENT => 16,
+            _ => bail!("unsupported file handle type {}", handle_type),
+        };
+        if bytes.len() != expected {
+            bail!(
+                "file handle of type {} with {} bytes, {} expected",
+                handle_type,
+                bytes.len(),
+                expected
+            );
+        }
+        let r = Reader::new(bytes);
+        let parent = if handle_type == Self::TYPE_INO32_GEN_PARENT {
+            Some((InodeNumber(r.u32(0x8)? as u64), r.u32(0xC)?))
+        } else {
+            None
+        };
+        Ok(Self {
+            inode: InodeNumber(r.u32(0x0)? as u64),
+            generation: r.u32(0x4)?,
+            parent,
+        })
+    }
+}
+
+impl fmt::Display for FileHandle {
+    /// `type:hex`, the handle type followed by its bytes
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.handle_type())?;
+        for b in self.to_bytes() {
+            write!(f, "{b:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FileHandle {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (handle_type, hex) = s
+            .split_once(':')
+            .ok_or_else(|| format_err!("file handle {:?} isn't type:hex", s))?;
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            bail!("bad file handle bytes {:?}", hex);
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        Self::from_bytes(handle_type.parse()?, &bytes)
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// A handle to inode `n`, and to it as found in directory `parent`.
+    pub fn file_handle(&self, n: InodeNumber, parent: Option<InodeNumber>) -> Result<FileHandle> {
+        let generation = self.inode(n)?.generation;
+        let parent = match parent {
+            Some(p) => Some((p, self.inode(p)?.generation)),
+            None => None,
+        };
+        Ok(FileHandle {
+            inode: n,
+            generation,
+            parent,
+        })
+    }
+
+    /// The inode a handle refers to. Fails as stale if the inode is free
+    /// or was reused with another generation since.
+    pub fn open_handle(&self, handle: &FileHandle) -> Result<Inode> {
+        let n = handle.inode;
+        if n.0 == 0 || n.0 > self.sb.inodes_count {
+            bail!("file handle to inode {} out of range", n.0);
+        }
+        let inode = self.inode(n)?;
+        if inode.mode == 0 || inode.links_count == 0 {
+            bail!("stale file handle: inode {} is free", n.0);
+        }
+        if inode.generation != handle.generation {
+            bail!(
+                "stale file handle: inode {} generation {}, handle has {}",
+                n.0,
+                inode.generation,
+                handle.generation
+            );
+        }
+        Ok(inode)
+    }
+}
@@ -0,0 +1,240 @@
+//! Hashed directory (htree) index, kept in the directory's own blocks
+//! behind fake directory entries so that it reads as empty space to
+//! anything unaware of it.
+
+use std::collections::BTreeSet;
+
+use positioned_io::{ReadAt, Slice};
+
+use crate::casefold;
+use crate::dirhash::{self, HashVersion};
+use crate::{DirectoryEntry, Ext4Fs, Inode, InodeFlags, InodeNumber, Reader, Result, SuperBlock};
+
+/// How the index of an indexed directory relates to its blocks. Listing
+/// entries never depends on it: leaves are read linearly, so a damaged
+/// index costs nothing but lookup speed.
+#[derive(Debug, Clone, Default)]
+pub struct HtreeReport {
+    /// `None` when the root is damaged
+    pub hash_version: Option<u8>,
+    pub levels: u8,
+    /// logical blocks holding index nodes, the root excluded
+    pub index_blocks: Vec<u64>,
+    /// logical blocks the index points to as leaves
+    pub leaves: Vec<u64>,
+    /// logical blocks holding entries which the index doesn't reach
+    pub unreferenced_leaves: Vec<u64>,
+    pub problems: Vec<String>,
+}
+
+// dx_root_info follows the `.` and `..` entries
+const ROOT_INFO: u64 = 0x18;
+const ROOT_INFO_LENGTH: u8 = 8;
+// index entries are 8 bytes, the first one holding limit and count
+// instead of a hash
+const ENTRY_SIZE: u64 = 8;
+/// Levels of index nodes, the root included: 2, or 3 with large_dir.
+fn max_levels(sb: &SuperBlock) -> u8 {
+    if sb.has_large_dir() {
+        3
+    } else {
+        2
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Read the index of a directory, `None` if it isn't indexed.
+    pub fn htree(&self, inode: &Inode) -> Result<Option<HtreeReport>> {
+        if !inode.flags.contains(InodeFlags::INDEX) {
+            return Ok(None);
+        }
+        let bs = self.sb.block_size;
+        let dir = inode.raw_reader(&self.sb, &self.dev)?;
+        let blocks = inode.size / bs;
+        let r = Reader::new(&dir);
+        let mut report = HtreeReport::default();
+
+        let dot_ok = r.u16(0x4)? == 12 && r.u8(0x6)? == 1 && r.u8(0x8)? == b'.';
+        let dotdot_ok = r.u8(0x12)? == 2 && r.vec(0x14, 2)? == b"..";
+        let reserved = r.u32(ROOT_INFO)?;
+        let info_length = r.u8(ROOT_INFO + 0x5)?;
+        let levels = r.u8(ROOT_INFO + 0x6)?;
+        let mut referenced = BTreeSet::new();
+        let mut index = BTreeSet::new();
+        if !dot_ok || !dotdot_ok || reserved != 0 || info_length != ROOT_INFO_LENGTH {
+            report.problems.push("damaged root".into());
+        } else if levels >= max_levels(&self.sb) {
+            report.problems.push(format!(
+                "{levels} indirect levels, at most {}",
+                max_levels(&self.sb) - 1
+            ));
+        } else {
+            report.hash_version = Some(r.u8(ROOT_INFO + 0x4)?);
+            report.levels = levels;
+            let mut nodes = vec![(ROOT_INFO + ROOT_INFO_LENGTH as u64, levels)];
+            while let Some((offset, level)) = nodes.pop() {
+                let limit = r.u16(offset)? as u64;
+                let count = r.u16(offset + 0x2)? as u64;
+                if count == 0 || count > limit || offset % bs + limit * ENTRY_SIZE > bs {
+                    report.problems.push(format!(
+                        "bad index node at offset {offset}: count {count}, limit {limit}"
+                    ));
+                    continue;
+                }
+                for i in 0..count {
+                    let block = r.u32(offset + i * ENTRY_SIZE + 0x4)? as u64;
+                    if block == 0 || block >= blocks {
+                        report
+                            .problems
+                            .push(format!("index points to block {block} of {blocks}"));
+                    } else if level == 0 {
+                        referenced.insert(block);
+                    } else if index.insert(block) {
+                        // interior nodes start with an empty entry
+                        // spanning the block
+                        nodes.push((block * bs + ENTRY_SIZE, level - 1));
+                    }
+                }
+            }
+        }
+        for block in 1..blocks {
+            if index.contains(&block) || referenced.contains(&block) {
+                continue;
+            }
+            // an interior node the damaged index no longer reaches looks
+            // like an empty block
+            let empty = r.u32(block * bs)? == 0 && r.u16(block * bs + 0x4)? as u64 == bs;
+            if !empty {
+                report.unreferenced_leaves.push(block);
+            }
+        }
+        report.index_blocks = index.into_iter().collect();
+        report.leaves = referenced.into_iter().collect();
+        if !report.unreferenced_leaves.is_empty() {
+            report.problems.push(format!(
+                "{} blocks with entries not reached by the index",
+                report.unreferenced_leaves.len()
+            ));
+        }
+        Ok(Some(report))
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Look `name` up in directory `inode`, following the hash index to
+    /// the one leaf which can hold it. Falls back to scanning every entry
+    /// when the directory isn't indexed, the index is damaged or uses a
+    /// hash this crate doesn't implement, and for names outside of ASCII
+    /// in casefolded directories.
+    pub fn lookup(&self, inode: &Inode, name: &str) -> Result<Option<InodeNumber>> {
+        // `.` and `..` come before the index root, in no leaf
+        let indexed = !matches!(name, "." | "..");
+        if indexed && inode.flags.contains(InodeFlags::INDEX) && !inode.is_encrypted() {
+            if let Ok(Some(found)) = self.index_lookup(inode, name) {
+                return Ok(found);
+            }
+        }
+        inode.find_entry_name(&self.sb, &self.dev, name)
+    }
+
+    /// `None` if the index can't be trusted for the lookup.
+    fn index_lookup(&self, inode: &Inode, name: &str) -> Result<Option<Option<InodeNumber>>> {
+        let bs = self.sb.block_size;
+        let dir = inode.raw_reader(&self.sb, &self.dev)?;
+        let blocks = inode.size / bs;
+        let r = Reader::new(&dir);
+        if r.u32(ROOT_INFO)? != 0 || r.u8(ROOT_INFO + 0x5)? != ROOT_INFO_LENGTH {
+            return Ok(None);
+        }
+        let version = HashVersion::new(r.u8(ROOT_INFO + 0x4)?, &self.sb)?;
+        let levels = r.u8(ROOT_INFO + 0x6)?;
+        if levels >= max_levels(&self.sb) {
+            return Ok(None);
+        }
+        let casefolded = inode.is_casefolded(&self.sb);
+        // the kernel hashes the NFD normalized casefolded name, which
+        // `casefold::fold` only gives for ASCII
+        if casefolded && !name.is_ascii() {
+            return Ok(None);
+        }
+        let hashed = if casefolded {
+            casefold::fold(name)
+        } else {
+            name.to_string()
+        };
+        let (hash, _) = dirhash::hash(version, hashed.as_bytes(), self.sb.hash_seed);
+
+        let mut offset = ROOT_INFO + ROOT_INFO_LENGTH as u64;
+        for level in (0..=levels).rev() {
+            let limit = r.u16(offset)? as u64;
+            let count = r.u16(offset + 0x2)? as u64;
+            if count == 0 || count > limit || offset % bs + limit * ENTRY_SIZE > bs {
+                return Ok(None);
+            }
+            let entry_hash = |i: u64| r.u32(offset + i * ENTRY_SIZE);
+            let entry_block = |i: u64| -> Result<Option<u64>> {
+                let block = r.u32(offset + i * ENTRY_SIZE + 0x4)? as u64;
+                Ok(Some(block).filter(|&x| x != 0 && x < blocks))
+            };
+            // the first entry covers hashes below the second one, its
+            // hash slot holds limit and count instead
+            let mut i = 0;
+            while i + 1 < count && entry_hash(i + 1)? <= hash {
+                i += 1;
+            }
+            let Some(block) = entry_block(i)? else {
+                return Ok(None);
+            };
+            if level > 0 {
+                // interior nodes start with an empty entry spanning the
+                // block
+                offset = block * bs + ENTRY_SIZE;
+                continue;
+            }
+            let mut block = block;
+            loop {
+                if let Some(found) = leaf_lookup(&dir, block * bs, bs, casefolded, name)? {
+                    return Ok(Some(Some(found)));
+                }
+                // names sharing the hash may continue in the next leaf,
+                // flagged by the low bit of its hash
+                i += 1;
+                if i >= count && levels > 0 {
+                    // continuing in the next index node isn't followed
+                    return Ok(None);
+                }
+                if i >= count || entry_hash(i)? != hash | 1 {
+                    return Ok(Some(None));
+                }
+                let Some(next) = entry_block(i)? else {
+                    return Ok(None);
+                };
+                block = next;
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn leaf_lookup(
+    dir: &dyn ReadAt,
+    start: u64,
+    bs: u64,
+    casefolded: bool,
+    name: &str,
+) -> Result<Option<InodeNumber>> {
+    let r = Reader::new(dir);
+    let mut offset = start;
+    while offset < start + bs {
+        let rec_len = r.u16(offset + 0x4)? as u64;
+        if rec_len < 8 || offset + rec_len > start + bs {
+            break;
+        }
+        let entry = DirectoryEntry::new(&Slice::new(dir, offset, None))?;
+        if entry.inode.0 != 0 && casefold::same_name(casefolded, &entry.name, name) {
+            return Ok(Some(entry.inode));
+        }
+        offset += rec_len;
+    }
+    Ok(None)
+}
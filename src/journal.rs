@@ -0,0 +1,576 @@
+//! jbd2 journal parsing. All journal structures are big endian.
+
+use std::collections::HashMap;
+use std::io;
+
+use byteorder::{BigEndian, ByteOrder};
+use failure::bail;
+use positioned_io::{ReadAt, Slice};
+
+use crate::features::FeaturePolicy;
+use crate::{Ext4Fs, Extent, Inode, InodeNumber, Result};
+
+const MAGIC: u32 = 0xC03B3998;
+
+const BLOCK_DESCRIPTOR: u32 = 1;
+const BLOCK_COMMIT: u32 = 2;
+const BLOCK_SUPERBLOCK_V1: u32 = 3;
+const BLOCK_SUPERBLOCK_V2: u32 = 4;
+const BLOCK_REVOKE: u32 = 5;
+
+const INCOMPAT_64BIT: u32 = 0x2;
+const INCOMPAT_CSUM_V2: u32 = 0x8;
+const INCOMPAT_CSUM_V3: u32 = 0x10;
+
+const TAG_ESCAPE: u32 = 0x1;
+const TAG_SAME_UUID: u32 = 0x2;
+const TAG_LAST: u32 = 0x8;
+
+// every journal block starts with magic, block type and sequence
+const HEADER_SIZE: usize = 12;
+
+/// Whether transaction `a` comes after `b`, as jbd2's tid_gt has it:
+/// sequence numbers wrap around, the journal never holds transactions
+/// 2^31 apart.
+fn tid_gt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+#[derive(Debug)]
+pub struct JournalSuperBlock {
+    pub block_size: u64,
+    /// total blocks in the journal
+    pub max_len: u64,
+    /// first block of the log
+    pub first: u64,
+    /// sequence of the first transaction in the log
+    pub sequence: u32,
+    /// block the log starts at, 0 when the journal is clean
+    pub start: u64,
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+}
+
+impl JournalSuperBlock {
+    fn new(buf: &[u8]) -> Result<Self> {
+        let magic = BigEndian::read_u32(&buf[0x0..]);
+        let block_type = BigEndian::read_u32(&buf[0x4..]);
+        if magic != MAGIC || !matches!(block_type, BLOCK_SUPERBLOCK_V1 | BLOCK_SUPERBLOCK_V2) {
+            bail!(
+                "bad journal superblock: magic 0x{:X} type {}",
+                magic,
+                block_type
+            );
+        }
+        // v1 superblocks have no feature fields
+        let v2 = block_type == BLOCK_SUPERBLOCK_V2;
+        let feature = |offset| {
+            if v2 {
+                BigEndian::read_u32(&buf[offset..])
+            } else {
+                0
+            }
+        };
+        Ok(Self {
+            block_size: BigEndian::read_u32(&buf[0xC..]) as u64,
+            max_len: BigEndian::read_u32(&buf[0x10..]) as u64,
+            first: BigEndian::read_u32(&buf[0x14..]) as u64,
+            sequence: BigEndian::read_u32(&buf[0x18..]),
+            start: BigEndian::read_u32(&buf[0x1C..]) as u64,
+            feature_compat: feature(0x24),
+            feature_incompat: feature(0x28),
+            feature_ro_compat: feature(0x2C),
+        })
+    }
+
+    fn has_incompat(&self, flag: u32) -> bool {
+        self.feature_incompat & flag != 0
+    }
+
+    /// size of a descriptor block tag, without the optional uuid
+    fn tag_size(&self) -> usize {
+        if self.has_incompat(INCOMPAT_CSUM_V3) {
+            return 16;
+        }
+        let size = if self.has_incompat(INCOMPAT_CSUM_V2) {
+            14
+        } else {
+            12
+        };
+        if self.has_incompat(INCOMPAT_64BIT) {
+            size
+        } else {
+            size - 4
+        }
+    }
+
+    /// descriptor and revoke blocks end with a checksum tail
+    fn tail_size(&self) -> usize {
+        if self.has_incompat(INCOMPAT_CSUM_V2 | INCOMPAT_CSUM_V3) {
+            4
+        } else {
+            0
+        }
+    }
+}
+
+/// A filesystem block logged in a transaction.
+#[derive(Debug, Clone)]
+pub struct JournalBlockTag {
+    /// filesystem block the logged copy belongs to
+    pub target: u64,
+    /// journal block holding the logged copy
+    pub journal_block: u64,
+    /// the copy started with the journal magic, which was zeroed and
+    /// needs to be restored when reading it
+    pub escaped: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum JournalRecordKind {
+    Descriptor(Vec<JournalBlockTag>),
+    Commit,
+    Revoke(Vec<u64>),
+}
+
+/// One metadata block of the log, in log order.
+#[derive(Debug, Clone)]
+pub struct JournalRecord {
+    /// journal block the record was read from
+    pub journal_block: u64,
+    pub sequence: u32,
+    pub kind: JournalRecordKind,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Transaction {
+    pub sequence: u32,
+    pub tags: Vec<JournalBlockTag>,
+    pub revoked: Vec<u64>,
+    /// whether the commit block was found, uncommitted transactions must
+    /// not be replayed
+    pub committed: bool,
+}
+
+pub struct Journal<'a, T: ReadAt> {
+    fs: &'a Ext4Fs<T>,
+    pub sb: JournalSuperBlock,
+    extents: Vec<Extent>,
+}
+
+impl<'a, T: ReadAt> Journal<'a, T> {
+    /// Open the journal stored in the inode named by the superblock.
+    pub fn open(fs: &'a Ext4Fs<T>) -> Result<Self> {
+        if fs.sb.journal_inum == 0 {
+            bail!("filesystem has no internal journal");
+        }
+        let inode = fs.inode(InodeNumber(fs.sb.journal_inum))?;
+        let extents = inode.extents(&fs.sb, &fs.dev)?;
+        let mut journal = Self {
+            fs,
+            sb: JournalSuperBlock {
+                block_size: fs.sb.block_size,
+                max_len: 0,
+                first: 0,
+                sequence: 0,
+                start: 0,
+                feature_compat: 0,
+                feature_incompat: 0,
+                feature_ro_compat: 0,
+            },
+            extents,
+        };
+        journal.sb = JournalSuperBlock::new(&journal.block(0)?)?;
+        if journal.sb.block_size != fs.sb.block_size {
+            bail!(
+                "journal block size {} differs from filesystem block size {}",
+                journal.sb.block_size,
+                fs.sb.block_size
+            );
+        }
+        Ok(journal)
+    }
+
+    /// Read block `n` of the journal.
+    pub fn block(&self, n: u64) -> Result<Vec<u8>> {
+        let physical = match self.extents.iter().find_map(|x| x.map(n)) {
+            Some(x) => x,
+            None => bail!("journal block {} is not mapped", n),
+        };
+        let mut buf = vec![0u8; self.fs.sb.block_size as usize];
+        self.fs
+            .dev
+            .read_exact_at(physical * self.fs.sb.block_size, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read the logged copy of a tagged block, undoing the escaping.
+    pub fn logged_block(&self, tag: &JournalBlockTag) -> Result<Vec<u8>> {
+        let mut buf = self.block(tag.journal_block)?;
+        if tag.escaped {
+            BigEndian::write_u32(&mut buf, MAGIC);
+        }
+        Ok(buf)
+    }
+
+    // the log is circular over blocks first..max_len
+    fn next(&self, block: u64) -> u64 {
+        if block + 1 >= self.sb.max_len {
+            self.sb.first
+        } else {
+            block + 1
+        }
+    }
+
+    /// Walk the log from its start, returning every descriptor, commit and
+    /// revoke block until the sequence breaks. A clean journal has an
+    /// empty log.
+    pub fn records(&self) -> Result<Vec<JournalRecord>> {
+        let mut records = Vec::new();
+        if self.sb.start == 0 {
+            return Ok(records);
+        }
+        let mut block = self.sb.start;
+        let mut sequence = self.sb.sequence;
+        // bounds the walk in case the log loops onto itself
+        for _ in 0..self.sb.max_len {
+            let buf = self.block(block)?;
+            if BigEndian::read_u32(&buf[0x0..]) != MAGIC
+                || BigEndian::read_u32(&buf[0x8..]) != sequence
+            {
+                break;
+            }
+            let record_block = block;
+            block = self.next(block);
+            let kind = match BigEndian::read_u32(&buf[0x4..]) {
+                BLOCK_DESCRIPTOR => {
+                    let mut tags = self.parse_tags(&buf, block);
+                    for tag in &mut tags {
+                        tag.journal_block = block;
+                        block = self.next(block);
+                    }
+                    JournalRecordKind::Descriptor(tags)
+                }
+                BLOCK_COMMIT => {
+                    sequence = sequence.wrapping_add(1);
+                    JournalRecordKind::Commit
+                }
+                BLOCK_REVOKE => JournalRecordKind::Revoke(self.parse_revoke(&buf)),
+                _ => break,
+            };
+            records.push(JournalRecord {
+                journal_block: record_block,
+                sequence: BigEndian::read_u32(&buf[0x8..]),
+                kind,
+            });
+        }
+        Ok(records)
+    }
+
+    /// Group the log records into transactions.
+    pub fn transactions(&self) -> Result<Vec<Transaction>> {
+        let mut transactions: Vec<Transaction> = Vec::new();
+        for record in self.records()? {
+            if transactions.last().map(|x| x.sequence) != Some(record.sequence) {
+                transactions.push(Transaction {
+                    sequence: record.sequence,
+                    ..Default::default()
+                });
+            }
+            let transaction = transactions.last_mut().unwrap();
+            match record.kind {
+                JournalRecordKind::Descriptor(tags) => transaction.tags.extend(tags),
+                JournalRecordKind::Revoke(blocks) => transaction.revoked.extend(blocks),
+                JournalRecordKind::Commit => transaction.committed = true,
+            }
+        }
+        Ok(transactions)
+    }
+
+    /// Parse the tags of a descriptor block. `journal_block` is left at
+    /// `first_data`, the caller assigns the real positions.
+    fn parse_tags(&self, buf: &[u8], first_data: u64) -> Vec<JournalBlockTag> {
+        let tag_size = self.sb.tag_size();
+        let csum_v3 = self.sb.has_incompat(INCOMPAT_CSUM_V3);
+        let is_64bit = self.sb.has_incompat(INCOMPAT_64BIT);
+        let end = buf.len() - self.sb.tail_size();
+        let mut tags = Vec::new();
+        let mut offset = HEADER_SIZE;
+        while offset + tag_size <= end {
+            let tag = &buf[offset..];
+            let low = BigEndian::read_u32(&tag[0x0..]) as u64;
+            // v3 tags have 32 bits flags, older ones 16 bits after a checksum
+            let flags = if csum_v3 {
+                BigEndian::read_u32(&tag[0x4..])
+            } else {
+                BigEndian::read_u16(&tag[0x6..]) as u32
+            };
+            let high = if is_64bit {
+                BigEndian::read_u32(&tag[0x8..]) as u64
+            } else {
+                0
+            };
+            tags.push(JournalBlockTag {
+                target: high << 32 | low,
+                journal_block: first_data,
+                escaped: flags & TAG_ESCAPE != 0,
+            });
+            offset += tag_size;
+            if flags & TAG_SAME_UUID == 0 {
+                offset += 16;
+            }
+            if flags & TAG_LAST != 0 {
+                break;
+            }
+        }
+        tags
+    }
+
+    fn parse_revoke(&self, buf: &[u8]) -> Vec<u64> {
+        // r_count counts bytes used, header included
+        let count = (BigEndian::read_u32(&buf[0xC..]) as usize).min(buf.len());
+        let record_size = if self.sb.has_incompat(INCOMPAT_64BIT) {
+            8
+        } else {
+            4
+        };
+        (HEADER_SIZE + 4..count)
+            .step_by(record_size)
+            .filter(|offset| offset + record_size <= count)
+            .map(|offset| {
+                if record_size == 8 {
+                    BigEndian::read_u64(&buf[offset..])
+                } else {
+                    BigEndian::read_u32(&buf[offset..]) as u64
+                }
+            })
+            .collect()
+    }
+}
+
+/// A device view with the committed journal transactions applied on top,
+/// so an image captured while mounted reads as the kernel would see it
+/// after recovery. Nothing is written back to the device.
+pub struct Replayed<T: ReadAt> {
+    dev: T,
+    block_size: u64,
+    blocks: HashMap<u64, Vec<u8>>,
+    stale: Vec<u64>,
+}
+
+impl<T: ReadAt> Replayed<T> {
+    /// number of filesystem blocks replaced by journaled versions
+    pub fn replayed_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Blocks changed after the point in time of an `as_of` view whose
+    /// earlier contents are no longer in the journal. They read as their
+    /// current version.
+    pub fn stale_blocks(&self) -> &[u64] {
+        &self.stale
+    }
+}
+
+impl<T: ReadAt> ReadAt for Replayed<T> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let block = pos / self.block_size;
+        let offset = (pos % self.block_size) as usize;
+        // never cross a block boundary, callers loop over short reads
+        let len = buf.len().min(self.block_size as usize - offset);
+        match self.blocks.get(&block) {
+            Some(data) => {
+                buf[..len].copy_from_slice(&data[offset..offset + len]);
+                Ok(len)
+            }
+            None => self.dev.read_at(pos, &mut buf[..len]),
+        }
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Reopen the filesystem over a view with committed but not yet
+    /// checkpointed journal transactions replayed in memory.
+    pub fn replayed(self) -> Result<Ext4Fs<Replayed<T>>> {
+        let blocks = {
+            let journal = Journal::open(&self)?;
+            let transactions: Vec<_> = journal
+                .transactions()?
+                .into_iter()
+                .filter(|x| x.committed)
+                .collect();
+            // a revoke cancels writes of the block from its own and
+            // earlier transactions
+            let mut revoked = HashMap::new();
+            for transaction in &transactions {
+                for &block in &transaction.revoked {
+                    revoked.insert(block, transaction.sequence);
+                }
+            }
+            let mut blocks = HashMap::new();
+            for transaction in &transactions {
+                for tag in &transaction.tags {
+                    if revoked
+                        .get(&tag.target)
+                        .is_some_and(|&seq| !tid_gt(transaction.sequence, seq))
+                    {
+                        continue;
+                    }
+                    blocks.insert(tag.target, journal.logged_block(tag)?);
+                }
+            }
+            blocks
+        };
+        let block_size = self.sb.block_size;
+        // the caller already accepted the feature set when opening `self`
+        Ext4Fs::with_policy(
+            Replayed {
+                dev: self.dev,
+                block_size,
+                blocks,
+                stale: Vec::new(),
+            },
+            FeaturePolicy::Ignore,
+        )
+    }
+}
+
+/// A copy of a filesystem block found in the journal area.
+#[derive(Debug, Clone)]
+pub struct BlockVersion {
+    pub sequence: u32,
+    pub tag: JournalBlockTag,
+    /// a commit block for the transaction is still in the journal
+    pub committed: bool,
+}
+
+impl<T: ReadAt> Journal<'_, T> {
+    /// Scan the whole journal area, not just the live log, for every
+    /// transaction still recorded there, oldest first. Old transactions
+    /// stay in the journal until overwritten, so this can turn up
+    /// versions of blocks the filesystem no longer has.
+    pub fn history(&self) -> Result<Vec<Transaction>> {
+        let mut transactions: HashMap<u32, Transaction> = HashMap::new();
+        let mut block = self.sb.first;
+        while block < self.sb.max_len {
+            let buf = self.block(block)?;
+            block += 1;
+            if BigEndian::read_u32(&buf[0x0..]) != MAGIC {
+                continue;
+            }
+            let sequence = BigEndian::read_u32(&buf[0x8..]);
+            let transaction = transactions.entry(sequence).or_insert_with(|| Transaction {
+                sequence,
+                ..Default::default()
+            });
+            match BigEndian::read_u32(&buf[0x4..]) {
+                BLOCK_COMMIT => transaction.committed = true,
+                BLOCK_DESCRIPTOR => {
+                    // data blocks follow the descriptor in tag order
+                    let mut data_block = block;
+                    for mut tag in self.parse_tags(&buf, block) {
+                        tag.journal_block = data_block;
+                        data_block = self.next(data_block);
+                        transaction.tags.push(tag);
+                    }
+                }
+                BLOCK_REVOKE => transaction.revoked.extend(self.parse_revoke(&buf)),
+                _ => {}
+            }
+        }
+        let mut transactions: Vec<_> = transactions.into_values().collect();
+        // before or after the start of the log, through any wrap around
+        let start = self.sb.sequence;
+        transactions.sort_by_key(|x| x.sequence.wrapping_sub(start) as i32);
+        Ok(transactions)
+    }
+
+    /// Logged copies of filesystem block `target` anywhere in the journal
+    /// area, oldest transaction first.
+    pub fn block_history(&self, target: u64) -> Result<Vec<BlockVersion>> {
+        let mut versions = Vec::new();
+        for transaction in self.history()? {
+            for tag in transaction.tags.into_iter().filter(|x| x.target == target) {
+                versions.push(BlockVersion {
+                    sequence: transaction.sequence,
+                    tag,
+                    committed: transaction.committed,
+                });
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Historical versions of an inode, decoded from journaled copies of
+    /// the inode table block holding it.
+    pub fn inode_history(&self, n: InodeNumber) -> Result<Vec<(BlockVersion, Inode)>> {
+        let (block, offset) = n.inode_location(&self.fs.sb, &self.fs.dev)?;
+        let inode_size = self.fs.sb.inode_size;
+        self.block_history(block)?
+            .into_iter()
+            .map(|version| {
+                let buf = self.logged_block(&version.tag)?;
+                let inode = Inode::new(Slice::new(&buf, offset, Some(inode_size)))?
+                    .numbered(n, &self.fs.sb);
+                Ok((version, inode))
+            })
+            .collect()
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Experimental: reopen the filesystem as it was `commits_back`
+    /// committed transactions before the newest one in the journal, to
+    /// see what changed right before a crash. Blocks take their version
+    /// from the newest transaction up to that point; blocks first logged
+    /// after it have no older copy and are listed by
+    /// [`Replayed::stale_blocks`]. Unjournaled file data always reads as
+    /// its current version.
+    pub fn as_of(self, commits_back: usize) -> Result<Ext4Fs<Replayed<T>>> {
+        let (blocks, stale) = {
+            let journal = Journal::open(&self)?;
+            let transactions: Vec<_> = journal
+                .history()?
+                .into_iter()
+                .filter(|x| x.committed)
+                .collect();
+            if commits_back > transactions.len() {
+                bail!(
+                    "only {} committed transactions left in the journal",
+                    transactions.len()
+                );
+            }
+            let (before, after) = transactions.split_at(transactions.len() - commits_back);
+            let mut blocks = HashMap::new();
+            for transaction in before {
+                // a revoked block was freed, later copies are not metadata
+                for block in &transaction.revoked {
+                    blocks.remove(block);
+                }
+                for tag in &transaction.tags {
+                    blocks.insert(tag.target, journal.logged_block(tag)?);
+                }
+            }
+            let mut stale: Vec<_> = after
+                .iter()
+                .flat_map(|x| &x.tags)
+                .map(|x| x.target)
+                .filter(|x| !blocks.contains_key(x))
+                .collect();
+            stale.sort_unstable();
+            stale.dedup();
+            (blocks, stale)
+        };
+        let block_size = self.sb.block_size;
+        Ext4Fs::with_policy(
+            Replayed {
+                dev: self.dev,
+                block_size,
+                blocks,
+                stale,
+            },
+            FeaturePolicy::Ignore,
+        )
+    }
+}
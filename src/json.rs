@@ -0,0 +1,87 @@
+//! Just enough JSON writing for one-object-per-line output: the manifest
+//! and the machine-readable output of the CLI.
+
+use std::fmt::{self, Write as _};
+
+use crate::Timestamp;
+
+/// A JSON object being built, fields in the order they are added.
+#[derive(Debug, Clone)]
+pub struct Object(String);
+
+impl Object {
+    pub fn new() -> Self {
+        Object(String::from("{"))
+    }
+
+    /// Add `"key":value`, `value` already JSON.
+    pub fn field(&mut self, key: &str, value: &str) -> &mut Self {
+        if !self.0.ends_with('{') {
+            self.0.push(',');
+        }
+        let _ = write!(self.0, "{}:{}", string(key), value);
+        self
+    }
+
+    pub fn string(&mut self, key: &str, value: &str) -> &mut Self {
+        self.field(key, &string(value))
+    }
+
+    pub fn number(&mut self, key: &str, value: impl Into<u64>) -> &mut Self {
+        self.field(key, &value.into().to_string())
+    }
+
+    pub fn bool(&mut self, key: &str, value: bool) -> &mut Self {
+        self.field(key, if value { "true" } else { "false" })
+    }
+
+    pub fn time(&mut self, key: &str, value: Timestamp) -> &mut Self {
+        self.field(key, &time(value))
+    }
+}
+
+impl Default for Object {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}}}", self.0)
+    }
+}
+
+/// `s` as a JSON string literal.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Seconds since the epoch with nanoseconds, as a JSON number.
+pub fn time(t: Timestamp) -> String {
+    if t.nanos == 0 {
+        t.seconds.to_string()
+    } else if t.seconds < 0 {
+        // -1.5s is stored as -2 seconds and 500000000 nanoseconds
+        let nanos = 1_000_000_000 - t.nanos;
+        format!("-{}.{:09}", -(t.seconds + 1), nanos)
+    } else {
+        format!("{}.{:09}", t.seconds, t.nanos)
+    }
+}
@@ -0,0 +1,1660 @@
+// `custom_debug_derive` wraps its impls in an anonymous const
+#![allow(non_local_definitions)]
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use failure::{bail, Fallible};
+use positioned_io::{Cursor, ReadAt, Size, Slice};
+
+use custom_debug_derive::Debug as CustomDebug;
+
+mod acl;
+pub mod bitmap;
+pub mod builder;
+mod carve;
+mod casefold;
+pub mod census;
+mod check;
+pub mod compression;
+mod cpio;
+mod crc;
+mod crypt;
+mod deadline;
+pub mod diff;
+pub mod digest;
+pub mod dirhash;
+pub mod events;
+pub mod export;
+#[cfg(unix)]
+pub mod extract;
+pub mod features;
+mod file;
+mod flags;
+mod fs;
+pub mod glob;
+pub mod gzip;
+mod handle;
+pub mod htree;
+pub mod journal;
+pub mod json;
+mod links;
+mod locate;
+mod manifest;
+#[cfg(unix)]
+pub mod mmap;
+pub mod mmp;
+mod orphan;
+mod owners;
+mod paths;
+pub mod progress;
+pub mod quota;
+pub mod quoting;
+mod recover;
+pub mod redact;
+pub mod regex;
+pub mod script;
+pub mod session;
+mod special;
+mod tar;
+mod time;
+mod timeline;
+pub mod trim;
+mod truncated;
+pub mod usage;
+pub mod verity;
+mod walk;
+mod xattr;
+pub mod zstd;
+
+pub use acl::{Acl, AclEntry, AclTag};
+pub use carve::CarvedFile;
+pub use check::CheckReport;
+pub use deadline::Deadline;
+pub use file::{FileReader, Segment};
+pub use flags::InodeFlags;
+pub use fs::Ext4Fs;
+pub use handle::FileHandle;
+pub use links::HardLinks;
+pub use locate::LocateHit;
+pub use paths::PathTable;
+pub use recover::RecoveryCandidate;
+pub use special::{SpecialInode, SpecialInodeEntry};
+pub use time::Timestamp;
+pub use truncated::TruncatedImage;
+pub use walk::{WalkEntry, WalkError, WalkSummary};
+pub use xattr::Xattr;
+
+pub type Result<T> = std::result::Result<T, failure::Error>;
+
+struct Reader<IO: ReadAt> {
+    inner: IO,
+}
+
+impl<IO: ReadAt> Reader<IO> {
+    fn new(io: IO) -> Self {
+        Self { inner: io }
+    }
+
+    fn u8(&self, offset: u64) -> Fallible<u8> {
+        let mut cursor = Cursor::new_pos(&self.inner, offset);
+        Ok(cursor.read_u8()?)
+    }
+
+    fn u16(&self, offset: u64) -> Fallible<u16> {
+        let mut cursor = Cursor::new_pos(&self.inner, offset);
+        Ok(cursor.read_u16::<LittleEndian>()?)
+    }
+
+    fn u32(&self, offset: u64) -> Fallible<u32> {
+        let mut cursor = Cursor::new_pos(&self.inner, offset);
+        Ok(cursor.read_u32::<LittleEndian>()?)
+    }
+
+    fn u64_lohi(&self, lo_offset: u64, hi_offset: u64) -> Fallible<u64> {
+        let lo = self.u32(lo_offset)?;
+        let hi = self.u32(hi_offset)?;
+        Ok((hi as u64) << 32 | lo as u64)
+    }
+
+    fn vec(&self, offset: u64, len: usize) -> Fallible<Vec<u8>> {
+        let mut buff = vec![0u8; len];
+        self.inner.read_exact_at(offset, &mut buff)?;
+        Ok(buff)
+    }
+}
+
+#[derive(Debug)]
+pub struct SuperBlock {
+    pub magic: u16,
+    pub inodes_count: u64,
+    pub blocks_count: u64,
+    pub block_size: u64,
+    pub cluster_size: u64,
+    pub block_per_group: u64,
+    pub cluster_per_group: u64,
+    pub inode_per_group: u64,
+    pub inode_size: u64,
+    pub first_data_block: u64,
+    pub uuid: [u8; 16],
+    /// `s_volume_name`, the label, empty if none was set
+    pub volume_name: String,
+    /// blocks reserved for the superuser
+    pub r_blocks_count: u64,
+    /// free block and inode counters, only updated lazily by the kernel,
+    /// see [`bitmap::FreeSpace`] for the counts in the bitmaps
+    pub free_blocks_count: u64,
+    pub free_inodes_count: u64,
+    /// 0 for the original ext2 layout, 1 once inode size and features
+    /// were made dynamic
+    pub rev_level: u32,
+    pub feature_compat: u32,
+    pub feature_incompat: u32,
+    pub feature_ro_compat: u32,
+    pub desc_size: u64,
+    pub first_meta_bg: u64,
+    pub reserved_gdt_blocks: u64,
+    pub log_groups_per_flex: u32,
+    pub journal_inum: u64,
+    /// first inode not reserved for the filesystem itself
+    pub first_ino: u64,
+    pub last_orphan: u64,
+    /// multi-mount protection block, with the mmp feature
+    pub mmp_block: u64,
+    /// seconds between MMP updates
+    pub mmp_update_interval: u16,
+    pub usr_quota_inum: u64,
+    pub grp_quota_inum: u64,
+    pub prj_quota_inum: u64,
+    /// filename encoding of casefolded directories
+    pub encoding: u16,
+    pub encoding_flags: u16,
+    /// seed of the directory index hashes, all zero for the default seed
+    pub hash_seed: [u32; 4],
+    /// hash of newly indexed directories
+    pub def_hash_version: u8,
+    pub flags: u32,
+    /// seeds the checksums of metadata, crc32c of the uuid unless the
+    /// filesystem stores one
+    pub checksum_seed: u32,
+    pub checksum: u32,
+    /// whether `checksum` matches the superblock, always true without
+    /// metadata_csum
+    pub checksum_valid: bool,
+}
+
+impl SuperBlock {
+    pub fn new<T: ReadAt>(dev: T) -> Result<Self> {
+        Self::parse(dev, None)
+    }
+
+    /// Parse the superblock with `size` as the block size rather than
+    /// the one it records, for images where that field is damaged.
+    pub fn with_block_size<T: ReadAt>(dev: T, size: u64) -> Result<Self> {
+        if !size.is_power_of_two() || !(1024..=65536).contains(&size) {
+            bail!(
+                "block size {} isn't a power of two from 1KiB to 64KiB",
+                size
+            );
+        }
+        Self::parse(dev, Some(size))
+    }
+
+    fn parse<T: ReadAt>(dev: T, block_size: Option<u64>) -> Result<Self> {
+        let r = Reader::new(Slice::new(dev, 1024, None));
+        let magic = r.u16(0x38)?;
+        if magic != Self::MAGIC {
+            bail!("bad superblock magic: 0x{:X}", magic);
+        }
+        let rev_level = r.u32(0x4C)?;
+        // revision 0 superblocks end before the dynamic fields, whatever
+        // lies there is garbage
+        let dynamic = |value: Fallible<u64>, good_old: u64| -> Fallible<u64> {
+            if rev_level == Self::GOOD_OLD_REV {
+                Ok(good_old)
+            } else {
+                value
+            }
+        };
+        let log_block_size = r.u32(0x18)?;
+        let block_size = match block_size {
+            Some(size) => size,
+            None if log_block_size <= 6 => 1024 << log_block_size,
+            None => bail!("block size 2^{} KiB is out of range", log_block_size),
+        };
+        let bpg = r.u32(0x20)?;
+        let ipg = r.u32(0x28)?;
+        let inode_size = dynamic(r.u16(0x58).map(u64::from), Inode::GOOD_OLD_INODE_SIZE)?;
+        let first_data_block = r.u32(0x14)? as u64;
+        let feature_incompat = dynamic(r.u32(0x60).map(u64::from), 0)? as u32;
+        let feature_ro_compat = dynamic(r.u32(0x64).map(u64::from), 0)? as u32;
+        // without bigalloc these were the fragment size fields of ext2,
+        // clusters are blocks then
+        let (cluster_size, cpg) = if feature_ro_compat & Self::RO_COMPAT_BIGALLOC != 0 {
+            let log_cluster_size = r.u32(0x1C)?;
+            if log_cluster_size > 20 {
+                bail!("cluster size 2^{} KiB is out of range", log_cluster_size);
+            }
+            // as many blocks per cluster as recorded, whatever the block size
+            let ratio = 1 << log_cluster_size.saturating_sub(log_block_size);
+            (block_size * ratio, r.u32(0x24)?)
+        } else {
+            (block_size, bpg)
+        };
+        // a bitmap block has a bit per cluster or inode of the group
+        let bits = block_size * 8;
+        if cpg == 0 || cpg as u64 > bits {
+            bail!("{} clusters per group, at most {}", cpg, bits);
+        }
+        if bpg as u64 > bits * (cluster_size / block_size) {
+            bail!("{} blocks per group for {} clusters", bpg, cpg);
+        }
+        if ipg == 0 || ipg as u64 > bits {
+            bail!("{} inodes per group, at most {}", ipg, bits);
+        }
+        // s_desc_size is only meaningful with the 64bit feature,
+        // otherwise descriptors are always 32 bytes
+        let desc_size = if feature_incompat & Self::INCOMPAT_64BIT != 0 {
+            let size = r.u16(0xFE)? as u64;
+            if size < 64 || !size.is_power_of_two() || size > block_size {
+                bail!(
+                    "descriptor size {} isn't a power of two from 64 bytes to the block size",
+                    size
+                );
+            }
+            size
+        } else {
+            32
+        };
+        let log_groups_per_flex = r.u8(0x174)? as u32;
+        if feature_incompat & Self::INCOMPAT_FLEX_BG != 0 && log_groups_per_flex > 31 {
+            bail!("flex group size 2^{} is out of range", log_groups_per_flex);
+        }
+        let checksum = r.u32(0x3FC)?;
+        let checksum_seed = if feature_incompat & Self::INCOMPAT_CSUM_SEED != 0 {
+            r.u32(0x270)?
+        } else {
+            crc::crc32c(!0, &r.vec(0x68, 16)?)
+        };
+        let checksum_valid = feature_ro_compat & Self::RO_COMPAT_METADATA_CSUM == 0
+            || Self::compute_checksum(&r)? == checksum;
+        let lohi = |lo, hi| -> Fallible<u64> {
+            if feature_incompat & Self::INCOMPAT_64BIT != 0 {
+                r.u64_lohi(lo, hi)
+            } else {
+                Ok(r.u32(lo)? as u64)
+            }
+        };
+        let blocks_count = lohi(0x4, 0x150)?;
+        // what `group_count` divides
+        if bpg == 0 {
+            bail!("no blocks per group");
+        }
+        if blocks_count <= first_data_block {
+            bail!(
+                "{} blocks, the first data block being {}",
+                blocks_count,
+                first_data_block
+            );
+        }
+        let volume_name = r.vec(0x78, 16)?;
+        let volume_name = volume_name.split(|&x| x == 0).next().unwrap_or(&[]);
+        Ok(Self {
+            magic,
+            inodes_count: r.u32(0x0)? as u64,
+            blocks_count,
+            block_size,
+            cluster_size,
+            block_per_group: bpg as _,
+            cluster_per_group: cpg as _,
+            inode_per_group: ipg as _,
+            inode_size,
+            first_data_block,
+            uuid: r.vec(0x68, 16)?.try_into().unwrap(),
+            volume_name: String::from_utf8_lossy(volume_name).into_owned(),
+            r_blocks_count: lohi(0x8, 0x154)?,
+            free_blocks_count: lohi(0xC, 0x158)?,
+            free_inodes_count: r.u32(0x10)? as u64,
+            rev_level,
+            feature_compat: dynamic(r.u32(0x5C).map(u64::from), 0)? as u32,
+            feature_incompat,
+            feature_ro_compat,
+            desc_size,
+            first_meta_bg: r.u32(0x104)? as u64,
+            reserved_gdt_blocks: r.u16(0xCE)? as u64,
+            log_groups_per_flex,
+            journal_inum: r.u32(0xE0)? as u64,
+            first_ino: dynamic(r.u32(0x54).map(u64::from), Self::GOOD_OLD_FIRST_INO)?,
+            last_orphan: r.u32(0xE8)? as u64,
+            mmp_block: r.u64_lohi(0x168, 0x16C)?,
+            mmp_update_interval: r.u16(0x166)?,
+            usr_quota_inum: r.u32(0x240)? as u64,
+            grp_quota_inum: r.u32(0x244)? as u64,
+            prj_quota_inum: r.u32(0x26C)? as u64,
+            encoding: r.u16(0x27C)?,
+            encoding_flags: r.u16(0x27E)?,
+            hash_seed: [r.u32(0xEC)?, r.u32(0xF0)?, r.u32(0xF4)?, r.u32(0xF8)?],
+            def_hash_version: r.u8(0xFC)?,
+            flags: r.u32(0x160)?,
+            checksum_seed,
+            checksum,
+            checksum_valid,
+        })
+    }
+
+    /// `uuid` in its usual `8-4-4-4-12` hex form
+    pub fn uuid_string(&self) -> String {
+        let hex: String = self.uuid.iter().map(|b| format!("{b:02x}")).collect();
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..]
+        )
+    }
+
+    pub const MAGIC: u16 = 0xEF53;
+
+    /// directory hashes treat name bytes as signed or unsigned chars
+    pub const FLAGS_SIGNED_HASH: u32 = 0x1;
+    pub const FLAGS_UNSIGNED_HASH: u32 = 0x2;
+
+    const GOOD_OLD_REV: u32 = 0;
+    // fixed first non-reserved inode of revision 0
+    const GOOD_OLD_FIRST_INO: u64 = 11;
+
+    const INCOMPAT_FILETYPE: u32 = 0x2;
+    const INCOMPAT_META_BG: u32 = 0x10;
+    const INCOMPAT_EXTENTS: u32 = 0x40;
+    const INCOMPAT_64BIT: u32 = 0x80;
+    const INCOMPAT_MMP: u32 = 0x100;
+    const INCOMPAT_FLEX_BG: u32 = 0x200;
+    const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+    const INCOMPAT_LARGEDIR: u32 = 0x4000;
+    const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+    const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+    const RO_COMPAT_HUGE_FILE: u32 = 0x8;
+    const RO_COMPAT_GDT_CSUM: u32 = 0x10;
+    const RO_COMPAT_DIR_NLINK: u32 = 0x20;
+    const RO_COMPAT_EXTRA_ISIZE: u32 = 0x40;
+    const RO_COMPAT_BIGALLOC: u32 = 0x200;
+    const RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+    const RO_COMPAT_PROJECT: u32 = 0x2000;
+
+    /// whether directory entries record the type of the inode they point to
+    pub fn has_filetype(&self) -> bool {
+        self.feature_incompat & Self::INCOMPAT_FILETYPE != 0
+    }
+
+    /// whether metadata blocks carry crc32c checksums
+    pub fn has_metadata_csum(&self) -> bool {
+        self.feature_ro_compat & Self::RO_COMPAT_METADATA_CSUM != 0
+    }
+
+    /// whether group descriptors are checksummed, uninit_bg or
+    /// metadata_csum, which is what makes their uninit flags and unused
+    /// inode counts trustworthy
+    pub fn has_group_desc_csum(&self) -> bool {
+        self.feature_ro_compat & (Self::RO_COMPAT_GDT_CSUM | Self::RO_COMPAT_METADATA_CSUM) != 0
+    }
+
+    /// whether inodes carry a project id for project quotas
+    pub fn has_project(&self) -> bool {
+        self.feature_ro_compat & Self::RO_COMPAT_PROJECT != 0
+    }
+
+    pub fn has_mmp(&self) -> bool {
+        self.feature_incompat & Self::INCOMPAT_MMP != 0
+    }
+
+    /// whether directories may be larger than 2 GiB and their hash tree
+    /// three levels deep
+    pub fn has_large_dir(&self) -> bool {
+        self.feature_incompat & Self::INCOMPAT_LARGEDIR != 0
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        self.feature_incompat & Self::INCOMPAT_64BIT != 0
+    }
+
+    pub fn has_meta_bg(&self) -> bool {
+        self.feature_incompat & Self::INCOMPAT_META_BG != 0
+    }
+
+    fn compute_checksum<T: ReadAt>(r: &Reader<T>) -> Result<u32> {
+        // covers everything up to s_checksum itself
+        Ok(crc::crc32c(!0, &r.vec(0, 0x3FC)?))
+    }
+
+    pub fn computed_checksum<T: ReadAt>(&self, dev: T) -> Result<u32> {
+        Self::compute_checksum(&Reader::new(Slice::new(dev, 1024, None)))
+    }
+
+    /// incompat features in use that this crate can't read
+    pub fn unsupported_incompat(&self) -> Vec<String> {
+        features::names(
+            self.feature_incompat & !features::SUPPORTED_INCOMPAT,
+            features::INCOMPAT,
+        )
+    }
+
+    pub fn has_bigalloc(&self) -> bool {
+        self.feature_ro_compat & Self::RO_COMPAT_BIGALLOC != 0
+    }
+
+    /// blocks per allocation cluster, 1 unless bigalloc is enabled.
+    /// extents keep addressing blocks, but bitmaps track clusters.
+    pub fn blocks_per_cluster(&self) -> u64 {
+        if self.has_bigalloc() {
+            self.cluster_size / self.block_size
+        } else {
+            1
+        }
+    }
+
+    /// The cluster holding `block`, none for the blocks before
+    /// `first_data_block`, which belong to no cluster.
+    pub fn block_to_cluster(&self, block: u64) -> Option<u64> {
+        let offset = block.checked_sub(self.first_data_block)?;
+        Some(offset / self.blocks_per_cluster())
+    }
+
+    pub fn cluster_to_block(&self, cluster: u64) -> u64 {
+        self.first_data_block + cluster * self.blocks_per_cluster()
+    }
+
+    /// Never 0, the superblock is refused without blocks past
+    /// `first_data_block` or with 0 blocks per group.
+    pub fn group_count(&self) -> u64 {
+        (self.blocks_count - self.first_data_block).div_ceil(self.block_per_group)
+    }
+
+    pub fn has_flex_bg(&self) -> bool {
+        self.feature_incompat & Self::INCOMPAT_FLEX_BG != 0
+    }
+
+    /// number of groups whose bitmaps and inode tables are packed together
+    /// into the first group of the flex group
+    pub fn groups_per_flex(&self) -> u64 {
+        if self.has_flex_bg() {
+            1 << self.log_groups_per_flex
+        } else {
+            1
+        }
+    }
+
+    /// the group a block physically lives in
+    pub fn block_group_of(&self, block: u64) -> BlockGroupNumber {
+        BlockGroupNumber(block.saturating_sub(self.first_data_block) / self.block_per_group)
+    }
+
+    pub fn descriptors_per_block(&self) -> u64 {
+        self.block_size / self.desc_size
+    }
+
+    /// The block after the superblock's, where the descriptors of group 0
+    /// start. The superblock is at byte 1024, in block 1 with 1 KiB blocks
+    /// even when bigalloc makes block 0 the first data block.
+    pub(crate) fn first_descriptor_block(&self) -> u64 {
+        1024 / self.block_size + 1
+    }
+
+    pub fn group_first_block(&self, group: u64) -> u64 {
+        self.first_data_block + group * self.block_per_group
+    }
+
+    /// whether `group` keeps a copy of the superblock in its first block.
+    /// with sparse_super only groups 0, 1 and powers of 3, 5, 7 do.
+    pub fn group_has_super(&self, group: u64) -> bool {
+        self.feature_ro_compat & Self::RO_COMPAT_SPARSE_SUPER == 0 || Self::sparse_has_super(group)
+    }
+
+    /// `group_has_super` with sparse_super
+    pub(crate) fn sparse_has_super(group: u64) -> bool {
+        if group <= 1 {
+            return true;
+        }
+        [3, 5, 7].iter().any(|&base| {
+            let mut n = base;
+            while n < group {
+                n *= base;
+            }
+            n == group
+        })
+    }
+}
+
+impl std::fmt::Display for SuperBlock {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fs_size = self.blocks_count * self.block_size;
+        writeln!(f, "{:<24}0x{:X}", "magic:", self.magic)?;
+        writeln!(f, "{:<24}{}", "block size:", human_bytes(self.block_size))?;
+        if self.has_bigalloc() {
+            writeln!(
+                f,
+                "{:<24}{}",
+                "cluster size:",
+                human_bytes(self.cluster_size)
+            )?;
+        }
+        writeln!(
+            f,
+            "{:<24}{} ({} blocks)",
+            "filesystem size:",
+            human_bytes(fs_size),
+            self.blocks_count
+        )?;
+        writeln!(f, "{:<24}{}", "first data block:", self.first_data_block)?;
+        writeln!(f, "{:<24}{}", "block groups:", self.group_count())?;
+        writeln!(
+            f,
+            "{:<24}{} ({})",
+            "blocks per group:",
+            self.block_per_group,
+            human_bytes(self.block_per_group * self.block_size)
+        )?;
+        if self.has_flex_bg() {
+            writeln!(f, "{:<24}{}", "groups per flex:", self.groups_per_flex())?;
+        }
+        writeln!(
+            f,
+            "{:<24}{} ({} per group)",
+            "inodes:", self.inodes_count, self.inode_per_group
+        )?;
+        writeln!(f, "{:<24}{} bytes", "inode size:", self.inode_size)?;
+        writeln!(f, "{:<24}{} bytes", "descriptor size:", self.desc_size)?;
+        let names = |flags, table| features::names(flags, table).join(" ");
+        writeln!(
+            f,
+            "{:<24}{}",
+            "compat features:",
+            names(self.feature_compat, features::COMPAT)
+        )?;
+        writeln!(
+            f,
+            "{:<24}{}",
+            "incompat features:",
+            names(self.feature_incompat, features::INCOMPAT)
+        )?;
+        write!(
+            f,
+            "{:<24}{}",
+            "ro_compat features:",
+            names(self.feature_ro_compat, features::RO_COMPAT)
+        )
+    }
+}
+
+/// Render a byte count with a binary unit, e.g. `4.0 KiB`.
+pub fn human_bytes(n: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = n as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{n} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+#[derive(Debug)]
+pub struct BlockGroupDescriptor {
+    pub block_bitmap: u64,
+    pub inode_bitmap: u64,
+    pub inode_table: u64,
+    /// free counters of the group, see [`SuperBlock::free_blocks_count`]
+    pub free_blocks_count: u64,
+    pub free_inodes_count: u64,
+    pub flags: u16,
+    /// inodes at the end of the inode table never used, which may hold
+    /// garbage
+    pub itable_unused: u64,
+    /// `bg_checksum`, see [`BlockGroupNumber::descriptor_checksum`]
+    pub checksum: u16,
+}
+
+impl BlockGroupDescriptor {
+    /// inode bitmap not initialized, no inode of the group is in use
+    pub const INODE_UNINIT: u16 = 0x1;
+    /// block bitmap not initialized, computed from the group layout instead
+    pub const BLOCK_UNINIT: u16 = 0x2;
+    /// inode table zeroed, as mke2fs or the kernel's lazy init do
+    pub const ITABLE_ZEROED: u16 = 0x4;
+
+    const FLAG_NAMES: &'static [(u32, &'static str)] = &[
+        (0x1, "inode_uninit"),
+        (0x2, "block_uninit"),
+        (0x4, "itable_zeroed"),
+    ];
+
+    pub fn new<T: ReadAt>(slice: T, sb: &SuperBlock) -> Result<Self> {
+        let r = Reader::new(slice);
+        // hi fields only exist in 64 bytes descriptors
+        let lohi = |lo, hi| -> Fallible<u64> {
+            if sb.is_64bit() {
+                r.u64_lohi(lo, hi)
+            } else {
+                Ok(r.u32(lo)? as u64)
+            }
+        };
+        let lohi16 = |lo, hi| -> Fallible<u64> {
+            let hi = if sb.is_64bit() { r.u16(hi)? } else { 0 };
+            Ok((hi as u64) << 16 | r.u16(lo)? as u64)
+        };
+        Ok(Self {
+            block_bitmap: lohi(0x0, 0x20)?,
+            inode_bitmap: lohi(0x4, 0x24)?,
+            inode_table: lohi(0x8, 0x28)?,
+            free_blocks_count: lohi16(0xC, 0x2C)?,
+            free_inodes_count: lohi16(0xE, 0x2E)?,
+            flags: r.u16(0x12)?,
+            itable_unused: lohi16(0x1C, 0x32)?,
+            checksum: r.u16(0x1E)?,
+        })
+    }
+
+    /// Name every flag set, unknown ones as hex.
+    pub fn flag_names(&self) -> Vec<String> {
+        features::names(self.flags as u32, Self::FLAG_NAMES)
+    }
+}
+
+/// A metadata block together with the group it is physically stored in,
+/// which with flex_bg is usually the first group of the flex group rather
+/// than the group it describes.
+#[derive(Debug)]
+pub struct MetadataLocation {
+    pub block: u64,
+    pub group: BlockGroupNumber,
+}
+
+impl MetadataLocation {
+    fn new(block: u64, sb: &SuperBlock) -> Self {
+        Self {
+            block,
+            group: sb.block_group_of(block),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GroupMetadataLayout {
+    pub group: BlockGroupNumber,
+    pub flex_group: u64,
+    pub block_bitmap: MetadataLocation,
+    pub inode_bitmap: MetadataLocation,
+    pub inode_table: MetadataLocation,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BlockGroupNumber(pub u64);
+impl BlockGroupNumber {
+    pub fn flex_group(self, sb: &SuperBlock) -> u64 {
+        self.0 / sb.groups_per_flex()
+    }
+
+    pub fn block_group_descriptor_slice<T: ReadAt>(self, sb: &SuperBlock, dev: T) -> Slice<T> {
+        let per_block = sb.descriptors_per_block();
+        let meta_group = self.0 / per_block;
+        let offset = if sb.has_meta_bg() && meta_group >= sb.first_meta_bg {
+            // with meta_bg every meta group (one block worth of descriptors)
+            // keeps its descriptor block in the first group it describes,
+            // right after the superblock backup if that group has one
+            let first_group = meta_group * per_block;
+            let block = match first_group {
+                0 => sb.first_descriptor_block(),
+                group => sb.group_first_block(group) + sb.group_has_super(group) as u64,
+            };
+            block * sb.block_size + (self.0 % per_block) * sb.desc_size
+        } else {
+            sb.first_descriptor_block() * sb.block_size + self.0 * sb.desc_size
+        };
+        Slice::new(dev, offset, Some(sb.desc_size))
+    }
+
+    pub fn block_group_descriptor<T: ReadAt>(
+        self,
+        sb: &SuperBlock,
+        dev: T,
+    ) -> Result<BlockGroupDescriptor> {
+        let slice = self.block_group_descriptor_slice(sb, dev);
+        BlockGroupDescriptor::new(slice, sb)
+    }
+
+    /// The checksum the descriptor should have: the low 16 bits of a
+    /// crc32c with metadata_csum, a crc16 with uninit_bg, `None` when
+    /// descriptors aren't checksummed.
+    pub fn descriptor_checksum<T: ReadAt>(self, sb: &SuperBlock, dev: T) -> Result<Option<u16>> {
+        let mut desc = vec![0u8; sb.desc_size as usize];
+        self.block_group_descriptor_slice(sb, dev)
+            .read_exact_at(0, &mut desc)?;
+        // the checksum itself is left out
+        let (before, after) = (&desc[..0x1E], &desc[0x20..]);
+        let group = (self.0 as u32).to_le_bytes();
+        if sb.has_metadata_csum() {
+            let crc = crc::crc32c(sb.checksum_seed, &group);
+            let crc = crc::crc32c(crc, before);
+            let crc = crc::crc32c(crc, &[0, 0]);
+            Ok(Some(crc::crc32c(crc, after) as u16))
+        } else if sb.has_group_desc_csum() {
+            let crc = crc::crc16(!0, &sb.uuid);
+            let crc = crc::crc16(crc, &group);
+            let crc = crc::crc16(crc, before);
+            Ok(Some(crc::crc16(crc, after)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn metadata_layout<T: ReadAt>(
+        self,
+        sb: &SuperBlock,
+        dev: T,
+    ) -> Result<GroupMetadataLayout> {
+        let bgd = self.block_group_descriptor(sb, dev)?;
+        Ok(GroupMetadataLayout {
+            group: self,
+            flex_group: self.flex_group(sb),
+            block_bitmap: MetadataLocation::new(bgd.block_bitmap, sb),
+            inode_bitmap: MetadataLocation::new(bgd.inode_bitmap, sb),
+            inode_table: MetadataLocation::new(bgd.inode_table, sb),
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct InodeNumber(pub u64);
+impl InodeNumber {
+    pub fn block_group_number(self, sb: &SuperBlock) -> BlockGroupNumber {
+        let n = (self.0 - 1) / sb.inode_per_group;
+        BlockGroupNumber(n)
+    }
+
+    pub fn inode_slice<T: ReadAt>(self, sb: &SuperBlock, dev: T) -> Result<Slice<T>> {
+        let (block, offset) = self.inode_location(sb, &dev)?;
+        let inode_offset = block * sb.block_size + offset;
+        Ok(Slice::new(dev, inode_offset, Some(sb.inode_size)))
+    }
+
+    /// The inode table block holding the inode and its offset in there.
+    pub fn inode_location<T: ReadAt>(self, sb: &SuperBlock, dev: T) -> Result<(u64, u64)> {
+        if self.0 == 0 || self.0 > sb.inodes_count {
+            bail!("inode number {} out of range", self.0);
+        }
+        let bgd = self
+            .block_group_number(sb)
+            .block_group_descriptor(sb, &dev)?;
+        let inode_index = (self.0 - 1) % sb.inode_per_group;
+        let offset = inode_index * sb.inode_size;
+        Ok((
+            bgd.inode_table + offset / sb.block_size,
+            offset % sb.block_size,
+        ))
+    }
+
+    pub fn inode(self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Inode> {
+        let slice = self.inode_slice(sb, dev)?;
+        Ok(Inode::new(slice)?.numbered(self, sb))
+    }
+
+    pub fn typed_inode(self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<TypedInode> {
+        Ok(TypedInode::new(self.inode(sb, dev)?))
+    }
+
+    /// What a reserved inode is used for, `None` for ordinary inodes.
+    pub fn special_label(self) -> Option<&'static str> {
+        SpecialInode::reserved(self).map(SpecialInode::label)
+    }
+}
+#[derive(CustomDebug, Clone)]
+pub struct Inode {
+    #[debug(format = "{:o}")]
+    pub mode: u16,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+    /// `nlink`, the number of directory entries naming the inode. 1 for
+    /// directories with more subdirectories than it can count
+    pub links_count: u16,
+    pub atime: Timestamp,
+    pub ctime: Timestamp,
+    pub mtime: Timestamp,
+    /// creation time, only stored in large inodes
+    pub crtime: Option<Timestamp>,
+    /// project id, only stored in large inodes
+    pub projid: Option<u32>,
+    /// deletion time, or the next inode while on the orphan list
+    pub dtime: u32,
+    pub flags: InodeFlags,
+    pub file_acl: u64,
+    /// i_blocks as stored, see [`Inode::allocated_bytes`]
+    pub blocks: u64,
+    pub generation: u32,
+    /// seeds the checksums of the inode's metadata blocks, `None` without
+    /// metadata_csum or when the inode number isn't known
+    checksum_seed: Option<u32>,
+
+    #[debug(skip)]
+    block: Vec<u8>,
+    #[debug(skip)]
+    xattr_area: Vec<u8>,
+}
+
+impl Inode {
+    // size of the original ext2 inode, anything past it is the extra area
+    const GOOD_OLD_INODE_SIZE: u64 = 128;
+
+    pub fn new<T: ReadAt + Size>(slice: T) -> Result<Self> {
+        let inode_size = slice.size()?.unwrap_or(Self::GOOD_OLD_INODE_SIZE);
+        let r = Reader::new(slice);
+        // end of the fields actually stored in the extra area
+        let extra_end = if inode_size > Self::GOOD_OLD_INODE_SIZE {
+            Self::GOOD_OLD_INODE_SIZE + r.u16(0x80)? as u64
+        } else {
+            Self::GOOD_OLD_INODE_SIZE
+        };
+        let xattr_area = if inode_size > extra_end {
+            r.vec(extra_end, (inode_size - extra_end) as usize)?
+        } else {
+            Vec::new()
+        };
+        let extra = |offset: u64| -> Fallible<Option<u32>> {
+            if offset + 4 <= extra_end {
+                Ok(Some(r.u32(offset)?))
+            } else {
+                Ok(None)
+            }
+        };
+        let crtime = match extra(0x90)? {
+            Some(seconds) => Some(Timestamp::decode(seconds, extra(0x94)?)),
+            None => None,
+        };
+        Ok(Self {
+            mode: r.u16(0x0)?,
+            // owner ids are split into a low half and a high half kept
+            // in the os dependent area
+            uid: (r.u16(0x78)? as u32) << 16 | r.u16(0x2)? as u32,
+            gid: (r.u16(0x7A)? as u32) << 16 | r.u16(0x18)? as u32,
+            size: r.u64_lohi(0x4, 0x6C)?,
+            links_count: r.u16(0x1A)?,
+            atime: Timestamp::decode(r.u32(0x8)?, extra(0x8C)?),
+            ctime: Timestamp::decode(r.u32(0xC)?, extra(0x84)?),
+            mtime: Timestamp::decode(r.u32(0x10)?, extra(0x88)?),
+            crtime,
+            projid: extra(0x9C)?,
+            dtime: r.u32(0x14)?,
+            flags: InodeFlags(r.u32(0x20)?),
+            file_acl: (r.u16(0x76)? as u64) << 32 | r.u32(0x68)? as u64,
+            blocks: (r.u16(0x74)? as u64) << 32 | r.u32(0x1C)? as u64,
+            generation: r.u32(0x64)?,
+            checksum_seed: None,
+            block: r.vec(0x28, 60)?,
+            xattr_area,
+        })
+    }
+
+    /// Remember that this is inode `n`, whose metadata checksums are
+    /// seeded with its number and generation.
+    pub(crate) fn numbered(self, n: InodeNumber, sb: &SuperBlock) -> Self {
+        let checksum_seed = sb.has_metadata_csum().then(|| {
+            let seed = crc::crc32c(sb.checksum_seed, &(n.0 as u32).to_le_bytes());
+            crc::crc32c(seed, &self.generation.to_le_bytes())
+        });
+        Self {
+            checksum_seed,
+            ..self
+        }
+    }
+
+    /// Classify how i_block is laid out, comparing the EXTENTS flag with
+    /// what is actually stored there. Inodes converted from ext3 but
+    /// interrupted half way often disagree.
+    pub fn block_map_kind(&self) -> BlockMapKind {
+        let has_header = u16::from_le_bytes([self.block[0], self.block[1]]) == ExtentHeader::MAGIC;
+        let has_flag = self.flags.contains(InodeFlags::EXTENTS);
+        let in_inode = self.flags.contains(InodeFlags::INLINE_DATA)
+            || matches!(
+                self.file_type(),
+                FileType::CharacterDevice
+                    | FileType::BlockDevice
+                    | FileType::Fifo
+                    | FileType::Socket
+            )
+            || matches!(self.file_type(), FileType::SymbolicLink)
+                && self.size < SymlinkInode::FAST_SYMLINK_MAX
+                && !has_flag;
+        match (has_flag, has_header) {
+            _ if in_inode => BlockMapKind::InInode,
+            (true, true) => BlockMapKind::Extents,
+            (true, false) => BlockMapKind::MissingExtentHeader,
+            (false, true) => BlockMapKind::UnflaggedExtentHeader,
+            (false, false) => BlockMapKind::Indirect,
+        }
+    }
+
+    // inline data fills i_block before spilling into system.data
+    const INLINE_DATA_IN_BLOCK: u64 = 60;
+
+    /// Space allocated to the inode, xattr block included. i_blocks counts
+    /// 512 bytes sectors, or filesystem blocks for huge files.
+    pub fn allocated_bytes(&self, sb: &SuperBlock) -> u64 {
+        if sb.feature_ro_compat & SuperBlock::RO_COMPAT_HUGE_FILE != 0
+            && self.flags.contains(InodeFlags::HUGE_FILE)
+        {
+            self.blocks * sb.block_size
+        } else if sb.feature_ro_compat & SuperBlock::RO_COMPAT_HUGE_FILE != 0 {
+            self.blocks * 512
+        } else {
+            // the high half is fragment data on older filesystems
+            (self.blocks & 0xFFFF_FFFF) * 512
+        }
+    }
+
+    /// fscrypt encrypted: names and content are ciphertext.
+    pub fn is_encrypted(&self) -> bool {
+        self.flags.contains(InodeFlags::ENCRYPT)
+    }
+
+    pub fn file_type(&self) -> FileType {
+        FileType::try_from(self.mode & 0xF000).unwrap_or(FileType::Unknown)
+    }
+
+    /// The type and permissions as `ls -l` shows them, `drwxr-xr-x`, with
+    /// setuid, setgid and sticky bits in the execute columns.
+    pub fn mode_string(&self) -> String {
+        let mut s = String::with_capacity(10);
+        s.push(self.file_type().letter());
+        // read, write, execute, and the special bit over execute with
+        // its letter when executable and when not
+        for (shift, special, letters) in [(6, 0o4000, "sS"), (3, 0o2000, "sS"), (0, 0o1000, "tT")] {
+            let bits = self.mode >> shift;
+            s.push(if bits & 4 != 0 { 'r' } else { '-' });
+            s.push(if bits & 2 != 0 { 'w' } else { '-' });
+            let letters = letters.as_bytes();
+            s.push(match (self.mode & special != 0, bits & 1 != 0) {
+                (true, true) => letters[0] as char,
+                (true, false) => letters[1] as char,
+                (false, true) => 'x',
+                (false, false) => '-',
+            });
+        }
+        s
+    }
+
+    /// Major and minor number of a character or block device. Old
+    /// filesystems pack 8 bits of each into i_block[0], others keep
+    /// Linux's 32 bits `new_encode_dev` form in i_block[1].
+    pub fn device(&self) -> Option<(u32, u32)> {
+        if !matches!(
+            self.file_type(),
+            FileType::CharacterDevice | FileType::BlockDevice
+        ) {
+            return None;
+        }
+        let word = |i: usize| u32::from_le_bytes(self.block[i * 4..i * 4 + 4].try_into().unwrap());
+        let old = word(0);
+        if old != 0 {
+            return Some(((old >> 8) & 0xFF, old & 0xFF));
+        }
+        let new = word(1);
+        Some(((new & 0xFFF00) >> 8, (new & 0xFF) | ((new >> 12) & 0xFFF00)))
+    }
+
+    /// All leaf extents of the inode in logical order. Block mapped
+    /// inodes get one extent per run of contiguous blocks, inodes keeping
+    /// their content in the inode itself get none.
+    pub fn extents(&self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Vec<Extent>> {
+        let mut extents = Vec::new();
+        if matches!(self.block_map_kind(), BlockMapKind::InInode) {
+            return Ok(extents);
+        }
+        if self.flags.contains(InodeFlags::EXTENTS) {
+            Extent::collect(&self.block, None, self.checksum_seed, sb, dev, &mut extents)?;
+        } else {
+            let blocks = self.size.div_ceil(sb.block_size);
+            let mut logical = 0;
+            // 12 direct blocks, then single, double and triple indirect
+            for i in 0..15 {
+                if logical >= blocks {
+                    break;
+                }
+                let ptr = u32::from_le_bytes(self.block[i * 4..i * 4 + 4].try_into().unwrap());
+                let level = i.saturating_sub(11) as u32;
+                Extent::collect_indirect(
+                    ptr as u64,
+                    level,
+                    blocks,
+                    &mut logical,
+                    sb,
+                    dev,
+                    &mut extents,
+                )?;
+            }
+        }
+        Ok(extents)
+    }
+
+    /// The blocks holding the block map itself rather than content:
+    /// extent tree nodes below the inode, or indirect blocks.
+    pub fn map_blocks(&self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Vec<u64>> {
+        let mut blocks = Vec::new();
+        match self.block_map_kind() {
+            BlockMapKind::Extents => {
+                Extent::collect_nodes(&self.block, None, sb, dev, &mut blocks)?
+            }
+            BlockMapKind::Indirect => {
+                // single, double and triple indirect
+                for i in 12..15 {
+                    let ptr = u32::from_le_bytes(self.block[i * 4..i * 4 + 4].try_into().unwrap());
+                    let level = i as u32 - 11;
+                    Extent::collect_indirect_blocks(ptr as u64, level, sb, dev, &mut blocks)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(blocks)
+    }
+
+    pub(crate) fn dir_entries(
+        &self,
+        sb: &SuperBlock,
+        dev: &dyn ReadAt,
+    ) -> Result<Vec<DirectoryEntry>> {
+        self.dir_entries_with(sb, dev, &mut |_, _| {})
+    }
+
+    /// Like `dir_entries`, but entries whose rec_len can't be right are
+    /// passed to `suspicious` as `(offset, rec_len)` and the rest of their
+    /// block is skipped instead of trusting the length.
+    pub(crate) fn dir_entries_with(
+        &self,
+        sb: &SuperBlock,
+        dev: &dyn ReadAt,
+        suspicious: &mut dyn FnMut(u64, u64),
+    ) -> Result<Vec<DirectoryEntry>> {
+        // the entries of an encrypted directory are laid out in the clear,
+        // only the names are encrypted
+        let data = self.raw_reader(sb, dev)?;
+        let r = Reader::new(&data);
+
+        let mut entries = Vec::new();
+        // entries never cross a region: a block, or for inline directories
+        // the rest of i_block and the system.data value
+        let regions: Vec<(u64, u64)> = if self.flags.contains(InodeFlags::INLINE_DATA) {
+            // inline directories start with the parent inode instead of
+            // `.` and `..` entries
+            entries.push(DirectoryEntry {
+                len: 4,
+                inode: InodeNumber(r.u32(0x0)? as u64),
+                name: "..".into(),
+                name_bytes: b"..".to_vec(),
+                encrypted: false,
+                file_type: Some(FileType::Directory),
+            });
+            let in_block = Self::INLINE_DATA_IN_BLOCK.min(self.size);
+            vec![(4, in_block), (in_block, self.size)]
+        } else {
+            (0..self.size)
+                .step_by(sb.block_size as usize)
+                .map(|start| (start, (start + sb.block_size).min(self.size)))
+                .collect()
+        };
+        for (start, end) in regions {
+            let mut offset = start;
+            while offset < end {
+                let rec_len = r.u16(offset + 0x4)? as u64;
+                let name_len = r.u8(offset + 0x6)? as u64;
+                let region_left = end - offset;
+                if rec_len < 8 + name_len || !rec_len.is_multiple_of(4) || rec_len > region_left {
+                    suspicious(offset, rec_len);
+                    break;
+                }
+                let mut entry = DirectoryEntry::new(&Slice::new(&data, offset, None))?;
+                offset += entry.len;
+                if !sb.has_filetype() {
+                    // the byte is the high half of name_len then
+                    entry.file_type = None;
+                }
+                if self.is_encrypted() && entry.name != "." && entry.name != ".." {
+                    entry.name = entry
+                        .name_bytes
+                        .iter()
+                        .map(|b| format!("{b:02x}"))
+                        .collect();
+                    entry.encrypted = true;
+                }
+                // inode 0 marks an unused slot
+                if entry.inode.0 != 0 {
+                    entries.push(entry);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    pub(crate) fn find_entry_name(
+        &self,
+        sb: &SuperBlock,
+        dev: &dyn ReadAt,
+        name: &str,
+    ) -> Result<Option<InodeNumber>> {
+        let entries = self.dir_entries(sb, dev)?;
+        let casefolded = self.is_casefolded(sb);
+        let found = entries
+            .iter()
+            .filter(|x| casefold::same_name(casefolded, &x.name, name))
+            .map(|x| x.inode)
+            .next();
+        if found.is_none() {
+            casefold::check_missing(casefolded, name)?;
+        }
+        Ok(found)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockMapKind {
+    /// EXTENTS flag set and i_block starts with an extent header
+    Extents,
+    /// classic direct/indirect block map
+    Indirect,
+    /// no block map at all, content lives in the inode itself
+    InInode,
+    /// EXTENTS flag set but i_block holds no extent header
+    MissingExtentHeader,
+    /// i_block holds an extent header but the EXTENTS flag is clear
+    UnflaggedExtentHeader,
+}
+
+/// An inode sorted by its file type, so operations which only make sense
+/// for one kind of inode are only reachable from the matching wrapper.
+#[derive(Debug, Clone)]
+pub enum TypedInode {
+    Dir(DirInode),
+    File(FileInode),
+    Symlink(SymlinkInode),
+    Other(Inode),
+}
+
+impl TypedInode {
+    pub fn new(inode: Inode) -> Self {
+        match inode.file_type() {
+            FileType::Directory => Self::Dir(DirInode(inode)),
+            FileType::Regular => Self::File(FileInode(inode)),
+            FileType::SymbolicLink => Self::Symlink(SymlinkInode(inode)),
+            _ => Self::Other(inode),
+        }
+    }
+
+    pub fn inode(&self) -> &Inode {
+        match self {
+            Self::Dir(x) => x,
+            Self::File(x) => x,
+            Self::Symlink(x) => x,
+            Self::Other(x) => x,
+        }
+    }
+
+    pub fn as_dir(&self) -> Result<&DirInode> {
+        match self {
+            Self::Dir(x) => Ok(x),
+            x => bail!("not a directory: {:?}", x.inode().file_type()),
+        }
+    }
+
+    pub fn as_file(&self) -> Result<&FileInode> {
+        match self {
+            Self::File(x) => Ok(x),
+            x => bail!("not a regular file: {:?}", x.inode().file_type()),
+        }
+    }
+
+    pub fn into_dir(self) -> Result<DirInode> {
+        match self {
+            Self::Dir(x) => Ok(x),
+            x => bail!("not a directory: {:?}", x.inode().file_type()),
+        }
+    }
+
+    pub fn into_file(self) -> Result<FileInode> {
+        match self {
+            Self::File(x) => Ok(x),
+            x => bail!("not a regular file: {:?}", x.inode().file_type()),
+        }
+    }
+
+    pub fn into_symlink(self) -> Result<SymlinkInode> {
+        match self {
+            Self::Symlink(x) => Ok(x),
+            x => bail!("not a symbolic link: {:?}", x.inode().file_type()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DirInode(Inode);
+
+impl DirInode {
+    pub fn dir_entries(&self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Vec<DirectoryEntry>> {
+        self.0.dir_entries(sb, dev)
+    }
+
+    pub fn find_entry_name(
+        &self,
+        sb: &SuperBlock,
+        dev: &dyn ReadAt,
+        name: &str,
+    ) -> Result<Option<InodeNumber>> {
+        self.0.find_entry_name(sb, dev, name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileInode(Inode);
+
+impl FileInode {
+    pub fn data<T: ReadAt>(&self, sb: &SuperBlock, dev: T) -> Result<FileReader<T>> {
+        self.0.reader(sb, dev)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SymlinkInode(Inode);
+
+impl SymlinkInode {
+    // targets shorter than 60 bytes are stored right in i_block
+    const FAST_SYMLINK_MAX: u64 = 60;
+
+    pub fn target(&self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<String> {
+        let len = self.0.size as usize;
+        if self.0.is_encrypted() {
+            bail!("symlink target is encrypted, no key");
+        }
+        let buf = if self.0.size < Self::FAST_SYMLINK_MAX {
+            self.0.block[..len].to_vec()
+        } else {
+            let mut buf = vec![0u8; len];
+            self.0.reader(sb, dev)?.read_exact_at(0, &mut buf)?;
+            buf
+        };
+        Ok(String::from_utf8_lossy(&buf).into())
+    }
+}
+
+macro_rules! deref_inode {
+    ($($t:ty),*) => {
+        $(impl std::ops::Deref for $t {
+            type Target = Inode;
+
+            fn deref(&self) -> &Inode {
+                &self.0
+            }
+        })*
+    };
+}
+
+deref_inode!(DirInode, FileInode, SymlinkInode);
+
+impl std::ops::Deref for TypedInode {
+    type Target = Inode;
+
+    fn deref(&self) -> &Inode {
+        self.inode()
+    }
+}
+
+use num_enum::*;
+use std::convert::TryFrom;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive)]
+#[repr(u16)]
+pub enum FileType {
+    /// no type bits, e.g. unused inodes and the bad blocks inode
+    Unknown = 0x0,
+    Fifo = 0x1000,
+    CharacterDevice = 0x2000,
+    Directory = 0x4000,
+    BlockDevice = 0x6000,
+    Regular = 0x8000,
+    SymbolicLink = 0xA000,
+    Socket = 0xC000,
+}
+
+impl FileType {
+    /// Decode the file type byte of a directory entry.
+    pub fn from_dirent(file_type: u8) -> Self {
+        match file_type {
+            1 => Self::Regular,
+            2 => Self::Directory,
+            3 => Self::CharacterDevice,
+            4 => Self::BlockDevice,
+            5 => Self::Fifo,
+            6 => Self::Socket,
+            7 => Self::SymbolicLink,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// The file type byte of a directory entry, see `from_dirent`.
+    pub fn dirent(self) -> u8 {
+        match self {
+            Self::Unknown => 0,
+            Self::Regular => 1,
+            Self::Directory => 2,
+            Self::CharacterDevice => 3,
+            Self::BlockDevice => 4,
+            Self::Fifo => 5,
+            Self::Socket => 6,
+            Self::SymbolicLink => 7,
+        }
+    }
+
+    /// The type letter `ls -l` shows in front of the permissions.
+    pub fn letter(self) -> char {
+        match self {
+            Self::Unknown => '?',
+            Self::Regular => '-',
+            Self::Directory => 'd',
+            Self::CharacterDevice => 'c',
+            Self::BlockDevice => 'b',
+            Self::Fifo => 'p',
+            Self::Socket => 's',
+            Self::SymbolicLink => 'l',
+        }
+    }
+
+    /// The name machine-readable output gives the type.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Unknown => "unknown",
+            Self::Fifo => "fifo",
+            Self::CharacterDevice => "char",
+            Self::Directory => "dir",
+            Self::BlockDevice => "block",
+            Self::Regular => "file",
+            Self::SymbolicLink => "symlink",
+            Self::Socket => "socket",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExtentHeader {
+    pub entries: u64,
+    /// entries the node has room for
+    pub max: u64,
+    pub depth: u64,
+}
+
+impl ExtentHeader {
+    const MAGIC: u16 = 0xF30A;
+
+    pub fn new<T: ReadAt>(slice: T) -> Result<Self> {
+        let r = Reader::new(slice);
+        let magic = r.u16(0x0)?;
+        if magic != Self::MAGIC {
+            bail!("bad extent header magic: 0x{:X}", magic);
+        }
+
+        Ok(Self {
+            entries: r.u16(0x2)? as u64,
+            max: r.u16(0x4)? as u64,
+            depth: r.u16(0x6)? as u64,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Extent {
+    /// first logical block of the file covered by the extent
+    pub block: u64,
+    pub len: u64,
+    pub start: u64,
+    /// allocated but never written, reads as zeros
+    pub uninit: bool,
+}
+
+impl Extent {
+    // ee_len above this marks an uninitialized extent
+    const MAX_INIT_LEN: u64 = 32768;
+
+    pub fn new(slice: &dyn ReadAt) -> Result<Self> {
+        let r = Reader::new(slice);
+        let len = r.u16(0x4)? as u64;
+        let uninit = len > Self::MAX_INIT_LEN;
+        Ok(Self {
+            block: r.u32(0x0)? as u64,
+            len: if uninit {
+                len - Self::MAX_INIT_LEN
+            } else {
+                len
+            },
+            // the block number the extent points to is split
+            // between upper 16-bits and lower 32-bits.
+            start: ((r.u16(0x6)? as u64) << 32) + r.u32(0x8)? as u64,
+            uninit,
+        })
+    }
+
+    /// physical block of logical block `block`, if the extent covers it
+    pub fn map(&self, block: u64) -> Option<u64> {
+        (self.block..self.block + self.len)
+            .contains(&block)
+            .then(|| self.start + block - self.block)
+    }
+
+    // the kernel's EXT4_MAX_EXTENT_DEPTH
+    const MAX_DEPTH: u64 = 5;
+
+    /// The header of tree node `node`, refused unless it sits at `depth`,
+    /// the depth of its parent less one or `None` for the root, and holds
+    /// the entries it claims. Depths going down to 0 is what keeps a
+    /// node pointing back up the tree from being walked forever.
+    fn node_header(node: &[u8], depth: Option<u64>) -> Result<ExtentHeader> {
+        let header = ExtentHeader::new(node)?;
+        if header.depth > Self::MAX_DEPTH {
+            bail!(
+                "extent tree of depth {}, at most {}",
+                header.depth,
+                Self::MAX_DEPTH
+            );
+        }
+        if let Some(depth) = depth.filter(|&x| x != header.depth) {
+            bail!(
+                "extent node of depth {} below one of depth {}",
+                header.depth,
+                depth + 1
+            );
+        }
+        // header and entries are all 12 bytes
+        let room = (node.len() as u64 / 12).saturating_sub(1);
+        if header.entries > header.max.min(room) {
+            bail!(
+                "extent node with {} entries, room for {}",
+                header.entries,
+                header.max.min(room)
+            );
+        }
+        Ok(header)
+    }
+
+    /// Collect the leaf extents of the tree rooted at `node`, which is
+    /// either i_block or an index/leaf block of the tree at `depth`, see
+    /// `node_header`, checking the tail checksum of every block below the
+    /// root when `seed` is given.
+    fn collect(
+        node: &[u8],
+        depth: Option<u64>,
+        seed: Option<u32>,
+        sb: &SuperBlock,
+        dev: &dyn ReadAt,
+        out: &mut Vec<Self>,
+    ) -> Result<()> {
+        let header = Self::node_header(node, depth)?;
+        for i in 0..header.entries {
+            let entry = &node[(12 + i * 12) as usize..];
+            if header.depth == 0 {
+                out.push(Self::new(&entry)?);
+            } else {
+                let r = Reader::new(entry);
+                let leaf = (r.u16(0x8)? as u64) << 32 | r.u32(0x4)? as u64;
+                let mut buf = vec![0u8; sb.block_size as usize];
+                dev.read_exact_at(leaf * sb.block_size, &mut buf)?;
+                if let Some(seed) = seed {
+                    Self::verify_tail(&buf, leaf, seed)?;
+                }
+                Self::collect(&buf, Some(header.depth - 1), seed, sb, dev, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The extent tree blocks below `node`, at `depth` as for `collect`.
+    fn collect_nodes(
+        node: &[u8],
+        depth: Option<u64>,
+        sb: &SuperBlock,
+        dev: &dyn ReadAt,
+        out: &mut Vec<u64>,
+    ) -> Result<()> {
+        let header = Self::node_header(node, depth)?;
+        if header.depth == 0 {
+            return Ok(());
+        }
+        for i in 0..header.entries {
+            let r = Reader::new(&node[(12 + i * 12) as usize..]);
+            let child = (r.u16(0x8)? as u64) << 32 | r.u32(0x4)? as u64;
+            out.push(child);
+            let mut buf = vec![0u8; sb.block_size as usize];
+            dev.read_exact_at(child * sb.block_size, &mut buf)?;
+            Self::collect_nodes(&buf, Some(header.depth - 1), sb, dev, out)?;
+        }
+        Ok(())
+    }
+
+    /// The indirect block `ptr` at `level` and the indirect blocks below.
+    fn collect_indirect_blocks(
+        ptr: u64,
+        level: u32,
+        sb: &SuperBlock,
+        dev: &dyn ReadAt,
+        out: &mut Vec<u64>,
+    ) -> Result<()> {
+        if ptr == 0 || level == 0 {
+            return Ok(());
+        }
+        out.push(ptr);
+        if level > 1 {
+            let mut buf = vec![0u8; sb.block_size as usize];
+            dev.read_exact_at(ptr * sb.block_size, &mut buf)?;
+            for chunk in buf.chunks_exact(4) {
+                let child = u32::from_le_bytes(chunk.try_into().unwrap()) as u64;
+                Self::collect_indirect_blocks(child, level - 1, sb, dev, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the `ext4_extent_tail` following the last entry slot of an
+    /// extent block, a crc32c of everything before it.
+    fn verify_tail(block: &[u8], physical: u64, seed: u32) -> Result<()> {
+        let header = ExtentHeader::new(block)?;
+        let tail = (12 + header.max * 12) as usize;
+        let Some(stored) = block.get(tail..tail + 4) else {
+            bail!("extent block {} holds {} entries", physical, header.max);
+        };
+        let stored = u32::from_le_bytes(stored.try_into().unwrap());
+        let computed = crc::crc32c(seed, &block[..tail]);
+        if stored != computed {
+            bail!(
+                "extent block {} checksum 0x{:08X}, computed 0x{:08X}",
+                physical,
+                stored,
+                computed
+            );
+        }
+        Ok(())
+    }
+
+    /// Map the blocks below pointer `ptr` of an indirect block map, `level`
+    /// 0 being a data block, starting at logical block `*logical`.
+    fn collect_indirect(
+        ptr: u64,
+        level: u32,
+        blocks: u64,
+        logical: &mut u64,
+        sb: &SuperBlock,
+        dev: &dyn ReadAt,
+        out: &mut Vec<Self>,
+    ) -> Result<()> {
+        let per_block = sb.block_size / 4;
+        let span = per_block.pow(level);
+        if ptr == 0 {
+            // a hole
+            *logical += span;
+            return Ok(());
+        }
+        if level == 0 {
+            match out.last_mut() {
+                Some(last) if last.block + last.len == *logical && last.start + last.len == ptr => {
+                    last.len += 1
+                }
+                _ => out.push(Self {
+                    block: *logical,
+                    len: 1,
+                    start: ptr,
+                    uninit: false,
+                }),
+            }
+            *logical += 1;
+            return Ok(());
+        }
+        let mut buf = vec![0u8; sb.block_size as usize];
+        dev.read_exact_at(ptr * sb.block_size, &mut buf)?;
+        for chunk in buf.chunks_exact(4) {
+            if *logical >= blocks {
+                break;
+            }
+            let child = u32::from_le_bytes(chunk.try_into().unwrap()) as u64;
+            Self::collect_indirect(child, level - 1, blocks, logical, sb, dev, out)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(CustomDebug)]
+pub struct DirectoryEntry {
+    #[debug(skip)]
+    pub len: u64,
+    pub inode: InodeNumber,
+    /// lossily decoded as UTF-8, for entries of encrypted directories the
+    /// hex encoded ciphertext
+    pub name: String,
+    /// the name as stored, see [`quoting::QuotingStyle`] to display it
+    pub name_bytes: Vec<u8>,
+    /// the name is encrypted
+    pub encrypted: bool,
+    /// type of the inode, recorded in the entry with the filetype feature
+    /// so listings don't need to read the inode
+    pub file_type: Option<FileType>,
+}
+
+impl DirectoryEntry {
+    pub fn new(slice: &dyn ReadAt) -> Result<Self> {
+        let r = Reader::new(slice);
+        let name_len = r.u8(0x6)? as usize;
+        let name_bytes = r.vec(0x8, name_len)?;
+        Ok(Self {
+            inode: InodeNumber(r.u32(0x0)? as u64),
+            len: r.u16(0x4)? as u64,
+            name: String::from_utf8_lossy(&name_bytes).into(),
+            name_bytes,
+            encrypted: false,
+            file_type: Some(FileType::from_dirent(r.u8(0x7)?)),
+        })
+    }
+}
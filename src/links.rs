@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use positioned_io::ReadAt;
+
+use crate::{Ext4Fs, FileType, InodeNumber, Result, WalkEntry};
+
+/// Directory entries sharing an inode, collected during a walk so hard
+/// links can be recreated rather than their content duplicated.
+#[derive(Debug, Default)]
+pub struct HardLinks {
+    /// paths in walk order and the inode's link count
+    inodes: HashMap<InodeNumber, (Vec<String>, u16)>,
+}
+
+impl HardLinks {
+    /// Record `entry`, returning the path its inode was first met under
+    /// if it was met before. Directories and inodes with a single link
+    /// are never recorded.
+    pub fn add(&mut self, entry: &WalkEntry) -> Option<&str> {
+        if entry.inode.links_count < 2 || entry.inode.file_type() == FileType::Directory {
+            return None;
+        }
+        let (paths, _) = self
+            .inodes
+            .entry(entry.inode_number)
+            .or_insert_with(|| (Vec::new(), entry.inode.links_count));
+        paths.push(entry.path.clone());
+        if paths.len() > 1 {
+            Some(&paths[0])
+        } else {
+            None
+        }
+    }
+
+    /// Inodes met under more than one path, with their paths.
+    pub fn groups(&self) -> impl Iterator<Item = (InodeNumber, &[String])> {
+        self.inodes
+            .iter()
+            .filter(|(_, (paths, _))| paths.len() > 1)
+            .map(|(n, (paths, _))| (*n, paths.as_slice()))
+    }
+
+    /// Inodes met fewer times than their link count says, linked from
+    /// outside the walked tree or with a wrong count.
+    pub fn incomplete(&self) -> impl Iterator<Item = (InodeNumber, usize, u16)> + '_ {
+        self.inodes
+            .iter()
+            .filter(|(_, (paths, nlink))| paths.len() < *nlink as usize)
+            .map(|(n, (paths, nlink))| (*n, paths.len(), *nlink))
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Group the hard links below `path`.
+    pub fn hard_links(&self, path: &str) -> Result<HardLinks> {
+        let mut links = HardLinks::default();
+        self.walk(path, false, |entry| {
+            links.add(entry);
+            Ok(())
+        })?;
+        Ok(links)
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::HashSet;
+
+use positioned_io::ReadAt;
+
+use crate::{Ext4Fs, FileType, InodeNumber, Reader, Result};
+
+/// A directory entry whose name matched.
+#[derive(Debug, Clone)]
+pub struct LocateHit {
+    /// block the entry was found in
+    pub block: u64,
+    /// directory owning the block, `None` for blocks found by scanning
+    /// outside any directory
+    pub dir: Option<InodeNumber>,
+    pub name: String,
+    pub inode: InodeNumber,
+    /// found in the slack space of another entry, where unlinked entries
+    /// remain until overwritten
+    pub deleted: bool,
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Find directory entries whose name contains `pattern` by reading
+    /// the blocks of every directory inode, without walking the tree.
+    /// With `scan_all_blocks`, every other block of the device that looks
+    /// like a directory block is searched too, which turns up entries of
+    /// directories that no longer exist.
+    pub fn locate(&self, pattern: &str, scan_all_blocks: bool) -> Result<Vec<LocateHit>> {
+        let mut hits = Vec::new();
+        let mut seen = HashSet::new();
+        for n in self.initialized_inodes()? {
+            let inode = self.inode(n)?;
+            if inode.links_count == 0 || !matches!(inode.file_type(), FileType::Directory) {
+                continue;
+            }
+            // directories this crate can't map are skipped, not fatal
+            let Ok(extents) = inode.extents(&self.sb, &self.dev) else {
+                continue;
+            };
+            for extent in extents.iter().filter(|x| !x.uninit) {
+                for block in extent.start..extent.start + extent.len {
+                    seen.insert(block);
+                    self.locate_in_block(block, Some(n), pattern, &mut hits)?;
+                }
+            }
+        }
+        if scan_all_blocks {
+            for block in self.sb.first_data_block..self.sb.blocks_count {
+                if !seen.contains(&block) {
+                    self.locate_in_block(block, None, pattern, &mut hits)?;
+                }
+            }
+        }
+        Ok(hits)
+    }
+
+    fn locate_in_block(
+        &self,
+        block: u64,
+        dir: Option<InodeNumber>,
+        pattern: &str,
+        hits: &mut Vec<LocateHit>,
+    ) -> Result<()> {
+        let mut buf = vec![0u8; self.sb.block_size as usize];
+        self.dev
+            .read_exact_at(block * self.sb.block_size, &mut buf)?;
+        let Some(entries) = scan_dir_block(&buf, self.sb.inodes_count) else {
+            return Ok(());
+        };
+        hits.extend(entries.into_iter().filter(|x| x.0.contains(pattern)).map(
+            |(name, inode, deleted)| LocateHit {
+                block,
+                dir,
+                name,
+                inode,
+                deleted,
+            },
+        ));
+        Ok(())
+    }
+}
+
+/// Parse `buf` as a directory block, returning `(name, inode, deleted)`
+/// for live entries and for plausible entries left in the slack space
+/// after them, skipping those whose name isn't UTF-8. `None` if the
+/// rec_len chain doesn't cover the block exactly, or a live entry has no
+/// valid name, meaning it isn't a directory block.
+fn scan_dir_block(buf: &[u8], inodes_count: u64) -> Option<Vec<(String, InodeNumber, bool)>> {
+    let r = Reader::new(buf);
+    let entry = |offset: u64| -> Option<(u64, u64, u64)> {
+        let inode = r.u32(offset).ok()? as u64;
+        let rec_len = r.u16(offset + 0x4).ok()? as u64;
+        let name_len = r.u8(offset + 0x6).ok()? as u64;
+        Some((inode, rec_len, name_len))
+    };
+    let name = |offset: u64, len: u64| -> Option<&[u8]> {
+        let bytes = buf.get((offset + 8) as usize..(offset + 8 + len) as usize)?;
+        if bytes.is_empty() || bytes.iter().any(|&b| b == 0 || b == b'/') {
+            return None;
+        }
+        Some(bytes)
+    };
+    let utf8 = |bytes: &[u8]| std::str::from_utf8(bytes).ok().map(String::from);
+    let len = buf.len() as u64;
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let (inode, rec_len, name_len) = entry(offset)?;
+        if rec_len < 8 + name_len || !rec_len.is_multiple_of(4) || offset + rec_len > len {
+            return None;
+        }
+        if inode != 0 && inode <= inodes_count {
+            if let Some(name) = utf8(name(offset, name_len)?) {
+                entries.push((name, InodeNumber(inode), false));
+            }
+        }
+        // unlinked entries are merged into the rec_len of the one before
+        let mut slack = offset + ((8 + name_len + 3) & !3);
+        while slack + 8 < offset + rec_len {
+            match entry(slack) {
+                Some((inode, _, name_len))
+                    if inode != 0
+                        && inode <= inodes_count
+                        && slack + 8 + name_len <= offset + rec_len =>
+                {
+                    if let Some(bytes) = name(slack, name_len) {
+                        if let Some(name) = utf8(bytes) {
+                            entries.push((name, InodeNumber(inode), true));
+                        }
+                        slack += (8 + name_len + 3) & !3;
+                        continue;
+                    }
+                    slack += 4;
+                }
+                _ => slack += 4,
+            }
+        }
+        offset += rec_len;
+    }
+    Some(entries)
+}
@@ -0,0 +1,135 @@
+//! Metadata-only export: the structure of a tree, its permissions,
+//! extended attributes and content digests, without the content itself,
+//! so an image's layout can be shared without sharing its data.
+//!
+//! The manifest is JSON lines, one object per entry in walk order.
+
+use std::io::Write;
+
+use positioned_io::ReadAt;
+
+use crate::digest::{HashAlgorithm, MultiHasher};
+use crate::export::NameTransform;
+use crate::json::Object;
+use crate::redact::RedactionPolicy;
+use crate::{Ext4Fs, FileType, HardLinks, Inode, Result, TypedInode, WalkSummary};
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Write a manifest of `path` and everything below it to `out`. Names
+    /// go through `transform`, entries it drops are left out. Regular
+    /// files get a digest of their content with each of `algorithms`,
+    /// further names of a hard-linked file only point to the first.
+    /// Files `redaction` replaces by a placeholder are described as if
+    /// holding it, and marked redacted. Entries that can't be read are
+    /// recorded in the summary with `keep_going`, otherwise the first is
+    /// an error.
+    pub fn write_manifest(
+        &self,
+        path: &str,
+        keep_going: bool,
+        transform: &dyn NameTransform,
+        redaction: &RedactionPolicy,
+        algorithms: &[HashAlgorithm],
+        out: &mut dyn Write,
+    ) -> Result<WalkSummary> {
+        let root = format!("/{}", path.trim_matches('/'));
+        let mut links = HardLinks::default();
+        self.walk(&root, keep_going, |entry| {
+            if redaction.skips(&entry.path) {
+                return Ok(());
+            }
+            let relative = entry.path[root.len()..].trim_start_matches('/');
+            // a single file is listed under its own name
+            let relative = match relative {
+                "" if entry.inode.file_type() != FileType::Directory => {
+                    root.rsplit('/').next().unwrap_or_default()
+                }
+                x => x,
+            };
+            let Some(name) = transform.transform(relative) else {
+                return Ok(());
+            };
+            let name = if name.is_empty() { "." } else { &name };
+            let mut line = Object::new();
+            line.string("path", name);
+            let placeholder = match entry.inode.file_type() {
+                FileType::Regular => redaction.placeholder(&entry.path),
+                _ => None,
+            };
+            if let Some(first) = placeholder.is_none().then(|| links.add(entry)).flatten() {
+                let first = first[root.len()..].trim_start_matches('/');
+                let first = transform.transform(first).unwrap_or_default();
+                line.string("link", &first);
+                writeln!(out, "{line}")?;
+                return Ok(());
+            }
+            self.manifest_fields(&mut line, &entry.inode, placeholder, redaction, algorithms)?;
+            writeln!(out, "{line}")?;
+            Ok(())
+        })
+    }
+
+    fn manifest_fields(
+        &self,
+        line: &mut Object,
+        inode: &Inode,
+        placeholder: Option<&[u8]>,
+        redaction: &RedactionPolicy,
+        algorithms: &[HashAlgorithm],
+    ) -> Result<()> {
+        line.string("type", inode.file_type().name());
+        line.string("mode", &format!("{:04o}", inode.mode & 0o7777));
+        line.number("uid", inode.uid);
+        line.number("gid", inode.gid);
+        if self.sb.has_project() {
+            line.number("project", inode.projid.unwrap_or(0));
+        }
+        let size = placeholder.map_or(inode.size, |x| x.len() as u64);
+        line.number("size", size);
+        line.time("mtime", inode.mtime);
+        line.time("ctime", inode.ctime);
+        if let Some(crtime) = inode.crtime {
+            line.time("crtime", crtime);
+        }
+        if let Some((major, minor)) = inode.device() {
+            line.field("rdev", &format!("[{major},{minor}]"));
+        }
+        match TypedInode::new(inode.clone()) {
+            TypedInode::Symlink(x) => {
+                let target = x.target(&self.sb, &self.dev)?;
+                line.string("target", &target);
+            }
+            TypedInode::File(_) if !algorithms.is_empty() => {
+                let digests = match placeholder {
+                    Some(text) => {
+                        let mut hasher = MultiHasher::new(algorithms);
+                        hasher.update(text);
+                        hasher.finish()
+                    }
+                    None => self.digests(inode, algorithms)?,
+                };
+                let mut object = Object::new();
+                for digest in digests {
+                    object.string(digest.algorithm.name(), &digest.to_string());
+                }
+                line.field("digests", &object.to_string());
+            }
+            _ => {}
+        }
+        if placeholder.is_some() {
+            line.bool("redacted", true);
+        }
+        let mut xattrs = inode.xattrs(&self.sb, &self.dev)?;
+        xattrs.retain(|x| redaction.keeps_xattr(&x.full_name()));
+        if !xattrs.is_empty() {
+            // values are binary as often as not, always hex
+            let mut object = Object::new();
+            for xattr in xattrs {
+                let value: String = xattr.value.iter().map(|b| format!("{b:02x}")).collect();
+                object.string(&xattr.full_name(), &value);
+            }
+            line.field("xattrs", &object.to_string());
+        }
+        Ok(())
+    }
+}
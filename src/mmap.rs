@@ -0,0 +1,246 @@
+//! A memory mapped image, letting readers borrow metadata and file
+//! content straight from the mapping instead of copying it into buffers.
+
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+
+use failure::bail;
+use positioned_io::{ReadAt, Size};
+
+use crate::{Ext4Fs, FileReader, Inode, InodeNumber, Result};
+
+/// Bytes copied at a time for content that can't be borrowed.
+const CHUNK: u64 = 1 << 20;
+
+/// An image or device mapped read-only. Truncating the file while it is
+/// mapped makes reads of the lost part fault.
+pub struct Mmap {
+    ptr: *const u8,
+    len: usize,
+}
+
+// the mapping is read-only and lives as long as the struct
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    pub fn open(file: &File) -> io::Result<Self> {
+        // block devices have no size in their metadata
+        let len = (&*file).seek(SeekFrom::End(0))? as usize;
+        if len == 0 {
+            return Ok(Self {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len,
+            });
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// `len` bytes at `pos`, `None` past the end of the mapping.
+    pub fn get(&self, pos: u64, len: u64) -> Option<&[u8]> {
+        get(self.as_slice(), pos, len)
+    }
+}
+
+/// `len` bytes at `pos` in `image`, `None` past its end.
+fn get(image: &[u8], pos: u64, len: u64) -> Option<&[u8]> {
+    let end = pos.checked_add(len)?;
+    image.get(usize::try_from(pos).ok()?..usize::try_from(end).ok()?)
+}
+
+/// Devices the borrowing readers of `Ext4Fs` can borrow from, those
+/// holding the whole image in memory.
+pub trait Mapped {
+    /// The whole image, `None` when it isn't mapped after all.
+    fn image(&self) -> Option<&[u8]>;
+}
+
+impl Mapped for Mmap {
+    fn image(&self) -> Option<&[u8]> {
+        Some(self.as_slice())
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+        }
+    }
+}
+
+impl ReadAt for Mmap {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.as_slice();
+        let start = usize::try_from(pos).unwrap_or(usize::MAX).min(data.len());
+        let len = buf.len().min(data.len() - start);
+        buf[..len].copy_from_slice(&data[start..start + len]);
+        Ok(len)
+    }
+}
+
+impl Size for Mmap {
+    fn size(&self) -> io::Result<Option<u64>> {
+        Ok(Some(self.len as u64))
+    }
+}
+
+/// A piece of file content, in file order.
+#[derive(Debug)]
+pub enum Chunk<'a> {
+    /// borrowed from the mapping
+    Data(&'a [u8]),
+    /// a hole or an uninitialized extent
+    Zeros(u64),
+    /// content that had to be copied, inline data or decrypted blocks
+    Owned(Vec<u8>),
+}
+
+impl Chunk<'_> {
+    pub fn len(&self) -> u64 {
+        match self {
+            Self::Data(x) => x.len() as u64,
+            Self::Zeros(len) => *len,
+            Self::Owned(x) => x.len() as u64,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// The content of a file piece by piece, see `Ext4Fs::chunks`.
+pub struct Chunks<'a, T: ReadAt> {
+    inner: ChunksInner<'a, T>,
+}
+
+enum ChunksInner<'a, T: ReadAt> {
+    Borrowed(std::vec::IntoIter<Chunk<'a>>),
+    /// read `CHUNK` bytes at a time from `pos` up to `size`
+    Copied {
+        reader: Box<FileReader<&'a T>>,
+        pos: u64,
+        size: u64,
+    },
+}
+
+impl<'a, T: ReadAt> Iterator for Chunks<'a, T> {
+    type Item = Result<Chunk<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.inner {
+            ChunksInner::Borrowed(chunks) => chunks.next().map(Ok),
+            ChunksInner::Copied { reader, pos, size } => {
+                if *pos >= *size {
+                    return None;
+                }
+                let mut data = vec![0; CHUNK.min(*size - *pos) as usize];
+                if let Err(e) = reader.read_exact_at(*pos, &mut data) {
+                    *pos = *size;
+                    return Some(Err(e.into()));
+                }
+                *pos += data.len() as u64;
+                Some(Ok(Chunk::Owned(data)))
+            }
+        }
+    }
+}
+
+impl Ext4Fs<Mmap> {
+    /// Open the filesystem over a memory mapping of `file`, enabling the
+    /// borrowing readers below. The usual readers keep working and copy
+    /// out of the mapping.
+    pub fn open_mmap(file: &File) -> Result<Self> {
+        Self::new(Mmap::open(file)?)
+    }
+}
+
+impl<T: ReadAt + Mapped> Ext4Fs<T> {
+    /// Borrow a whole block.
+    pub fn block_bytes(&self, block: u64) -> Result<&[u8]> {
+        let Some(image) = self.dev.image() else {
+            bail!("block {}: the image isn't mapped", block);
+        };
+        let bs = self.sb.block_size;
+        let bytes = block.checked_mul(bs).and_then(|pos| get(image, pos, bs));
+        match bytes {
+            Some(x) => Ok(x),
+            None => bail!("block {} beyond the end of the image", block),
+        }
+    }
+
+    /// Borrow the on-disk record of inode `n`.
+    pub fn inode_bytes(&self, n: InodeNumber) -> Result<&[u8]> {
+        let (block, offset) = n.inode_location(&self.sb, &self.dev)?;
+        match get(self.block_bytes(block)?, offset, self.sb.inode_size) {
+            Some(x) => Ok(x),
+            None => bail!("inode {} past the end of block {}", n.0, block),
+        }
+    }
+
+    /// The content of `inode` up to its size, borrowed from the mapping
+    /// wherever it is stored in the clear, read a piece at a time
+    /// otherwise.
+    pub fn chunks(&self, inode: &Inode) -> Result<Chunks<'_, T>> {
+        let reader = self.reader_of(inode)?;
+        let borrowed = self.dev.image().and_then(|x| reader.chunks(x));
+        let inner = match borrowed {
+            Some(chunks) => ChunksInner::Borrowed(chunks.into_iter()),
+            // copy out inline data, decrypted blocks and extents past the
+            // end of the image, for the reader to refuse
+            None => ChunksInner::Copied {
+                reader: Box::new(reader),
+                pos: 0,
+                size: inode.size,
+            },
+        };
+        Ok(Chunks { inner })
+    }
+
+    /// Write the content of `inode` to `out` without intermediate copies,
+    /// returning the number of bytes written.
+    pub fn cat(&self, inode: &Inode, out: &mut dyn Write) -> Result<u64> {
+        static ZEROS: [u8; 64 * 1024] = [0; 64 * 1024];
+
+        let mut written = 0;
+        for chunk in self.chunks(inode)? {
+            let chunk = chunk?;
+            match &chunk {
+                Chunk::Data(x) => out.write_all(x)?,
+                Chunk::Owned(x) => out.write_all(x)?,
+                Chunk::Zeros(len) => {
+                    let mut left = *len;
+                    while left > 0 {
+                        let n = left.min(ZEROS.len() as u64);
+                        out.write_all(&ZEROS[..n as usize])?;
+                        left -= n;
+                    }
+                }
+            }
+            written += chunk.len();
+        }
+        Ok(written)
+    }
+}
@@ -0,0 +1,81 @@
+use failure::bail;
+use positioned_io::ReadAt;
+
+use crate::{Ext4Fs, Reader, Result, Timestamp};
+
+/// What the sequence number of the MMP block says about the filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmpState {
+    /// cleanly unmounted
+    Clean,
+    /// being checked by e2fsck
+    Fsck,
+    /// mounted, the node bumps the sequence every update interval
+    InUse(u32),
+}
+
+/// The multi-mount protection block, through which a node mounting the
+/// filesystem tells the others it is in use.
+#[derive(Debug, Clone)]
+pub struct Mmp {
+    pub block: u64,
+    pub state: MmpState,
+    /// last update by the node holding the filesystem
+    pub time: Timestamp,
+    pub node_name: String,
+    pub device_name: String,
+    /// seconds between updates while mounted
+    pub check_interval: u16,
+}
+
+impl Mmp {
+    const MAGIC: u32 = 0x004D4D50;
+    const SEQ_CLEAN: u32 = 0xFF4D4D50;
+    const SEQ_FSCK: u32 = 0xE24D4D50;
+
+    pub fn in_use(&self) -> bool {
+        matches!(self.state, MmpState::InUse(_))
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Read the MMP block, `None` without the mmp feature.
+    pub fn mmp(&self) -> Result<Option<Mmp>> {
+        if !self.sb.has_mmp() {
+            return Ok(None);
+        }
+        let block = self.sb.mmp_block;
+        if block == 0 || block >= self.sb.blocks_count {
+            bail!("mmp block {} out of range", block);
+        }
+        let mut buf = vec![0u8; self.sb.block_size as usize];
+        self.dev
+            .read_exact_at(block * self.sb.block_size, &mut buf)?;
+        let r = Reader::new(buf.as_slice());
+        let magic = r.u32(0x0)?;
+        if magic != Mmp::MAGIC {
+            bail!("bad mmp magic: 0x{:X}", magic);
+        }
+        let string = |offset: u64, len: usize| -> Result<String> {
+            let raw = r.vec(offset, len)?;
+            let end = raw.iter().position(|&b| b == 0).unwrap_or(len);
+            Ok(String::from_utf8_lossy(&raw[..end]).into())
+        };
+        let state = match r.u32(0x4)? {
+            Mmp::SEQ_CLEAN => MmpState::Clean,
+            Mmp::SEQ_FSCK => MmpState::Fsck,
+            sequence => MmpState::InUse(sequence),
+        };
+        Ok(Some(Mmp {
+            block,
+            state,
+            time: Timestamp {
+                seconds: r.u64_lohi(0x8, 0xC)? as i64,
+                nanos: 0,
+            },
+            node_name: string(0x10, 64)?,
+            device_name: string(0x50, 32)?,
+            check_interval: r.u16(0x70)?,
+        }))
+    }
+}
@@ -0,0 +1,23 @@
+use std::collections::HashSet;
+
+use positioned_io::ReadAt;
+
+use crate::{Ext4Fs, InodeNumber, Result};
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Inodes on the orphan list: unlinked (or being truncated) while
+    /// still open when the image was captured. The list starts at
+    /// s_last_orphan and each orphan's i_dtime holds the next one.
+    pub fn orphans(&self) -> Result<Vec<InodeNumber>> {
+        let mut orphans = Vec::new();
+        let mut seen = HashSet::new();
+        let mut next = self.sb.last_orphan;
+        // a corrupted chain may loop or point outside the inode table
+        while next != 0 && next <= self.sb.inodes_count && seen.insert(next) {
+            let n = InodeNumber(next);
+            orphans.push(n);
+            next = self.inode(n)?.dtime as u64;
+        }
+        Ok(orphans)
+    }
+}
@@ -0,0 +1,52 @@
+//! From blocks back to the inodes using them, debugfs `icheck` style.
+
+use std::collections::HashMap;
+
+use positioned_io::ReadAt;
+
+use crate::{Ext4Fs, InodeNumber, Result};
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// The inode using each of `blocks` for its content, its block map or
+    /// its extended attributes, `None` for blocks no inode uses. Every
+    /// in-use inode is scanned until all blocks are found, those whose
+    /// block map can't be read are passed over. A block claimed by more
+    /// than one inode is given to the first.
+    pub fn block_owners(&self, blocks: &[u64]) -> Result<Vec<Option<InodeNumber>>> {
+        let mut wanted: Vec<u64> = blocks.to_vec();
+        wanted.sort_unstable();
+        wanted.dedup();
+        let mut owners: HashMap<u64, InodeNumber> = HashMap::new();
+        for n in self.initialized_inodes()? {
+            if owners.len() == wanted.len() {
+                break;
+            }
+            let Ok(inode) = self.inode(n) else {
+                continue;
+            };
+            if inode.mode == 0 || inode.links_count == 0 {
+                continue;
+            }
+            let mut claim = |first: u64, len: u64| {
+                let from = wanted.partition_point(|&x| x < first);
+                for &block in wanted[from..].iter().take_while(|&&x| x < first + len) {
+                    owners.entry(block).or_insert(n);
+                }
+            };
+            if inode.file_acl != 0 {
+                claim(inode.file_acl, 1);
+            }
+            if let Ok(blocks) = inode.map_blocks(&self.sb, &self.dev) {
+                for block in blocks {
+                    claim(block, 1);
+                }
+            }
+            if let Ok(extents) = inode.extents(&self.sb, &self.dev) {
+                for extent in extents {
+                    claim(extent.start, extent.len);
+                }
+            }
+        }
+        Ok(blocks.iter().map(|x| owners.get(x).copied()).collect())
+    }
+}
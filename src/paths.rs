@@ -0,0 +1,76 @@
+use std::collections::{HashMap, HashSet};
+
+use positioned_io::ReadAt;
+
+use crate::{Ext4Fs, FileType, InodeNumber, Result};
+
+/// Every path of the image by inode number, built once so reverse
+/// lookups don't need a tree walk each.
+#[derive(Debug, Default)]
+pub struct PathTable {
+    paths: HashMap<InodeNumber, Vec<String>>,
+}
+
+impl PathTable {
+    /// All paths of `n`, several for hard links.
+    pub fn paths(&self, n: InodeNumber) -> &[String] {
+        self.paths.get(&n).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// The first path of `n` in on-disk order.
+    pub fn path(&self, n: InodeNumber) -> Option<&str> {
+        self.paths(n).first().map(String::as_str)
+    }
+
+    /// Number of inodes reachable from the root.
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (InodeNumber, &[String])> {
+        self.paths.iter().map(|(n, paths)| (*n, paths.as_slice()))
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Map every inode reachable from the root to its paths. Only
+    /// directories are read when entries record their file type.
+    /// Unreadable directories are skipped.
+    pub fn path_table(&self) -> Result<PathTable> {
+        let mut table = PathTable::default();
+        table.paths.insert(Self::ROOT, vec!["/".to_string()]);
+        let mut visited = HashSet::new();
+        let mut stack = vec![(Self::ROOT, String::new())];
+        while let Some((dir, path)) = stack.pop() {
+            // a directory reachable twice is corrupted, don't loop
+            if !visited.insert(dir) {
+                continue;
+            }
+            let Ok(entries) = self.inode(dir).and_then(|x| self.entries_of(dir, &x)) else {
+                continue;
+            };
+            for entry in entries {
+                if entry.name == "." || entry.name == ".." {
+                    continue;
+                }
+                let child = format!("{path}/{}", entry.name);
+                let is_dir = match entry.file_type {
+                    Some(file_type) => file_type == FileType::Directory,
+                    None => matches!(
+                        self.inode(entry.inode).map(|x| x.file_type()),
+                        Ok(FileType::Directory)
+                    ),
+                };
+                if is_dir {
+                    stack.push((entry.inode, child.clone()));
+                }
+                table.paths.entry(entry.inode).or_default().push(child);
+            }
+        }
+        Ok(table)
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::{Duration, Instant};
+
+/// A snapshot of a long running operation, handed to a [`ProgressSink`]
+/// as work is done.
+#[derive(Debug, Clone)]
+pub struct Progress<'a> {
+    /// entries, files or inodes, dealt with so far
+    pub entries_done: u64,
+    pub bytes_done: u64,
+    /// 0 when the total isn't known up front
+    pub bytes_total: u64,
+    pub current_path: &'a str,
+    pub elapsed: Duration,
+}
+
+impl Progress<'_> {
+    /// bytes per second since the operation started
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.bytes_done as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// estimated time left at the current throughput
+    pub fn eta(&self) -> Option<Duration> {
+        let throughput = self.throughput();
+        if self.bytes_total == 0 || throughput <= 0.0 {
+            return None;
+        }
+        let left = self.bytes_total.saturating_sub(self.bytes_done);
+        Some(Duration::from_secs_f64(left as f64 / throughput))
+    }
+}
+
+pub trait ProgressSink {
+    fn progress(&mut self, progress: &Progress<'_>);
+}
+
+impl<F: FnMut(&Progress<'_>)> ProgressSink for F {
+    fn progress(&mut self, progress: &Progress<'_>) {
+        self(progress)
+    }
+}
+
+/// Ignores all progress, for callers that don't care.
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {
+    fn progress(&mut self, _: &Progress<'_>) {}
+}
+
+/// Accumulates work done by an operation and forwards it to a sink.
+pub struct ProgressTracker<'a> {
+    sink: &'a mut dyn ProgressSink,
+    start: Instant,
+    entries_done: u64,
+    bytes_done: u64,
+    bytes_total: u64,
+}
+
+impl<'a> ProgressTracker<'a> {
+    pub fn new(sink: &'a mut dyn ProgressSink, bytes_total: u64) -> Self {
+        Self {
+            sink,
+            start: Instant::now(),
+            entries_done: 0,
+            bytes_done: 0,
+            bytes_total,
+        }
+    }
+
+    pub fn advance(&mut self, current_path: &str, bytes: u64) {
+        self.bytes_done += bytes;
+        self.report(current_path);
+    }
+
+    /// Count one more entry done.
+    pub fn entry(&mut self, current_path: &str) {
+        self.entries_done += 1;
+        self.report(current_path);
+    }
+
+    fn report(&mut self, current_path: &str) {
+        self.sink.progress(&Progress {
+            entries_done: self.entries_done,
+            bytes_done: self.bytes_done,
+            bytes_total: self.bytes_total,
+            current_path,
+            elapsed: self.start.elapsed(),
+        });
+    }
+}
@@ -0,0 +1,160 @@
+use failure::bail;
+use positioned_io::ReadAt;
+
+use crate::{Ext4Fs, InodeNumber, Reader, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotaType {
+    User,
+    Group,
+    Project,
+}
+
+impl QuotaType {
+    fn magic(self) -> u32 {
+        match self {
+            Self::User => 0xD9C01F11,
+            Self::Group => 0xD9C01927,
+            Self::Project => 0xD9C03F14,
+        }
+    }
+}
+
+/// Grace periods and flags of a quota file.
+#[derive(Debug, Clone)]
+pub struct QuotaInfo {
+    /// seconds
+    pub block_grace: u32,
+    /// seconds
+    pub inode_grace: u32,
+    pub flags: u32,
+}
+
+/// Usage and limits of one uid, gid or project id. Space is in bytes,
+/// a limit of 0 means unlimited.
+#[derive(Debug, Clone)]
+pub struct QuotaEntry {
+    pub id: u32,
+    pub inode_hard_limit: u64,
+    pub inode_soft_limit: u64,
+    pub inodes_used: u64,
+    pub space_hard_limit: u64,
+    pub space_soft_limit: u64,
+    pub space_used: u64,
+    /// when the soft space limit grace period ends, 0 if not over it
+    pub space_grace_expires: u64,
+    /// when the soft inode limit grace period ends, 0 if not over it
+    pub inode_grace_expires: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct QuotaFile {
+    pub kind: QuotaType,
+    pub inode: InodeNumber,
+    pub info: QuotaInfo,
+    pub entries: Vec<QuotaEntry>,
+}
+
+impl QuotaFile {
+    /// Usage and limits of `id`, `None` if the file has no entry for it.
+    pub fn entry(&self, id: u32) -> Option<&QuotaEntry> {
+        let i = self.entries.binary_search_by_key(&id, |x| x.id).ok()?;
+        Some(&self.entries[i])
+    }
+}
+
+// the quota tree is made of 1 KiB blocks, the root is block 1
+const TREE_BLOCK_SIZE: u64 = 1024;
+const TREE_ROOT: u64 = 1;
+const TREE_DEPTH: u32 = 4;
+// leaf blocks start with a 16 bytes header, then v2r1 entries
+const LEAF_HEADER_SIZE: u64 = 16;
+const ENTRY_SIZE: u64 = 72;
+// limits are counted in 1 KiB quota blocks
+const QUOTA_BLOCK_SIZE: u64 = 1024;
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Parse the hidden quota file of `kind`, `None` if the superblock
+    /// doesn't advertise one.
+    pub fn quota(&self, kind: QuotaType) -> Result<Option<QuotaFile>> {
+        let inum = match kind {
+            QuotaType::User => self.sb.usr_quota_inum,
+            QuotaType::Group => self.sb.grp_quota_inum,
+            QuotaType::Project => self.sb.prj_quota_inum,
+        };
+        if inum == 0 {
+            return Ok(None);
+        }
+        let n = InodeNumber(inum);
+        let file = self.inode(n)?.reader(&self.sb, &self.dev)?;
+        let r = Reader::new(&file);
+        let magic = r.u32(0x0)?;
+        if magic != kind.magic() {
+            bail!("bad {:?} quota magic: 0x{:X}", kind, magic);
+        }
+        let info = QuotaInfo {
+            block_grace: r.u32(0x8)?,
+            inode_grace: r.u32(0xC)?,
+            flags: r.u32(0x10)?,
+        };
+        let mut leaves = Vec::new();
+        collect_leaves(&r, TREE_ROOT, 0, &mut leaves)?;
+        leaves.sort_unstable();
+        leaves.dedup();
+        let mut entries = Vec::new();
+        for leaf in leaves {
+            let base = leaf * TREE_BLOCK_SIZE;
+            let mut offset = LEAF_HEADER_SIZE;
+            while offset + ENTRY_SIZE <= TREE_BLOCK_SIZE {
+                let raw = r.vec(base + offset, ENTRY_SIZE as usize)?;
+                // unused slots are all zeros
+                if raw.iter().any(|&b| b != 0) {
+                    let r = Reader::new(raw.as_slice());
+                    let u64_at = |offset| -> Result<u64> {
+                        Ok((r.u32(offset + 4)? as u64) << 32 | r.u32(offset)? as u64)
+                    };
+                    entries.push(QuotaEntry {
+                        id: r.u32(0x0)?,
+                        inode_hard_limit: u64_at(0x8)?,
+                        inode_soft_limit: u64_at(0x10)?,
+                        inodes_used: u64_at(0x18)?,
+                        space_hard_limit: u64_at(0x20)? * QUOTA_BLOCK_SIZE,
+                        space_soft_limit: u64_at(0x28)? * QUOTA_BLOCK_SIZE,
+                        space_used: u64_at(0x30)?,
+                        space_grace_expires: u64_at(0x38)?,
+                        inode_grace_expires: u64_at(0x40)?,
+                    });
+                }
+                offset += ENTRY_SIZE;
+            }
+        }
+        entries.sort_by_key(|x| x.id);
+        Ok(Some(QuotaFile {
+            kind,
+            inode: n,
+            info,
+            entries,
+        }))
+    }
+}
+
+/// Collect the leaf blocks referenced below tree block `block`.
+fn collect_leaves<R: ReadAt>(
+    r: &Reader<R>,
+    block: u64,
+    depth: u32,
+    leaves: &mut Vec<u64>,
+) -> Result<()> {
+    for i in 0..TREE_BLOCK_SIZE / 4 {
+        let child = r.u32(block * TREE_BLOCK_SIZE + i * 4)? as u64;
+        if child == 0 {
+            continue;
+        }
+        if depth + 1 == TREE_DEPTH {
+            leaves.push(child);
+        } else {
+            collect_leaves(r, child, depth + 1, leaves)?;
+        }
+    }
+    Ok(())
+}
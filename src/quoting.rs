@@ -0,0 +1,178 @@
+//! Rendering of file names which may hold anything but `/` and NUL, for
+//! output read by humans or shell pipelines.
+
+use std::fmt::Write;
+use std::str::FromStr;
+
+use failure::{bail, Error};
+
+/// How to render a name, named after `ls --quoting-style`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuotingStyle {
+    /// as is, invalid UTF-8 replaced
+    Literal,
+    /// as is, but control characters and invalid UTF-8 shown as `?`
+    #[default]
+    Printable,
+    /// shell quoted when needed, control characters left as is
+    Shell,
+    /// always shell quoted
+    ShellAlways,
+    /// shell quoted when needed, control bytes as `$'\ooo'`
+    ShellEscape,
+    /// C string literal
+    C,
+    /// backslash escapes, no quotes
+    Escape,
+    /// percent-encoded, as in URIs
+    Uri,
+}
+
+impl FromStr for QuotingStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        Ok(match s {
+            "literal" => Self::Literal,
+            "locale" | "printable" => Self::Printable,
+            "shell" => Self::Shell,
+            "shell-always" => Self::ShellAlways,
+            "shell-escape" => Self::ShellEscape,
+            "c" => Self::C,
+            "escape" => Self::Escape,
+            "uri" => Self::Uri,
+            _ => bail!("unknown quoting style: {}", s),
+        })
+    }
+}
+
+/// Split `name` into its valid UTF-8 characters and invalid bytes.
+fn chunks(name: &[u8]) -> Vec<Result<char, u8>> {
+    let mut out = Vec::new();
+    for chunk in name.utf8_chunks() {
+        out.extend(chunk.valid().chars().map(Ok));
+        out.extend(chunk.invalid().iter().map(|&b| Err(b)));
+    }
+    out
+}
+
+fn c_escape(out: &mut String, c: Result<char, u8>, quote: Option<char>) {
+    match c {
+        Ok('\\') => out.push_str("\\\\"),
+        Ok('\n') => out.push_str("\\n"),
+        Ok('\t') => out.push_str("\\t"),
+        Ok('\r') => out.push_str("\\r"),
+        Ok(c) if Some(c) == quote => {
+            out.push('\\');
+            out.push(c);
+        }
+        Ok(c) if !c.is_control() => out.push(c),
+        Ok(c) => {
+            let mut buf = [0u8; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                let _ = write!(out, "\\{b:03o}");
+            }
+        }
+        Err(b) => {
+            let _ = write!(out, "\\{b:03o}");
+        }
+    }
+}
+
+// characters the shell never needs quoted
+fn shell_safe(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c) || !c.is_ascii() && !c.is_control()
+}
+
+// characters which keep a special meaning inside double quotes
+fn double_quote_special(c: char) -> bool {
+    "$`\\\"!".contains(c) || c.is_control()
+}
+
+impl QuotingStyle {
+    pub fn render(self, name: &[u8]) -> String {
+        let chars = chunks(name);
+        let mut out = String::new();
+        match self {
+            Self::Literal => out = String::from_utf8_lossy(name).into(),
+            Self::Printable => {
+                for c in chars {
+                    out.push(match c {
+                        Ok(c) if !c.is_control() => c,
+                        _ => '?',
+                    });
+                }
+            }
+            Self::Escape => {
+                for c in chars {
+                    match c {
+                        Ok(' ') => out.push_str("\\ "),
+                        c => c_escape(&mut out, c, None),
+                    }
+                }
+            }
+            Self::C => {
+                out.push('"');
+                for c in chars {
+                    c_escape(&mut out, c, Some('"'));
+                }
+                out.push('"');
+            }
+            Self::Uri => {
+                for b in name {
+                    if b.is_ascii_alphanumeric() || b"-._~/".contains(b) {
+                        out.push(*b as char);
+                    } else {
+                        let _ = write!(out, "%{b:02X}");
+                    }
+                }
+            }
+            Self::Shell | Self::ShellAlways | Self::ShellEscape => {
+                let needs_quotes = self == Self::ShellAlways
+                    || chars.is_empty()
+                    || chars.iter().any(|c| !matches!(c, Ok(c) if shell_safe(*c)));
+                if !needs_quotes {
+                    return String::from_utf8_lossy(name).into();
+                }
+                // a single quote alone reads better in double quotes
+                let double = chars.contains(&Ok('\''))
+                    && chars
+                        .iter()
+                        .all(|c| matches!(c, Ok(c) if !double_quote_special(*c)));
+                if double {
+                    return format!("\"{}\"", String::from_utf8_lossy(name));
+                }
+                let mut quoted = false;
+                for c in chars {
+                    let plain = match c {
+                        Ok(c) => !c.is_control() || self != Self::ShellEscape,
+                        Err(_) => self != Self::ShellEscape,
+                    };
+                    if plain {
+                        if !quoted {
+                            out.push('\'');
+                            quoted = true;
+                        }
+                        match c {
+                            Ok('\'') => out.push_str("'\\''"),
+                            Ok(c) => out.push(c),
+                            Err(_) => out.push(char::REPLACEMENT_CHARACTER),
+                        }
+                    } else {
+                        if quoted {
+                            out.push('\'');
+                            quoted = false;
+                        }
+                        out.push_str("$'");
+                        c_escape(&mut out, c, Some('\''));
+                        out.push('\'');
+                    }
+                }
+                if quoted {
+                    out.push('\'');
+                }
+            }
+        }
+        out
+    }
+}
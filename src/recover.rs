@@ -0,0 +1,196 @@
+use std::io::Write;
+
+use positioned_io::ReadAt;
+
+use crate::trim::FreeRange;
+use crate::{Ext4Fs, Extent, ExtentHeader, Inode, InodeFlags, InodeNumber, Result, SuperBlock};
+
+/// Leading bytes of common file formats, used to tell whether the first
+/// block of a deleted file still holds the start of a file.
+pub(crate) const SIGNATURES: &[(&str, &[u8])] = &[
+    ("png", b"\x89PNG\r\n\x1a\n"),
+    ("jpeg", b"\xff\xd8\xff"),
+    ("gif", b"GIF8"),
+    ("pdf", b"%PDF-"),
+    ("zip", b"PK\x03\x04"),
+    ("gzip", b"\x1f\x8b"),
+    ("xz", b"\xfd7zXZ\x00"),
+    ("bzip2", b"BZh"),
+    ("zstd", b"\x28\xb5\x2f\xfd"),
+    ("elf", b"\x7fELF"),
+    ("sqlite", b"SQLite format 3\x00"),
+    ("script", b"#!"),
+];
+
+/// The format whose signature `data` starts with.
+pub(crate) fn sniff(data: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(_, magic)| data.starts_with(magic))
+        .map(|(name, _)| *name)
+}
+
+/// A deleted inode together with how likely its content is to be
+/// recoverable.
+#[derive(Debug, Clone)]
+pub struct RecoveryCandidate {
+    pub inode_number: InodeNumber,
+    pub inode: Inode,
+    /// data extents, possibly salvaged from a truncated extent header
+    pub extents: Vec<Extent>,
+    /// 0 to 100, higher is more likely recoverable
+    pub score: u32,
+    /// what the score is made of, for display
+    pub reasons: Vec<String>,
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Every deleted inode still holding metadata, most recoverable first.
+    pub fn recovery_candidates(&self) -> Result<Vec<RecoveryCandidate>> {
+        let free = self.trim_report()?.ranges;
+        let mut candidates = Vec::new();
+        for n in self.initialized_inodes()? {
+            if n.0 < self.sb.first_ino {
+                continue;
+            }
+            let inode = self.inode(n)?;
+            if inode.mode == 0 || inode.links_count != 0 || inode.dtime == 0 {
+                continue;
+            }
+            candidates.push(self.score(n, inode, &free)?);
+        }
+        candidates.sort_by(|a, b| {
+            b.score
+                .cmp(&a.score)
+                .then(b.inode.dtime.cmp(&a.inode.dtime))
+        });
+        Ok(candidates)
+    }
+
+    /// Write what the extents of `candidate` still hold to `out`, in file
+    /// order with holes as zeros, up to its size or the end of the last
+    /// extent if that comes first. Blocks may have been reused since, the
+    /// score says how likely that is. Returns the number of bytes written.
+    pub fn recover_content(
+        &self,
+        candidate: &RecoveryCandidate,
+        out: &mut dyn Write,
+    ) -> Result<u64> {
+        let block_size = self.sb.block_size;
+        let mut extents = candidate.extents.clone();
+        extents.sort_by_key(|x| x.block);
+        let mapped = extents.last().map_or(0, |x| (x.block + x.len) * block_size);
+        let end = candidate.inode.size.min(mapped);
+        let mut buf = vec![0u8; block_size as usize];
+        let mut pos = 0;
+        for extent in &extents {
+            let from = extent.block * block_size;
+            let to = ((extent.block + extent.len) * block_size).min(end);
+            while pos < to {
+                // zeros for a hole before the extent
+                if pos < from {
+                    let len = (from.min(to) - pos).min(block_size) as usize;
+                    buf.fill(0);
+                    out.write_all(&buf[..len])?;
+                    pos += len as u64;
+                    continue;
+                }
+                let len = (to - pos).min(block_size) as usize;
+                if extent.uninit {
+                    buf.fill(0);
+                } else {
+                    let offset = extent.start * block_size + pos - from;
+                    self.dev.read_exact_at(offset, &mut buf[..len])?;
+                }
+                out.write_all(&buf[..len])?;
+                pos += len as u64;
+            }
+        }
+        Ok(pos)
+    }
+
+    fn score(&self, n: InodeNumber, inode: Inode, free: &[FreeRange]) -> Result<RecoveryCandidate> {
+        let sb = &self.sb;
+        let mut score = 0;
+        let mut reasons = Vec::new();
+
+        let device_bytes = sb.blocks_count * sb.block_size;
+        if inode.size > 0 && inode.size <= device_bytes {
+            score += 15;
+            reasons.push("sane size".to_string());
+        }
+
+        let dtime = inode.dtime as i64;
+        if inode.mtime.seconds > 0 && inode.mtime.seconds <= dtime && inode.ctime.seconds <= dtime {
+            score += 15;
+            reasons.push("timestamps before deletion".to_string());
+        }
+
+        let extents = inode
+            .extents(sb, &self.dev)
+            .ok()
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| inode.salvage_extents(sb));
+        let in_range = extents
+            .iter()
+            .all(|x| x.len > 0 && x.start + x.len <= sb.blocks_count);
+        if !extents.is_empty() && in_range {
+            let mapped: u64 = extents.iter().map(|x| x.len).sum();
+            if mapped * sb.block_size >= inode.size {
+                score += 30;
+                reasons.push("extents cover the size".to_string());
+            } else {
+                score += 10;
+                reasons.push("extents cover part of the size".to_string());
+            }
+            let is_free = |block: u64| {
+                free.iter()
+                    .any(|x| (x.start..x.start + x.blocks).contains(&block))
+            };
+            if extents
+                .iter()
+                .all(|x| is_free(x.start) && is_free(x.start + x.len - 1))
+            {
+                score += 25;
+                reasons.push("blocks not reallocated".to_string());
+            }
+            let first = extents.iter().min_by_key(|x| x.block).unwrap();
+            let mut head = vec![0u8; 16];
+            self.dev
+                .read_exact_at(first.start * sb.block_size, &mut head)?;
+            if let Some(format) = sniff(&head).filter(|_| first.block == 0) {
+                score += 15;
+                reasons.push(format!("{format} signature"));
+            }
+        } else {
+            reasons.push("no usable extents".to_string());
+        }
+
+        Ok(RecoveryCandidate {
+            inode_number: n,
+            inode,
+            extents,
+            score,
+            reasons,
+        })
+    }
+}
+
+impl Inode {
+    /// Extents left in i_block by a truncate, which clears the entry count
+    /// of the header but may leave the entries themselves behind.
+    pub(crate) fn salvage_extents(&self, sb: &SuperBlock) -> Vec<Extent> {
+        let Ok(header) = ExtentHeader::new(&self.block[..12]) else {
+            return Vec::new();
+        };
+        if !self.flags.contains(InodeFlags::EXTENTS) || header.depth != 0 {
+            return Vec::new();
+        }
+        // i_block holds at most 4 entries after the header
+        self.block[12..]
+            .chunks_exact(12)
+            .filter_map(|entry| Extent::new(&entry).ok())
+            .filter(|x| x.len > 0 && x.start != 0 && x.start + x.len <= sb.blocks_count)
+            .collect()
+    }
+}
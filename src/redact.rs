@@ -0,0 +1,110 @@
+//! Redaction policies, applied while exporting or extracting so an image
+//! can be shared without what it shouldn't carry outside.
+//!
+//! ```text
+//! # lines starting with # are comments
+//! skip /home/*/.ssh
+//! skip *.key
+//! placeholder /etc/shadow
+//! placeholder **/secrets/*.json {"redacted": true}
+//! strip-xattr security.*
+//! ```
+//!
+//! `skip` leaves out matching entries and everything below them.
+//! `placeholder` replaces the content of matching regular files with the
+//! rest of the line and a newline, `[redacted]` if there is none.
+//! `strip-xattr` drops extended attributes by full name.
+//!
+//! Paths are matched from the root of the image, whatever is exported,
+//! see [`crate::glob`] for the patterns. Rules apply to names, not inodes: a
+//! hard link under a name no rule matches keeps the content.
+
+use failure::bail;
+
+use crate::glob::{glob, path_matches};
+use crate::Result;
+
+const DEFAULT_PLACEHOLDER: &str = "[redacted]";
+
+#[derive(Debug, Clone, Default)]
+pub struct RedactionPolicy {
+    skip: Vec<String>,
+    placeholders: Vec<(String, Vec<u8>)>,
+    strip_xattrs: Vec<String>,
+}
+
+impl RedactionPolicy {
+    /// A policy redacting nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut policy = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let (pattern, rest) = rest
+                .trim()
+                .split_once(char::is_whitespace)
+                .unwrap_or((rest.trim(), ""));
+            if pattern.is_empty() {
+                bail!("line {}: {} without a pattern", number, keyword);
+            }
+            let pattern = pattern.to_string();
+            match keyword {
+                "skip" | "strip-xattr" if !rest.is_empty() => {
+                    bail!("line {}: trailing {:?}", number, rest.trim())
+                }
+                "skip" => policy.skip.push(pattern),
+                "strip-xattr" => policy.strip_xattrs.push(pattern),
+                "placeholder" => {
+                    let text = match rest.trim() {
+                        "" => DEFAULT_PLACEHOLDER,
+                        x => x,
+                    };
+                    policy
+                        .placeholders
+                        .push((pattern, format!("{text}\n").into_bytes()));
+                }
+                _ => bail!("line {}: unknown rule {:?}", number, keyword),
+            }
+        }
+        Ok(policy)
+    }
+
+    /// Whether the entry at the absolute image path `path` is left out,
+    /// because it or a directory above it matches a `skip` rule.
+    pub fn skips(&self, path: &str) -> bool {
+        let path = path.trim_matches('/');
+        let ancestors = path
+            .match_indices('/')
+            .map(|(i, _)| &path[..i])
+            .chain([path]);
+        ancestors
+            .filter(|x| !x.is_empty())
+            .any(|x| self.skip.iter().any(|p| path_matches(p, x)))
+    }
+
+    /// Content replacing that of the file at `path`, from the first
+    /// matching `placeholder` rule.
+    pub fn placeholder(&self, path: &str) -> Option<&[u8]> {
+        let path = path.trim_matches('/');
+        self.placeholders
+            .iter()
+            .find(|(p, _)| path_matches(p, path))
+            .map(|(_, text)| text.as_slice())
+    }
+
+    /// Whether the extended attribute `name`, prefix included, is kept.
+    pub fn keeps_xattr(&self, name: &str) -> bool {
+        !self
+            .strip_xattrs
+            .iter()
+            .any(|p| glob(p.as_bytes(), name.as_bytes()))
+    }
+}
@@ -0,0 +1,628 @@
+//! Regular expressions over bytes, for searching file content and names.
+//!
+//! The syntax is POSIX extended: `.`, `[...]` classes with ranges and
+//! `[^...]`, `*`, `+`, `?`, `{m,n}`, `|`, `(...)`, `^` and `$`, plus the
+//! `\d`, `\w`, `\s` classes, their negations and `\b`, `\B`. Matching
+//! runs all alternatives side by side, so it takes time linear in the
+//! text whatever the pattern.
+
+use failure::bail;
+
+use crate::Result;
+
+// counted repetitions are unrolled, keep programs small
+const MAX_REPEAT: u32 = 1000;
+const MAX_PROGRAM: usize = 100_000;
+
+/// A set of bytes, one bit each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct ByteSet([u64; 4]);
+
+impl ByteSet {
+    fn insert(&mut self, b: u8) {
+        self.0[(b >> 6) as usize] |= 1 << (b & 63);
+    }
+
+    fn insert_range(&mut self, from: u8, to: u8) {
+        for b in from..=to {
+            self.insert(b);
+        }
+    }
+
+    fn contains(&self, b: u8) -> bool {
+        self.0[(b >> 6) as usize] & (1 << (b & 63)) != 0
+    }
+
+    fn union(&mut self, other: &ByteSet) {
+        for (x, y) in self.0.iter_mut().zip(other.0) {
+            *x |= y;
+        }
+    }
+
+    fn negate(&mut self) {
+        for x in &mut self.0 {
+            *x = !*x;
+        }
+    }
+
+    /// Add the other case of every ASCII letter in the set.
+    fn fold_case(&mut self) {
+        for b in b'a'..=b'z' {
+            let upper = b.to_ascii_uppercase();
+            if self.contains(b) || self.contains(upper) {
+                self.insert(b);
+                self.insert(upper);
+            }
+        }
+    }
+
+    fn of(class: u8) -> Option<ByteSet> {
+        let mut set = ByteSet::default();
+        match class.to_ascii_lowercase() {
+            b'd' => set.insert_range(b'0', b'9'),
+            b'w' => {
+                set.insert_range(b'a', b'z');
+                set.insert_range(b'A', b'Z');
+                set.insert_range(b'0', b'9');
+                set.insert(b'_');
+            }
+            b's' => b" \t\n\r\x0b\x0c".iter().for_each(|&b| set.insert(b)),
+            _ => return None,
+        }
+        if class.is_ascii_uppercase() {
+            set.negate();
+        }
+        Some(set)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Empty,
+    Set(ByteSet),
+    /// `^`, the start of the text or of a line
+    LineStart,
+    /// `$`, the end of the text or of a line
+    LineEnd,
+    /// `\b`, or `\B` when false
+    WordBoundary(bool),
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum Inst {
+    Set(ByteSet),
+    LineStart,
+    LineEnd,
+    WordBoundary(bool),
+    /// carry on at both
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self> {
+        Self::build(pattern, false)
+    }
+
+    /// Letters match either case, ASCII ones only.
+    pub fn case_insensitive(pattern: &str) -> Result<Self> {
+        Self::build(pattern, true)
+    }
+
+    fn build(pattern: &str, fold_case: bool) -> Result<Self> {
+        let mut parser = Parser {
+            pattern: pattern.as_bytes(),
+            pos: 0,
+            fold_case,
+        };
+        let node = parser.alternation()?;
+        if parser.pos < parser.pattern.len() {
+            bail!("regex {:?}: unmatched )", pattern);
+        }
+        // before unrolling anything, nested counts multiply
+        if program_size(&node) > MAX_PROGRAM {
+            bail!(
+                "regex {:?}: too large once repetitions are unrolled",
+                pattern
+            );
+        }
+        let mut program = Vec::new();
+        compile(&node, &mut program);
+        program.push(Inst::Match);
+        Ok(Regex { program })
+    }
+
+    /// Whether the pattern matches anywhere in `text`.
+    pub fn is_match(&self, text: &[u8]) -> bool {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        for pos in 0..=text.len() {
+            // a match may start anywhere
+            self.add(&mut current, 0, text, pos);
+            for i in 0..current.list.len() {
+                let pc = current.list[i];
+                match &self.program[pc] {
+                    Inst::Match => return true,
+                    Inst::Set(set) if pos < text.len() && set.contains(text[pos]) => {
+                        self.add(&mut next, pc + 1, text, pos + 1);
+                    }
+                    _ => {}
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+        false
+    }
+
+    /// Add the thread at `pc` and those it leads to without consuming a
+    /// byte of `text`, at `pos`.
+    fn add(&self, threads: &mut Threads, pc: usize, text: &[u8], pos: usize) {
+        let mut stack = vec![pc];
+        while let Some(pc) = stack.pop() {
+            if !threads.insert(pc) {
+                continue;
+            }
+            let word = |i: Option<usize>| i.and_then(|i| text.get(i)).is_some_and(|&b| is_word(b));
+            match self.program[pc] {
+                Inst::Jump(to) => stack.push(to),
+                Inst::Split(a, b) => {
+                    // first choice on top
+                    stack.push(b);
+                    stack.push(a);
+                }
+                Inst::LineStart if pos == 0 || text[pos - 1] == b'\n' => stack.push(pc + 1),
+                Inst::LineEnd if pos == text.len() || text[pos] == b'\n' => stack.push(pc + 1),
+                Inst::WordBoundary(at) if (word(pos.checked_sub(1)) != word(Some(pos))) == at => {
+                    stack.push(pc + 1)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Quote the special characters of `text` so it matches itself.
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn is_word(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+/// The threads alive at one position of the text, each instruction once.
+struct Threads {
+    list: Vec<usize>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Threads {
+            list: Vec::with_capacity(len),
+            seen: vec![false; len],
+        }
+    }
+
+    fn insert(&mut self, pc: usize) -> bool {
+        if std::mem::replace(&mut self.seen[pc], true) {
+            return false;
+        }
+        self.list.push(pc);
+        true
+    }
+
+    fn clear(&mut self) {
+        for &pc in &self.list {
+            self.seen[pc] = false;
+        }
+        self.list.clear();
+    }
+}
+
+/// How many instructions `compile` emits for `node`, saturating.
+fn program_size(node: &Node) -> usize {
+    let sum = |nodes: &[Node], extra: usize| {
+        nodes
+            .iter()
+            .map(|x| program_size(x).saturating_add(extra))
+            .fold(0, usize::saturating_add)
+    };
+    match node {
+        Node::Empty => 0,
+        Node::Set(_) | Node::LineStart | Node::LineEnd | Node::WordBoundary(_) => 1,
+        Node::Concat(nodes) => sum(nodes, 0),
+        // a split and a jump per alternative, at most
+        Node::Alternate(nodes) => sum(nodes, 2),
+        Node::Repeat { node, min, max } => {
+            let copies = max.unwrap_or(min + 1) as usize;
+            // each copy has a split at most, the loop a jump
+            (program_size(node) + 1)
+                .saturating_mul(copies)
+                .saturating_add(1)
+        }
+    }
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Set(set) => program.push(Inst::Set(*set)),
+        Node::LineStart => program.push(Inst::LineStart),
+        Node::LineEnd => program.push(Inst::LineEnd),
+        Node::WordBoundary(at) => program.push(Inst::WordBoundary(*at)),
+        Node::Concat(nodes) => nodes.iter().for_each(|x| compile(x, program)),
+        Node::Alternate(nodes) => {
+            // split to each alternative in turn, each jumping to the end
+            let mut jumps = Vec::new();
+            for (i, x) in nodes.iter().enumerate() {
+                if i + 1 < nodes.len() {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(x, program);
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    let next = program.len();
+                    program[split] = Inst::Split(split + 1, next);
+                } else {
+                    compile(x, program);
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, program);
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    program.push(Inst::Jump(split));
+                    let end = program.len();
+                    program[split] = Inst::Split(split + 1, end);
+                }
+                Some(max) => {
+                    // each optional copy may skip all those after it
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(0, 0));
+                        compile(node, program);
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    pattern: &'a [u8],
+    pos: usize,
+    fold_case: bool,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.pattern.get(self.pos).copied()
+    }
+
+    fn error(&self, what: &str) -> failure::Error {
+        failure::format_err!(
+            "regex {:?}: {} at {}",
+            String::from_utf8_lossy(self.pattern),
+            what,
+            self.pos
+        )
+    }
+
+    fn alternation(&mut self) -> Result<Node> {
+        let mut alternatives = vec![self.concatenation()?];
+        while self.peek() == Some(b'|') {
+            self.pos += 1;
+            alternatives.push(self.concatenation()?);
+        }
+        Ok(match alternatives.len() {
+            1 => alternatives.pop().unwrap(),
+            _ => Node::Alternate(alternatives),
+        })
+    }
+
+    fn concatenation(&mut self) -> Result<Node> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == b'|' || c == b')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn quantified(&mut self, mut node: Node) -> Result<Node> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some(b'{') => match self.counts()? {
+                    Some(counts) => counts,
+                    // a brace not starting a count is a literal one
+                    None => return Ok(node),
+                },
+                Some(c) if b"*+?".contains(&c) => {
+                    self.pos += 1;
+                    match c {
+                        b'*' => (0, None),
+                        b'+' => (1, None),
+                        _ => (0, Some(1)),
+                    }
+                }
+                _ => return Ok(node),
+            };
+            if matches!(
+                node,
+                Node::LineStart | Node::LineEnd | Node::WordBoundary(_)
+            ) {
+                return Err(self.error("nothing to repeat"));
+            }
+            node = Node::Repeat {
+                node: Box::new(node),
+                min,
+                max,
+            };
+        }
+    }
+
+    /// `{m}`, `{m,}` or `{m,n}`, leaving the position past the closing
+    /// brace. None if the brace doesn't start a count.
+    fn counts(&mut self) -> Result<Option<(u32, Option<u32>)>> {
+        let rest = &self.pattern[self.pos + 1..];
+        let Some(end) = rest.iter().position(|&c| c == b'}') else {
+            return Ok(None);
+        };
+        let inside = std::str::from_utf8(&rest[..end]).unwrap_or_default();
+        let number = |s: &str| s.parse::<u32>().ok();
+        let (min, max) = match inside.split_once(',') {
+            None => match number(inside) {
+                Some(n) => (n, Some(n)),
+                None => return Ok(None),
+            },
+            Some((min, "")) => match number(min) {
+                Some(min) => (min, None),
+                None => return Ok(None),
+            },
+            Some((min, max)) => match (number(min), number(max)) {
+                (Some(min), Some(max)) => (min, Some(max)),
+                _ => return Ok(None),
+            },
+        };
+        if max.is_some_and(|max| max < min) || min.max(max.unwrap_or(0)) > MAX_REPEAT {
+            return Err(self.error("bad repetition count"));
+        }
+        self.pos += end + 2;
+        Ok(Some((min, max)))
+    }
+
+    fn atom(&mut self) -> Result<Node> {
+        let c = self.peek().ok_or_else(|| self.error("pattern ends"))?;
+        self.pos += 1;
+        let node = match c {
+            b'(' => {
+                let node = self.alternation()?;
+                if self.peek() != Some(b')') {
+                    return Err(self.error("unmatched ("));
+                }
+                self.pos += 1;
+                node
+            }
+            b'[' => Node::Set(self.class()?),
+            b'.' => {
+                let mut set = ByteSet::default();
+                set.insert(b'\n');
+                set.negate();
+                Node::Set(set)
+            }
+            b'^' => Node::LineStart,
+            b'$' => Node::LineEnd,
+            b'*' | b'+' | b'?' => return Err(self.error("nothing to repeat")),
+            b'\\' => {
+                let c = self.peek().ok_or_else(|| self.error("trailing \\"))?;
+                self.pos += 1;
+                match c {
+                    b'b' => Node::WordBoundary(true),
+                    b'B' => Node::WordBoundary(false),
+                    c => match ByteSet::of(c) {
+                        Some(set) => Node::Set(set),
+                        None => self.literal(unescape(c)),
+                    },
+                }
+            }
+            c => self.literal(c),
+        };
+        Ok(node)
+    }
+
+    fn literal(&self, c: u8) -> Node {
+        let mut set = ByteSet::default();
+        set.insert(c);
+        if self.fold_case {
+            set.fold_case();
+        }
+        Node::Set(set)
+    }
+
+    /// A bracket expression, after its `[`.
+    fn class(&mut self) -> Result<ByteSet> {
+        let mut set = ByteSet::default();
+        let negated = self.peek() == Some(b'^');
+        if negated {
+            self.pos += 1;
+        }
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or_else(|| self.error("unmatched ["))?;
+            self.pos += 1;
+            // a `]` first is a literal one
+            if c == b']' && !first {
+                break;
+            }
+            first = false;
+            let from = match c {
+                b'\\' => {
+                    let c = self.peek().ok_or_else(|| self.error("unmatched ["))?;
+                    self.pos += 1;
+                    if let Some(class) = ByteSet::of(c) {
+                        set.union(&class);
+                        continue;
+                    }
+                    unescape(c)
+                }
+                c => c,
+            };
+            let to = match (self.peek(), self.pattern.get(self.pos + 1)) {
+                (Some(b'-'), Some(&to)) if to != b']' => {
+                    self.pos += 2;
+                    if to < from {
+                        return Err(self.error("bad range"));
+                    }
+                    to
+                }
+                _ => from,
+            };
+            set.insert_range(from, to);
+        }
+        if self.fold_case {
+            set.fold_case();
+        }
+        if negated {
+            set.negate();
+        }
+        Ok(set)
+    }
+}
+
+fn unescape(c: u8) -> u8 {
+    match c {
+        b'n' => b'\n',
+        b't' => b'\t',
+        b'r' => b'\r',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str) -> bool {
+        Regex::new(pattern).unwrap().is_match(text.as_bytes())
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("[a-c]", "xbx"));
+        assert!(!matches("[a-c]", "xdx"));
+        assert!(!matches("[^a-c]", "abc"));
+        assert!(matches("[^a-c]", "abcd"));
+        // `]` first and `-` last are literal
+        assert!(matches("[]a]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[\\d_]", "_"));
+        assert!(matches("^\\d+$", "0123456789"));
+        assert!(!matches("\\D", "123"));
+        assert!(matches("^\\w+\\s\\W$", "a_1\t!"));
+        assert!(!matches("a.b", "a\nb"));
+        assert!(Regex::case_insensitive("^[a-c]x$").unwrap().is_match(b"BX"));
+        assert!(Regex::new("[c-a]").is_err());
+        assert!(Regex::new("[ab").is_err());
+    }
+
+    #[test]
+    fn anchors() {
+        assert!(matches("^abc$", "abc"));
+        assert!(!matches("^abc", "xabc"));
+        assert!(!matches("abc$", "abcx"));
+        // at line breaks too
+        assert!(matches("^abc$", "x\nabc\ny"));
+        assert!(matches("^$", ""));
+        assert!(Regex::new("^*").is_err());
+    }
+
+    #[test]
+    fn word_boundaries() {
+        assert!(matches("\\bcat\\b", "a cat here"));
+        assert!(matches("\\bcat\\b", "cat"));
+        assert!(!matches("\\bcat\\b", "concatenate"));
+        assert!(matches("\\Bcat\\B", "concatenate"));
+        assert!(!matches("\\Bcat", "cat"));
+        assert!(matches("\\bx_1\\b", "(x_1)"));
+    }
+
+    #[test]
+    fn counted_repeats() {
+        let exact = Regex::new("^a{3}$").unwrap();
+        assert!(exact.is_match(b"aaa"));
+        assert!(!exact.is_match(b"aa") && !exact.is_match(b"aaaa"));
+        let at_least = Regex::new("^a{2,}$").unwrap();
+        assert!(!at_least.is_match(b"a") && at_least.is_match(&[b'a'; 50]));
+        let between = Regex::new("^(ab){1,2}$").unwrap();
+        assert!(between.is_match(b"ab") && between.is_match(b"abab"));
+        assert!(!between.is_match(b"") && !between.is_match(b"ababab"));
+        // braces not starting a count are literal
+        assert!(matches("^a{,3}$", "a{,3}"));
+        assert!(matches("x{", "x{"));
+        assert!(Regex::new("a{3,2}").is_err());
+        assert!(Regex::new("a{1001}").is_err());
+    }
+
+    #[test]
+    fn size_limit() {
+        assert!(Regex::new("a{1000}").is_ok());
+        assert!(Regex::new("(a{100}){100}").is_ok());
+        assert!(Regex::new("a{1000}{1000}").is_err());
+        // refused before anything is unrolled
+        assert!(Regex::new("((a{1000}){1000}){1000}").is_err());
+        assert!(Regex::new("(a{1000}|b{1000}){1000}").is_err());
+    }
+
+    #[test]
+    fn escaped_text_matches_itself() {
+        let text = "a.b*(c)|[d]{2}^$\\";
+        let regex = Regex::new(&escape(text)).unwrap();
+        assert!(regex.is_match(text.as_bytes()));
+        assert!(!regex.is_match(b"axb"));
+    }
+}
@@ -0,0 +1,217 @@
+//! Command files for the shell: one command per line, plus variables and
+//! conditionals so inspection procedures can be shared and rerun.
+//!
+//! ```text
+//! # lines starting with # are comments
+//! set dir /var/log
+//! if exists $dir/auth.log
+//!     cat ${dir}/auth.log
+//! else
+//!     ls $dir
+//! end
+//! if $dir != /tmp
+//!     stat $dir
+//! end
+//! ```
+//!
+//! Conditions are `exists PATH`, `A == B` and `A != B`, each optionally
+//! preceded by `not`. `$$` is a literal `$`.
+
+use std::collections::HashMap;
+
+use failure::{bail, format_err, ResultExt};
+
+use crate::Result;
+
+/// What a script runs against, implemented by the shell.
+pub trait ScriptHost {
+    /// Run one shell command, variables already substituted.
+    fn command(&mut self, line: &str) -> Result<()>;
+    /// Whether `path` exists in the image.
+    fn exists(&mut self, path: &str) -> Result<bool>;
+}
+
+#[derive(Debug, Clone)]
+enum Statement {
+    Set(String, String),
+    Command(String),
+    If {
+        condition: String,
+        then: Vec<(usize, Statement)>,
+        otherwise: Vec<(usize, Statement)>,
+    },
+}
+
+/// A parsed command file.
+#[derive(Debug, Clone)]
+pub struct Script {
+    statements: Vec<(usize, Statement)>,
+}
+
+/// An `if` whose `end` wasn't reached yet.
+struct Open {
+    line: usize,
+    condition: String,
+    then: Vec<(usize, Statement)>,
+    otherwise: Option<Vec<(usize, Statement)>>,
+}
+
+impl Script {
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut statements = Vec::new();
+        let mut open: Vec<Open> = Vec::new();
+        fn target<'a>(
+            statements: &'a mut Vec<(usize, Statement)>,
+            open: &'a mut [Open],
+        ) -> &'a mut Vec<(usize, Statement)> {
+            match open.last_mut() {
+                Some(Open {
+                    otherwise: Some(x), ..
+                }) => x,
+                Some(x) => &mut x.then,
+                None => statements,
+            }
+        }
+        for (i, line) in text.lines().enumerate() {
+            let number = i + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim();
+            match keyword {
+                "set" => {
+                    let (name, value) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                    if !is_name(name) {
+                        bail!("line {}: bad variable name {:?}", number, name);
+                    }
+                    let statement = Statement::Set(name.to_string(), value.trim().to_string());
+                    target(&mut statements, &mut open).push((number, statement));
+                }
+                "if" => open.push(Open {
+                    line: number,
+                    condition: rest.to_string(),
+                    then: Vec::new(),
+                    otherwise: None,
+                }),
+                "else" => match open.last_mut() {
+                    Some(x) if x.otherwise.is_none() => x.otherwise = Some(Vec::new()),
+                    Some(_) => bail!("line {}: second else", number),
+                    None => bail!("line {}: else without if", number),
+                },
+                "end" => {
+                    let Some(closed) = open.pop() else {
+                        bail!("line {}: end without if", number);
+                    };
+                    let statement = Statement::If {
+                        condition: closed.condition,
+                        then: closed.then,
+                        otherwise: closed.otherwise.unwrap_or_default(),
+                    };
+                    target(&mut statements, &mut open).push((closed.line, statement));
+                }
+                _ => target(&mut statements, &mut open)
+                    .push((number, Statement::Command(line.to_string()))),
+            }
+        }
+        if let Some(x) = open.last() {
+            bail!("line {}: if without end", x.line);
+        }
+        Ok(Self { statements })
+    }
+
+    /// Run the script, stopping at the first failing command. `vars`
+    /// holds the variables set so far and is updated by `set`.
+    pub fn run(&self, host: &mut dyn ScriptHost, vars: &mut HashMap<String, String>) -> Result<()> {
+        run(&self.statements, host, vars)
+    }
+}
+
+fn run(
+    statements: &[(usize, Statement)],
+    host: &mut dyn ScriptHost,
+    vars: &mut HashMap<String, String>,
+) -> Result<()> {
+    for (number, statement) in statements {
+        let number = *number;
+        match statement {
+            Statement::Set(name, value) => {
+                let value = expand(value, vars).with_context(|e| format!("line {number}: {e}"))?;
+                vars.insert(name.clone(), value);
+            }
+            Statement::Command(line) => {
+                let line = expand(line, vars).with_context(|e| format!("line {number}: {e}"))?;
+                host.command(&line)
+                    .with_context(|e| format!("line {number}: {line}: {e}"))?;
+            }
+            Statement::If {
+                condition,
+                then,
+                otherwise,
+            } => {
+                let holds = evaluate(condition, host, vars)
+                    .with_context(|e| format!("line {number}: {e}"))?;
+                run(if holds { then } else { otherwise }, host, vars)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn evaluate(
+    condition: &str,
+    host: &mut dyn ScriptHost,
+    vars: &HashMap<String, String>,
+) -> Result<bool> {
+    let condition = condition.trim();
+    if let Some(rest) = condition.strip_prefix("not ") {
+        return Ok(!evaluate(rest, host, vars)?);
+    }
+    if let Some(path) = condition.strip_prefix("exists ") {
+        return host.exists(&expand(path.trim(), vars)?);
+    }
+    for (operator, equal) in [("==", true), ("!=", false)] {
+        if let Some((a, b)) = condition.split_once(operator) {
+            let same = expand(a.trim(), vars)? == expand(b.trim(), vars)?;
+            return Ok(same == equal);
+        }
+    }
+    bail!("bad condition: {}", condition)
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Substitute `$name` and `${name}`.
+fn expand(text: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(i) = rest.find('$') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+        let (name, after) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced
+                .find('}')
+                .ok_or_else(|| format_err!("unterminated ${{ in {:?}", text))?;
+            (&braced[..end], &braced[end + 1..])
+        } else if let Some(after) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = after;
+            continue;
+        } else {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        match vars.get(name) {
+            Some(value) => out.push_str(value),
+            None => bail!("undefined variable ${}", name),
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    Ok(out)
+}
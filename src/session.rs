@@ -0,0 +1,150 @@
+//! State of an interactive session, saved to a small text file so a long
+//! investigation over a slow backend can be resumed later.
+//!
+//! ```text
+//! session 1
+//! image /evidence/disk.img
+//! uuid 0b9a3c1e-5f7d-4e2a-9c1b-2d3e4f5a6b7c
+//! cwd 1234 /var/log
+//! bookmark logs /var/log
+//! cached 2 1234 1301
+//! ```
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+
+use failure::bail;
+use positioned_io::ReadAt;
+
+use crate::{Ext4Fs, FileType, InodeNumber, Result};
+
+const VERSION: &str = "session 1";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub image: PathBuf,
+    /// filesystem the session was over, checked on restore
+    pub uuid: String,
+    pub cwd: InodeNumber,
+    pub cwd_path: String,
+    /// named paths to jump back to
+    pub bookmarks: BTreeMap<String, String>,
+    /// directories whose listings were cached, read again on restore to
+    /// warm the cache
+    pub cached_dirs: Vec<InodeNumber>,
+}
+
+impl Session {
+    /// A fresh session at the root of `fs`.
+    pub fn new<T: ReadAt>(image: PathBuf, fs: &Ext4Fs<T>) -> Self {
+        Self {
+            image,
+            uuid: fs.sb.uuid_string(),
+            cwd: Ext4Fs::<T>::ROOT,
+            cwd_path: "/".to_string(),
+            bookmarks: BTreeMap::new(),
+            cached_dirs: Vec::new(),
+        }
+    }
+
+    /// Write the session out. Fails for paths holding a newline and
+    /// bookmark names holding spaces, which the line based format can't
+    /// represent.
+    pub fn save(&self, out: &mut dyn Write) -> io::Result<()> {
+        let image = self.image.to_string_lossy();
+        let paths = [&*image, &self.cwd_path].into_iter().chain(
+            self.bookmarks
+                .iter()
+                .flat_map(|(k, v)| [k.as_str(), v.as_str()]),
+        );
+        for path in paths {
+            if path.contains('\n') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("can't save a path with a newline: {path:?}"),
+                ));
+            }
+        }
+        if let Some(name) = self
+            .bookmarks
+            .keys()
+            .find(|x| x.contains(char::is_whitespace))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("bookmark names can't hold spaces: {name:?}"),
+            ));
+        }
+        writeln!(out, "{VERSION}")?;
+        writeln!(out, "image {image}")?;
+        writeln!(out, "uuid {}", self.uuid)?;
+        writeln!(out, "cwd {} {}", self.cwd.0, self.cwd_path)?;
+        for (name, path) in &self.bookmarks {
+            writeln!(out, "bookmark {name} {path}")?;
+        }
+        let cached: Vec<String> = self.cached_dirs.iter().map(|x| x.0.to_string()).collect();
+        writeln!(out, "cached {}", cached.join(" "))
+    }
+
+    pub fn load(input: &mut dyn BufRead) -> Result<Self> {
+        let mut lines = input.lines();
+        match lines.next() {
+            Some(Ok(line)) if line == VERSION => {}
+            _ => bail!("not a session file"),
+        }
+        let mut session = Self {
+            image: PathBuf::new(),
+            uuid: String::new(),
+            cwd: InodeNumber(2),
+            cwd_path: "/".to_string(),
+            bookmarks: BTreeMap::new(),
+            cached_dirs: Vec::new(),
+        };
+        for line in lines {
+            let line = line?;
+            let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+            match key {
+                "image" => session.image = value.into(),
+                "uuid" => session.uuid = value.to_string(),
+                "cwd" => {
+                    let Some((n, path)) = value.split_once(' ') else {
+                        bail!("bad cwd line: {}", line);
+                    };
+                    session.cwd = InodeNumber(n.parse()?);
+                    session.cwd_path = path.to_string();
+                }
+                "bookmark" => {
+                    let Some((name, path)) = value.split_once(' ') else {
+                        bail!("bad bookmark line: {}", line);
+                    };
+                    session.bookmarks.insert(name.to_string(), path.to_string());
+                }
+                "cached" => {
+                    for n in value.split_whitespace() {
+                        session.cached_dirs.push(InodeNumber(n.parse()?));
+                    }
+                }
+                "" => {}
+                _ => bail!("unknown session line: {}", line),
+            }
+        }
+        Ok(session)
+    }
+
+    /// Check that the session belongs to `fs` and its working directory
+    /// still is one.
+    pub fn check<T: ReadAt>(&self, fs: &Ext4Fs<T>) -> Result<()> {
+        if self.uuid != fs.sb.uuid_string() {
+            bail!(
+                "session was saved for filesystem {}, this is {}",
+                self.uuid,
+                fs.sb.uuid_string()
+            );
+        }
+        if fs.inode(self.cwd)?.file_type() != FileType::Directory {
+            bail!("working directory inode {} is no directory", self.cwd.0);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use positioned_io::ReadAt;
+
+use crate::{Ext4Fs, Inode, InodeNumber, Result};
+
+/// What a special inode is used for. Inodes below `s_first_ino` are
+/// reserved, the superblock may point at others (quota, journal).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialInode {
+    BadBlocks,
+    Root,
+    UserQuota,
+    GroupQuota,
+    ProjectQuota,
+    BootLoader,
+    Undelete,
+    /// reserved group descriptor blocks for online resize
+    Resize,
+    Journal,
+    /// snapshot exclude bitmap
+    Exclude,
+    Replica,
+    LostAndFound,
+    /// reserved without a known use
+    Reserved,
+}
+
+impl SpecialInode {
+    /// The fixed role of reserved inode `n`.
+    pub fn reserved(n: InodeNumber) -> Option<Self> {
+        Some(match n.0 {
+            1 => Self::BadBlocks,
+            2 => Self::Root,
+            3 => Self::UserQuota,
+            4 => Self::GroupQuota,
+            5 => Self::BootLoader,
+            6 => Self::Undelete,
+            7 => Self::Resize,
+            8 => Self::Journal,
+            9 => Self::Exclude,
+            10 => Self::Replica,
+            _ => return None,
+        })
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::BadBlocks => "bad blocks",
+            Self::Root => "root directory",
+            Self::UserQuota => "user quota",
+            Self::GroupQuota => "group quota",
+            Self::ProjectQuota => "project quota",
+            Self::BootLoader => "boot loader",
+            Self::Undelete => "undelete directory",
+            Self::Resize => "reserved group descriptors (resize)",
+            Self::Journal => "journal",
+            Self::Exclude => "exclude (snapshots)",
+            Self::Replica => "replica",
+            Self::LostAndFound => "lost+found",
+            Self::Reserved => "reserved",
+        }
+    }
+
+    /// Whether the inode holds a directory tree users put files in, as
+    /// opposed to filesystem metadata.
+    pub fn is_user_visible(self) -> bool {
+        matches!(self, Self::Root | Self::LostAndFound)
+    }
+}
+
+/// A special inode found on the image.
+#[derive(Debug, Clone)]
+pub struct SpecialInodeEntry {
+    pub number: InodeNumber,
+    pub kind: SpecialInode,
+    pub inode: Inode,
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Every reserved inode, plus the quota and journal inodes and
+    /// `/lost+found` wherever the superblock and root put them, sorted by
+    /// inode number. Inodes the superblock names take precedence over
+    /// the fixed roles, so a project quota in inode 12 is labelled as such.
+    pub fn special_inodes(&self) -> Result<Vec<SpecialInodeEntry>> {
+        let sb = &self.sb;
+        let mut found: Vec<(InodeNumber, SpecialInode)> = Vec::new();
+        let mut add = |n: u64, kind| {
+            if n != 0 && n <= sb.inodes_count && !found.iter().any(|(x, _)| x.0 == n) {
+                found.push((InodeNumber(n), kind));
+            }
+        };
+        add(sb.journal_inum, SpecialInode::Journal);
+        add(sb.usr_quota_inum, SpecialInode::UserQuota);
+        add(sb.grp_quota_inum, SpecialInode::GroupQuota);
+        add(sb.prj_quota_inum, SpecialInode::ProjectQuota);
+        for n in 1..sb.first_ino {
+            let kind = SpecialInode::reserved(InodeNumber(n)).unwrap_or(SpecialInode::Reserved);
+            add(n, kind);
+        }
+        // a missing or unreadable lost+found isn't an error here
+        if let Ok(Some(n)) = self.resolve("/lost+found") {
+            add(n.0, SpecialInode::LostAndFound);
+        }
+        found.sort_by_key(|(n, _)| n.0);
+        found
+            .into_iter()
+            .map(|(number, kind)| {
+                Ok(SpecialInodeEntry {
+                    number,
+                    kind,
+                    inode: self.inode(number)?,
+                })
+            })
+            .collect()
+    }
+}
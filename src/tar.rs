@@ -0,0 +1,309 @@
+//! Export as a POSIX tar archive: ustar headers, with pax extended
+//! headers for what doesn't fit in them, long names and large sizes or
+//! ids among others.
+
+use std::io::{self, Write};
+
+use positioned_io::ReadAt;
+
+use crate::export::NameTransform;
+use crate::redact::RedactionPolicy;
+use crate::{Ext4Fs, FileType, HardLinks, Inode, Result, TypedInode, WalkSummary};
+
+const BLOCK: usize = 512;
+// file content is copied at most 1 MiB at once
+const CHUNK: u64 = 1 << 20;
+
+/// An archive member, as its header describes it.
+struct Member<'a> {
+    name: &'a str,
+    kind: u8,
+    mode: u16,
+    uid: u32,
+    gid: u32,
+    size: u64,
+    mtime: i64,
+    link: &'a str,
+    device: (u32, u32),
+}
+
+/// Passes writes on, remembering the first that failed: those end the
+/// archive, where an entry that can't be read is only left out.
+pub(crate) struct Output<'a> {
+    out: &'a mut dyn Write,
+    pub(crate) failed: Option<io::Error>,
+}
+
+impl<'a> Output<'a> {
+    pub(crate) fn new(out: &'a mut dyn Write) -> Self {
+        Output { out, failed: None }
+    }
+}
+
+impl Write for Output<'_> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.out.write(data).inspect_err(|e| {
+            self.failed
+                .get_or_insert_with(|| io::Error::new(e.kind(), e.to_string()));
+        })
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Write `path` and everything below it to `out` as a tar archive,
+    /// with modes, owners, modification times, symlinks, hard links
+    /// between files, device nodes and fifos. Sockets have no place in
+    /// it and are left out. Names go through `transform`, entries it
+    /// drops are left out, as are those `redaction` skips. Entries that
+    /// can't be read are recorded in the summary with `keep_going`, or
+    /// end the archive with an error as a failure to write does.
+    pub fn write_tar(
+        &self,
+        path: &str,
+        keep_going: bool,
+        transform: &dyn NameTransform,
+        redaction: &RedactionPolicy,
+        out: &mut dyn Write,
+    ) -> Result<WalkSummary> {
+        let root = format!("/{}", path.trim_matches('/'));
+        let mut links = HardLinks::default();
+        let mut out = Output::new(out);
+        let summary = self.walk(&root, keep_going, |entry| {
+            if out.failed.is_some() || redaction.skips(&entry.path) {
+                return Ok(());
+            }
+            let relative = entry.path[root.len()..].trim_start_matches('/');
+            // a single file is archived under its own name
+            let relative = match relative {
+                "" if entry.inode.file_type() != FileType::Directory => {
+                    root.rsplit('/').next().unwrap_or_default()
+                }
+                x => x,
+            };
+            let Some(name) = transform.transform(relative) else {
+                return Ok(());
+            };
+            let mut name = if name.is_empty() {
+                ".".to_string()
+            } else {
+                name
+            };
+            let inode = &entry.inode;
+            let placeholder = match inode.file_type() {
+                FileType::Regular => redaction.placeholder(&entry.path),
+                _ => None,
+            };
+            let mut member = Member {
+                name: "",
+                kind: b'0',
+                mode: inode.mode & 0o7777,
+                uid: inode.uid,
+                gid: inode.gid,
+                size: 0,
+                mtime: inode.mtime.seconds,
+                link: "",
+                device: inode.device().unwrap_or_default(),
+            };
+            if let Some(first) = placeholder.is_none().then(|| links.add(entry)).flatten() {
+                let first = first[root.len()..].trim_start_matches('/');
+                let first = transform.transform(first).unwrap_or_default();
+                member.name = &name;
+                member.kind = b'1';
+                member.link = &first;
+                write_header(&mut out, &member)?;
+                return Ok(());
+            }
+            if let Some(text) = placeholder {
+                member.name = &name;
+                member.size = text.len() as u64;
+                write_header(&mut out, &member)?;
+                out.write_all(text)?;
+                out.write_all(&[0; BLOCK][..padding(member.size)])?;
+                return Ok(());
+            }
+            let target;
+            match TypedInode::new(inode.clone()) {
+                TypedInode::Dir(_) => {
+                    name.push('/');
+                    member.kind = b'5';
+                }
+                TypedInode::File(_) => member.size = inode.size,
+                TypedInode::Symlink(x) => {
+                    target = x.target(&self.sb, &self.dev)?;
+                    member.kind = b'2';
+                    member.link = &target;
+                }
+                TypedInode::Other(_) => {
+                    member.kind = match inode.file_type() {
+                        FileType::CharacterDevice => b'3',
+                        FileType::BlockDevice => b'4',
+                        FileType::Fifo => b'6',
+                        _ => return Ok(()),
+                    }
+                }
+            }
+            member.name = &name;
+            if member.kind == b'0' {
+                self.write_content(inode, &member, &mut out)
+            } else {
+                write_header(&mut out, &member)?;
+                Ok(())
+            }
+        })?;
+        if let Some(error) = out.failed {
+            return Err(error.into());
+        }
+        // the end of the archive
+        out.write_all(&[0; 2 * BLOCK])?;
+        out.flush()?;
+        Ok(summary)
+    }
+
+    /// Write a regular file, header and content.
+    fn write_content(&self, inode: &Inode, member: &Member, out: &mut Output) -> Result<()> {
+        let reader = self.reader_of(inode)?;
+        write_header(out, member)?;
+        let result = copy_content(&reader, member.size, out);
+        out.write_all(&[0; BLOCK][..padding(member.size)])?;
+        result
+    }
+}
+
+/// Copy `size` bytes of file content to `out`. Content that fails to
+/// read is replaced by zeros so the archive stays whole, and the failure
+/// returned.
+pub(crate) fn copy_content(reader: &dyn ReadAt, size: u64, out: &mut Output) -> Result<()> {
+    let mut buf = vec![0u8; CHUNK.min(size) as usize];
+    let mut pos = 0;
+    let mut result = Ok(());
+    while pos < size {
+        let len = CHUNK.min(size - pos) as usize;
+        if result.is_ok() {
+            result = reader.read_exact_at(pos, &mut buf[..len]);
+        }
+        if result.is_err() {
+            buf.fill(0);
+        }
+        out.write_all(&buf[..len])?;
+        pos += len as u64;
+    }
+    Ok(result?)
+}
+
+/// Zeros needed after `size` bytes of content to fill its last block.
+fn padding(size: u64) -> usize {
+    (BLOCK - (size % BLOCK as u64) as usize) % BLOCK
+}
+
+/// Write the header of `member`, preceded by a pax extended header if
+/// some of it doesn't fit in ustar fields.
+fn write_header(out: &mut dyn Write, member: &Member) -> io::Result<()> {
+    let mut records = Vec::new();
+    let mut header = [0u8; BLOCK];
+    match split_name(member.name) {
+        Some((prefix, name)) => {
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+        }
+        None => {
+            record(&mut records, "path", member.name);
+            put_truncated(&mut header[..100], member.name);
+        }
+    }
+    if member.link.len() > 100 {
+        record(&mut records, "linkpath", member.link);
+    }
+    put_truncated(&mut header[157..257], member.link);
+    put_octal(&mut header[100..108], member.mode as u64);
+    for (field, key, value) in [
+        (108..116, "uid", member.uid as u64),
+        (116..124, "gid", member.gid as u64),
+        (124..136, "size", member.size),
+    ] {
+        if !put_octal(&mut header[field], value) {
+            record(&mut records, key, &value.to_string());
+        }
+    }
+    if member.mtime < 0 || !put_octal(&mut header[136..148], member.mtime as u64) {
+        record(&mut records, "mtime", &member.mtime.to_string());
+    }
+    header[156] = member.kind;
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    if matches!(member.kind, b'3' | b'4') {
+        put_octal(&mut header[329..337], member.device.0 as u64);
+        put_octal(&mut header[337..345], member.device.1 as u64);
+    }
+    if !records.is_empty() {
+        let mut pax = [0u8; BLOCK];
+        let base = member.name.trim_end_matches('/').rsplit('/').next();
+        put_truncated(
+            &mut pax[..100],
+            &format!("PaxHeader/{}", base.unwrap_or_default()),
+        );
+        put_octal(&mut pax[100..108], 0o644);
+        put_octal(&mut pax[124..136], records.len() as u64);
+        put_octal(&mut pax[136..148], member.mtime.max(0) as u64);
+        pax[156] = b'x';
+        pax[257..265].copy_from_slice(b"ustar\x0000");
+        checksum(&mut pax);
+        out.write_all(&pax)?;
+        out.write_all(&records)?;
+        out.write_all(&[0; BLOCK][..padding(records.len() as u64)])?;
+    }
+    checksum(&mut header);
+    out.write_all(&header)
+}
+
+/// Split `name` into the prefix and name fields of a ustar header, at a
+/// slash, None if it is too long for them.
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    // the slash is implied between the two, a directory keeps its own
+    let i = name[..name.len() - 1]
+        .match_indices('/')
+        .map(|(i, _)| i)
+        .find(|i| *i <= 155 && name.len() - i - 1 <= 100)?;
+    Some((&name[..i], &name[i + 1..]))
+}
+
+/// Append a pax record, `<length> <key>=<value>\n` where the length
+/// counts itself.
+fn record(records: &mut Vec<u8>, key: &str, value: &str) {
+    let rest = key.len() + value.len() + 3;
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() {
+        len = rest + len.to_string().len();
+    }
+    let _ = writeln!(records, "{len} {key}={value}");
+}
+
+fn put_truncated(field: &mut [u8], s: &str) {
+    let len = s.len().min(field.len());
+    field[..len].copy_from_slice(&s.as_bytes()[..len]);
+}
+
+/// Write `value` as zero-padded octal ending in a NUL, false if it has
+/// too many digits for the field.
+fn put_octal(field: &mut [u8], value: u64) -> bool {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    if digits.len() >= field.len() {
+        return false;
+    }
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    true
+}
+
+/// Fill in the header checksum, the sum of its bytes with the checksum
+/// field taken as spaces.
+fn checksum(header: &mut [u8; BLOCK]) {
+    header[148..156].fill(b' ');
+    let sum: u32 = header.iter().map(|&b| b as u32).sum();
+    let digits = format!("{sum:06o}\0 ");
+    header[148..156].copy_from_slice(digits.as_bytes());
+}
@@ -0,0 +1,68 @@
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// An inode timestamp: seconds since the epoch, extended past 2038 by the
+/// epoch bits of the `*_extra` field, plus its nanoseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Timestamp {
+    pub seconds: i64,
+    pub nanos: u32,
+}
+
+impl Timestamp {
+    /// Decode a 32-bit timestamp and its optional extra field. The low 2
+    /// bits of `extra` extend the signed seconds, the rest are nanoseconds.
+    pub fn decode(seconds: u32, extra: Option<u32>) -> Self {
+        let extra = extra.unwrap_or(0);
+        Self {
+            seconds: seconds as i32 as i64 + (((extra & 0x3) as i64) << 32),
+            nanos: extra >> 2,
+        }
+    }
+
+    /// The seconds and extra field storing the timestamp, the inverse of
+    /// `decode`.
+    pub fn encode(self) -> (u32, u32) {
+        let seconds = self.seconds as u32;
+        let epoch = ((self.seconds - seconds as i32 as i64) >> 32) as u32 & 0x3;
+        (seconds, self.nanos << 2 | epoch)
+    }
+
+    pub fn to_system_time(self) -> SystemTime {
+        let nanos = Duration::from_nanos(self.nanos as u64);
+        if self.seconds >= 0 {
+            UNIX_EPOCH + Duration::from_secs(self.seconds as u64) + nanos
+        } else {
+            UNIX_EPOCH - Duration::from_secs(self.seconds.unsigned_abs()) + nanos
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    /// UTC, `YYYY-MM-DD HH:MM:SS.nnnnnnnnn`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days = self.seconds.div_euclid(86400);
+        let secs = self.seconds.rem_euclid(86400);
+        // days to civil date, after Howard Hinnant's algorithm
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as i64;
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:09}",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            self.nanos
+        )
+    }
+}
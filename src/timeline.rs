@@ -0,0 +1,124 @@
+//! Timelines of a tree for incident response: the mactime CSV of The
+//! Sleuth Kit, and the body file its `mactime` builds one from.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use positioned_io::ReadAt;
+
+use crate::tar::Output;
+use crate::{Ext4Fs, Inode, InodeNumber, Result, Timestamp, WalkSummary};
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Write the timeline of `path` and everything below it to `out` as
+    /// mactime CSV: a row per file and second at which one of its times
+    /// falls, in time order, with `macb` saying which of modification,
+    /// access, change and birth. Times are UTC, to the second, unset
+    /// ones are left out. Entries that can't be read are recorded in the
+    /// summary with `keep_going`, otherwise the first is an error.
+    pub fn write_timeline(
+        &self,
+        path: &str,
+        keep_going: bool,
+        out: &mut dyn Write,
+    ) -> Result<WalkSummary> {
+        // (second, path) to the inode and the times falling then
+        let mut rows: BTreeMap<(i64, String), (InodeNumber, Inode, [bool; 4])> = BTreeMap::new();
+        let summary = self.walk(path, keep_going, |entry| {
+            let inode = &entry.inode;
+            let times = [
+                Some(inode.mtime),
+                Some(inode.atime),
+                Some(inode.ctime),
+                inode.crtime,
+            ];
+            for (i, time) in times.into_iter().enumerate() {
+                let Some(time) = time.filter(|x| x.seconds != 0) else {
+                    continue;
+                };
+                let row = rows
+                    .entry((time.seconds, entry.path.clone()))
+                    .or_insert_with(|| (entry.inode_number, inode.clone(), [false; 4]));
+                row.2[i] = true;
+            }
+            Ok(())
+        })?;
+        writeln!(out, "Date,Size,Type,Mode,UID,GID,Meta,File Name")?;
+        for ((seconds, path), (n, inode, kinds)) in rows {
+            let macb: String = kinds
+                .iter()
+                .zip("macb".chars())
+                .map(|(&set, c)| if set { c } else { '.' })
+                .collect();
+            writeln!(
+                out,
+                "{},{},{},{},{},{},{},{}",
+                iso_date(seconds),
+                inode.size,
+                macb,
+                inode.mode_string(),
+                inode.uid,
+                inode.gid,
+                n.0,
+                csv_field(&path)
+            )?;
+        }
+        out.flush()?;
+        Ok(summary)
+    }
+
+    /// Write a body file of `path` and everything below it to `out`, the
+    /// TSK 3 format `mactime -b` reads: `MD5|name|inode|mode|UID|GID|
+    /// size|atime|mtime|ctime|crtime` with times in seconds since the
+    /// epoch, 0 for unset ones, and no MD5. Entries that can't be read
+    /// are handled as `write_timeline` does.
+    pub fn write_bodyfile(
+        &self,
+        path: &str,
+        keep_going: bool,
+        out: &mut dyn Write,
+    ) -> Result<WalkSummary> {
+        let mut out = Output::new(out);
+        let summary = self.walk(path, keep_going, |entry| {
+            if out.failed.is_some() {
+                return Ok(());
+            }
+            let inode = &entry.inode;
+            writeln!(
+                out,
+                "0|{}|{}|{}|{}|{}|{}|{}|{}|{}|{}",
+                entry.path,
+                entry.inode_number.0,
+                inode.mode_string(),
+                inode.uid,
+                inode.gid,
+                inode.size,
+                inode.atime.seconds,
+                inode.mtime.seconds,
+                inode.ctime.seconds,
+                inode.crtime.map_or(0, |x| x.seconds),
+            )?;
+            Ok(())
+        })?;
+        if let Some(error) = out.failed {
+            return Err(error.into());
+        }
+        out.flush()?;
+        Ok(summary)
+    }
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ`, as `mactime -y` prints dates.
+fn iso_date(seconds: i64) -> String {
+    let time = Timestamp { seconds, nanos: 0 }.to_string();
+    // drop the nanoseconds
+    format!("{}T{}Z", &time[..10], &time[11..19])
+}
+
+/// `s` quoted if it has a comma, a quote or a line break in it.
+fn csv_field(s: &str) -> String {
+    match s.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", s.replace('"', "\"\"")),
+        false => s.to_string(),
+    }
+}
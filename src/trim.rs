@@ -0,0 +1,150 @@
+use std::fmt;
+
+use positioned_io::ReadAt;
+
+use crate::{BlockGroupNumber, Ext4Fs, Result};
+
+/// A run of unallocated blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FreeRange {
+    pub start: u64,
+    pub blocks: u64,
+}
+
+/// Every unallocated block range of the image, as found in the block
+/// bitmaps.
+///
+/// Display prints one `offset length` line in bytes per range, ready for
+/// `while read o l; do fallocate --punch-hole -o $o -l $l image; done`
+/// or `blkdiscard -o $o -l $l`.
+#[derive(Debug, Clone)]
+pub struct TrimReport {
+    pub block_size: u64,
+    pub ranges: Vec<FreeRange>,
+}
+
+impl TrimReport {
+    pub fn free_blocks(&self) -> u64 {
+        self.ranges.iter().map(|x| x.blocks).sum()
+    }
+}
+
+impl fmt::Display for TrimReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for range in &self.ranges {
+            writeln!(
+                f,
+                "{} {}",
+                range.start * self.block_size,
+                range.blocks * self.block_size
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Collect the unallocated block ranges, adjacent ranges of
+    /// consecutive groups merged.
+    pub fn trim_report(&self) -> Result<TrimReport> {
+        let sb = &self.sb;
+        let mut ranges: Vec<FreeRange> = Vec::new();
+        let mut push = |block: u64, blocks: u64| match ranges.last_mut() {
+            Some(last) if last.start + last.blocks == block => last.blocks += blocks,
+            _ => ranges.push(FreeRange {
+                start: block,
+                blocks,
+            }),
+        };
+        for group in 0..sb.group_count() {
+            let first = sb.group_first_block(group);
+            let end = (first + sb.block_per_group).min(sb.blocks_count);
+            let used = self.block_bitmap(BlockGroupNumber(group))?;
+            // one bit per cluster
+            let step = sb.blocks_per_cluster();
+            for cluster in 0..sb.cluster_per_group {
+                let block = first + cluster * step;
+                if block >= end {
+                    break;
+                }
+                if used[(cluster / 8) as usize] & (1 << (cluster % 8)) == 0 {
+                    push(block, step.min(end - block));
+                }
+            }
+        }
+        Ok(TrimReport {
+            block_size: sb.block_size,
+            ranges,
+        })
+    }
+}
+
+impl TrimReport {
+    /// Deallocate the free ranges in the raw image file `image`, the
+    /// filesystem starting `base` bytes into it, by punching holes into
+    /// it, leaving its size unchanged. Only the container file is touched,
+    /// filesystem structures are never written. Returns the number of
+    /// bytes punched.
+    #[cfg(target_os = "linux")]
+    pub fn punch_holes(&self, image: &std::fs::File, base: u64) -> std::io::Result<u64> {
+        use std::os::unix::io::AsRawFd;
+
+        let mut punched = 0;
+        for range in &self.ranges {
+            let offset = base + range.start * self.block_size;
+            let len = range.blocks * self.block_size;
+            let ret = unsafe {
+                libc::fallocate(
+                    image.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    offset as libc::off_t,
+                    len as libc::off_t,
+                )
+            };
+            if ret != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            punched += len;
+        }
+        Ok(punched)
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Copy the image into `dst` as a sparse raw image, writing only the
+    /// allocated blocks (metadata included) and leaving holes elsewhere,
+    /// so the copy stays mountable. Returns the number of bytes written.
+    #[cfg(unix)]
+    pub fn sparse_copy(&self, dst: &std::fs::File) -> Result<u64> {
+        use std::os::unix::fs::FileExt;
+
+        // copy in chunks of at most 1 MiB
+        const CHUNK_BLOCKS: u64 = 256;
+
+        let sb = &self.sb;
+        let free = self.trim_report()?.ranges;
+        dst.set_len(sb.blocks_count * sb.block_size)?;
+        let mut written = 0;
+        let mut copy = |mut block: u64, end: u64| -> Result<()> {
+            while block < end {
+                let count = CHUNK_BLOCKS.min(end - block);
+                let mut buf = vec![0u8; (count * sb.block_size) as usize];
+                self.dev.read_exact_at(block * sb.block_size, &mut buf)?;
+                // blocks that are allocated but zero stay holes too
+                if buf.iter().any(|&b| b != 0) {
+                    dst.write_all_at(&buf, block * sb.block_size)?;
+                    written += buf.len() as u64;
+                }
+                block += count;
+            }
+            Ok(())
+        };
+        let mut next = 0;
+        for range in &free {
+            copy(next, range.start)?;
+            next = range.start + range.blocks;
+        }
+        copy(next, sb.blocks_count)?;
+        Ok(written)
+    }
+}
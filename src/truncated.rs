@@ -0,0 +1,100 @@
+//! Images cut short of the size their superblock gives, as left by an
+//! interrupted copy. Whatever lies past the end reads as zeros, so that
+//! everything that did make it into the image stays readable.
+
+use std::io;
+
+use positioned_io::{ReadAt, Size};
+
+use crate::features::FeaturePolicy;
+use crate::{Ext4Fs, Inode, InodeFlags, Result};
+
+/// A device reading as zeros past its end instead of failing.
+pub struct TruncatedImage<T> {
+    dev: T,
+    len: u64,
+}
+
+impl<T: ReadAt + Size> TruncatedImage<T> {
+    pub fn new(dev: T) -> Result<Self> {
+        let len = dev.size()?.unwrap_or(u64::MAX);
+        Ok(Self { dev, len })
+    }
+}
+
+impl<T> TruncatedImage<T> {
+    /// Bytes actually present.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T: ReadAt> ReadAt for TruncatedImage<T> {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if pos >= self.len {
+            buf.fill(0);
+            return Ok(buf.len());
+        }
+        let present = buf.len().min((self.len - pos) as usize);
+        self.dev.read_exact_at(pos, &mut buf[..present])?;
+        buf[present..].fill(0);
+        Ok(buf.len())
+    }
+}
+
+impl<T: ReadAt + Size> Ext4Fs<TruncatedImage<T>> {
+    /// Open an image which may be shorter than the filesystem it holds.
+    /// Blocks past its end read as zeros; [`Ext4Fs::missing_blocks`]
+    /// tells which files lost content to it.
+    pub fn open_truncated(dev: T, policy: FeaturePolicy) -> Result<Self> {
+        let dev = TruncatedImage::new(dev)?;
+        let len = dev.len();
+        let mut fs = Ext4Fs::with_policy(dev, policy)?;
+        if len < fs.sb.blocks_count * fs.sb.block_size {
+            fs.image_len = Some(len);
+        }
+        Ok(fs)
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Blocks of the filesystem physically present in the image, `None`
+    /// unless it was opened with [`Ext4Fs::open_truncated`] and found
+    /// short.
+    pub fn present_blocks(&self) -> Option<u64> {
+        self.image_len.map(|len| len / self.sb.block_size)
+    }
+
+    /// Whether a run of device blocks lies, at least partly, past the
+    /// end of a truncated image.
+    pub(crate) fn is_missing(&self, start: u64, len: u64) -> bool {
+        self.present_blocks()
+            .is_some_and(|present| start + len > present)
+    }
+
+    /// Data blocks of `inode` past the end of a truncated image, read as
+    /// zeros. Holes and uninitialized extents never count.
+    pub fn missing_blocks(&self, inode: &Inode) -> Result<u64> {
+        let Some(present) = self.present_blocks() else {
+            return Ok(0);
+        };
+        if inode.flags.contains(InodeFlags::INLINE_DATA) {
+            return Ok(0);
+        }
+        let blocks = inode.size.div_ceil(self.sb.block_size);
+        let mut missing = 0;
+        for extent in inode.extents(&self.sb, &self.dev)? {
+            if extent.uninit || extent.block >= blocks {
+                continue;
+            }
+            let len = extent.len.min(blocks - extent.block);
+            let end = extent.start + len;
+            missing += end - extent.start.max(present).min(end);
+        }
+        Ok(missing)
+    }
+}
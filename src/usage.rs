@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use positioned_io::ReadAt;
+
+use crate::{human_bytes, Ext4Fs, Result};
+
+/// Inodes and space owned by one uid, gid or project.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Usage {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+/// Usage per owner, computed from the inodes rather than the quota files,
+/// so it is available whether quotas were enabled or not.
+#[derive(Debug, Default)]
+pub struct UsageReport {
+    pub by_uid: BTreeMap<u32, Usage>,
+    pub by_gid: BTreeMap<u32, Usage>,
+    /// empty unless the filesystem has the project feature
+    pub by_project: BTreeMap<u32, Usage>,
+}
+
+impl fmt::Display for UsageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (title, table) in [
+            ("uid", &self.by_uid),
+            ("gid", &self.by_gid),
+            ("project", &self.by_project),
+        ] {
+            if table.is_empty() && title == "project" {
+                continue;
+            }
+            writeln!(f, "*** usage by {title}")?;
+            writeln!(f, "{:<10} {:>12} {:>10}", title, "space", "files")?;
+            for (id, usage) in table {
+                writeln!(
+                    f,
+                    "{:<10} {:>12} {:>10}",
+                    id,
+                    human_bytes(usage.bytes),
+                    usage.files
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Aggregate inode counts and allocated space by owner over every
+    /// in-use inode, the way quota accounting does: reserved inodes other
+    /// than the root directory aren't charged to anyone.
+    pub fn usage_report(&self) -> Result<UsageReport> {
+        let mut report = UsageReport::default();
+        for n in self.initialized_inodes()? {
+            if n.0 < self.sb.first_ino && n != Self::ROOT {
+                continue;
+            }
+            let inode = self.inode(n)?;
+            if inode.mode == 0 || inode.links_count == 0 {
+                continue;
+            }
+            let bytes = inode.allocated_bytes(&self.sb);
+            let mut tables = vec![
+                report.by_uid.entry(inode.uid).or_default(),
+                report.by_gid.entry(inode.gid).or_default(),
+            ];
+            // small inodes have no room for a project id, they're in
+            // project 0
+            if self.sb.has_project() {
+                let project = inode.projid.unwrap_or(0);
+                tables.push(report.by_project.entry(project).or_default());
+            }
+            for usage in tables {
+                usage.files += 1;
+                usage.bytes += bytes;
+            }
+        }
+        Ok(report)
+    }
+}
@@ -0,0 +1,275 @@
+//! fs-verity: a Merkle tree over the content of a read-only file, kept
+//! past i_size together with the descriptor the file's digest is computed
+//! from.
+
+use std::fmt::Write;
+
+use failure::bail;
+use positioned_io::ReadAt;
+
+use crate::digest::{sha256, sha512};
+use crate::{Ext4Fs, Inode, InodeFlags, Reader, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerityHash {
+    Sha256,
+    Sha512,
+}
+
+impl VerityHash {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+        }
+    }
+
+    pub fn digest_size(self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha512 => 64,
+        }
+    }
+
+    // salts are padded to the block size of the hash
+    fn block_size(self) -> usize {
+        match self {
+            Self::Sha256 => 64,
+            Self::Sha512 => 128,
+        }
+    }
+
+    fn hash(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Self::Sha256 => sha256(data).to_vec(),
+            Self::Sha512 => sha512(data).to_vec(),
+        }
+    }
+}
+
+/// The verity descriptor of a file.
+#[derive(Debug, Clone)]
+pub struct VerityDescriptor {
+    pub hash: VerityHash,
+    /// log2 of the size of data and tree blocks
+    pub log_blocksize: u8,
+    pub salt: Vec<u8>,
+    pub data_size: u64,
+    pub root_hash: Vec<u8>,
+    /// optional PKCS#7 signature of the digest
+    pub signature: Vec<u8>,
+    /// the descriptor as hashed for the digest, signature size zeroed
+    hashed: Vec<u8>,
+}
+
+/// Result of checking a file's content against its Merkle tree.
+#[derive(Debug, Clone)]
+pub struct VerityCheck {
+    pub descriptor: VerityDescriptor,
+    /// data blocks whose hash doesn't match the tree
+    pub bad_data_blocks: Vec<u64>,
+    /// tree blocks, counted from the start of the tree, whose hash doesn't
+    /// match the level above
+    pub bad_tree_blocks: Vec<u64>,
+    /// whether the top of the tree hashes to the descriptor's root hash
+    pub root_matches: bool,
+}
+
+impl VerityCheck {
+    pub fn is_ok(&self) -> bool {
+        self.root_matches && self.bad_data_blocks.is_empty() && self.bad_tree_blocks.is_empty()
+    }
+}
+
+// the tree starts at the first 64K boundary past i_size
+const METADATA_ALIGN: u64 = 65536;
+// size of the fixed part of the descriptor, the signature follows
+const DESCRIPTOR_SIZE: usize = 256;
+
+impl VerityDescriptor {
+    fn parse(raw: &[u8]) -> Result<Self> {
+        if raw.len() < DESCRIPTOR_SIZE {
+            bail!("verity descriptor of {} bytes", raw.len());
+        }
+        let r = Reader::new(raw);
+        let version = r.u8(0x0)?;
+        if version != 1 {
+            bail!("unknown verity descriptor version {}", version);
+        }
+        let hash = match r.u8(0x1)? {
+            1 => VerityHash::Sha256,
+            2 => VerityHash::Sha512,
+            n => bail!("unknown verity hash algorithm {}", n),
+        };
+        let log_blocksize = r.u8(0x2)?;
+        let salt_size = r.u8(0x3)? as usize;
+        let sig_size = r.u32(0x4)? as usize;
+        if !(10..=16).contains(&log_blocksize) || salt_size > 32 {
+            bail!("bad verity descriptor: log block size {log_blocksize}, salt size {salt_size}");
+        }
+        let Some(signature) = raw.get(DESCRIPTOR_SIZE..DESCRIPTOR_SIZE + sig_size) else {
+            bail!("verity signature of {} bytes out of bounds", sig_size);
+        };
+        let mut hashed = raw[..DESCRIPTOR_SIZE].to_vec();
+        hashed[0x4..0x8].fill(0);
+        Ok(Self {
+            hash,
+            log_blocksize,
+            salt: r.vec(0x50, salt_size)?,
+            data_size: r.u64_lohi(0x8, 0xC)?,
+            root_hash: r.vec(0x10, hash.digest_size())?,
+            signature: signature.to_vec(),
+            hashed,
+        })
+    }
+
+    /// The file's digest, what signatures sign and `fsverity measure`
+    /// prints.
+    pub fn digest(&self) -> Vec<u8> {
+        self.hash.hash(&self.hashed)
+    }
+
+    /// `digest` as `algorithm:hex`.
+    pub fn digest_string(&self) -> String {
+        let mut s = format!("{}:", self.hash.name());
+        for b in self.digest() {
+            let _ = write!(s, "{b:02x}");
+        }
+        s
+    }
+
+    fn block_size(&self) -> u64 {
+        1 << self.log_blocksize
+    }
+
+    /// Hash of a tree or data block, salted.
+    fn hash_block(&self, block: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        if !self.salt.is_empty() {
+            let align = self.hash.block_size();
+            data = self.salt.clone();
+            data.resize(self.salt.len().div_ceil(align) * align, 0);
+        }
+        data.extend_from_slice(block);
+        self.hash.hash(&data)
+    }
+
+    /// Blocks of each tree level, from the one hashing the data blocks
+    /// up to the single block the root hash covers.
+    fn levels(&self) -> Vec<u64> {
+        let per_block = self.block_size() / self.hash.digest_size() as u64;
+        let mut blocks = self.data_size.div_ceil(self.block_size());
+        let mut levels = Vec::new();
+        while blocks > 1 {
+            blocks = blocks.div_ceil(per_block);
+            levels.push(blocks);
+        }
+        levels
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// The verity descriptor of `inode`, `None` if verity isn't enabled
+    /// on it.
+    pub fn verity(&self, inode: &Inode) -> Result<Option<VerityDescriptor>> {
+        if !inode.flags.contains(InodeFlags::VERITY) {
+            return Ok(None);
+        }
+        let bs = self.sb.block_size;
+        let reader = self.reader_of(inode)?;
+        // the metadata ends with the last extent, its last 4 bytes giving
+        // the size of the descriptor stored from a block boundary before
+        let end = reader
+            .extents()
+            .iter()
+            .map(|x| (x.block + x.len) * bs)
+            .max()
+            .unwrap_or(0);
+        let reader = reader.with_size(end);
+        let metadata = inode.size.div_ceil(METADATA_ALIGN) * METADATA_ALIGN;
+        if end < metadata + 4 {
+            bail!("no verity metadata past i_size");
+        }
+        let desc_size = Reader::new(&reader).u32(end - 4)? as u64;
+        if desc_size > end - 4 - metadata {
+            bail!("verity descriptor of {} bytes out of bounds", desc_size);
+        }
+        let desc_pos = (end - 4 - desc_size) / bs * bs;
+        let mut raw = vec![0u8; desc_size as usize];
+        reader.read_exact_at(desc_pos, &mut raw)?;
+        let descriptor = VerityDescriptor::parse(&raw)?;
+        if descriptor.data_size != inode.size {
+            bail!(
+                "verity descriptor covers {} bytes, file has {}",
+                descriptor.data_size,
+                inode.size
+            );
+        }
+        Ok(Some(descriptor))
+    }
+
+    /// Check the content of `inode` against its Merkle tree, and the
+    /// tree against its root hash. `None` if verity isn't enabled on it.
+    pub fn verify_verity(&self, inode: &Inode) -> Result<Option<VerityCheck>> {
+        let Some(descriptor) = self.verity(inode)? else {
+            return Ok(None);
+        };
+        let bs = descriptor.block_size();
+        let ds = descriptor.hash.digest_size();
+        let levels = descriptor.levels();
+        // levels are stored top down, the one over the data last
+        let mut starts = vec![0u64; levels.len()];
+        for level in (0..levels.len().saturating_sub(1)).rev() {
+            starts[level] = starts[level + 1] + levels[level + 1];
+        }
+        let tree_start = inode.size.div_ceil(METADATA_ALIGN) * METADATA_ALIGN;
+        let mut tree = vec![0u8; (levels.iter().sum::<u64>() * bs) as usize];
+        let reader = self.reader_of(inode)?;
+        let end = tree_start + tree.len() as u64;
+        reader.with_size(end).read_exact_at(tree_start, &mut tree)?;
+        let reader = self.reader_of(inode)?;
+
+        let mut bad_data_blocks = Vec::new();
+        let mut bad_tree_blocks = Vec::new();
+        let mut root_matches = false;
+        // the hash of block `i` of the level below `level`, kept in `level`
+        let stored = |level: usize, i: u64| {
+            let per_block = bs / ds as u64;
+            let at =
+                ((starts[level] + i / per_block) * bs) as usize + (i % per_block) as usize * ds;
+            &tree[at..at + ds]
+        };
+        let mut block = vec![0u8; bs as usize];
+        for i in 0..descriptor.data_size.div_ceil(bs) {
+            let len = bs.min(descriptor.data_size - i * bs) as usize;
+            block.fill(0);
+            reader.read_exact_at(i * bs, &mut block[..len])?;
+            let hash = descriptor.hash_block(&block);
+            if levels.is_empty() {
+                root_matches = hash == descriptor.root_hash;
+            } else if hash != stored(0, i) {
+                bad_data_blocks.push(i);
+            }
+        }
+        for (level, &blocks) in levels.iter().enumerate() {
+            for i in 0..blocks {
+                let at = ((starts[level] + i) * bs) as usize;
+                let hash = descriptor.hash_block(&tree[at..at + bs as usize]);
+                if level + 1 == levels.len() {
+                    root_matches = hash == descriptor.root_hash;
+                } else if hash != stored(level + 1, i) {
+                    bad_tree_blocks.push(starts[level] + i);
+                }
+            }
+        }
+        if descriptor.data_size == 0 {
+            root_matches = descriptor.root_hash.iter().all(|&b| b == 0);
+        }
+        Ok(Some(VerityCheck {
+            descriptor,
+            bad_data_blocks,
+            bad_tree_blocks,
+            root_matches,
+        }))
+    }
+}
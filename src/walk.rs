@@ -0,0 +1,138 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use positioned_io::ReadAt;
+
+use crate::progress::{NoProgress, ProgressSink, ProgressTracker};
+use crate::{Ext4Fs, FileType, Inode, InodeNumber, Result};
+
+/// An inode reached while walking the tree.
+#[derive(Debug)]
+pub struct WalkEntry {
+    /// absolute path, `/` for the root
+    pub path: String,
+    pub inode_number: InodeNumber,
+    pub inode: Inode,
+    /// 0 for the walk root
+    pub depth: usize,
+}
+
+/// An error recorded instead of aborting the walk.
+#[derive(Debug)]
+pub struct WalkError {
+    pub path: String,
+    pub error: failure::Error,
+}
+
+#[derive(Debug, Default)]
+pub struct WalkSummary {
+    pub visited: u64,
+    pub errors: Vec<WalkError>,
+}
+
+impl fmt::Display for WalkSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} entries visited, {} errors",
+            self.visited,
+            self.errors.len()
+        )?;
+        for error in &self.errors {
+            write!(f, "\n  {}: {}", error.path, error.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: ReadAt> Ext4Fs<T> {
+    /// Visit `path` and everything below it depth first, calling `f` for
+    /// each entry. Symlinks are not followed. Entries whose name has a
+    /// `/` or a NUL in it are errors rather than visited, so are
+    /// directories met a second time, which a corrupted tree can loop
+    /// back to. With `keep_going`, errors reading an entry or returned by
+    /// `f` are recorded in the summary and the walk carries on with the
+    /// next entry, otherwise the first error aborts the walk, returned
+    /// with the path of its entry.
+    pub fn walk(
+        &self,
+        path: &str,
+        keep_going: bool,
+        f: impl FnMut(&WalkEntry) -> Result<()>,
+    ) -> Result<WalkSummary> {
+        self.walk_with_progress(path, keep_going, &mut NoProgress, f)
+    }
+
+    /// `walk`, counting each entry visited to `progress`.
+    pub fn walk_with_progress(
+        &self,
+        path: &str,
+        keep_going: bool,
+        progress: &mut dyn ProgressSink,
+        mut f: impl FnMut(&WalkEntry) -> Result<()>,
+    ) -> Result<WalkSummary> {
+        let mut tracker = ProgressTracker::new(progress, 0);
+        let root = match self.resolve(path)? {
+            Some(x) => x,
+            None => failure::bail!("no such file or directory: {}", path),
+        };
+        let path = format!("/{}", path.trim_matches('/'));
+        let mut summary = WalkSummary::default();
+        let mut stack = vec![(path, Some(root), 0)];
+        let mut dirs = HashSet::new();
+        while let Some((path, n, depth)) = stack.pop() {
+            let result = match n {
+                Some(n) => self.visit(&path, n, depth, &mut f, &mut stack, &mut dirs),
+                None => Err(failure::format_err!("invalid entry name")),
+            };
+            summary.visited += 1;
+            tracker.entry(&path);
+            if let Err(error) = result {
+                if !keep_going {
+                    failure::bail!("{}: {}", path, error);
+                }
+                summary.errors.push(WalkError { path, error });
+            }
+        }
+        Ok(summary)
+    }
+
+    fn visit(
+        &self,
+        path: &str,
+        n: InodeNumber,
+        depth: usize,
+        f: &mut impl FnMut(&WalkEntry) -> Result<()>,
+        stack: &mut Vec<(String, Option<InodeNumber>, usize)>,
+        dirs: &mut HashSet<InodeNumber>,
+    ) -> Result<()> {
+        let entry = WalkEntry {
+            path: path.to_string(),
+            inode_number: n,
+            inode: self.inode(n)?,
+            depth,
+        };
+        f(&entry)?;
+        if matches!(entry.inode.file_type(), FileType::Directory) {
+            if !dirs.insert(n) {
+                failure::bail!("directory {} reached again, the tree loops", n.0);
+            }
+            let mut children: Vec<_> = self
+                .entries_of(n, &entry.inode)?
+                .into_iter()
+                .filter(|x| x.name != "." && x.name != "..")
+                .map(|x| {
+                    let path = format!("{}/{}", path.trim_end_matches('/'), x.name);
+                    // a name with a slash would pass for a path below, up
+                    // or out of the tree
+                    let valid = !x.name_bytes.contains(&b'/') && !x.name_bytes.contains(&0);
+                    (path, valid.then_some(x.inode), depth + 1)
+                })
+                .collect();
+            // the stack pops from the back, keep on-disk order
+            children.reverse();
+            stack.extend(children);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use failure::bail;
+use positioned_io::ReadAt;
+
+use crate::crc::crc32c;
+use crate::{Inode, InodeFlags, InodeNumber, Reader, Result, SuperBlock};
+
+/// An extended attribute. `name` is stored without the namespace prefix
+/// implied by `name_index`, see [`Xattr::full_name`].
+#[derive(Debug, Clone)]
+pub struct Xattr {
+    pub name_index: u8,
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+impl Xattr {
+    // header before the first in-inode entry
+    pub(crate) const MAGIC: u32 = 0xEA020000;
+
+    pub fn full_name(&self) -> String {
+        let prefix = match self.name_index {
+            1 => "user.",
+            2 => "system.posix_acl_access",
+            3 => "system.posix_acl_default",
+            4 => "trusted.",
+            6 => "security.",
+            7 => "system.",
+            8 => "system.richacl",
+            _ => "",
+        };
+        format!("{prefix}{}", self.name)
+    }
+
+    /// Parse the entry table at the start of `entries` up to the
+    /// terminating null entry. Value offsets are relative to `values`,
+    /// values too big for it are read from their EA inode.
+    pub(crate) fn parse_entries(
+        entries: &[u8],
+        values: &[u8],
+        sb: &SuperBlock,
+        dev: &dyn ReadAt,
+    ) -> Result<Vec<Self>> {
+        let r = Reader::new(entries);
+        let mut xattrs = Vec::new();
+        let mut offset = 0u64;
+        // an entry is at least 16 bytes, anything shorter is the end
+        while offset + 16 <= entries.len() as u64 && r.u32(offset)? != 0 {
+            let name_len = r.u8(offset)? as usize;
+            let name_index = r.u8(offset + 0x1)?;
+            let value_offs = r.u16(offset + 0x2)? as usize;
+            let value_inum = r.u32(offset + 0x4)?;
+            let value_size = r.u32(offset + 0x8)? as usize;
+            let hash = r.u32(offset + 0xC)?;
+            let raw_name = r.vec(offset + 0x10, name_len)?;
+            let name = String::from_utf8_lossy(&raw_name).into();
+            let value = if value_inum != 0 {
+                let value = ea_inode_value(value_inum, value_size, sb, dev)
+                    .map_err(|e| failure::format_err!("xattr {name:?}: {e}"))?;
+                if !entry_hash_matches(&raw_name, &value, sb, hash) {
+                    bail!("xattr {name:?} hash doesn't match its EA inode {value_inum}");
+                }
+                value
+            } else {
+                match values.get(value_offs..value_offs + value_size) {
+                    Some(value) => value.to_vec(),
+                    None => bail!("xattr {name:?} value out of bounds"),
+                }
+            };
+            xattrs.push(Self {
+                name_index,
+                name,
+                value,
+            });
+            // entries are padded to 4 bytes
+            offset += (0x10 + name_len as u64 + 3) & !3;
+        }
+        Ok(xattrs)
+    }
+}
+
+/// Read a value stored as the content of inode `n`, which keeps the
+/// value's crc32c in its atime field.
+fn ea_inode_value(n: u32, size: usize, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Vec<u8>> {
+    let inode = InodeNumber(n as u64).inode(sb, dev)?;
+    if !inode.flags.contains(InodeFlags::EA_INODE) {
+        bail!("inode {} is no EA inode", n);
+    }
+    if inode.size != size as u64 {
+        bail!(
+            "EA inode {} holds {} bytes, {} expected",
+            n,
+            inode.size,
+            size
+        );
+    }
+    let mut value = vec![0u8; size];
+    inode.reader(sb, dev)?.read_exact_at(0, &mut value)?;
+    if crc32c(sb.checksum_seed, &value) != inode.atime.seconds as u32 {
+        bail!("EA inode {} content doesn't match its hash", n);
+    }
+    Ok(value)
+}
+
+/// Whether `hash` is the entry hash of an EA inode value: a hash of the
+/// name folded with the value's crc32c. Older kernels hashed names as
+/// signed chars, both are accepted.
+fn entry_hash_matches(name: &[u8], value: &[u8], sb: &SuperBlock, hash: u32) -> bool {
+    let value_hash = crc32c(sb.checksum_seed, value);
+    let compute = |byte: fn(u8) -> u32| {
+        let name_hash = name
+            .iter()
+            .fold(0u32, |h, &c| (h << 5) ^ (h >> 27) ^ byte(c));
+        (name_hash << 16) ^ (name_hash >> 16) ^ value_hash
+    };
+    hash == compute(|c| c as u32) || hash == compute(|c| c as i8 as u32)
+}
+
+impl Inode {
+    // entries of an EA block follow a 32 bytes header
+    const XATTR_BLOCK_HEADER_SIZE: usize = 0x20;
+
+    /// Extended attributes of the inode, those stored in the inode itself
+    /// past i_extra_isize followed by those in the EA block i_file_acl
+    /// points to.
+    pub fn xattrs(&self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Vec<Xattr>> {
+        let mut xattrs = self.inode_xattrs(sb, dev)?;
+        xattrs.extend(self.block_xattrs(sb, dev)?);
+        Ok(xattrs)
+    }
+
+    fn inode_xattrs(&self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Vec<Xattr>> {
+        let area = &self.xattr_area;
+        if area.len() < 4 || Reader::new(area.as_slice()).u32(0)? != Xattr::MAGIC {
+            return Ok(Vec::new());
+        }
+        // in-inode value offsets count from the first entry
+        Xattr::parse_entries(&area[4..], &area[4..], sb, dev)
+    }
+
+    fn block_xattrs(&self, sb: &SuperBlock, dev: &dyn ReadAt) -> Result<Vec<Xattr>> {
+        if self.file_acl == 0 {
+            return Ok(Vec::new());
+        }
+        let mut block = vec![0u8; sb.block_size as usize];
+        dev.read_exact_at(self.file_acl * sb.block_size, &mut block)?;
+        let magic = Reader::new(block.as_slice()).u32(0)?;
+        if magic != Xattr::MAGIC {
+            bail!("bad xattr block magic: 0x{:X}", magic);
+        }
+        // block value offsets count from the start of the block
+        Xattr::parse_entries(&block[Self::XATTR_BLOCK_HEADER_SIZE..], &block, sb, dev)
+    }
+}
@@ -0,0 +1,453 @@
+//! A streaming zstd writer for the archives the image is exported to.
+//!
+//! Blocks are compressed on their own, matches never reaching back into
+//! the block before, so that worker threads compress them while the
+//! caller goes on reading the image. Literals are stored as they are and
+//! sequences coded with the predefined tables of RFC 8878: less dense
+//! than the reference encoder, but any zstd decoder reads it.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+// the largest block content
+const BLOCK_SIZE: usize = 128 * 1024;
+// magic, no content size, checksum or dictionary, and a window of
+// 128 KiB: 2^(10 + 7)
+const HEADER: [u8; 6] = [0x28, 0xB5, 0x2F, 0xFD, 0x00, 7 << 3];
+// blocks handed to the workers and not written out yet, at most
+const IN_FLIGHT: u64 = 8;
+const MAX_WORKERS: usize = 4;
+
+/// Compress everything written to it as a single zstd frame written on
+/// to `inner`. [`finish`](Self::finish) must be called to complete it.
+pub struct ZstdWriter<W: Write> {
+    inner: W,
+    buf: Vec<u8>,
+    /// blocks to compress, numbered, and whether each is the last one
+    jobs: Option<SyncSender<(u64, Vec<u8>, bool)>>,
+    done: Receiver<(u64, Vec<u8>)>,
+    workers: Vec<JoinHandle<()>>,
+    /// compressed blocks come back in any order
+    ready: BTreeMap<u64, Vec<u8>>,
+    sent: u64,
+    written: u64,
+    header_written: bool,
+}
+
+impl<W: Write> ZstdWriter<W> {
+    /// `level` as zstd's, 1 for fastest to 19 for smallest: how many
+    /// earlier positions are tried for each match.
+    pub fn new(inner: W, level: u8) -> Self {
+        let depth = 1 << (level.clamp(1, 19) / 3);
+        let threads = thread::available_parallelism().map_or(1, |x| x.get().min(MAX_WORKERS));
+        let (jobs, queue) = mpsc::sync_channel::<(u64, Vec<u8>, bool)>(IN_FLIGHT as usize);
+        let (results, done) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+        let workers = (0..threads)
+            .map(|_| {
+                let queue = queue.clone();
+                let results = results.clone();
+                thread::spawn(move || loop {
+                    let job = queue
+                        .lock()
+                        .map_err(|_| ())
+                        .and_then(|x| x.recv().map_err(|_| ()));
+                    let Ok((index, data, last)) = job else {
+                        return;
+                    };
+                    if results.send((index, block(&data, last, depth))).is_err() {
+                        return;
+                    }
+                })
+            })
+            .collect();
+        ZstdWriter {
+            inner,
+            buf: Vec::with_capacity(BLOCK_SIZE),
+            jobs: Some(jobs),
+            done,
+            workers,
+            ready: BTreeMap::new(),
+            sent: 0,
+            written: 0,
+            header_written: false,
+        }
+    }
+
+    /// Write the last block and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.send(true)?;
+        self.drain(0)?;
+        // the workers end once the queue is closed
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Hand what is buffered to the workers as a block, then write out
+    /// what they are done with.
+    fn send(&mut self, last: bool) -> io::Result<()> {
+        if !self.header_written {
+            self.inner.write_all(&HEADER)?;
+            self.header_written = true;
+        }
+        let data = std::mem::replace(&mut self.buf, Vec::with_capacity(BLOCK_SIZE));
+        let jobs = self.jobs.as_ref().expect("zstd writer already finished");
+        jobs.send((self.sent, data, last))
+            .map_err(|_| io::Error::other("zstd worker failed"))?;
+        self.sent += 1;
+        self.drain(IN_FLIGHT)
+    }
+
+    /// Write compressed blocks in order until at most `in_flight` are
+    /// left with the workers.
+    fn drain(&mut self, in_flight: u64) -> io::Result<()> {
+        loop {
+            while let Some(data) = self.ready.remove(&self.written) {
+                self.inner.write_all(&data)?;
+                self.written += 1;
+            }
+            let result = match self.sent - self.written > in_flight {
+                true => self.done.recv().ok(),
+                false => self.done.try_recv().ok(),
+            };
+            match result {
+                Some((index, data)) => {
+                    self.ready.insert(index, data);
+                }
+                None if self.sent - self.written > in_flight => {
+                    return Err(io::Error::other("zstd worker failed"))
+                }
+                None => return Ok(()),
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for ZstdWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // a full block is only sent once more comes, the last block is
+        // flagged as such
+        if self.buf.len() == BLOCK_SIZE {
+            self.send(false)?;
+        }
+        let len = data.len().min(BLOCK_SIZE - self.buf.len());
+        self.buf.extend_from_slice(&data[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            self.send(false)?;
+        }
+        self.drain(0)?;
+        self.inner.flush()
+    }
+}
+
+/// `data` as a block, with its header: a run of one byte, compressed, or
+/// as it is when compressing doesn't pay.
+fn block(data: &[u8], last: bool, depth: usize) -> Vec<u8> {
+    let header = |kind: u32, size: usize| {
+        let header = last as u32 | kind << 1 | (size as u32) << 3;
+        header.to_le_bytes()[..3].to_vec()
+    };
+    if data.len() > 1 && data.iter().all(|&b| b == data[0]) {
+        let mut out = header(1, data.len());
+        out.push(data[0]);
+        return out;
+    }
+    let compressed = compress(data, depth);
+    let (kind, body) = match compressed.len() < data.len() {
+        true => (2, compressed.as_slice()),
+        false => (0, data),
+    };
+    let mut out = header(kind, body.len());
+    out.extend_from_slice(body);
+    out
+}
+
+const MIN_MATCH: usize = 4;
+const HASH_BITS: u32 = 15;
+
+/// A match of `len` bytes `offset` back, after `literals` bytes.
+struct Sequence {
+    literals: usize,
+    len: usize,
+    offset: usize,
+}
+
+/// The literals and sequences sections of a compressed block.
+fn compress(data: &[u8], depth: usize) -> Vec<u8> {
+    let hash = |i: usize| {
+        let word = u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        (word.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    };
+    // the last position of each hash, and the one before each position
+    // with the same hash
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let insert = |i: usize, head: &mut [usize], prev: &mut [usize]| {
+        let h = hash(i);
+        prev[i] = head[h];
+        head[h] = i;
+    };
+    let mut literals = Vec::new();
+    let mut sequences = Vec::new();
+    let mut anchor = 0;
+    let mut i = 0;
+    while i + MIN_MATCH <= data.len() {
+        let (mut len, mut offset) = (0, 0);
+        let mut candidate = head[hash(i)];
+        for _ in 0..depth {
+            if candidate == usize::MAX {
+                break;
+            }
+            let same = data[candidate..]
+                .iter()
+                .zip(&data[i..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if same > len {
+                (len, offset) = (same, i - candidate);
+            }
+            candidate = prev[candidate];
+        }
+        insert(i, &mut head, &mut prev);
+        if len < MIN_MATCH {
+            i += 1;
+            continue;
+        }
+        for j in i + 1..(i + len).min(data.len() + 1 - MIN_MATCH) {
+            insert(j, &mut head, &mut prev);
+        }
+        literals.extend_from_slice(&data[anchor..i]);
+        sequences.push(Sequence {
+            literals: i - anchor,
+            len,
+            offset,
+        });
+        i += len;
+        anchor = i;
+    }
+    literals.extend_from_slice(&data[anchor..]);
+
+    // raw literals, their size in 5, 12 or 20 bits
+    let n = literals.len();
+    let mut out = match n {
+        0..=31 => vec![(n << 3) as u8],
+        32..=4095 => vec![(0b0100 | (n & 0xF) << 4) as u8, (n >> 4) as u8],
+        _ => vec![
+            (0b1100 | (n & 0xF) << 4) as u8,
+            (n >> 4) as u8,
+            (n >> 12) as u8,
+        ],
+    };
+    out.extend_from_slice(&literals);
+    let n = sequences.len();
+    match n {
+        0..=127 => out.push(n as u8),
+        128..=0x7EFF => out.extend_from_slice(&[(n >> 8) as u8 + 0x80, n as u8]),
+        _ => out.extend_from_slice(&[0xFF, (n - 0x7F00) as u8, ((n - 0x7F00) >> 8) as u8]),
+    }
+    if n > 0 {
+        // all three codes with their predefined tables
+        out.push(0);
+        out.extend_from_slice(&encode_sequences(&sequences));
+    }
+    out
+}
+
+// baselines and extra bits of the literal length and match length codes
+const LL_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LL_BITS: [u32; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+const ML_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+const ML_BITS: [u32; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+// the predefined distributions, -1 for less than one
+const LL_DIST: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+const ML_DIST: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OF_DIST: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+
+/// A value as its code and the extra bits after the code's baseline.
+fn code(base: &[u32], bits: &[u32], value: u32) -> (usize, u64, u32) {
+    let code = base.partition_point(|&x| x <= value) - 1;
+    (code, (value - base[code]) as u64, bits[code])
+}
+
+/// The bitstream of the sequences, written last to first so that the
+/// decoder, reading it backwards, gets the first one first.
+fn encode_sequences(sequences: &[Sequence]) -> Vec<u8> {
+    let ll_table = FseTable::new(&LL_DIST, 6);
+    let ml_table = FseTable::new(&ML_DIST, 6);
+    let of_table = FseTable::new(&OF_DIST, 5);
+    let codes: Vec<_> = sequences
+        .iter()
+        .map(|x| {
+            // offsets past the three repeated ones
+            let offset = x.offset as u32 + 3;
+            let of_code = 31 - offset.leading_zeros();
+            (
+                code(&LL_BASE, &LL_BITS, x.literals as u32),
+                code(&ML_BASE, &ML_BITS, x.len as u32),
+                (of_code as usize, (offset - (1 << of_code)) as u64, of_code),
+            )
+        })
+        .collect();
+    let mut bits = BitWriter::default();
+    let (ll, ml, of) = codes[codes.len() - 1];
+    let mut ml_state = ml_table.init(ml.0);
+    let mut of_state = of_table.init(of.0);
+    let mut ll_state = ll_table.init(ll.0);
+    for (i, &(ll, ml, of)) in codes.iter().enumerate().rev() {
+        if i + 1 < codes.len() {
+            of_table.encode(&mut of_state, of.0, &mut bits);
+            ml_table.encode(&mut ml_state, ml.0, &mut bits);
+            ll_table.encode(&mut ll_state, ll.0, &mut bits);
+        }
+        bits.add(ll.1, ll.2);
+        bits.add(ml.1, ml.2);
+        bits.add(of.1, of.2);
+    }
+    bits.add(ml_state as u64, ml_table.log);
+    bits.add(of_state as u64, of_table.log);
+    bits.add(ll_state as u64, ll_table.log);
+    bits.finish()
+}
+
+/// Bits packed from the lowest up, closed by a 1 bit.
+#[derive(Default)]
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn add(&mut self, value: u64, bits: u32) {
+        self.acc |= (value & ((1 << bits) - 1)) << self.len;
+        self.len += bits;
+        while self.len >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.add(1, 1);
+        if self.len > 0 {
+            self.out.push(self.acc as u8);
+        }
+        self.out
+    }
+}
+
+/// The encoding side of an FSE table: symbols spread over the states as
+/// the decoder spreads them, and for each symbol where its states start
+/// and how many bits leaving one of them takes.
+struct FseTable {
+    log: u32,
+    /// states, `1 << log` added, grouped by symbol
+    states: Vec<u16>,
+    /// per symbol: where its states start, less the state number of its
+    /// first one, and the bit count offset
+    symbols: Vec<(i32, u32)>,
+}
+
+impl FseTable {
+    fn new(dist: &[i16], log: u32) -> Self {
+        let size = 1usize << log;
+        let mask = size - 1;
+        let mut symbol_at = vec![0usize; size];
+        // symbols of less than one get a state each, from the end
+        let mut high = size - 1;
+        let mut start = vec![0usize; dist.len() + 1];
+        for (s, &n) in dist.iter().enumerate() {
+            start[s + 1] = start[s] + if n == -1 { 1 } else { n as usize };
+            if n == -1 {
+                symbol_at[high] = s;
+                high -= 1;
+            }
+        }
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut position = 0;
+        for (s, &n) in dist.iter().enumerate() {
+            for _ in 0..n.max(0) {
+                symbol_at[position] = s;
+                position = (position + step) & mask;
+                while position > high {
+                    position = (position + step) & mask;
+                }
+            }
+        }
+        let mut states = vec![0u16; size];
+        let mut next = start.clone();
+        for (u, &s) in symbol_at.iter().enumerate() {
+            states[next[s]] = (size + u) as u16;
+            next[s] += 1;
+        }
+        let symbols = dist
+            .iter()
+            .enumerate()
+            .map(|(s, &n)| {
+                let first = start[s] as i32;
+                match n {
+                    0 => (0, ((log + 1) << 16) - size as u32),
+                    -1 | 1 => (first - 1, (log << 16) - size as u32),
+                    n => {
+                        let n = n as u32;
+                        let max_bits = log - (31 - (n - 1).leading_zeros());
+                        (first - n as i32, (max_bits << 16) - (n << max_bits))
+                    }
+                }
+            })
+            .collect();
+        FseTable {
+            log,
+            states,
+            symbols,
+        }
+    }
+
+    /// The state to start from so that `symbol` is the last one decoded.
+    fn init(&self, symbol: usize) -> u16 {
+        let (find, nb) = self.symbols[symbol];
+        let bits = (nb + (1 << 15)) >> 16;
+        let value = (bits << 16) - nb;
+        self.states[((value >> bits) as i32 + find) as usize]
+    }
+
+    fn encode(&self, state: &mut u16, symbol: usize, out: &mut BitWriter) {
+        let (find, nb) = self.symbols[symbol];
+        let bits = (*state as u32 + nb) >> 16;
+        out.add(*state as u64, bits);
+        *state = self.states[((*state as u32 >> bits) as i32 + find) as usize];
+    }
+}
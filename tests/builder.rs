@@ -0,0 +1,406 @@
+//! Images written by the builder read back as the tree they were built
+//! from.
+
+use std::fs::File;
+use std::path::PathBuf;
+
+use positioned_io::ReadAt;
+use read_file_block_way::builder::{ImageBuilder, NodeKind, NodeMeta};
+use read_file_block_way::events::Anomaly;
+use read_file_block_way::{Ext4Fs, FileType, Timestamp, TypedInode};
+
+fn read(fs: &Ext4Fs<Vec<u8>>, path: &str) -> Vec<u8> {
+    let inode = fs.stat(path).unwrap().unwrap();
+    let mut buf = vec![0u8; inode.size as usize];
+    fs.reader(inode.as_file().unwrap())
+        .unwrap()
+        .read_exact_at(0, &mut buf)
+        .unwrap();
+    buf
+}
+
+#[test]
+fn built_tree_reads_back() {
+    for block_size in [1024, 2048, 4096] {
+        let mut builder = ImageBuilder::new().block_size(block_size);
+        let owned = NodeMeta {
+            permissions: 0o640,
+            uid: 100_000,
+            gid: 42,
+            mtime: Some(Timestamp {
+                seconds: -1,
+                nanos: 5,
+            }),
+        };
+        builder
+            .add("/etc", NodeKind::Dir, NodeMeta::new(0o755))
+            .unwrap();
+        builder
+            .add(
+                "/etc/passwd",
+                NodeKind::File(b"root:x:0:0\n".to_vec()),
+                owned,
+            )
+            .unwrap();
+        builder
+            .add(
+                "/etc/link",
+                NodeKind::Symlink("passwd".into()),
+                NodeMeta::new(0o777),
+            )
+            .unwrap();
+        let long_target = "../".repeat(40) + "etc/passwd";
+        builder
+            .add(
+                "/long",
+                NodeKind::Symlink(long_target.clone()),
+                NodeMeta::new(0o777),
+            )
+            .unwrap();
+        builder
+            .add("/dir", NodeKind::Dir, NodeMeta::new(0o700))
+            .unwrap();
+        for i in 0..200 {
+            let path = format!("/dir/entry-{i:04}");
+            builder
+                .add(
+                    &path,
+                    NodeKind::File(vec![i as u8; i]),
+                    NodeMeta::new(0o644),
+                )
+                .unwrap();
+        }
+        // spans several groups with 1 KiB blocks
+        let big: Vec<u8> = (0..20_000_000u32).map(|i| (i % 251) as u8).collect();
+        builder
+            .add("/big", NodeKind::File(big.clone()), NodeMeta::new(0o644))
+            .unwrap();
+        assert!(builder
+            .add("/nowhere/x", NodeKind::Dir, NodeMeta::new(0o755))
+            .is_err());
+        assert!(builder
+            .add("/etc/passwd", NodeKind::Dir, NodeMeta::new(0o755))
+            .is_err());
+
+        let mut image = Vec::new();
+        let size = builder.build(&mut image).unwrap();
+        assert_eq!(size, image.len() as u64);
+        let fs = Ext4Fs::new(image).unwrap();
+        assert_eq!(fs.sb.block_size, block_size);
+
+        assert_eq!(read(&fs, "/etc/passwd"), b"root:x:0:0\n");
+        let passwd = fs.stat("/etc/passwd").unwrap().unwrap();
+        assert_eq!(passwd.mode, 0o100640);
+        assert_eq!((passwd.uid, passwd.gid), (100_000, 42));
+        assert_eq!(passwd.mtime, owned.mtime.unwrap());
+        assert_eq!(read(&fs, "/big"), big);
+        for i in [0, 1, 199] {
+            assert_eq!(read(&fs, &format!("/dir/entry-{i:04}")), vec![i as u8; i]);
+        }
+        for (path, target) in [("/etc/link", "passwd"), ("/long", long_target.as_str())] {
+            let inode = fs.stat(path).unwrap().unwrap();
+            assert_eq!(inode.file_type(), FileType::SymbolicLink);
+            let link = inode.into_symlink().unwrap();
+            assert_eq!(link.target(&fs.sb, &fs.dev).unwrap(), target);
+        }
+        let dir = fs.stat("/dir").unwrap().unwrap().into_dir().unwrap();
+        let entries = fs
+            .dir_entries(fs.resolve("/dir").unwrap().unwrap(), &dir)
+            .unwrap();
+        assert_eq!(entries.len(), 202);
+        assert!(fs.stat("/lost+found").unwrap().is_some());
+        let typed = fs
+            .stat_many(&["/etc", "/etc/passwd", "/etc/link", "/missing"])
+            .unwrap();
+        assert!(matches!(
+            typed[..],
+            [
+                Some(TypedInode::Dir(_)),
+                Some(TypedInode::File(_)),
+                Some(TypedInode::Symlink(_)),
+                None
+            ]
+        ));
+        let report = fs.check().unwrap();
+        assert!(report.findings.is_empty(), "{:?}", report.findings);
+        // root, lost+found, /etc and below, /long, /dir and below, /big
+        assert_eq!(report.inodes_checked, 2 + 3 + 1 + 201 + 1);
+    }
+}
+
+#[test]
+fn check_goes_past_unreadable_inodes() {
+    let mut builder = ImageBuilder::new();
+    for path in ["/a", "/b"] {
+        builder
+            .add(path, NodeKind::File(b"x".to_vec()), NodeMeta::new(0o644))
+            .unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let fs = Ext4Fs::new(image).unwrap();
+    let a = fs.resolve("/a").unwrap().unwrap();
+    let (block, offset) = a.inode_location(&fs.sb, &fs.dev).unwrap();
+    let mut image = fs.dev;
+    // cut off in the inode table, right before /a
+    image.truncate((block * fs.sb.block_size + offset) as usize);
+
+    let fs = Ext4Fs::new(image).unwrap();
+    assert!(fs.inode(a).is_err());
+    let report = fs.check().unwrap();
+    assert!(!report.findings.is_empty());
+    assert!(
+        report
+            .findings
+            .iter()
+            .all(|x| matches!(x, Anomaly::UnreadableInode { inode, .. } if inode.0 >= a.0)),
+        "{:?}",
+        report.findings
+    );
+    // root and lost+found
+    assert_eq!(report.inodes_checked, 2);
+}
+
+#[test]
+fn data_reaches_the_end_of_the_image() {
+    let mut builder = ImageBuilder::new();
+    let content: Vec<u8> = (0..4).flat_map(|i| vec![b'a' + i; 4096]).collect();
+    builder
+        .add("/f", NodeKind::File(content.clone()), NodeMeta::new(0o644))
+        .unwrap();
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let fs = Ext4Fs::new(image).unwrap();
+    // no free blocks, the file ends the image
+    assert_eq!(read(&fs, "/f"), content);
+}
+
+#[test]
+fn reads_find_extents_in_any_order() {
+    let mut builder = ImageBuilder::new();
+    let content: Vec<u8> = (0..4).flat_map(|i| vec![b'a' + i; 4096]).collect();
+    builder
+        .add("/f", NodeKind::File(content), NodeMeta::new(0o644))
+        .unwrap();
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let fs = Ext4Fs::new(image).unwrap();
+    let n = fs.resolve("/f").unwrap().unwrap();
+    let inode = fs.inode(n).unwrap();
+    let start = inode.extents(&fs.sb, &fs.dev).unwrap()[0].start;
+    let (block, offset) = n.inode_location(&fs.sb, &fs.dev).unwrap();
+    let at = (block * fs.sb.block_size + offset) as usize + 0x28;
+    let mut image = fs.dev;
+    // blocks 2 and 3, then 0, leaving block 1 a hole
+    let extent = |logical: u32, len: u16, physical: u64| {
+        let mut entry = logical.to_le_bytes().to_vec();
+        entry.extend(len.to_le_bytes());
+        entry.extend(((physical >> 32) as u16).to_le_bytes());
+        entry.extend((physical as u32).to_le_bytes());
+        entry
+    };
+    image[at + 2..at + 4].copy_from_slice(&2u16.to_le_bytes());
+    image[at + 12..at + 24].copy_from_slice(&extent(2, 2, start + 2));
+    image[at + 24..at + 36].copy_from_slice(&extent(0, 1, start));
+
+    let fs = Ext4Fs::new(image).unwrap();
+    let read = read(&fs, "/f");
+    for (i, expected) in [b'a', 0, b'c', b'd'].into_iter().enumerate() {
+        assert!(
+            read[i * 4096..(i + 1) * 4096]
+                .iter()
+                .all(|&x| x == expected),
+            "block {i}"
+        );
+    }
+}
+
+#[test]
+fn uninit_bitmaps_stay_in_their_group() {
+    let mut image = Vec::new();
+    ImageBuilder::new().build(&mut image).unwrap();
+    let sb = Ext4Fs::new(image.clone()).unwrap().sb;
+    let put = |image: &mut Vec<u8>, at: u64, x: u32| {
+        image[at as usize..][..4].copy_from_slice(&x.to_le_bytes())
+    };
+    // gdt_csum so that BLOCK_UNINIT is honored, 64 bytes descriptors to
+    // point past 2^64
+    put(&mut image, 1024 + 0x64, sb.feature_ro_compat | 0x10);
+    put(&mut image, 1024 + 0x60, sb.feature_incompat | 0x80);
+    image[1024 + 0xFE..][..2].copy_from_slice(&64u16.to_le_bytes());
+    let desc = (sb.first_data_block + 1) * sb.block_size;
+    put(&mut image, desc, u32::MAX);
+    put(&mut image, desc + 0x8, u32::MAX);
+    put(&mut image, desc + 0x28, u32::MAX);
+    image[desc as usize + 0x12] |= 0x2;
+    let fs = Ext4Fs::new(image.clone()).unwrap();
+    let free = fs.free_space().unwrap();
+    // the superblock, descriptors and reserved descriptor blocks, and the
+    // inode bitmap left in the group
+    let used = 3 + sb.reserved_gdt_blocks;
+    assert_eq!(
+        free.free_blocks(),
+        sb.blocks_count - sb.first_data_block - used
+    );
+    assert!(fs.is_block_allocated(sb.first_data_block).unwrap());
+    assert!(!fs.is_block_allocated(sb.blocks_count - 1).unwrap());
+
+    // meta_bg with a first meta group as far as it goes, a descriptor
+    // table as long, taking the group and no more
+    put(&mut image, 1024 + 0x60, sb.feature_incompat | 0x80 | 0x10);
+    put(&mut image, 1024 + 0x104, u32::MAX);
+    let fs = Ext4Fs::new(image).unwrap();
+    assert_eq!(fs.free_space().unwrap().free_blocks(), 0);
+}
+
+#[test]
+fn mapped_reads_stay_in_the_image() {
+    let content: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+    let mut builder = ImageBuilder::new();
+    builder
+        .add("/f", NodeKind::File(content.clone()), NodeMeta::new(0o644))
+        .unwrap();
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("mapped_reads_stay_in_the_image");
+    std::fs::write(&path, &image).unwrap();
+    let fs = Ext4Fs::open_mmap(&File::open(&path).unwrap()).unwrap();
+    let n = fs.resolve("/f").unwrap().unwrap();
+    let file = fs.stat("/f").unwrap().unwrap().into_file().unwrap();
+    let mut out = Vec::new();
+    assert_eq!(fs.cat(&file, &mut out).unwrap(), content.len() as u64);
+    assert_eq!(out, content);
+    let bs = fs.sb.block_size;
+    let (block, offset) = n.inode_location(&fs.sb, &fs.dev).unwrap();
+    let record = &image[(block * bs + offset) as usize..][..fs.sb.inode_size as usize];
+    assert_eq!(fs.inode_bytes(n).unwrap(), record);
+    assert!(fs.block_bytes(fs.sb.blocks_count).is_err());
+    assert!(fs.block_bytes(u64::MAX).is_err());
+
+    // cut short of the last block of the file, which is copied instead
+    // and fails to read
+    let reader = fs.reader(&file).unwrap();
+    let last = reader.extents().last().unwrap();
+    let cut = (last.start + last.len - 1) * bs;
+    std::fs::write(&path, &image[..cut as usize]).unwrap();
+    let fs = Ext4Fs::open_mmap(&File::open(&path).unwrap()).unwrap();
+    let chunks: Vec<_> = fs.chunks(&file).unwrap().collect();
+    assert_eq!(chunks[0].as_ref().unwrap().len(), 1 << 20);
+    assert!(chunks.last().unwrap().is_err());
+    assert!(fs.cat(&file, &mut Vec::new()).is_err());
+}
+
+/// An image counting the reads made of it.
+struct Counted {
+    image: Vec<u8>,
+    reads: std::cell::Cell<usize>,
+}
+
+impl ReadAt for Counted {
+    fn read_at(&self, pos: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reads.set(self.reads.get() + 1);
+        self.image.read_at(pos, buf)
+    }
+}
+
+#[test]
+fn stat_many_reads_shared_parents_once() {
+    let mut builder = ImageBuilder::new();
+    for dir in ["/a", "/a/b"] {
+        builder
+            .add(dir, NodeKind::Dir, NodeMeta::new(0o755))
+            .unwrap();
+    }
+    let paths: Vec<String> = (0..100).map(|i| format!("/a/b/f{i}")).collect();
+    for (i, path) in paths.iter().enumerate() {
+        builder
+            .add(path, NodeKind::File(vec![0; i]), NodeMeta::new(0o644))
+            .unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let fs = Ext4Fs::new(Counted {
+        image,
+        reads: Default::default(),
+    })
+    .unwrap();
+    let mut paths: Vec<&str> = paths.iter().map(|x| x.as_str()).collect();
+    paths.extend(["/a/b/missing", "/a/../a/./b/f9"]);
+
+    let reads = || fs.dev.reads.get();
+    let start = reads();
+    let one_by_one: Vec<_> = paths.iter().map(|x| fs.stat(x).unwrap()).collect();
+    let alone = reads() - start;
+    let start = reads();
+    let batched = fs.stat_many(&paths).unwrap();
+    let together = reads() - start;
+
+    let sizes = |stats: &[Option<TypedInode>]| -> Vec<Option<u64>> {
+        stats.iter().map(|x| x.as_ref().map(|x| x.size)).collect()
+    };
+    assert_eq!(sizes(&batched), sizes(&one_by_one));
+    assert_eq!(batched[7].as_ref().unwrap().size, 7);
+    assert!(batched[100].is_none());
+    assert_eq!(batched[101].as_ref().unwrap().size, 9);
+    // the root, /a and /a/b read once instead of once a path
+    assert!(
+        together * 3 < alone,
+        "{together} reads batched, {alone} alone"
+    );
+}
+
+#[test]
+fn compression_report_tells_zeros_from_noise() {
+    let mut builder = ImageBuilder::new();
+    for dir in ["/z", "/r"] {
+        builder
+            .add(dir, NodeKind::Dir, NodeMeta::new(0o755))
+            .unwrap();
+    }
+    // xorshift, nothing deflate can shrink
+    let mut x = 0x2545_f491_4f6c_dd1du64;
+    let noise: Vec<u8> = (0..65536)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x as u8
+        })
+        .collect();
+    for (path, content) in [
+        ("/z/zeros", vec![0; 65536]),
+        ("/z/tiny", b"0123456789".to_vec()),
+        ("/r/noise", noise),
+    ] {
+        builder
+            .add(path, NodeKind::File(content), NodeMeta::new(0o644))
+            .unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let fs = Ext4Fs::new(image).unwrap();
+
+    let report = fs.compression_report("/", 8192).unwrap();
+    let dirs: Vec<&str> = report.dirs.keys().map(|x| x.as_str()).collect();
+    assert_eq!(dirs, ["/", "/lost+found", "/r", "/z"]);
+    let z = report.dirs["/z"];
+    assert_eq!((z.files, z.bytes), (2, 65546));
+    // 4 samples of 2048 bytes, the tiny file sampled once whole
+    assert_eq!(z.sampled, 8192 + 10);
+    assert!(z.ratio() < 0.05, "{}", z.ratio());
+    let r = report.dirs["/r"];
+    assert_eq!((r.files, r.bytes, r.sampled), (1, 65536, 8192));
+    assert!(r.ratio() > 0.95, "{}", r.ratio());
+    let root = report.dirs["/"];
+    assert_eq!((root.files, root.bytes), (3, 131_082));
+    assert_eq!(root.compressed, z.compressed + r.compressed);
+    assert_eq!(report.dirs["/lost+found"].files, 0);
+    assert!(report
+        .to_string()
+        .starts_with("        size    estimated  ratio    files  path\n"));
+
+    // a subtree is charged to its own directories only
+    let report = fs.compression_report("/r", 8192).unwrap();
+    assert_eq!(report.dirs.keys().collect::<Vec<_>>(), ["/r"]);
+    assert_eq!(report.dirs["/r"].files, 1);
+}
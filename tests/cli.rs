@@ -0,0 +1,1638 @@
+//! The ext4cat binary run on images written by the builder, some of them
+//! damaged on purpose.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use positioned_io::ReadAt;
+use read_file_block_way::builder::{ImageBuilder, NodeKind, NodeMeta};
+use read_file_block_way::{Ext4Fs, Timestamp};
+
+/// A fresh directory under the target's temporary directory.
+fn scratch(name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The image `tests/images/NAME.img.z` decompressed into `dir`.
+fn fixture(dir: &Path, name: &str) -> PathBuf {
+    let path = format!("{}/tests/images/{name}.img.z", env!("CARGO_MANIFEST_DIR"));
+    let compressed = std::fs::read(&path).unwrap();
+    let data = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap();
+    let img = dir.join(name);
+    std::fs::write(&img, data).unwrap();
+    img
+}
+
+/// Rename the directory entry `from` to `to`, no longer than it, in place.
+fn rename_entry(image: &mut [u8], from: &[u8], to: &[u8]) {
+    let at = image
+        .windows(from.len())
+        .position(|x| x == from)
+        .expect("entry name not in the image");
+    // name_len and file_type come right before the name
+    image[at - 2] = to.len() as u8;
+    image[at..at + to.len()].copy_from_slice(to);
+}
+
+/// The output of ext4cat run with `args` on `img`, which must succeed.
+fn ext4cat(img: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(img)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{args:?}: {output:?}");
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// The error ext4cat run with `args` on `img`, which must fail, reports.
+fn ext4cat_fails(img: &Path, args: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(img)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(!output.status.success(), "{args:?}: {output:?}");
+    String::from_utf8(output.stderr).unwrap()
+}
+
+/// A small tree written to `dir/img`: /etc with an owned passwd, a
+/// hostname and a link to passwd, and /docs with a text file, a hidden
+/// one and a file of several blocks, all of it modified at 1600000000.
+fn sample(dir: &Path) -> PathBuf {
+    let at = Timestamp {
+        seconds: 1_600_000_000,
+        nanos: 0,
+    };
+    let meta = |permissions| NodeMeta {
+        mtime: Some(at),
+        ..NodeMeta::new(permissions)
+    };
+    let mut builder = ImageBuilder::new()
+        .label("sample")
+        .uuid(*b"0123456789abcdef")
+        .timestamp(at);
+    let nodes = [
+        ("/etc", NodeKind::Dir, meta(0o755)),
+        (
+            "/etc/passwd",
+            NodeKind::File(b"root:x:0:0:root:/root:/bin/sh\n".to_vec()),
+            NodeMeta {
+                uid: 1000,
+                gid: 100,
+                ..meta(0o640)
+            },
+        ),
+        (
+            "/etc/hostname",
+            NodeKind::File(b"sample\n".to_vec()),
+            meta(0o644),
+        ),
+        ("/etc/link", NodeKind::Symlink("passwd".into()), meta(0o777)),
+        ("/docs", NodeKind::Dir, meta(0o755)),
+        (
+            "/docs/readme.txt",
+            NodeKind::File(b"hello world\nsecond line\n".to_vec()),
+            meta(0o644),
+        ),
+        ("/docs/.hidden", NodeKind::File(b"x".to_vec()), meta(0o600)),
+        (
+            "/docs/big.bin",
+            NodeKind::File((0..100_000u32).map(|i| (i % 251) as u8).collect()),
+            meta(0o644),
+        ),
+    ];
+    for (path, kind, meta) in nodes {
+        builder.add(path, kind, meta).unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+    img
+}
+
+#[test]
+fn extract_stays_in_destination() {
+    let dir = scratch("extract_stays_in_destination");
+    let mut builder = ImageBuilder::new();
+    builder
+        .add("/sub", NodeKind::Dir, NodeMeta::new(0o755))
+        .unwrap();
+    builder
+        .add(
+            "/sub/escapee-with-a-long-enough-name",
+            NodeKind::File(b"out\n".to_vec()),
+            NodeMeta::new(0o644),
+        )
+        .unwrap();
+    builder
+        .add(
+            "/sub/kept",
+            NodeKind::File(b"in\n".to_vec()),
+            NodeMeta::new(0o644),
+        )
+        .unwrap();
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    rename_entry(
+        &mut image,
+        b"escapee-with-a-long-enough-name",
+        b"../../../escaped",
+    );
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+
+    let dest = dir.join("out/x/y");
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["extract", "--keep-going", "/sub"])
+        .arg(&dest)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("escaped"), "{stderr}");
+    assert!(!dir.join("escaped").exists());
+    assert_eq!(std::fs::read(dest.join("kept")).unwrap(), b"in\n");
+}
+
+#[test]
+fn zstd_export_decompresses_to_the_archive() {
+    // checked against the reference decoder, where there is one
+    if Command::new("zstd").arg("--version").output().is_err() {
+        return;
+    }
+    let dir = scratch("zstd_export_decompresses_to_the_archive");
+    let mut builder = ImageBuilder::new();
+    let text: Vec<u8> = (0..200_000u32)
+        .flat_map(|i| format!("line {}\n", i % 977).into_bytes())
+        .collect();
+    builder
+        .add("/text", NodeKind::File(text), NodeMeta::new(0o644))
+        .unwrap();
+    builder
+        .add(
+            "/zeros",
+            NodeKind::File(vec![0; 300_000]),
+            NodeMeta::new(0o644),
+        )
+        .unwrap();
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+
+    let export = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+            .arg(&img)
+            .arg("export")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+    let tar = export(&[]);
+    for level in ["1", "3", "19"] {
+        let compressed = dir.join(format!("tar-{level}.zst"));
+        std::fs::write(
+            &compressed,
+            export(&["--compress", &format!("zstd:{level}")]),
+        )
+        .unwrap();
+        assert!(std::fs::metadata(&compressed).unwrap().len() < tar.len() as u64 / 4);
+        let output = Command::new("zstd")
+            .arg("-dc")
+            .arg(&compressed)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert!(output.stdout == tar, "level {level}");
+    }
+}
+
+#[test]
+fn locate_skips_names_that_arent_utf8() {
+    let dir = scratch("locate_skips_names_that_arent_utf8");
+    let mut builder = ImageBuilder::new();
+    builder
+        .add("/d", NodeKind::Dir, NodeMeta::new(0o755))
+        .unwrap();
+    for name in ["/d/about-to-be-garbled", "/d/wanted-one", "/wanted-two"] {
+        builder
+            .add(name, NodeKind::File(Vec::new()), NodeMeta::new(0o644))
+            .unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    rename_entry(&mut image, b"about-to-be-garbled", b"wanted-\xff");
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["locate", "wanted"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mut paths: Vec<_> = stdout.lines().skip(1).map(|x| &x[24..]).collect();
+    paths.sort();
+    assert_eq!(paths, ["/d/wanted-one", "/wanted-two"]);
+}
+
+#[test]
+fn image_copy_reads_back() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = scratch("image_copy_reads_back");
+    let mut builder = ImageBuilder::new().block_size(4096).free_blocks(256);
+    builder
+        .add(
+            "/data",
+            NodeKind::File(vec![7; 100_000]),
+            NodeMeta::new(0o644),
+        )
+        .unwrap();
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    // a free block with stale data, left out of the copy
+    let fs = read_file_block_way::Ext4Fs::new(image.clone()).unwrap();
+    let free = fs.trim_report().unwrap().ranges[0].start * 4096;
+    image[free as usize..][..4096].fill(0xAA);
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+
+    let copy = dir.join("copy");
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .arg("image-copy")
+        .arg(&copy)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let copied = std::fs::read(&copy).unwrap();
+    assert_eq!(copied.len(), image.len());
+    assert!(copied[free as usize..][..4096].iter().all(|&b| b == 0));
+    assert!(std::fs::metadata(&copy).unwrap().blocks() * 512 < image.len() as u64);
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&copy)
+        .args(["cat", "/data"])
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, vec![7; 100_000]);
+}
+
+#[test]
+fn trim_punches_free_blocks_only() {
+    use std::os::unix::fs::MetadataExt;
+
+    let dir = scratch("trim_punches_free_blocks_only");
+    let mut builder = ImageBuilder::new().block_size(4096).free_blocks(256);
+    builder
+        .add(
+            "/data",
+            NodeKind::File(vec![7; 100_000]),
+            NodeMeta::new(0o644),
+        )
+        .unwrap();
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    // stale data in every free block, the filesystem after an offset
+    let fs = read_file_block_way::Ext4Fs::new(image.clone()).unwrap();
+    let report = fs.trim_report().unwrap();
+    for range in &report.ranges {
+        image[(range.start * 4096) as usize..][..(range.blocks * 4096) as usize].fill(0xAA);
+    }
+    let img = dir.join("img");
+    std::fs::write(&img, [vec![0xAA; 65536], image.clone()].concat()).unwrap();
+    let before = std::fs::metadata(&img).unwrap().blocks();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .args(["-o", "65536"])
+        .arg(&img)
+        .args(["trim", "--punch-holes"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let punched = std::fs::read(&img).unwrap();
+    assert_eq!(punched.len(), 65536 + image.len());
+    assert!(punched[..65536].iter().all(|&b| b == 0xAA));
+    for range in &report.ranges {
+        let at = 65536 + (range.start * 4096) as usize;
+        assert!(punched[at..][..(range.blocks * 4096) as usize]
+            .iter()
+            .all(|&b| b == 0));
+    }
+    assert!(std::fs::metadata(&img).unwrap().blocks() < before);
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .args(["-o", "65536"])
+        .arg(&img)
+        .args(["cat", "/data"])
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, vec![7; 100_000]);
+}
+
+#[test]
+fn walks_stop_at_loops_unless_kept_going() {
+    let dir = scratch("walks_stop_at_loops_unless_kept_going");
+    let mut builder = ImageBuilder::new();
+    for path in ["/a", "/a/b"] {
+        builder
+            .add(path, NodeKind::Dir, NodeMeta::new(0o755))
+            .unwrap();
+    }
+    for path in ["/a/b/back-up-to-a-once-patched", "/a/b/z"] {
+        builder
+            .add(path, NodeKind::File(Vec::new()), NodeMeta::new(0o644))
+            .unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let fs = read_file_block_way::Ext4Fs::new(image.clone()).unwrap();
+    let a = fs.resolve("/a").unwrap().unwrap().0 as u32;
+    let name = b"back-up-to-a-once-patched";
+    let at = image.windows(name.len()).position(|x| x == name).unwrap();
+    // the entry becomes a directory entry for /a, a loop
+    image[at - 8..at - 4].copy_from_slice(&a.to_le_bytes());
+    image[at - 1] = 2;
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+
+    let find = |keep_going: bool| {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_ext4cat"));
+        command.arg(&img).args(["find", "/a/**"]);
+        if keep_going {
+            command.arg("--keep-going");
+        }
+        command.output().unwrap()
+    };
+    let output = find(false);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("/a/b/back-up-to-a-once-patched: "),
+        "{stderr}"
+    );
+    assert!(stderr.contains("loops"), "{stderr}");
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("/a/b/z"));
+
+    let output = find(true);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 entries couldn't be read"), "{stderr}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        ["/a/b", "/a/b/back-up-to-a-once-patched", "/a/b/z"]
+    );
+}
+
+#[test]
+fn shell_runs_scripts() {
+    let dir = scratch("shell_runs_scripts");
+    let mut builder = ImageBuilder::new();
+    builder
+        .add("/etc", NodeKind::Dir, NodeMeta::new(0o755))
+        .unwrap();
+    builder
+        .add(
+            "/etc/hostname",
+            NodeKind::File(b"box\n".to_vec()),
+            NodeMeta::new(0o644),
+        )
+        .unwrap();
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+    let script = dir.join("script");
+    std::fs::write(
+        &script,
+        "set dir etc\n\
+         cd /$dir\n\
+         if exists hostname\n    cat hostname\nelse\n    pwd\nend\n\
+         if not exists /missing\n    pwd\nend\n",
+    )
+    .unwrap();
+
+    let shell = |script: &std::path::Path| {
+        Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+            .arg(&img)
+            .arg("shell")
+            .arg(format!("--script={}", script.display()))
+            .output()
+            .unwrap()
+    };
+    let output = shell(&script);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(output.stdout, b"box\n/etc\n");
+    // a failing command fails the script, saying where
+    std::fs::write(&script, "pwd\ncat /missing\npwd\n").unwrap();
+    let output = shell(&script);
+    assert!(!output.status.success());
+    assert_eq!(output.stdout, b"/\n");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 2"), "{stderr}");
+    // commands without a path work on the working directory
+    std::fs::write(&script, "cd /etc\nls\ntree\nhash -r\n").unwrap();
+    let output = shell(&script);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines[0], "hostname");
+    assert!(lines[1].starts_with("/etc"), "{stdout}");
+    assert!(lines[2].ends_with("hostname"), "{stdout}");
+    assert!(
+        lines.last().unwrap().ends_with("  /etc/hostname"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn redaction_rules_apply_to_extract_and_tar() {
+    let dir = scratch("redaction_rules_apply_to_extract_and_tar");
+    let mut builder = ImageBuilder::new();
+    for path in ["/etc", "/keys"] {
+        builder
+            .add(path, NodeKind::Dir, NodeMeta::new(0o755))
+            .unwrap();
+    }
+    for (path, content) in [
+        ("/etc/shadow", "root:hash-of-the-root-password\n"),
+        ("/etc/hostname", "box\n"),
+        ("/keys/private-key-of-the-box", "-----BEGIN\n"),
+    ] {
+        builder
+            .add(path, NodeKind::File(content.into()), NodeMeta::new(0o600))
+            .unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+    let rules = dir.join("rules");
+    std::fs::write(&rules, "skip /keys\nplaceholder /etc/shadow\n").unwrap();
+    let redact = format!("--redact={}", rules.display());
+
+    let dest = dir.join("out");
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["extract", &redact, "/"])
+        .arg(&dest)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        std::fs::read(dest.join("etc/shadow")).unwrap(),
+        b"[redacted]\n"
+    );
+    assert_eq!(std::fs::read(dest.join("etc/hostname")).unwrap(), b"box\n");
+    assert!(!dest.join("keys").exists());
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["tar", &redact])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let contains = |x: &[u8]| output.stdout.windows(x.len()).any(|w| w == x);
+    assert!(contains(b"[redacted]\n"));
+    assert!(contains(b"etc/hostname"));
+    assert!(!contains(b"hash-of-the-root-password"));
+    assert!(!contains(b"private-key-of-the-box"));
+
+    // a bad rules file is an error, before anything is written
+    std::fs::write(&rules, "hide /keys\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["tar", &redact, "-f"])
+        .arg(dir.join("archive.tar"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unknown rule"));
+    assert!(!dir.join("archive.tar").exists());
+}
+
+#[test]
+fn metadata_only_export_has_no_content() {
+    let dir = scratch("metadata_only_export_has_no_content");
+    let mut builder = ImageBuilder::new();
+    builder
+        .add("/etc", NodeKind::Dir, NodeMeta::new(0o755))
+        .unwrap();
+    for (path, content) in [("/etc/shadow", "root:secret\n"), ("/etc/hostname", "abc")] {
+        builder
+            .add(path, NodeKind::File(content.into()), NodeMeta::new(0o640))
+            .unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+    let rules = dir.join("rules");
+    std::fs::write(&rules, "placeholder /etc/shadow\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["export", "--metadata-only", "-a", "sha256,md5"])
+        .arg(format!("--redact={}", rules.display()))
+        .arg("/etc")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(!stdout.contains("secret"));
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{stdout}");
+    let line = |path: &str| {
+        *lines
+            .iter()
+            .find(|x| x.contains(&format!("\"path\":\"{path}\"")))
+            .unwrap()
+    };
+    assert!(line(".").contains("\"type\":\"dir\""));
+    let hostname = line("hostname");
+    assert!(hostname.contains("\"mode\":\"0640\""));
+    assert!(hostname.contains("\"size\":3"));
+    // the sha256 and md5 of "abc"
+    assert!(hostname.contains("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+    assert!(hostname.contains("900150983cd24fb0d6963f7d28e17f72"));
+    assert!(line("shadow").contains("\"redacted\":true"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["export", "--metadata-only", "--format", "cpio"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn journal_history_and_replay() {
+    let dir = scratch("journal_history_and_replay");
+    let img = fixture(&dir, "journal");
+    let run = |options: &[&str], args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+            .args(options)
+            .arg(&img)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{args:?}: {output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let sequences: Vec<_> = run(&[], &["journal"])
+        .lines()
+        .skip(1)
+        .map(|x| x.split_whitespace().next().unwrap().to_owned())
+        .collect();
+    assert_eq!(sequences, ["4294967294", "4294967295", "0"]);
+    let versions = dir.join("versions");
+    let dest = versions.to_str().unwrap();
+    run(&[], &["journal", "--block", "3000", "-d", dest]);
+    assert_eq!(
+        std::fs::read(versions.join("block-3000.4294967295")).unwrap(),
+        [b'b'; 1024]
+    );
+
+    // the first 8 bytes of the block, as xxd shows them
+    let first = |options: &[&str], block: &str| {
+        run(options, &["xxd", "--block", block])
+            .lines()
+            .next()
+            .unwrap()[10..33]
+            .to_owned()
+    };
+    assert_eq!(first(&[], "3001"), "00 00 00 00 00 00 00 00");
+    assert_eq!(first(&["--replay"], "3001"), "00 00 00 00 00 00 00 00");
+    assert_eq!(first(&["--replay"], "3002"), "63 63 63 63 63 63 63 63");
+    assert_eq!(first(&["--as-of", "1"], "3001"), "62 62 62 62 62 62 62 62");
+}
+
+#[test]
+fn special_inodes_are_labelled() {
+    let dir = scratch("special_inodes_are_labelled");
+    let img = fixture(&dir, "journal");
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+            .arg(&img)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{args:?}: {output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert!(run(&["stat", "-i", "8"]).contains("\nspecial:                journal\n"));
+    assert!(!run(&["stat", "/"]).contains("journal"));
+    assert!(run(&["stat", "--json", "-i", "7"])
+        .contains("\"special\":\"reserved group descriptors (resize)\""));
+    assert_eq!(
+        run(&["ncheck", "8", "2"]),
+        "inode       path\n8           <journal>\n2           /\n"
+    );
+    // the journal superblock, where mke2fs put it
+    assert_eq!(
+        run(&["icheck", "48"]).lines().nth(1),
+        Some("48          8           <journal>")
+    );
+}
+
+#[test]
+fn repack_writes_the_subtree_as_an_image() {
+    let dir = scratch("repack_writes_the_subtree_as_an_image");
+    let mut builder = ImageBuilder::new().label("source");
+    let owned = NodeMeta {
+        uid: 1000,
+        gid: 100,
+        ..NodeMeta::new(0o600)
+    };
+    let nodes = [
+        ("/etc", NodeKind::Dir, NodeMeta::new(0o755)),
+        ("/etc/ssh", NodeKind::Dir, NodeMeta::new(0o700)),
+        ("/etc/ssh/key", NodeKind::File(vec![5; 10_000]), owned),
+        (
+            "/etc/hostname",
+            NodeKind::File(b"box\n".to_vec()),
+            NodeMeta::new(0o644),
+        ),
+        (
+            "/etc/link",
+            NodeKind::Symlink("ssh/key".into()),
+            NodeMeta::new(0o777),
+        ),
+        (
+            "/other",
+            NodeKind::File(b"left out".to_vec()),
+            NodeMeta::new(0o644),
+        ),
+    ];
+    for (path, kind, meta) in nodes {
+        builder.add(path, kind, meta).unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+    let source = read_file_block_way::Ext4Fs::new(image).unwrap();
+
+    let repacked = dir.join("repacked");
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["repack", "--block-size", "1024", "/etc"])
+        .arg(&repacked)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let fs = read_file_block_way::Ext4Fs::new(std::fs::read(&repacked).unwrap()).unwrap();
+    assert_eq!(fs.sb.block_size, 1024);
+    assert_eq!(fs.sb.volume_name, "source");
+    assert_eq!(fs.sb.uuid, source.sb.uuid);
+    let key = fs.stat("/ssh/key").unwrap().unwrap().into_file().unwrap();
+    assert_eq!((key.mode & 0o7777, key.uid, key.gid), (0o600, 1000, 100));
+    let mut content = vec![0; key.size as usize];
+    fs.reader(&key)
+        .unwrap()
+        .read_exact_at(0, &mut content)
+        .unwrap();
+    assert_eq!(content, [5; 10_000]);
+    assert_eq!(fs.stat("/ssh").unwrap().unwrap().mode & 0o7777, 0o700);
+    assert!(fs.stat("/hostname").unwrap().is_some());
+    assert!(fs.stat("/other").unwrap().is_none());
+    let link = fs.stat("/link").unwrap().unwrap().into_symlink().unwrap();
+    assert_eq!(link.target(&fs.sb, &fs.dev).unwrap(), "ssh/key");
+    assert!(fs.check().unwrap().findings.is_empty());
+
+    // only directories become the root
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["repack", "/etc/hostname"])
+        .arg(dir.join("file"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn extract_keeps_few_files_open() {
+    let dir = scratch("extract_keeps_few_files_open");
+    let mut builder = ImageBuilder::new();
+    for i in 0..200 {
+        let content = format!("file {i}\n").repeat(i + 1).into_bytes();
+        builder
+            .add(
+                &format!("/f{i}"),
+                NodeKind::File(content),
+                NodeMeta::new(0o644),
+            )
+            .unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+
+    // far fewer descriptors than files
+    let dest = dir.join("out");
+    let script = format!(
+        "ulimit -n 32 && exec {} {} extract / {}",
+        env!("CARGO_BIN_EXE_ext4cat"),
+        img.display(),
+        dest.display()
+    );
+    let output = Command::new("sh").args(["-c", &script]).output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for i in 0..200 {
+        let content = std::fs::read(dest.join(format!("f{i}"))).unwrap();
+        assert_eq!(content, format!("file {i}\n").repeat(i + 1).into_bytes());
+    }
+}
+
+#[test]
+fn bad_group_geometry_is_refused() {
+    let dir = scratch("bad_group_geometry_is_refused");
+    let mut image = Vec::new();
+    ImageBuilder::new().build(&mut image).unwrap();
+    // offsets in the superblock, zeroed one at a time
+    for (field, at) in [
+        ("inodes per group", 0x28),
+        ("blocks per group", 0x20),
+        ("blocks count", 0x4),
+    ] {
+        let mut damaged = image.clone();
+        damaged[1024 + at..][..4].fill(0);
+        let img = dir.join("img");
+        std::fs::write(&img, &damaged).unwrap();
+        for args in [
+            &["info"][..],
+            &["dump-super"],
+            &["locate", "f"],
+            &["icheck", "1"],
+        ] {
+            let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+                .arg(&img)
+                .args(args)
+                .output()
+                .unwrap();
+            // an error, not a panic
+            assert_eq!(output.status.code(), Some(1), "{field}: {args:?}");
+        }
+    }
+}
+
+#[test]
+fn info_ends_its_last_line() {
+    let dir = scratch("info_ends_its_last_line");
+    let mut image = Vec::new();
+    ImageBuilder::new().build(&mut image).unwrap();
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+    let info = ext4cat(&img, &["info"]);
+    let last = info.lines().last().unwrap();
+    assert!(last.starts_with("ro_compat features:"), "{info}");
+    assert!(info.ends_with('\n'), "{info}");
+}
+
+#[test]
+fn timeouts_bound_reads_of_the_image() {
+    let dir = scratch("timeouts_bound_reads_of_the_image");
+    let img = fixture(&dir, "inline");
+    let plain = ext4cat(&img, &["ls", "/dir"]);
+    let timed = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .args(["--timeout", "600", "--read-timeout", "60000"])
+        .arg(&img)
+        .args(["ls", "/dir"])
+        .output()
+        .unwrap();
+    assert!(timed.status.success(), "{timed:?}");
+    assert_eq!(String::from_utf8(timed.stdout).unwrap(), plain);
+    let expired = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .args(["--timeout", "0"])
+        .arg(&img)
+        .arg("info")
+        .output()
+        .unwrap();
+    assert_eq!(expired.status.code(), Some(1));
+    let stderr = String::from_utf8(expired.stderr).unwrap();
+    assert!(stderr.ends_with(": deadline exceeded\n"), "{stderr}");
+}
+
+#[test]
+fn cat_reads_the_same_mapped_or_not() {
+    let dir = scratch("cat_reads_the_same_mapped_or_not");
+    let content: Vec<u8> = (0..3_000_000u32).map(|i| (i % 251) as u8).collect();
+    let mut builder = ImageBuilder::new();
+    builder
+        .add("/f", NodeKind::File(content.clone()), NodeMeta::new(0o644))
+        .unwrap();
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+    let cat = |options: &[&str], args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+            .args(options)
+            .arg(&img)
+            .arg("cat")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{args:?}: {output:?}");
+        output.stdout
+    };
+    assert_eq!(cat(&[], &["/f"]), content);
+    assert_eq!(cat(&["--read-timeout", "60000"], &["/f"]), content);
+    assert_eq!(cat(&[], &["--offset", "5", "/f"]), content[5..]);
+    assert_eq!(cat(&[], &["/f", "/f"]).len(), 2 * content.len());
+}
+
+#[test]
+fn ls_lists_directories() {
+    let dir = scratch("ls_lists_directories");
+    let img = sample(&dir);
+    assert_eq!(ext4cat(&img, &["ls", "/"]), "docs\netc\nlost+found\n");
+    assert_eq!(ext4cat(&img, &["ls", "/docs"]), "big.bin\nreadme.txt\n");
+    assert_eq!(
+        ext4cat(&img, &["ls", "-a", "/docs"]),
+        ".\n..\n.hidden\nbig.bin\nreadme.txt\n"
+    );
+    assert_eq!(
+        ext4cat(&img, &["ls", "-i", "/etc"]),
+        "17 hostname\n18 link\n19 passwd\n"
+    );
+}
+
+#[test]
+fn cat_writes_files_in_turn() {
+    let dir = scratch("cat_writes_files_in_turn");
+    let img = sample(&dir);
+    assert_eq!(
+        ext4cat(&img, &["cat", "/etc/hostname", "/docs/readme.txt"]),
+        "sample\nhello world\nsecond line\n"
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["cat", "/docs/big.bin"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let big: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    assert_eq!(output.stdout, big);
+    assert_eq!(
+        ext4cat_fails(&img, &["cat", "/etc/link"]),
+        "ext4cat: /etc/link: not a regular file\n"
+    );
+    assert_eq!(
+        ext4cat_fails(&img, &["cat", "/nope"]),
+        "ext4cat: /nope: no such file or directory\n"
+    );
+}
+
+#[test]
+fn stat_describes_inodes() {
+    let dir = scratch("stat_describes_inodes");
+    let img = sample(&dir);
+    assert_eq!(
+        ext4cat(&img, &["stat", "/etc/passwd"]),
+        "file:                   /etc/passwd
+inode:                  19
+type:                   Regular
+mode:                   0640
+uid:                    1000
+gid:                    100
+size:                   30
+blocks:                 1 (4.0 KiB)
+links:                  1
+generation:             0
+flags:                  extents
+atime:                  2020-09-13 12:26:40.000000000
+ctime:                  2020-09-13 12:26:40.000000000
+mtime:                  2020-09-13 12:26:40.000000000
+crtime:                 2020-09-13 12:26:40.000000000
+block map:              Extents
+extents:                1 (1 blocks)
+  (0-0):9-9
+"
+    );
+    let link = ext4cat(&img, &["stat", "/etc/link"]);
+    assert!(
+        link.contains("type:                   SymbolicLink\n"),
+        "{link}"
+    );
+    assert!(link.contains("target:                 passwd\n"), "{link}");
+    assert!(
+        link.ends_with("block map:              InInode\n"),
+        "{link}"
+    );
+}
+
+#[test]
+fn find_matches_globs() {
+    let dir = scratch("find_matches_globs");
+    let img = sample(&dir);
+    let find = |pattern| ext4cat(&img, &["find", pattern]);
+    assert_eq!(
+        ext4cat(&img, &["find"]),
+        "/\n/docs\n/docs/.hidden\n/docs/big.bin\n/docs/readme.txt\n\
+         /etc\n/etc/hostname\n/etc/link\n/etc/passwd\n/lost+found\n"
+    );
+    assert_eq!(find("/docs/*.txt"), "/docs/readme.txt\n");
+    // a name in any directory
+    assert_eq!(find("h*"), "/etc/hostname\n");
+    // `**/` also matching no directory at all
+    assert_eq!(find("/**/l*"), "/etc/link\n/lost+found\n");
+    assert_eq!(find("/*/?ink"), "/etc/link\n");
+    assert_eq!(find("/nope/*"), "");
+}
+
+#[test]
+fn tree_draws_the_hierarchy() {
+    let dir = scratch("tree_draws_the_hierarchy");
+    let img = sample(&dir);
+    assert_eq!(
+        ext4cat(&img, &["tree"]),
+        "/
+├── docs
+│   ├── big.bin
+│   └── readme.txt
+├── etc
+│   ├── hostname
+│   ├── link -> passwd
+│   └── passwd
+└── lost+found
+
+3 directories, 5 files
+"
+    );
+    assert_eq!(
+        ext4cat(&img, &["tree", "-s", "-a", "/docs"]),
+        "/docs
+├── [          1]  .hidden
+├── [     100000]  big.bin
+└── [         24]  readme.txt
+
+0 directories, 3 files
+"
+    );
+    assert!(
+        ext4cat(&img, &["tree", "-L", "1"]).ends_with("└── lost+found\n\n3 directories, 0 files\n")
+    );
+}
+
+#[test]
+fn df_reports_usage() {
+    let dir = scratch("df_reports_usage");
+    let img = sample(&dir);
+    assert_eq!(
+        ext4cat(&img, &["df"]),
+        "label:                  sample
+uuid:                   30313233-3435-3637-3839-616263646566
+block size:             4.0 KiB
+size:                   156.0 KiB total, 156.0 KiB used, 0 B free
+reserved blocks:        0 (0 B)
+
+                total         used         free  use%
+blocks             39           39            0  100%
+inodes             32           19           13   59%
+"
+    );
+    let mut image = Vec::new();
+    ImageBuilder::new()
+        .free_blocks(61)
+        .build(&mut image)
+        .unwrap();
+    let img = dir.join("free");
+    std::fs::write(&img, &image).unwrap();
+    let df = ext4cat(&img, &["df"]);
+    assert!(
+        df.contains("\nblocks             68            7           61   10%\n"),
+        "{df}"
+    );
+}
+
+#[test]
+fn dump_super_matches_dumpe2fs() {
+    let dir = scratch("dump_super_matches_dumpe2fs");
+    let img = fixture(&dir, "casefold");
+    let dump = ext4cat(&img, &["dump-super"]);
+    // as `dumpe2fs` shows the image mke2fs made
+    for line in [
+        "magic:                  0xEF53\n",
+        "uuid:                   0b0a0c0d-0102-0304-0506-0708090a0b0c\n",
+        "compat features:        has_journal ext_attr resize_inode dir_index\n",
+        "incompat features:      filetype extent 64bit flex_bg casefold\n",
+        "inode count:            256\n",
+        "block count:            2048\n",
+        "reserved block count:   102\n",
+        "free blocks:            922\n",
+        "free inodes:            164\n",
+        "first data block:       1\n",
+        "reserved gdt blocks:    15\n",
+        "journal inode:          8\n",
+        "filename encoding:      1\n",
+        "default hash:           half_md4 (1)\n",
+    ] {
+        assert!(dump.contains(line), "{line}{dump}");
+    }
+    assert!(dump.ends_with(
+        "group 0: blocks 1-2047
+  superblock copy:      yes
+  flags:                none
+  checksum:             0xd169
+  block bitmap:         18
+  inode bitmap:         34
+  inode table:          50
+  free blocks:          922
+  free inodes:          164
+  unused inodes:        164
+"
+    ));
+}
+
+#[test]
+fn tar_lists_in_gnu_tar() {
+    // checked against the reference reader, where there is one
+    if Command::new("tar").arg("--version").output().is_err() {
+        return;
+    }
+    let dir = scratch("tar_lists_in_gnu_tar");
+    let img = sample(&dir);
+    let archive = dir.join("etc.tar");
+    ext4cat(&img, &["tar", "-f", archive.to_str().unwrap(), "/etc"]);
+    let tar = |args: &[&str]| {
+        let output = Command::new("tar")
+            .args(args)
+            .arg(&archive)
+            .env("TZ", "UTC")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+    assert_eq!(
+        tar(&["--numeric-owner", "-tvf"]),
+        "drwxr-xr-x 0/0               0 2020-09-13 12:26 ./
+-rw-r--r-- 0/0               7 2020-09-13 12:26 hostname
+lrwxrwxrwx 0/0               0 2020-09-13 12:26 link -> passwd
+-rw-r----- 1000/100         30 2020-09-13 12:26 passwd
+"
+    );
+    let output = Command::new("tar")
+        .arg("-xOf")
+        .arg(&archive)
+        .arg("passwd")
+        .output()
+        .unwrap();
+    assert_eq!(output.stdout, b"root:x:0:0:root:/root:/bin/sh\n");
+}
+
+#[test]
+fn cpio_writes_newc_entries() {
+    let dir = scratch("cpio_writes_newc_entries");
+    let img = sample(&dir);
+    let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+        .arg(&img)
+        .args(["cpio", "/etc"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let archive = output.stdout;
+    // name, mode, uid, gid, mtime and content of each `070701` entry
+    let mut entries = Vec::new();
+    let mut at = 0;
+    let align = |x: usize| (x + 3) & !3;
+    loop {
+        let header = &archive[at..at + 110];
+        assert_eq!(&header[..6], b"070701");
+        let field = |i: usize| {
+            let hex = std::str::from_utf8(&header[6 + i * 8..14 + i * 8]).unwrap();
+            usize::from_str_radix(hex, 16).unwrap()
+        };
+        let (size, name_size) = (field(6), field(11));
+        let name = &archive[at + 110..at + 110 + name_size - 1];
+        let name = String::from_utf8(name.to_vec()).unwrap();
+        let data = align(at + 110 + name_size);
+        at = align(data + size);
+        if name == "TRAILER!!!" {
+            break;
+        }
+        let content = archive[data..data + size].to_vec();
+        entries.push((name, field(1), field(2), field(3), field(5), content));
+    }
+    assert_eq!(at, archive.len());
+    let names: Vec<_> = entries.iter().map(|x| x.0.as_str()).collect();
+    assert_eq!(names, [".", "hostname", "link", "passwd"]);
+    assert_eq!(entries[0].1, 0o40755);
+    assert_eq!(entries[2].1, 0o120777);
+    assert_eq!(entries[2].5, b"passwd");
+    let passwd = &entries[3];
+    assert_eq!(
+        (passwd.1, passwd.2, passwd.3, passwd.4),
+        (0o100640, 1000, 100, 1_600_000_000)
+    );
+    assert_eq!(passwd.5, b"root:x:0:0:root:/root:/bin/sh\n");
+}
+
+#[test]
+fn json_output_has_a_line_per_record() {
+    let dir = scratch("json_output_has_a_line_per_record");
+    let img = sample(&dir);
+    assert_eq!(
+        ext4cat(&img, &["ls", "--json", "/etc"]),
+        r#"{"inode":17,"type":"file","mode":"0644","uid":0,"gid":0,"size":7,"mtime":1600000000,"name":"hostname"}
+{"inode":18,"type":"symlink","mode":"0777","uid":0,"gid":0,"size":6,"mtime":1600000000,"name":"link"}
+{"inode":19,"type":"file","mode":"0640","uid":1000,"gid":100,"size":30,"mtime":1600000000,"name":"passwd"}
+"#
+    );
+    assert_eq!(
+        ext4cat(&img, &["find", "--json", "/etc/p*"]),
+        r#"{"inode":19,"type":"file","mode":"0640","uid":1000,"gid":100,"size":30,"mtime":1600000000,"path":"/etc/passwd"}
+"#
+    );
+    let link = ext4cat(&img, &["stat", "--json", "/etc/link"]);
+    assert!(
+        link.starts_with(r#"{"inode":18,"type":"symlink","#),
+        "{link}"
+    );
+    assert!(link.contains(r#""target":"passwd""#), "{link}");
+    assert_eq!(
+        ext4cat(&img, &["df", "--json"]),
+        r#"{"label":"sample","uuid":"30313233-3435-3637-3839-616263646566","block_size":4096,"blocks":39,"free_blocks":0,"reserved_blocks":0,"inodes":32,"free_inodes":13,"superblock_free_blocks":0,"superblock_free_inodes":13}
+"#
+    );
+    let tree = ext4cat(&img, &["tree", "--json", "/etc"]);
+    assert!(
+        tree.contains(r#""path":"/etc/link","depth":1,"target":"passwd"}"#),
+        "{tree}"
+    );
+}
+
+#[test]
+fn xxd_dumps_blocks_inodes_and_files() {
+    let dir = scratch("xxd_dumps_blocks_inodes_and_files");
+    let img = sample(&dir);
+    // the superblock's magic, numbered by its offset on the device
+    assert_eq!(
+        ext4cat(
+            &img,
+            &["xxd", "--block", "0", "--offset", "1080", "--len", "16"]
+        ),
+        "00000438  53 ef 01 00 01 00 00 00  00 10 5e 5f 00 00 00 00  |S.........^_....|\n\
+         00000448\n"
+    );
+    assert_eq!(
+        ext4cat(&img, &["xxd", "--file", "/etc/hostname"]),
+        "00000000  73 61 6d 70 6c 65 0a                              |sample.|\n\
+         00000007\n"
+    );
+    // 19th record of the table at block 4: mode 0100640, uid 1000, size 30
+    let inode = ext4cat(&img, &["xxd", "--inode", "19"]);
+    assert!(
+        inode.starts_with("00005200  a0 81 e8 03 1e 00 00 00 "),
+        "{inode}"
+    );
+    assert!(inode.ends_with("\n00005300\n"), "{inode}");
+}
+
+#[test]
+fn icheck_finds_the_owners_of_blocks() {
+    let dir = scratch("icheck_finds_the_owners_of_blocks");
+    let img = sample(&dir);
+    // as debugfs's icheck, with paths; block 0 is the superblock's
+    assert_eq!(
+        ext4cat(&img, &["icheck", "9", "0", "11", "38"]),
+        "block       inode       path
+9           19          /etc/passwd
+0           <block not found>
+11          12          /docs
+38          15          /docs/big.bin
+"
+    );
+    assert_eq!(
+        ext4cat(&img, &["icheck", "--json", "9", "0"]),
+        r#"{"block":9,"inode":19,"path":"/etc/passwd","allocated":true}
+{"block":0,"inode":null,"allocated":true}
+"#
+    );
+}
+
+#[test]
+fn ncheck_finds_every_link() {
+    let dir = scratch("ncheck_finds_every_link");
+    let img = fixture(&dir, "links");
+    // the paths debugfs's ncheck lists, in the order the directories
+    // were read
+    assert_eq!(
+        ext4cat(&img, &["ncheck", "14", "15", "12", "99"]),
+        "inode       path
+14          /b/h
+14          /a/f
+14          /a/g
+15          /b/lone
+12          /a
+99          <no path>
+"
+    );
+    assert_eq!(
+        ext4cat(&img, &["ncheck", "--json", "14", "8"]),
+        r#"{"inode":14,"paths":["/b/h","/a/f","/a/g"]}
+{"inode":8,"paths":[],"special":"journal"}
+"#
+    );
+    assert_eq!(
+        ext4cat_fails(&img, &["ncheck", "1000"]),
+        "ext4cat: ncheck: inode 1000 out of range\n"
+    );
+}
+
+#[test]
+fn grep_searches_file_contents() {
+    let dir = scratch("grep_searches_file_contents");
+    let img = sample(&dir);
+    assert_eq!(
+        ext4cat(&img, &["grep", "root", "/etc/passwd"]),
+        "root:x:0:0:root:/root:/bin/sh\n"
+    );
+    assert_eq!(
+        ext4cat(&img, &["grep", "-r", "line", "/"]),
+        "/docs/readme.txt:second line\n"
+    );
+    assert_eq!(
+        ext4cat(&img, &["grep", "-r", "-i", "HELLO", "/docs"]),
+        "/docs/readme.txt:hello world\n"
+    );
+    assert_eq!(
+        ext4cat(&img, &["grep", "-n", "-r", "o", "/etc"]),
+        "/etc/passwd:1:root:x:0:0:root:/root:/bin/sh\n"
+    );
+    // big.bin holds every byte value, an `o` among them
+    assert_eq!(
+        ext4cat(&img, &["grep", "-l", "-r", "o", "/"]),
+        "/docs/big.bin\n/docs/readme.txt\n/etc/passwd\n"
+    );
+    assert_eq!(ext4cat(&img, &["grep", "zzz", "/etc/passwd"]), "");
+}
+
+#[test]
+fn hash_matches_sha256sum() {
+    let dir = scratch("hash_matches_sha256sum");
+    let img = sample(&dir);
+    // as sha256sum, md5sum and sha1sum print them for the same content
+    assert_eq!(
+        ext4cat(&img, &["hash", "/etc/passwd", "/docs/big.bin"]),
+        "79492b90d473e2387338ef1518dfbceb9e3392ae5740235a6696621e42218efa  /etc/passwd\n\
+         cd2df694e424bc7968cc37f47751019e5ca0cd1bdf2e479ea537c3a1c32ee1aa  /docs/big.bin\n"
+    );
+    assert_eq!(
+        ext4cat(&img, &["hash", "-a", "md5,sha1", "/etc/hostname"]),
+        "MD5 (/etc/hostname) = 5177a58dc65c8a14dc90c69db3bf3dd2\n\
+         SHA1 (/etc/hostname) = 5f34ec0bfeac225b1c854340257a65b106f70ea6\n"
+    );
+    let tree = ext4cat(&img, &["hash", "-r", "/etc"]);
+    assert_eq!(tree.lines().count(), 2, "{tree}");
+    assert!(tree.ends_with("  /etc/passwd\n"), "{tree}");
+}
+
+#[test]
+fn diff_names_the_changes() {
+    let dir = scratch("diff_names_the_changes");
+    let img = sample(&dir);
+    let mut image = std::fs::read(&img).unwrap();
+    rename_entry(&mut image, b"readme.txt", b"readme.tx");
+    let at = image.windows(7).position(|x| x == b"sample\n").unwrap();
+    image[at..at + 7].copy_from_slice(b"SAMPLE\n");
+    // passwd's mode, at the start of the 19th record of the inode table
+    // in block 4, from 0640 to 0644
+    assert_eq!(image[0x5200..0x5202], [0xa0, 0x81]);
+    image[0x5200] = 0xa4;
+    let other = dir.join("other");
+    std::fs::write(&other, &image).unwrap();
+    let other = other.to_str().unwrap();
+
+    assert_eq!(
+        ext4cat(&img, &["diff", other]),
+        "A /docs/readme.tx
+D /docs/readme.txt
+M /etc/hostname (content)
+M /etc/passwd (mode)
+"
+    );
+    assert_eq!(
+        ext4cat(&img, &["diff", "--content-only", other, "/etc"]),
+        "M /etc/hostname (content)\n"
+    );
+    assert_eq!(
+        ext4cat(&img, &["diff", "--metadata-only", other, "/etc"]),
+        "M /etc/passwd (mode)\n"
+    );
+    assert_eq!(ext4cat(&img, &["diff", img.to_str().unwrap()]), "");
+}
+
+#[test]
+fn undelete_ranks_and_recovers() {
+    let dir = scratch("undelete_ranks_and_recovers");
+    let img = fixture(&dir, "recover");
+    // deleted by debugfs at E2FSPROGS_FAKE_TIME
+    assert_eq!(
+        ext4cat(&img, &["undelete"]),
+        "      12 100         3080 2023-11-14 22:13:20 sane size, timestamps before deletion, \
+         extents cover the size, blocks not reallocated, png signature
+      13  60         2700 2023-11-14 22:13:20 sane size, timestamps before deletion, \
+         extents cover the size
+"
+    );
+    let best = ext4cat(&img, &["undelete", "--min-score", "80"]);
+    assert!(best.starts_with("      12 100 "), "{best}");
+    assert_eq!(best.lines().count(), 1);
+
+    let dest = dir.join("out");
+    let dest = dest.to_str().unwrap();
+    assert_eq!(
+        ext4cat(&img, &["undelete", "-d", dest, "12"]),
+        format!("{dest}/inode-12: 3080 bytes\n")
+    );
+    let png = std::fs::read(format!("{dest}/inode-12")).unwrap();
+    assert_eq!(png.len(), 3080);
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    assert!(png[8..].iter().enumerate().all(|(i, &x)| x == i as u8));
+    assert_eq!(
+        ext4cat_fails(&img, &["undelete", "2"]),
+        "ext4cat: undelete: 2: not a deleted inode\n"
+    );
+}
+
+#[test]
+fn carve_finds_files_in_free_blocks() {
+    let dir = scratch("carve_finds_files_in_free_blocks");
+    let mut image = Vec::new();
+    ImageBuilder::new()
+        .free_blocks(8)
+        .build(&mut image)
+        .unwrap();
+    let fs = Ext4Fs::new(image).unwrap();
+    let free = fs.trim_report().unwrap().ranges[0].start * 4096;
+    let mut image = fs.dev;
+    // a png of an IHDR and an IEND chunk, a pdf and text, a block apart
+    let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    png.extend([0; 17]);
+    png.extend(b"\0\0\0\0IEND\xae\x42\x60\x82");
+    let pdf = b"%PDF-1.4\n1 0 obj\n<<>>\nendobj\n%%EOF\n";
+    let at = |i: u64| (free + i * 4096) as usize;
+    image[at(0)..at(0) + png.len()].copy_from_slice(&png);
+    image[at(2)..at(2) + pdf.len()].copy_from_slice(pdf);
+    image[at(4)..at(4) + 5].copy_from_slice(b"text\n");
+    let img = dir.join("img");
+    std::fs::write(&img, &image).unwrap();
+
+    assert_eq!(
+        ext4cat(&img, &["carve"]),
+        format!(
+            "{:>12} {:>12} png\n{:>12} {:>12} pdf\n",
+            at(0),
+            png.len(),
+            at(2),
+            pdf.len()
+        )
+    );
+    let dest = dir.join("out");
+    let dest = dest.to_str().unwrap();
+    let written = ext4cat(&img, &["carve", "-d", dest]);
+    assert!(written.starts_with(&format!("{dest}/carved-{}.png: 45 bytes\n", at(0))));
+    let carved = std::fs::read(format!("{dest}/carved-{}.png", at(0))).unwrap();
+    assert_eq!(carved, png);
+}
+
+#[test]
+fn timeline_orders_every_time() {
+    let dir = scratch("timeline_orders_every_time");
+    let img = fixture(&dir, "timestamps");
+    // f's four times apart, one before 1970 and two past 2038
+    assert_eq!(
+        ext4cat(&img, &["timeline"]),
+        "Date,Size,Type,Mode,UID,GID,Meta,File Name
+1960-01-01T00:00:00Z,0,.a..,-rw-rw-rw-,0,0,12,/f
+2020-09-13T00:00:00Z,0,..c.,-rw-rw-rw-,0,0,12,/f
+2023-11-14T22:13:20Z,1024,macb,drwxr-xr-x,0,0,2,/
+2023-11-14T22:13:20Z,12288,macb,drwx------,0,0,11,/lost+found
+2040-01-01T00:00:00Z,0,m...,-rw-rw-rw-,0,0,12,/f
+2242-01-01T00:00:00Z,0,...b,-rw-rw-rw-,0,0,12,/f
+"
+    );
+    let body = ext4cat(&img, &["timeline", "--body"]);
+    assert!(
+        body.ends_with("0|/f|12|-rw-rw-rw-|0|0|0|-315619200|2208988800|1599955200|8583494400\n"),
+        "{body}"
+    );
+}
+
+#[test]
+fn stat_formats_as_coreutils() {
+    let dir = scratch("stat_formats_as_coreutils");
+    let img = sample(&dir);
+    let format = |format, path| ext4cat(&img, &["stat", "-c", format, path]);
+    assert_eq!(
+        format("%n|%s|%a|%A|%u|%g|%F|%Y", "/etc/passwd"),
+        "/etc/passwd|30|640|-rw-r-----|1000|100|regular file|1600000000\n"
+    );
+    // escapes are --printf's, left alone as by GNU stat -c
+    assert_eq!(
+        format("%i %h %b %%\\t%N", "/etc/link"),
+        "18 1 0 %\\t'/etc/link' -> 'passwd'\n"
+    );
+    assert_eq!(
+        format("%w %X %z", "/docs"),
+        "2020-09-13 12:26:40.000000000 +0000 1600000000 \
+         2020-09-13 12:26:40.000000000 +0000\n"
+    );
+    assert_eq!(
+        ext4cat_fails(&img, &["stat", "-c", "%Q", "/etc/link"]),
+        "ext4cat: stat: unknown directive %Q\n"
+    );
+}
+
+#[test]
+fn ls_long_shows_symbolic_modes() {
+    let dir = scratch("ls_long_shows_symbolic_modes");
+    let img = sample(&dir);
+    assert_eq!(
+        ext4cat(&img, &["ls", "-l", "/etc"]),
+        "total 8
+-rw-r--r-- 1    0   0  7 Sep 13  2020 hostname
+lrwxrwxrwx 1    0   0  6 Sep 13  2020 link -> passwd
+-rw-r----- 1 1000 100 30 Sep 13  2020 passwd
+"
+    );
+    let mut builder = ImageBuilder::new();
+    for (path, kind, permissions) in [
+        ("/tmp", NodeKind::Dir, 0o1777),
+        ("/su", NodeKind::File(Vec::new()), 0o4755),
+        ("/wall", NodeKind::File(Vec::new()), 0o2644),
+        ("/odd", NodeKind::File(Vec::new()), 0o7000),
+    ] {
+        builder.add(path, kind, NodeMeta::new(permissions)).unwrap();
+    }
+    let mut image = Vec::new();
+    builder.build(&mut image).unwrap();
+    let img = dir.join("special");
+    std::fs::write(&img, &image).unwrap();
+    let modes: Vec<String> = ext4cat(&img, &["ls", "-l", "/"])
+        .lines()
+        .skip(1)
+        .map(|x| format!("{} {}", &x[..10], x.rsplit(' ').next().unwrap()))
+        .collect();
+    // as ls -l shows set-id and sticky bits, with execute or without
+    assert_eq!(
+        modes,
+        [
+            "drwx------ lost+found",
+            "---S--S--T odd",
+            "-rwsr-xr-x su",
+            "drwxrwxrwt tmp",
+            "-rw-r-Sr-- wall",
+        ]
+    );
+}
+
+#[test]
+fn cat_reads_part_of_a_file() {
+    let dir = scratch("cat_reads_part_of_a_file");
+    let img = sample(&dir);
+    let cat = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_ext4cat"))
+            .arg(&img)
+            .arg("cat")
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{args:?}: {output:?}");
+        output.stdout
+    };
+    assert_eq!(
+        cat(&["--offset", "6", "--length", "5", "/docs/readme.txt"]),
+        b"world"
+    );
+    let big: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    // across the end of the first block
+    assert_eq!(
+        cat(&["--offset", "4090", "--length", "12", "/docs/big.bin"]),
+        big[4090..4102]
+    );
+    assert_eq!(cat(&["--offset", "99990", "/docs/big.bin"]), big[99990..]);
+    // cut at the end of the file, or past it
+    assert_eq!(
+        cat(&["--offset", "20", "--length", "100", "/docs/readme.txt"]),
+        b"ine\n"
+    );
+    assert_eq!(cat(&["--offset", "200000", "/docs/big.bin"]), b"");
+    assert_eq!(
+        ext4cat_fails(&img, &["cat", "--offset", "-1", "/docs/readme.txt"]),
+        "ext4cat: cat: --offset wants a number, not \"-1\"\n"
+    );
+}
+
+#[test]
+fn extents_map_as_filefrag() {
+    let dir = scratch("extents_map_as_filefrag");
+    let img = sample(&dir);
+    assert_eq!(
+        ext4cat(&img, &["extents", "/docs/big.bin"]),
+        "Filesystem type is: ef53
+File size of /docs/big.bin is 100000 (25 blocks of 4096 bytes)
+ ext:     logical_offset:        physical_offset: length:   expected: flags:
+   0:          0..        24:         14..        38:     25:            last,eof
+/docs/big.bin: 1 extent found, 0 discontinuities, 0.00% fragmented
+"
+    );
+    // f0 goes round the metadata at the start of group 1, 257 to 266
+    let img = fixture(&dir, "meta_bg");
+    assert_eq!(
+        ext4cat(&img, &["extents", "/f0"]),
+        "Filesystem type is: ef53
+File size of /f0 is 300000 (293 blocks of 1024 bytes)
+ ext:     logical_offset:        physical_offset: length:   expected: flags:
+   0:          0..       232:         24..       256:    233:
+   1:        233..       292:        267..       326:     60:       257: last,eof
+/f0: 2 extents found, 1 discontinuity, 0.34% fragmented
+"
+    );
+}
+
+#[test]
+fn xattr_lists_and_gets_as_getfattr() {
+    let dir = scratch("xattr_lists_and_gets_as_getfattr");
+    let img = fixture(&dir, "xattr");
+    // the inode's entries, big's value in an EA inode, then the block's
+    assert_eq!(
+        ext4cat(&img, &["xattr", "list", "/f"]),
+        "user.small\nuser.big\nuser.block\n"
+    );
+    let big: String = (0..1024).map(|i| (b'a' + (i % 26) as u8) as char).collect();
+    assert_eq!(
+        ext4cat(&img, &["xattr", "list", "--dump", "/f"]),
+        format!(
+            "user.small=\"1\"\nuser.big=\"{big}\"\nuser.block=\"{}\"\n",
+            "b".repeat(300)
+        )
+    );
+    let get = |args: &[&str]| {
+        let mut all = vec!["xattr", "get"];
+        all.extend(args);
+        ext4cat(&img, &all)
+    };
+    assert_eq!(get(&["/f", "user.small"]), "user.small=\"1\"\n");
+    assert_eq!(get(&["-e", "hex", "/f", "user.small"]), "user.small=0x31\n");
+    assert_eq!(
+        get(&["-e", "base64", "/f", "user.small"]),
+        "user.small=0sMQ==\n"
+    );
+    assert_eq!(get(&["--only-values", "/f", "user.big"]), big);
+    assert_eq!(
+        ext4cat_fails(&img, &["xattr", "get", "/f", "user.nope"]),
+        "ext4cat: /f: user.nope: no such attribute\n"
+    );
+}
+
+#[test]
+fn find_filters_entries() {
+    let dir = scratch("find_filters_entries");
+    let img = sample(&dir);
+    let find = |args: &[&str]| {
+        let mut all = vec!["find"];
+        all.extend(args);
+        ext4cat(&img, &all)
+    };
+    assert_eq!(find(&["-type", "d"]), "/\n/docs\n/etc\n/lost+found\n");
+    assert_eq!(find(&["-type", "l"]), "/etc/link\n");
+    assert_eq!(find(&["-type", "f", "-size", "+10k"]), "/docs/big.bin\n");
+    assert_eq!(find(&["-size", "-2c"]), "/docs/.hidden\n");
+    // in 512 byte blocks, rounded up, as find counts them
+    assert_eq!(
+        find(&["-size", "1"]),
+        "/docs/.hidden\n/docs/readme.txt\n/etc/hostname\n/etc/link\n/etc/passwd\n"
+    );
+    assert_eq!(find(&["-uid", "1000"]), "/etc/passwd\n");
+    assert_eq!(find(&["-regex", ".*/l.*"]), "/etc/link\n/lost+found\n");
+    // everything was modified in 2020
+    assert_eq!(find(&["-mtime", "+30"]).lines().count(), 10);
+    assert_eq!(find(&["-mtime", "-30"]), "");
+    // filters and a glob together
+    assert_eq!(
+        find(&["-type", "f", "-uid", "0", "/docs/*"]),
+        "/docs/.hidden\n/docs/big.bin\n/docs/readme.txt\n"
+    );
+    assert_eq!(
+        ext4cat_fails(&img, &["find", "-type", "x"]),
+        "ext4cat: find: -type wants f, d, l, b, c, p or s, not \"x\"\n"
+    );
+}
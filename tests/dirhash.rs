@@ -0,0 +1,123 @@
+//! Directory hashes against the values `debugfs -R "dx_hash -h N -s
+//! SEED name"` gives for them.
+
+use read_file_block_way::dirhash::{hash, HashVersion};
+
+// 0b0a0c0d-0102-0304-0506-0708090a0b0c, read as little endian words
+const SEED: [u32; 4] = [0x0d0c0a0b, 0x04030201, 0x08070605, 0x0c0b0a09];
+
+#[test]
+fn hashes_match_debugfs() {
+    use HashVersion::*;
+    // hash, name, seed, major and minor hash
+    let cases: &[(HashVersion, &str, [u32; 4], u32, u32)] = &[
+        (Legacy, "hello", SEED, 0x32252546, 0x00000000),
+        (Legacy, "é_1", SEED, 0x9ad567f2, 0x00000000),
+        (
+            Legacy,
+            "a-name-longer-than-thirty-two-bytes-for-md4",
+            SEED,
+            0xb288d2f8,
+            0x00000000,
+        ),
+        (
+            Legacy,
+            "ñandú-ÿ-longer-than-sixteen",
+            SEED,
+            0x0ad5d958,
+            0x00000000,
+        ),
+        (Legacy, "a", [0; 4], 0xe74b53e2, 0x00000000),
+        (HalfMd4, "hello", SEED, 0x7225ad5e, 0x195b52e4),
+        (HalfMd4, "é_1", SEED, 0x84ada882, 0x42331f8f),
+        (
+            HalfMd4,
+            "a-name-longer-than-thirty-two-bytes-for-md4",
+            SEED,
+            0xf2bba46c,
+            0x732b3bec,
+        ),
+        (
+            HalfMd4,
+            "ñandú-ÿ-longer-than-sixteen",
+            SEED,
+            0x886cf2ca,
+            0x3d21dca5,
+        ),
+        (HalfMd4, "a", [0; 4], 0xd5fa7d7a, 0xacb48187),
+        (Tea, "hello", SEED, 0x743bc79e, 0x2335da29),
+        (Tea, "é_1", SEED, 0xb35002d4, 0x6c400ca9),
+        (
+            Tea,
+            "a-name-longer-than-thirty-two-bytes-for-md4",
+            SEED,
+            0xf31934b0,
+            0xfbbf6ca6,
+        ),
+        (
+            Tea,
+            "ñandú-ÿ-longer-than-sixteen",
+            SEED,
+            0x1e080ed4,
+            0x2ef5358c,
+        ),
+        (Tea, "a", [0; 4], 0x6d0ea4c0, 0xc18922df),
+        (LegacyUnsigned, "hello", SEED, 0x32252546, 0x00000000),
+        (LegacyUnsigned, "é_1", SEED, 0x679529ea, 0x00000000),
+        (
+            LegacyUnsigned,
+            "a-name-longer-than-thirty-two-bytes-for-md4",
+            SEED,
+            0xb288d2f8,
+            0x00000000,
+        ),
+        (
+            LegacyUnsigned,
+            "ñandú-ÿ-longer-than-sixteen",
+            SEED,
+            0xe3a5590a,
+            0x00000000,
+        ),
+        (HalfMd4Unsigned, "hello", SEED, 0x7225ad5e, 0x195b52e4),
+        (HalfMd4Unsigned, "é_1", SEED, 0x85be9e9e, 0x4f30df10),
+        (
+            HalfMd4Unsigned,
+            "a-name-longer-than-thirty-two-bytes-for-md4",
+            SEED,
+            0xf2bba46c,
+            0x732b3bec,
+        ),
+        (
+            HalfMd4Unsigned,
+            "ñandú-ÿ-longer-than-sixteen",
+            SEED,
+            0x868281fa,
+            0x3f892cf7,
+        ),
+        (TeaUnsigned, "hello", SEED, 0x743bc79e, 0x2335da29),
+        (TeaUnsigned, "é_1", SEED, 0xdefbf870, 0x915ec653),
+        (
+            TeaUnsigned,
+            "a-name-longer-than-thirty-two-bytes-for-md4",
+            SEED,
+            0xf31934b0,
+            0xfbbf6ca6,
+        ),
+        (
+            TeaUnsigned,
+            "ñandú-ÿ-longer-than-sixteen",
+            SEED,
+            0xf8fa05da,
+            0x9e42f5a5,
+        ),
+    ];
+    for &(version, name, seed, major, minor) in cases {
+        assert_eq!(
+            hash(version, name.as_bytes(), seed),
+            (major, minor),
+            "{} of {:?}",
+            version.name(),
+            name
+        );
+    }
+}
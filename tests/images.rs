@@ -0,0 +1,604 @@
+//! Images made by mke2fs, debugfs and e2fsck, see `images/make.sh`, read
+//! as those tools see them.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
+
+use positioned_io::ReadAt;
+use read_file_block_way::census::Census;
+use read_file_block_way::events::Anomaly;
+use read_file_block_way::journal::Journal;
+use read_file_block_way::mmp::MmpState;
+use read_file_block_way::quota::QuotaType;
+use read_file_block_way::{
+    BlockGroupNumber, Ext4Fs, FileHandle, FileType, InodeFlags, InodeNumber, Timestamp, TypedInode,
+};
+
+fn raw(name: &str) -> Vec<u8> {
+    let path = format!("{}/tests/images/{name}.img.z", env!("CARGO_MANIFEST_DIR"));
+    let compressed = std::fs::read(&path).unwrap();
+    miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).unwrap()
+}
+
+fn image(name: &str) -> Ext4Fs<Vec<u8>> {
+    Ext4Fs::new(raw(name)).unwrap()
+}
+
+#[test]
+fn casefolded_htree_lookups() {
+    let fs = image("casefold");
+    let dir = fs.stat("/cf").unwrap().unwrap();
+    assert!(dir.is_casefolded(&fs.sb));
+    let htree = fs.htree(&dir).unwrap().unwrap();
+    assert!(htree.problems.is_empty(), "{:?}", htree.problems);
+    for i in 1..=40 {
+        for name in [
+            format!("é_{i}"),
+            format!("É_{i}"),
+            format!("file-with-a-long-name-{i}"),
+            format!("FILE-With-A-Long-Name-{i}"),
+        ] {
+            assert!(fs.lookup(&dir, &name).unwrap().is_some(), "{name}");
+        }
+    }
+    // paths resolve through the index too
+    let n = fs.lookup(&dir, "é_7").unwrap();
+    assert_eq!(fs.resolve("/cf/É_7").unwrap(), n);
+    assert_eq!(fs.resolve("/cf/../cf/./É_7").unwrap(), n);
+    // missing, or decomposed and there without NFD to tell
+    assert!(fs.lookup(&dir, "é_41").is_err());
+    assert!(fs.lookup(&dir, "e\u{301}_1").is_err());
+    assert!(fs.resolve("/cf/e\u{301}_1").is_err());
+    assert_eq!(fs.lookup(&dir, "missing").unwrap(), None);
+    assert_eq!(fs.resolve("/cf/missing").unwrap(), None);
+}
+
+#[test]
+fn inline_data_directory() {
+    let fs = image("inline");
+    let n = fs.resolve("/dir").unwrap().unwrap();
+    let dir = TypedInode::new(fs.inode(n).unwrap()).into_dir().unwrap();
+    assert!(dir.flags.contains(InodeFlags::INLINE_DATA));
+    // `..` and `a` in i_block, `spilled` in system.data
+    let entries = fs.dir_entries(n, &dir).unwrap();
+    let names: Vec<_> = entries.iter().map(|x| x.name.as_str()).collect();
+    assert_eq!(names, ["..", "a", "spilled"]);
+    let a = fs.resolve("/dir/a").unwrap().unwrap();
+    assert_eq!(fs.lookup(&dir, "spilled").unwrap(), Some(a));
+    assert_eq!(fs.resolve("/dir/spilled").unwrap(), Some(a));
+    let file = fs
+        .stat("/dir/spilled")
+        .unwrap()
+        .unwrap()
+        .into_file()
+        .unwrap();
+    let mut buf = [0; 3];
+    fs.reader(&file)
+        .unwrap()
+        .read_exact_at(0, &mut buf)
+        .unwrap();
+    assert_eq!(&buf, b"hi\n");
+}
+
+/// The first 1024 bytes of filesystem block `n`.
+fn block<T: ReadAt>(fs: &Ext4Fs<T>, n: u64) -> Vec<u8> {
+    let mut buf = vec![0; 1024];
+    fs.dev.read_exact_at(n * 1024, &mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn journal_sequences_wrap_around() {
+    let fs = image("journal");
+    let journal = Journal::open(&fs).unwrap();
+    let history = journal.history().unwrap();
+    let sequences: Vec<_> = history.iter().map(|x| x.sequence).collect();
+    assert_eq!(sequences, [u32::MAX - 1, u32::MAX, 0]);
+    assert!(history.iter().all(|x| x.committed));
+    let versions: Vec<_> = journal
+        .block_history(3000)
+        .unwrap()
+        .iter()
+        .map(|x| (x.sequence, journal.logged_block(&x.tag).unwrap()[0]))
+        .collect();
+    assert_eq!(versions, [(u32::MAX - 1, b'a'), (u32::MAX, b'b')]);
+    drop(journal);
+
+    let zeros = vec![0; 1024];
+    let filled = |c: u8| vec![c; 1024];
+    let replayed = image("journal").replayed().unwrap();
+    assert_eq!(block(&replayed, 3000), filled(b'b'));
+    // revoked by transaction 0, which comes after the wrap
+    assert_eq!(block(&replayed, 3001), zeros);
+    assert_eq!(block(&replayed, 3002), filled(b'c'));
+
+    let before = image("journal").as_of(1).unwrap();
+    assert_eq!(block(&before, 3000), filled(b'b'));
+    assert_eq!(block(&before, 3001), filled(b'b'));
+    assert_eq!(block(&before, 3002), zeros);
+    assert_eq!(before.dev.stale_blocks(), [3002]);
+    let before = image("journal").as_of(2).unwrap();
+    assert_eq!(block(&before, 3000), filled(b'a'));
+    assert!(image("journal").as_of(4).is_err());
+}
+
+fn htree_names() -> impl Iterator<Item = String> {
+    (1..=4000).map(|i| format!("a-name-long-enough-to-fill-leaves-quickly-{i}"))
+}
+
+#[test]
+fn htree_with_two_levels() {
+    let fs = image("htree");
+    let dir = fs.stat("/dir").unwrap().unwrap().into_dir().unwrap();
+    let htree = fs.htree(&dir).unwrap().unwrap();
+    assert!(htree.problems.is_empty(), "{:?}", htree.problems);
+    assert_eq!(htree.hash_version, Some(1));
+    assert_eq!(htree.levels, 1);
+    assert_eq!(htree.index_blocks, [268, 269, 270]);
+    assert!(htree.unreferenced_leaves.is_empty());
+    // every block but the root and the index nodes is a leaf
+    assert_eq!(htree.leaves.len() as u64, dir.size / 1024 - 4);
+    for name in htree_names() {
+        assert!(fs.lookup(&dir, &name).unwrap().is_some(), "{name}");
+    }
+    assert_eq!(fs.lookup(&dir, "missing").unwrap(), None);
+
+    // an index node with no entries loses its leaves to the index, not
+    // to lookups or listings
+    let mut data = raw("htree");
+    let at = dir
+        .extents(&fs.sb, &fs.dev)
+        .unwrap()
+        .iter()
+        .find_map(|x| x.map(269))
+        .unwrap();
+    data[at as usize * 1024 + 10..][..2].fill(0);
+    let fs = Ext4Fs::new(data).unwrap();
+    let n = fs.resolve("/dir").unwrap().unwrap();
+    let dir = TypedInode::new(fs.inode(n).unwrap()).into_dir().unwrap();
+    let htree = fs.htree(&dir).unwrap().unwrap();
+    assert!(htree
+        .problems
+        .iter()
+        .any(|x| x.starts_with("bad index node")));
+    assert!(!htree.unreferenced_leaves.is_empty());
+    assert!(htree
+        .leaves
+        .iter()
+        .all(|x| !htree.unreferenced_leaves.contains(x)));
+    assert_eq!(fs.dir_entries(n, &dir).unwrap().len(), 4002);
+    for name in htree_names() {
+        assert!(fs.lookup(&dir, &name).unwrap().is_some(), "{name}");
+    }
+}
+
+#[test]
+fn xattrs_in_inode_block_and_ea_inode() {
+    let fs = image("xattr");
+    let f = fs.stat("/f").unwrap().unwrap();
+    assert_ne!(f.file_acl, 0);
+    let mut xattrs: Vec<_> = f
+        .xattrs(&fs.sb, &fs.dev)
+        .unwrap()
+        .into_iter()
+        .map(|x| (x.full_name(), x.value))
+        .collect();
+    xattrs.sort();
+    let big: Vec<u8> = (0..1024).map(|i| b'a' + (i % 26) as u8).collect();
+    assert_eq!(
+        xattrs,
+        [
+            ("user.big".to_string(), big),
+            ("user.block".to_string(), vec![b'b'; 300]),
+            ("user.small".to_string(), b"1".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn quota_usage_and_limits() {
+    let fs = image("quota");
+    let user = fs.quota(QuotaType::User).unwrap().unwrap();
+    assert_eq!(user.info.block_grace, 604800);
+    let ids: Vec<_> = user.entries.iter().map(|x| x.id).collect();
+    assert_eq!(ids, [0, 1000]);
+    let q = user.entry(1000).unwrap();
+    assert_eq!(q.inodes_used, 1);
+    assert_eq!(q.inode_soft_limit, 10);
+    assert_eq!(q.inode_hard_limit, 0);
+    assert_eq!(q.space_used, 5120);
+    assert_eq!(q.space_hard_limit, 8 << 20);
+    assert_eq!(q.space_soft_limit, 0);
+    assert!(user.entry(1).is_none());
+
+    let group = fs.quota(QuotaType::Group).unwrap().unwrap();
+    let root = group.entry(0).unwrap();
+    assert_eq!(group.entries.len(), 1);
+    assert_eq!(root.inodes_used, 3);
+    assert_eq!(root.space_used, user.entry(0).unwrap().space_used + 5120);
+    assert!(fs.quota(QuotaType::Project).unwrap().is_none());
+}
+
+#[test]
+fn mmp_sequence() {
+    let fs = image("mmp");
+    let mmp = fs.mmp().unwrap().unwrap();
+    assert_eq!(mmp.state, MmpState::Clean);
+    assert_eq!(mmp.check_interval, 5);
+    let mounted = |x: &Anomaly| matches!(x, Anomaly::MountedElsewhere { .. });
+    assert!(!fs.check().unwrap().findings.iter().any(mounted));
+
+    // as a node holding it leaves it
+    let mut data = raw("mmp");
+    let at = mmp.block as usize * 1024;
+    data[at + 4..at + 8].copy_from_slice(&5u32.to_le_bytes());
+    data[at + 0x10..at + 0x50].fill(0);
+    data[at + 0x10..at + 0x15].copy_from_slice(b"other");
+    let fs = Ext4Fs::new(data).unwrap();
+    let mmp = fs.mmp().unwrap().unwrap();
+    assert_eq!(mmp.state, MmpState::InUse(5));
+    assert!(mmp.in_use());
+    let found = fs.check().unwrap().findings;
+    let by_other = |x: &Anomaly| matches!(x, Anomaly::MountedElsewhere { node, sequence: 5 } if node == "other");
+    assert!(found.iter().any(by_other), "{found:?}");
+}
+
+#[test]
+fn verity_file() {
+    let fs = image("verity");
+    let v = fs.stat("/v").unwrap().unwrap();
+    assert!(v.flags.contains(InodeFlags::VERITY));
+    let descriptor = fs.verity(&v).unwrap().unwrap();
+    assert_eq!(descriptor.data_size, 130 * 4096 - 1000);
+    assert_eq!(descriptor.log_blocksize, 12);
+    assert_eq!(descriptor.salt, b"saltsalt");
+    // computed by make.sh after the kernel's layout
+    assert_eq!(
+        descriptor.digest_string(),
+        "sha256:d2742d5ce93745302e6059046d9216a21097e79291dacf7c5178575fca8937bb"
+    );
+    let check = fs.verify_verity(&v).unwrap().unwrap();
+    assert!(check.is_ok(), "{check:?}");
+    let mut content = vec![0; descriptor.data_size as usize];
+    fs.reader(v.as_file().unwrap())
+        .unwrap()
+        .read_exact_at(0, &mut content)
+        .unwrap();
+    assert!(content
+        .iter()
+        .enumerate()
+        .all(|(i, &b)| b as usize == i * 7 % 251));
+
+    // a changed byte in the 101st block
+    let mut data = raw("verity");
+    let at = v
+        .extents(&fs.sb, &fs.dev)
+        .unwrap()
+        .iter()
+        .find_map(|x| x.map(100))
+        .unwrap();
+    data[at as usize * 4096 + 17] ^= 1;
+    let fs = Ext4Fs::new(data).unwrap();
+    let check = fs.verify_verity(&v).unwrap().unwrap();
+    assert_eq!(check.bad_data_blocks, [100]);
+    assert!(check.bad_tree_blocks.is_empty());
+    assert!(check.root_matches);
+    assert!(!check.is_ok());
+    let f = fs.stat("/lost+found").unwrap().unwrap();
+    assert!(fs.verify_verity(&f).unwrap().is_none());
+}
+
+#[test]
+fn meta_bg_descriptors() {
+    let fs = image("meta_bg");
+    let sb = &fs.sb;
+    assert!(sb.has_meta_bg());
+    assert_eq!((sb.group_count(), sb.descriptors_per_block()), (40, 16));
+    for group in 0..sb.group_count() {
+        let n = BlockGroupNumber(group);
+        let desc = n.block_group_descriptor(sb, &fs.dev).unwrap();
+        // each group's metadata in the group itself, without flex_bg
+        let first = sb.group_first_block(group);
+        for block in [desc.block_bitmap, desc.inode_bitmap, desc.inode_table] {
+            assert!((first..first + 256).contains(&block), "group {group}");
+        }
+        let checksum = n.descriptor_checksum(sb, &fs.dev).unwrap();
+        assert_eq!(checksum, Some(desc.checksum), "group {group}");
+    }
+    for (i, c) in [b'a', b'b', b'c'].into_iter().enumerate() {
+        let file = fs.stat(&format!("/f{i}")).unwrap().unwrap();
+        let mut content = vec![0; file.size as usize];
+        fs.reader(file.as_file().unwrap())
+            .unwrap()
+            .read_exact_at(0, &mut content)
+            .unwrap();
+        assert_eq!(content, [c; 300_000]);
+    }
+    assert!(fs.check().unwrap().findings.is_empty());
+}
+
+#[test]
+fn bigalloc_clusters() {
+    let fs = image("bigalloc");
+    let sb = &fs.sb;
+    assert_eq!((sb.first_data_block, sb.blocks_per_cluster()), (0, 16));
+    // counted in the cluster bitmap as e2fsck did
+    let free = fs.free_space().unwrap();
+    assert_eq!(free.free_blocks(), sb.free_blocks_count);
+    assert_eq!(free.free_inodes(), sb.free_inodes_count);
+    let f = fs.stat("/f").unwrap().unwrap().into_file().unwrap();
+    let mut content = vec![0; f.size as usize];
+    let reader = fs.reader(&f).unwrap();
+    reader.read_exact_at(0, &mut content).unwrap();
+    let expected: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    assert_eq!(content, expected);
+    // the middle of the file's last cluster
+    let extent = reader.extents()[0].clone();
+    let n = fs.resolve("/f").unwrap().unwrap();
+    let block = extent.start + extent.len - 1;
+    assert_eq!(fs.block_owners(&[block]).unwrap(), [Some(n)]);
+    assert!(fs.is_block_allocated(block).unwrap());
+    assert!(!fs.is_block_allocated(sb.blocks_count - 1).unwrap());
+    let small = fs.stat("/sub/small").unwrap().unwrap().into_file().unwrap();
+    let mut content = [0; 6];
+    fs.reader(&small)
+        .unwrap()
+        .read_exact_at(0, &mut content)
+        .unwrap();
+    assert_eq!(&content, b"small\n");
+    assert!(fs.check().unwrap().findings.is_empty());
+}
+
+#[test]
+fn orphan_list() {
+    let fs = image("orphan");
+    let orphans = fs.orphans().unwrap();
+    assert_eq!(orphans, [InodeNumber(12), InodeNumber(14), InodeNumber(13)]);
+    // unlinked, their content still there
+    for n in orphans {
+        let inode = TypedInode::new(fs.inode(n).unwrap()).into_file().unwrap();
+        assert_eq!(inode.links_count, 0);
+        let mut content = [0; 5];
+        fs.reader(&inode)
+            .unwrap()
+            .read_exact_at(0, &mut content)
+            .unwrap();
+        assert_eq!(&content, b"kept\n");
+    }
+    assert_eq!(fs.resolve("/a").unwrap(), None);
+}
+
+#[test]
+fn timestamps_past_32_bits() {
+    let fs = image("timestamps");
+    let f = fs.stat("/f").unwrap().unwrap();
+    let at = |seconds, nanos| Timestamp { seconds, nanos };
+    // 1960, 2040 and 2242, each written by debugfs with its epoch bits
+    assert_eq!(f.atime, at(-315_619_200, 0));
+    assert_eq!(f.mtime, at(2_208_988_800, 0));
+    assert_eq!(f.crtime, Some(at(8_583_494_400, 0)));
+    // 0x3b9ac9fc, nanoseconds above the 2 epoch bits
+    assert_eq!(f.ctime, at(1_599_955_200, 249_999_999));
+    assert_eq!(
+        f.ctime.to_system_time(),
+        UNIX_EPOCH + Duration::new(1_599_955_200, 249_999_999)
+    );
+    assert_eq!(
+        f.atime.to_system_time(),
+        UNIX_EPOCH - Duration::from_secs(315_619_200)
+    );
+}
+
+#[test]
+fn trim_report_matches_dumpe2fs() {
+    let fs = image("meta_bg");
+    let report = fs.trim_report().unwrap();
+    let ranges: Vec<_> = report.ranges.iter().map(|x| (x.start, x.blocks)).collect();
+    // the free blocks dumpe2fs lists, runs across groups merged, the
+    // groups left uninitialized by mke2fs among them
+    assert_eq!(ranges.len(), 37);
+    assert_eq!(ranges[..3], [(930, 95), (1033, 248), (1290, 247)]);
+    assert_eq!(ranges.last(), Some(&(9993, 247)));
+    assert_eq!(report.free_blocks(), 9011);
+    assert_eq!(report.free_blocks(), fs.sb.free_blocks_count);
+    assert!(report
+        .to_string()
+        .starts_with("952320 97280\n1057792 253952\n"));
+}
+
+#[test]
+fn usage_report_matches_quota_files() {
+    let fs = image("quota");
+    let report = fs.usage_report().unwrap();
+    // what e2fsck counted into the quota files, from the inodes too
+    for (kind, table) in [
+        (QuotaType::User, &report.by_uid),
+        (QuotaType::Group, &report.by_gid),
+    ] {
+        let quota = fs.quota(kind).unwrap().unwrap();
+        let ids: Vec<_> = quota.entries.iter().map(|x| x.id).collect();
+        assert_eq!(ids, table.keys().copied().collect::<Vec<_>>(), "{kind:?}");
+        for entry in &quota.entries {
+            let usage = table[&entry.id];
+            assert_eq!(usage.files, entry.inodes_used, "{kind:?} {}", entry.id);
+            assert_eq!(usage.bytes, entry.space_used, "{kind:?} {}", entry.id);
+        }
+    }
+    assert_eq!(report.by_uid[&1000].files, 1);
+    assert_eq!(report.by_uid[&1000].bytes, 5120);
+    assert!(report.by_project.is_empty());
+}
+
+#[test]
+fn recovery_ranking() {
+    let fs = image("recover");
+    let candidates = fs.recovery_candidates().unwrap();
+    let ranked: Vec<_> = candidates
+        .iter()
+        .map(|x| (x.inode_number.0, x.score))
+        .collect();
+    // pic.png whole, then old.txt, its blocks reused by new.txt
+    assert_eq!(ranked, [(12, 100), (13, 60)]);
+    assert!(candidates[0].reasons.contains(&"png signature".to_string()));
+    assert!(candidates[0]
+        .reasons
+        .contains(&"blocks not reallocated".to_string()));
+    assert!(!candidates[1]
+        .reasons
+        .contains(&"blocks not reallocated".to_string()));
+    let mut png = Vec::new();
+    assert_eq!(fs.recover_content(&candidates[0], &mut png).unwrap(), 3080);
+    assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    assert!(png[8..].iter().enumerate().all(|(i, &x)| x == i as u8));
+    let mut old = Vec::new();
+    fs.recover_content(&candidates[1], &mut old).unwrap();
+    assert!(old.starts_with(b"new text\n"));
+}
+
+/// The names in the root directory but `.` and `..`, sorted.
+fn root_names<T: ReadAt>(fs: &Ext4Fs<T>) -> Vec<String> {
+    let root = TypedInode::new(fs.inode(Ext4Fs::<T>::ROOT).unwrap());
+    let entries = fs
+        .dir_entries(Ext4Fs::<T>::ROOT, root.as_dir().unwrap())
+        .unwrap();
+    let mut names: Vec<_> = entries.into_iter().map(|x| x.name).collect();
+    names.retain(|x| x != "." && x != "..");
+    names.sort();
+    names
+}
+
+#[test]
+fn listing_as_of_an_older_commit() {
+    assert_eq!(root_names(&image("as_of")), ["a", "b", "lost+found"]);
+    let replayed = image("as_of").replayed().unwrap();
+    assert_eq!(root_names(&replayed), ["a", "b", "lost+found"]);
+    // before the last commit, b not yet added
+    let before = image("as_of").as_of(1).unwrap();
+    assert_eq!(root_names(&before), ["a", "lost+found"]);
+    assert_eq!(before.resolve("/b").unwrap(), None);
+    assert!(before.resolve("/a").unwrap().is_some());
+    assert!(image("as_of").as_of(3).is_err());
+}
+
+#[test]
+fn device_numbers() {
+    let fs = image("devices");
+    let device = |path| fs.stat(path).unwrap().unwrap().device();
+    // 8 bits each in i_block[0], as debugfs's mknod writes them
+    assert_eq!(device("/null"), Some((1, 3)));
+    assert_eq!(device("/sda"), Some((8, 0)));
+    // past 8 bits, only `new_encode_dev`'s form in i_block[1] holds them
+    assert_eq!(device("/big"), Some((300, 70000)));
+    assert_eq!(device("/fifo"), None);
+    let big = fs.stat("/big").unwrap().unwrap();
+    assert_eq!(big.file_type(), FileType::CharacterDevice);
+}
+
+#[test]
+fn hard_links_across_directories() {
+    let fs = image("links");
+    let links = fs.hard_links("/").unwrap();
+    let groups: Vec<_> = links.groups().collect();
+    assert_eq!(
+        groups,
+        [(
+            InodeNumber(14),
+            &["/a/f".to_string(), "/a/g".into(), "/b/h".into()][..]
+        )]
+    );
+    assert_eq!(links.incomplete().count(), 0);
+    // the link in b is outside the walk
+    let links = fs.hard_links("/a").unwrap();
+    assert_eq!(links.groups().count(), 1);
+    assert_eq!(
+        links.incomplete().collect::<Vec<_>>(),
+        [(InodeNumber(14), 2, 3)]
+    );
+    // lone, with a single link, is never recorded
+    let links = fs.hard_links("/b").unwrap();
+    assert_eq!(links.groups().count(), 0);
+    assert_eq!(
+        links.incomplete().collect::<Vec<_>>(),
+        [(InodeNumber(14), 1, 3)]
+    );
+}
+
+#[test]
+fn census_of_fixtures() {
+    let mut census = Census::new();
+    for name in ["casefold", "bigalloc", "meta_bg"] {
+        census.add(&image(name).sb);
+    }
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let inline = dir.join("census-inline.img");
+    std::fs::write(&inline, raw("inline")).unwrap();
+    census.add_image(&inline);
+    let garbage = dir.join("census-garbage.img");
+    std::fs::write(&garbage, vec![0xaa; 4096]).unwrap();
+    census.add_image(&garbage);
+
+    assert_eq!(census.images, 4);
+    let count = |counts: &BTreeMap<String, u64>, name: &str| counts.get(name).copied();
+    assert_eq!(count(&census.incompat, "extent"), Some(4));
+    assert_eq!(count(&census.incompat, "casefold"), Some(1));
+    assert_eq!(count(&census.incompat, "inline_data"), Some(1));
+    assert_eq!(count(&census.incompat, "meta_bg"), Some(1));
+    assert_eq!(count(&census.incompat, "flex_bg"), Some(3));
+    assert_eq!(count(&census.ro_compat, "bigalloc"), Some(1));
+    assert_eq!(count(&census.ro_compat, "metadata_csum"), Some(4));
+    assert_eq!(count(&census.compat, "has_journal"), Some(1));
+    assert_eq!(count(&census.compat, "resize_inode"), Some(3));
+    assert_eq!(census.block_sizes.iter().collect::<Vec<_>>(), [(&1024, &4)]);
+    assert_eq!(census.inode_sizes.iter().collect::<Vec<_>>(), [(&256, &4)]);
+    assert_eq!(count(&census.hash_versions, "half_md4"), Some(4));
+    assert_eq!(
+        census.failed,
+        [(
+            garbage.display().to_string(),
+            "no ext2/3/4 superblock".to_string()
+        )]
+    );
+    let text = census.to_string();
+    assert!(text.starts_with("4 images, 1 unreadable\n"), "{text}");
+    assert!(
+        text.contains("  casefold                        1   25.0%\n"),
+        "{text}"
+    );
+}
+
+#[test]
+fn file_handles_and_generations() {
+    let fs = image("handles");
+    let handle = fs
+        .file_handle(InodeNumber(13), Some(InodeNumber(12)))
+        .unwrap();
+    assert_eq!(handle.generation, 0x1234_5678);
+    assert_eq!(handle.parent, Some((InodeNumber(12), 7)));
+    // the words Linux's name_to_handle_at gives for d/f
+    let text = handle.to_string();
+    assert_eq!(text, "2:0d000000785634120c00000007000000");
+    assert_eq!(text.parse::<FileHandle>().unwrap(), handle);
+    assert_eq!(fs.open_handle(&handle).unwrap().size, 8);
+
+    let mut reused = fs.file_handle(InodeNumber(13), None).unwrap();
+    reused.generation = 0x1234_5677;
+    let e = fs.open_handle(&reused).unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "stale file handle: inode 13 generation 305419896, handle has 305419895"
+    );
+    // gone, removed by debugfs, kept its generation but no links
+    let gone = FileHandle {
+        inode: InodeNumber(14),
+        generation: 0,
+        parent: None,
+    };
+    let e = fs.open_handle(&gone).unwrap_err();
+    assert_eq!(e.to_string(), "stale file handle: inode 14 is free");
+    let far = FileHandle {
+        inode: InodeNumber(fs.sb.inodes_count + 1),
+        ..gone
+    };
+    assert!(fs.open_handle(&far).is_err());
+}
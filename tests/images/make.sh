@@ -0,0 +1,352 @@
+#!/bin/sh
+# Regenerate the images tests/images.rs reads, with e2fsprogs 1.47. Each
+# is stored zlib compressed as NAME.img.z.
+set -e
+cd "$(dirname "$0")"
+export E2FSPROGS_FAKE_TIME=1700000000
+UUID=0b0a0c0d-0102-0304-0506-0708090a0b0c
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+store() {
+    python3 -c 'import sys, zlib; sys.stdout.buffer.write(zlib.compress(open(sys.argv[1], "rb").read(), 9))' \
+        "$tmp/$1.img" > "$1.img.z"
+}
+
+# a casefolded directory indexed by e2fsck, names in and out of ASCII
+casefold() {
+    mkfs.ext4 -q -b 1024 -U $UUID -E encoding=utf8,hash_seed=$UUID -O casefold "$tmp/casefold.img" 2M
+    {
+        echo "mkdir cf"
+        echo "set_inode_field cf flags 0x40080000"
+        for i in $(seq 1 40); do
+            echo "write /dev/null cf/é_$i"
+            echo "write /dev/null cf/file-with-a-long-name-$i"
+        done
+    } > "$tmp/cmds"
+    debugfs -w -f "$tmp/cmds" "$tmp/casefold.img" > /dev/null
+    e2fsck -fyD "$tmp/casefold.img" > /dev/null || [ $? -eq 1 ]
+    store casefold
+}
+
+# a directory held in i_block and continued in its system.data xattr,
+# which mke2fs and debugfs never write: the entry there is added by hand
+inline() {
+    mkdir -p "$tmp/inline/dir"
+    echo hi > "$tmp/inline/dir/a"
+    mkfs.ext4 -q -b 1024 -I 256 -U $UUID -O inline_data -d "$tmp/inline" "$tmp/inline.img" 1M
+    # `spilled`, a second link to dir/a, inode 13, spanning the 20 bytes
+    printf '\015\000\000\000\024\000\007\001spilled\000\000\000\000\000' > "$tmp/data"
+    debugfs -w -f - "$tmp/inline.img" > /dev/null <<-END
+		ea_set -f $tmp/data dir system.data
+		sif dir size 80
+		sif dir/a links_count 2
+	END
+    e2fsck -fn "$tmp/inline.img" > /dev/null
+    store inline
+}
+
+# a journal needing recovery, its transactions numbered across the wrap
+# of the sequence: 4294967294 logs 'a' to 3000 and 'b' to 3001,
+# 4294967295 'b' to 3000, 0 'c' to 3002 and revokes 3001, all of them free
+journal() {
+    mkfs.ext4 -q -b 1024 -U $UUID -O has_journal -J size=1 "$tmp/journal.img" 4M
+    for b in 3000 3001 3002; do
+        debugfs -R "testb $b" "$tmp/journal.img" 2>&1 | grep -q "not in use"
+    done
+    # start the log two transactions before the wrap
+    jsb=$(debugfs -R "bmap <8> 0" "$tmp/journal.img" 2> /dev/null)
+    printf '\377\377\377\376' |
+        dd of="$tmp/journal.img" bs=1 seek=$((jsb * 1024 + 0x18)) conv=notrunc 2> /dev/null
+    for c in a b c; do
+        python3 -c 'import sys; sys.stdout.write(sys.argv[1] * 1024)' $c > "$tmp/$c"
+    done
+    cat "$tmp/a" "$tmp/b" > "$tmp/ab"
+    debugfs -w -f - "$tmp/journal.img" > /dev/null <<-END
+		jo
+		jw -b 3000,3001 $tmp/ab
+		jw -c
+		jw -b 3000 $tmp/b
+		jw -c
+		jw -b 3002 -r 3001 $tmp/c
+		jw -c
+		jc
+	END
+    store journal
+}
+
+# a directory of 4000 names indexed by e2fsck, two levels deep
+htree() {
+    mkdir -p "$tmp/htree/dir"
+    for i in $(seq 1 4000); do
+        : > "$tmp/htree/dir/a-name-long-enough-to-fill-leaves-quickly-$i"
+    done
+    mkfs.ext4 -q -b 1024 -N 4100 -U $UUID -E hash_seed=$UUID -d "$tmp/htree" "$tmp/htree.img" 8M
+    e2fsck -fyD "$tmp/htree.img" > /dev/null || [ $? -eq 1 ]
+    store htree
+}
+
+# xattrs in the inode, in an EA block and, no longer fitting there, in
+# an EA inode; e2fsck charges its block to f as debugfs doesn't
+xattr() {
+    mkfs.ext4 -q -b 1024 -I 256 -U $UUID -O ea_inode "$tmp/xattr.img" 2M
+    python3 -c 'import sys; sys.stdout.write("b" * 300)' > "$tmp/block"
+    # debugfs reads at most a block of a value
+    python3 -c 'import sys; sys.stdout.write("".join(chr(97 + i % 26) for i in range(1024)))' > "$tmp/big"
+    debugfs -w -f - "$tmp/xattr.img" > /dev/null <<-END
+		write /dev/null f
+		ea_set f user.small 1
+		ea_set -f $tmp/block f user.block
+		ea_set -f $tmp/big f user.big
+	END
+    e2fsck -fy "$tmp/xattr.img" > /dev/null || [ $? -eq 1 ]
+    e2fsck -fn "$tmp/xattr.img" > /dev/null
+    store xattr
+}
+
+# user and group quotas, usage counted by e2fsck, limits written into
+# the v2r1 records of the user quota file by hand, as debugfs can't
+quota() {
+    mkdir -p "$tmp/quota"
+    python3 -c 'import sys; sys.stdout.write("q" * 5000)' > "$tmp/quota/q"
+    mkfs.ext4 -q -b 1024 -U $UUID -O quota -d "$tmp/quota" "$tmp/quota.img" 2M
+    debugfs -w -R "sif q uid 1000" "$tmp/quota.img" > /dev/null
+    e2fsck -fy "$tmp/quota.img" > /dev/null 2>&1 || [ $? -eq 1 ]
+    # the records are 72 bytes after a 16 bytes leaf header: id, padding,
+    # then inode hard and soft limits, inodes, space hard and soft limits
+    blocks=$(debugfs -R "blocks <3>" "$tmp/quota.img" 2> /dev/null)
+    python3 - "$tmp/quota.img" $blocks <<-END
+		import struct, sys
+		path, blocks = sys.argv[1], [int(x) for x in sys.argv[2:]]
+		image = bytearray(open(path, "rb").read())
+		found = 0
+		for block in blocks:
+		    for at in range(block * 1024 + 16, (block + 1) * 1024 - 71, 72):
+		        if struct.unpack_from("<I", image, at)[0] == 1000:
+		            struct.pack_into("<Q", image, at + 16, 10)
+		            struct.pack_into("<Q", image, at + 32, 8192)
+		            found += 1
+		assert found == 1, found
+		open(path, "wb").write(image)
+	END
+    e2fsck -fn "$tmp/quota.img" > /dev/null
+    store quota
+}
+
+# a verity file of 130 blocks, two tree levels, salted: its Merkle tree
+# and descriptor are written past i_size as the kernel lays them out,
+# which nothing in e2fsprogs does
+verity() {
+    mkdir -p "$tmp/verity"
+    python3 - "$tmp/verity/v" <<-END
+		import hashlib, struct, sys
+		bs, salt = 4096, b"saltsalt"
+		data = bytes(i * 7 % 251 for i in range(130 * bs - 1000))
+		def h(block):
+		    # the salt is padded to the 64 bytes block of SHA-256
+		    return hashlib.sha256(salt.ljust(64, b"\\0") + block.ljust(bs, b"\\0")).digest()
+		levels, blocks = [], [data[i:i + bs] for i in range(0, len(data), bs)]
+		while len(blocks) > 1:
+		    hashes = b"".join(h(x) for x in blocks)
+		    blocks = [hashes[i:i + bs].ljust(bs, b"\\0") for i in range(0, len(hashes), bs)]
+		    levels.append(blocks)
+		root = h(levels[-1][0])
+		# top level first
+		tree = b"".join(b"".join(x) for x in reversed(levels))
+		desc = struct.pack("<BBBBIQ", 1, 1, 12, len(salt), 0, len(data))
+		desc += root.ljust(64, b"\\0") + salt.ljust(32, b"\\0") + bytes(144)
+		content = data.ljust(-(-len(data) // 65536) * 65536, b"\\0") + tree
+		content = content.ljust(-(-len(content) // bs) * bs, b"\\0") + desc
+		content = content.ljust(-(-(len(content) + 4) // bs) * bs - 4, b"\\0")
+		open(sys.argv[1], "wb").write(content + struct.pack("<I", len(desc)))
+		open(sys.argv[1] + ".size", "w").write(str(len(data)))
+	END
+    size=$(cat "$tmp/verity/v.size")
+    rm "$tmp/verity/v.size"
+    mkfs.ext4 -q -b 4096 -U $UUID -O verity -d "$tmp/verity" "$tmp/verity.img" 8M
+    debugfs -w -f - "$tmp/verity.img" > /dev/null <<-END
+		sif v size $size
+		sif v flags 0x180000
+	END
+    e2fsck -fn "$tmp/verity.img" > /dev/null
+    store verity
+}
+
+# multi-mount protection, its block as a clean unmount leaves it
+mmp() {
+    mkfs.ext4 -q -b 1024 -U $UUID -O mmp "$tmp/mmp.img" 2M
+    store mmp
+}
+
+# 40 groups of 256 blocks, their 64 bytes descriptors kept 16 to a block
+# at the start of every 16th group, files spanning several groups
+meta_bg() {
+    mkdir -p "$tmp/meta_bg"
+    for i in 0 1 2; do
+        python3 -c 'import sys; sys.stdout.write(chr(97 + int(sys.argv[1])) * 300000)' $i > "$tmp/meta_bg/f$i"
+    done
+    mkfs.ext4 -q -b 1024 -g 256 -N 1024 -U $UUID -O meta_bg,^resize_inode,^flex_bg,^has_journal \
+        -d "$tmp/meta_bg" "$tmp/meta_bg.img" 10M
+    e2fsck -fn "$tmp/meta_bg.img" > /dev/null
+    store meta_bg
+}
+
+# clusters of 16 1 KiB blocks, the descriptors in block 2 even though
+# block 0 is the first data block
+bigalloc() {
+    mkdir -p "$tmp/bigalloc/sub"
+    python3 -c 'import sys; sys.stdout.buffer.write(bytes(i * 7 % 251 for i in range(200000)))' > "$tmp/bigalloc/f"
+    echo small > "$tmp/bigalloc/sub/small"
+    mkfs.ext4 -q -b 1024 -C 16384 -U $UUID -O bigalloc,^has_journal -d "$tmp/bigalloc" "$tmp/bigalloc.img" 8M
+    e2fsck -fn "$tmp/bigalloc.img" > /dev/null
+    store bigalloc
+}
+
+# three files unlinked while open, as a crash leaves them: on the orphan
+# list from s_last_orphan through their i_dtime, 12 then 14 then 13
+orphan() {
+    mkfs.ext4 -q -b 1024 -U $UUID -O ^has_journal "$tmp/orphan.img" 1M
+    echo kept > "$tmp/data"
+    debugfs -w -f - "$tmp/orphan.img" > /dev/null <<-END
+		write $tmp/data a
+		write $tmp/data b
+		write $tmp/data c
+		unlink a
+		unlink b
+		unlink c
+		sif <12> links_count 0
+		sif <13> links_count 0
+		sif <14> links_count 0
+		sif <12> dtime 14
+		sif <14> dtime 13
+		ssv last_orphan 12
+	END
+    store orphan
+}
+
+# times before 1970 and past 2038, which take the epoch bits of the
+# extra fields, and nanoseconds
+timestamps() {
+    mkfs.ext4 -q -b 1024 -I 256 -U $UUID -O ^has_journal "$tmp/timestamps.img" 1M
+    debugfs -w -f - "$tmp/timestamps.img" > /dev/null <<-END
+		write /dev/null f
+		sif f atime @-315619200
+		sif f mtime @2208988800
+		sif f crtime @8583494400
+		sif f ctime @1599955200
+		sif f ctime_extra 0x3b9ac9fc
+	END
+    e2fsck -fn "$tmp/timestamps.img" > /dev/null
+    store timestamps
+}
+
+# two deleted files: pic.png, its blocks still free, and old.txt, whose
+# blocks new.txt took over but not its inode, held in use meanwhile
+recover() {
+    mkfs.ext4 -q -b 1024 -U $UUID -O ^has_journal "$tmp/recover.img" 1M
+    python3 -c 'import sys; sys.stdout.buffer.write(b"\x89PNG\r\n\x1a\n" + bytes(range(256)) * 12)' > "$tmp/png"
+    python3 -c 'import sys; sys.stdout.write("old text\n" * 300)' > "$tmp/old"
+    python3 -c 'import sys; sys.stdout.write("new text\n" * 300)' > "$tmp/new"
+    debugfs -w -f - "$tmp/recover.img" > /dev/null <<-END
+		write $tmp/png pic.png
+		write $tmp/old old.txt
+		rm old.txt
+		seti <13>
+		write $tmp/new new.txt
+		freei <13>
+		rm pic.png
+	END
+    e2fsck -fn "$tmp/recover.img" > /dev/null
+    store recover
+}
+
+# the root directory before and after b was added, logged by two
+# transactions as the kernel would have
+as_of() {
+    mkfs.ext4 -q -b 1024 -U $UUID -O has_journal -J size=1 "$tmp/as_of.img" 4M
+    echo a > "$tmp/a"
+    debugfs -w -R "write $tmp/a a" "$tmp/as_of.img" > /dev/null
+    dir=$(debugfs -R "bmap <2> 0" "$tmp/as_of.img" 2> /dev/null)
+    dd if="$tmp/as_of.img" of="$tmp/before" bs=1024 skip=$dir count=1 2> /dev/null
+    debugfs -w -R "write $tmp/a b" "$tmp/as_of.img" > /dev/null
+    dd if="$tmp/as_of.img" of="$tmp/after" bs=1024 skip=$dir count=1 2> /dev/null
+    debugfs -w -f - "$tmp/as_of.img" > /dev/null <<-END
+		jo
+		jw -b $dir $tmp/before
+		jc
+		jo
+		jw -b $dir $tmp/after
+		jc
+	END
+    store as_of
+}
+
+# device nodes: null and sda in the old encoding debugfs writes, big
+# with a major and minor past 8 bits, set by hand in the new one, as
+# debugfs's mknod takes neither
+devices() {
+    mkfs.ext4 -q -b 1024 -U $UUID -O ^has_journal "$tmp/devices.img" 1M
+    debugfs -w -f - "$tmp/devices.img" > /dev/null <<-END
+		mknod null c 1 3
+		mknod sda b 8 0
+		mknod big c 1 1
+		sif big block[0] 0
+		sif big block[1] 0x11112c70
+		mknod fifo p
+	END
+    e2fsck -fn "$tmp/devices.img" > /dev/null
+    store devices
+}
+
+# f linked three times, twice in a and once in b, and a lone file
+links() {
+    mkfs.ext4 -q -b 1024 -U $UUID -O ^has_journal "$tmp/links.img" 1M
+    echo linked > "$tmp/f"
+    debugfs -w -f - "$tmp/links.img" > /dev/null <<-END
+		mkdir a
+		mkdir b
+		write $tmp/f a/f
+		ln a/f a/g
+		ln a/f b/h
+		sif a/f links_count 3
+		write $tmp/f b/lone
+	END
+    e2fsck -fn "$tmp/links.img" > /dev/null
+    store links
+}
+
+# generations set on d and d/f, and gone, removed since; d's block
+# checksums, seeded with its generation, redone by e2fsck
+handles() {
+    mkfs.ext4 -q -b 1024 -U $UUID -O ^has_journal "$tmp/handles.img" 1M
+    echo handled > "$tmp/f"
+    debugfs -w -f - "$tmp/handles.img" > /dev/null <<-END
+		mkdir d
+		write $tmp/f d/f
+		write $tmp/f gone
+		sif d/f generation 0x12345678
+		sif d generation 7
+		rm gone
+	END
+    e2fsck -fy "$tmp/handles.img" > /dev/null || [ $? -eq 1 ]
+    e2fsck -fn "$tmp/handles.img" > /dev/null
+    store handles
+}
+
+casefold
+inline
+journal
+htree
+xattr
+quota
+verity
+mmp
+meta_bg
+bigalloc
+orphan
+timestamps
+recover
+as_of
+devices
+links
+handles
@@ -0,0 +1,520 @@
+//! Randomized round trips: extent trees, directory blocks and inodes are
+//! generated, laid out the way ext4 stores them in a small in-memory
+//! image, and parsed back, which must give what was generated.
+
+use std::collections::HashSet;
+
+use read_file_block_way::{
+    Ext4Fs, FileType, Inode, InodeNumber, SuperBlock, Timestamp, TypedInode,
+};
+
+const CASES: u64 = 200;
+
+/// xorshift64*, seeded per case so a failure names the case to replay.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// uniform in `lo..=hi`
+    fn range(&mut self, lo: u64, hi: u64) -> u64 {
+        lo + self.next() % (hi - lo + 1)
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+}
+
+const BLOCK_SIZE: usize = 1024;
+const BLOCKS: u64 = 4096;
+const INODE_SIZE: usize = 256;
+const INODES: u64 = 32;
+const INODE_TABLE: u64 = 5;
+// superblock, descriptors, bitmaps and the inode table come first
+const FIRST_FREE_BLOCK: u64 = INODE_TABLE + INODES * INODE_SIZE as u64 / BLOCK_SIZE as u64;
+
+const INCOMPAT_FILETYPE: u32 = 0x2;
+const INCOMPAT_EXTENTS: u32 = 0x40;
+const EXTENTS_FL: u32 = 0x80000;
+const EXTENT_MAGIC: u16 = 0xF30A;
+
+fn put16(buf: &mut [u8], at: usize, x: u16) {
+    buf[at..at + 2].copy_from_slice(&x.to_le_bytes());
+}
+
+fn put32(buf: &mut [u8], at: usize, x: u32) {
+    buf[at..at + 4].copy_from_slice(&x.to_le_bytes());
+}
+
+/// A single group filesystem with 1 KiB blocks, blocks handed out in
+/// order past its metadata.
+struct Image {
+    data: Vec<u8>,
+    next_block: u64,
+}
+
+impl Image {
+    fn new() -> Self {
+        let mut data = vec![0u8; BLOCKS as usize * BLOCK_SIZE];
+        let sb = &mut data[1024..2048];
+        put32(sb, 0x0, INODES as u32);
+        put32(sb, 0x4, BLOCKS as u32);
+        put32(sb, 0x14, 1);
+        put32(sb, 0x20, 8192);
+        put32(sb, 0x28, INODES as u32);
+        put16(sb, 0x38, SuperBlock::MAGIC);
+        put32(sb, 0x4C, 1);
+        put32(sb, 0x54, 11);
+        put16(sb, 0x58, INODE_SIZE as u16);
+        put32(sb, 0x60, INCOMPAT_FILETYPE | INCOMPAT_EXTENTS);
+        let gd = &mut data[2 * BLOCK_SIZE..];
+        put32(gd, 0x0, 3);
+        put32(gd, 0x4, 4);
+        put32(gd, 0x8, INODE_TABLE as u32);
+        Self {
+            data,
+            next_block: FIRST_FREE_BLOCK,
+        }
+    }
+
+    fn alloc(&mut self) -> u64 {
+        let block = self.next_block;
+        assert!(block < BLOCKS, "test image full");
+        self.next_block += 1;
+        block
+    }
+
+    fn block_mut(&mut self, n: u64) -> &mut [u8] {
+        let start = n as usize * BLOCK_SIZE;
+        &mut self.data[start..start + BLOCK_SIZE]
+    }
+
+    fn put_inode(&mut self, n: u64, raw: &[u8]) {
+        let start = INODE_TABLE as usize * BLOCK_SIZE + (n as usize - 1) * INODE_SIZE;
+        self.data[start..start + INODE_SIZE].copy_from_slice(raw);
+    }
+
+    fn open(self) -> Ext4Fs<Vec<u8>> {
+        Ext4Fs::new(self.data).unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Run {
+    block: u64,
+    len: u64,
+    start: u64,
+    uninit: bool,
+}
+
+fn random_runs(rng: &mut Rng, max_count: u64) -> Vec<Run> {
+    let count = rng.range(1, max_count);
+    let mut block = rng.range(0, 100);
+    let mut runs = Vec::new();
+    for _ in 0..count {
+        let uninit = rng.chance(20);
+        let len = match rng.next() % 3 {
+            0 => 1,
+            1 => rng.range(1, 16),
+            _ if uninit => rng.range(1, 32767),
+            _ => rng.range(1, 32768),
+        };
+        runs.push(Run {
+            block,
+            len,
+            // 48 bits of physical block number
+            start: rng.range(0, (1 << 48) - 1 - len),
+            uninit,
+        });
+        block += len;
+        if rng.chance(30) {
+            block += rng.range(1, 1000);
+        }
+    }
+    runs
+}
+
+/// Lay `runs` out as an extent tree with nodes of random fill, returning
+/// the 60 bytes of i_block holding its root.
+fn write_extent_tree(image: &mut Image, rng: &mut Rng, runs: &[Run]) -> [u8; 60] {
+    let mut entries: Vec<[u8; 12]> = runs
+        .iter()
+        .map(|run| {
+            let mut e = [0u8; 12];
+            put32(&mut e, 0x0, run.block as u32);
+            let len = if run.uninit { run.len + 32768 } else { run.len };
+            put16(&mut e, 0x4, len as u16);
+            put16(&mut e, 0x6, (run.start >> 32) as u16);
+            put32(&mut e, 0x8, run.start as u32);
+            e
+        })
+        .collect();
+    let slots = (BLOCK_SIZE - 12) / 12;
+    let mut depth = 0;
+    // the root in i_block holds 4 entries
+    while entries.len() > 4 {
+        let fanout = rng.range(2, slots as u64) as usize;
+        let mut index = Vec::new();
+        for chunk in entries.chunks(fanout) {
+            let n = image.alloc();
+            let max = rng.range(chunk.len() as u64, slots as u64);
+            let block = image.block_mut(n);
+            put16(block, 0x0, EXTENT_MAGIC);
+            put16(block, 0x2, chunk.len() as u16);
+            put16(block, 0x4, max as u16);
+            put16(block, 0x6, depth);
+            for (i, e) in chunk.iter().enumerate() {
+                block[12 + i * 12..24 + i * 12].copy_from_slice(e);
+            }
+            // an index entry starts with the first logical block below it
+            let mut e = [0u8; 12];
+            e[0..4].copy_from_slice(&chunk[0][0..4]);
+            put32(&mut e, 0x4, n as u32);
+            put16(&mut e, 0x8, (n >> 32) as u16);
+            index.push(e);
+        }
+        entries = index;
+        depth += 1;
+    }
+    let mut root = [0u8; 60];
+    put16(&mut root, 0x0, EXTENT_MAGIC);
+    put16(&mut root, 0x2, entries.len() as u16);
+    put16(&mut root, 0x4, 4);
+    put16(&mut root, 0x6, depth);
+    for (i, e) in entries.iter().enumerate() {
+        root[12 + i * 12..24 + i * 12].copy_from_slice(e);
+    }
+    root
+}
+
+fn raw_inode(mode: u16, size: u64, flags: u32, block: &[u8; 60]) -> [u8; INODE_SIZE] {
+    let mut raw = [0u8; INODE_SIZE];
+    put16(&mut raw, 0x0, mode);
+    put32(&mut raw, 0x4, size as u32);
+    put32(&mut raw, 0x6C, (size >> 32) as u32);
+    put16(&mut raw, 0x1A, 1);
+    put32(&mut raw, 0x20, flags);
+    raw[0x28..0x28 + 60].copy_from_slice(block);
+    put16(&mut raw, 0x80, 32);
+    raw
+}
+
+#[test]
+fn extent_trees_round_trip() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let mut image = Image::new();
+        let max_count = if rng.chance(50) { 4 } else { 2000 };
+        let runs = random_runs(&mut rng, max_count);
+        let root = write_extent_tree(&mut image, &mut rng, &runs);
+        let end = runs.last().map_or(0, |x| x.block + x.len);
+        let raw = raw_inode(0o100644, end * BLOCK_SIZE as u64, EXTENTS_FL, &root);
+        image.put_inode(12, &raw);
+        let fs = image.open();
+        let inode = fs.inode(InodeNumber(12)).unwrap();
+        let extents = inode
+            .extents(&fs.sb, &fs.dev)
+            .unwrap_or_else(|e| panic!("case {seed}: {e}"));
+        let parsed: Vec<Run> = extents
+            .iter()
+            .map(|x| Run {
+                block: x.block,
+                len: x.len,
+                start: x.start,
+                uninit: x.uninit,
+            })
+            .collect();
+        assert_eq!(parsed, runs, "case {seed}");
+        // the last block of each run maps to the last block it points to
+        for run in &runs {
+            let last = run.block + run.len - 1;
+            let mapped = extents.iter().find_map(|x| x.map(last));
+            assert_eq!(mapped, Some(run.start + run.len - 1), "case {seed}");
+        }
+    }
+}
+
+/// An inode whose root is an index node over block `n`.
+fn index_over(image: &mut Image, n: u64, depth: u16) {
+    let mut root = [0u8; 60];
+    put16(&mut root, 0x0, EXTENT_MAGIC);
+    put16(&mut root, 0x2, 1);
+    put16(&mut root, 0x4, 4);
+    put16(&mut root, 0x6, depth);
+    put32(&mut root, 12 + 0x4, n as u32);
+    let raw = raw_inode(0o100644, 1024, EXTENTS_FL, &root);
+    image.put_inode(12, &raw);
+}
+
+#[test]
+fn damaged_extent_trees_are_refused() {
+    let walk = |image: Image| {
+        let fs = image.open();
+        let inode = fs.inode(InodeNumber(12)).unwrap();
+        let map = inode.map_blocks(&fs.sb, &fs.dev).map(|_| ());
+        (inode.extents(&fs.sb, &fs.dev).map(|_| ()), map)
+    };
+    let node = |image: &mut Image, n: u64, entries: u16, max: u16, depth: u16| {
+        let block = image.block_mut(n);
+        put16(block, 0x0, EXTENT_MAGIC);
+        put16(block, 0x2, entries);
+        put16(block, 0x4, max);
+        put16(block, 0x6, depth);
+        // pointing back at itself
+        put32(block, 12 + 0x4, n as u32);
+    };
+
+    // a loop at the same depth
+    let mut image = Image::new();
+    let n = image.alloc();
+    node(&mut image, n, 1, 84, 1);
+    index_over(&mut image, n, 2);
+    let (extents, map) = walk(image);
+    assert!(extents.is_err() && map.is_err());
+
+    // deeper than the root
+    let mut image = Image::new();
+    let n = image.alloc();
+    node(&mut image, n, 1, 84, 3);
+    index_over(&mut image, n, 1);
+    let (extents, map) = walk(image);
+    assert!(extents.is_err() && map.is_err());
+
+    // deeper than the kernel goes
+    let mut image = Image::new();
+    let n = image.alloc();
+    node(&mut image, n, 0, 84, 6);
+    index_over(&mut image, n, 7);
+    let (extents, map) = walk(image);
+    assert!(extents.is_err() && map.is_err());
+
+    // more entries than the block holds
+    let mut image = Image::new();
+    let n = image.alloc();
+    node(&mut image, n, 100, 100, 0);
+    index_over(&mut image, n, 1);
+    let (extents, map) = walk(image);
+    assert!(extents.is_err() && map.is_err());
+
+    // a well formed leaf below the same root
+    let mut image = Image::new();
+    let n = image.alloc();
+    node(&mut image, n, 1, 84, 0);
+    index_over(&mut image, n, 1);
+    let (extents, map) = walk(image);
+    assert!(extents.is_ok() && map.is_ok());
+}
+
+/// A name of 1 to 255 bytes, without `/` or NUL, mixing in multi-byte
+/// characters.
+fn random_name(rng: &mut Rng) -> String {
+    const CHARS: &[&str] = &["a", "z", "Q", "0", "9", ".", "-", "_", " ", "é", "€", "🙂"];
+    let mut name = String::new();
+    let target = rng.range(1, 255) as usize;
+    loop {
+        let c = CHARS[rng.range(0, CHARS.len() as u64 - 1) as usize];
+        if name.len() + c.len() > target {
+            break;
+        }
+        name.push_str(c);
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        name = format!("n{}", rng.range(0, 9999));
+    }
+    name
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    inode: u64,
+    name: Vec<u8>,
+    file_type: u8,
+}
+
+/// Pack `entries` into directory blocks the way ext4 does: records padded
+/// to 4 bytes, the last one of a block stretched to its end, with random
+/// slack and deleted records in between.
+fn write_dir_blocks(image: &mut Image, rng: &mut Rng, entries: &[Entry]) -> Vec<u64> {
+    let mut blocks = vec![image.alloc()];
+    let mut offset = 0;
+    // offset of the last record in the current block
+    let mut last: Option<usize> = None;
+    let mut records: Vec<(Entry, bool)> = Vec::new();
+    for entry in entries {
+        if rng.chance(10) {
+            let deleted = Entry {
+                inode: 0,
+                name: random_name(rng).into_bytes(),
+                file_type: 1,
+            };
+            records.push((deleted, true));
+        }
+        records.push((entry.clone(), false));
+    }
+    for (entry, _) in records {
+        let needed = (8 + entry.name.len() + 3) & !3;
+        if offset + needed > BLOCK_SIZE {
+            let block = *blocks.last().unwrap();
+            let last = last.unwrap();
+            put16(image.block_mut(block), last + 4, (BLOCK_SIZE - last) as u16);
+            blocks.push(image.alloc());
+            offset = 0;
+        }
+        let mut rec_len = needed;
+        let slack = rng.range(0, 8) as usize * 4;
+        if rng.chance(20) && offset + rec_len + slack <= BLOCK_SIZE {
+            rec_len += slack;
+        }
+        let block = image.block_mut(*blocks.last().unwrap());
+        put32(block, offset, entry.inode as u32);
+        put16(block, offset + 4, rec_len as u16);
+        block[offset + 6] = entry.name.len() as u8;
+        block[offset + 7] = entry.file_type;
+        block[offset + 8..offset + 8 + entry.name.len()].copy_from_slice(&entry.name);
+        last = Some(offset);
+        offset += rec_len;
+    }
+    let block = *blocks.last().unwrap();
+    let last = last.unwrap();
+    put16(image.block_mut(block), last + 4, (BLOCK_SIZE - last) as u16);
+    blocks
+}
+
+#[test]
+fn directory_blocks_round_trip() {
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let mut image = Image::new();
+        let mut names = HashSet::new();
+        let mut entries = vec![
+            Entry {
+                inode: 2,
+                name: b".".to_vec(),
+                file_type: 2,
+            },
+            Entry {
+                inode: 2,
+                name: b"..".to_vec(),
+                file_type: 2,
+            },
+        ];
+        for _ in 0..rng.range(0, 300) {
+            let name = random_name(&mut rng);
+            if names.insert(name.clone()) {
+                entries.push(Entry {
+                    inode: rng.range(11, INODES),
+                    name: name.into_bytes(),
+                    file_type: rng.range(1, 7) as u8,
+                });
+            }
+        }
+        let blocks = write_dir_blocks(&mut image, &mut rng, &entries);
+        // blocks were handed out in order, one run covers them
+        let run = Run {
+            block: 0,
+            len: blocks.len() as u64,
+            start: blocks[0],
+            uninit: false,
+        };
+        let root = write_extent_tree(&mut image, &mut rng, &[run]);
+        let size = (blocks.len() * BLOCK_SIZE) as u64;
+        image.put_inode(2, &raw_inode(0o40755, size, EXTENTS_FL, &root));
+        let fs = image.open();
+        let root = TypedInode::new(fs.inode(Ext4Fs::<Vec<u8>>::ROOT).unwrap());
+        let parsed: Vec<Entry> = fs
+            .dir_entries(Ext4Fs::<Vec<u8>>::ROOT, root.as_dir().unwrap())
+            .unwrap_or_else(|e| panic!("case {seed}: {e}"))
+            .into_iter()
+            .map(|x| Entry {
+                inode: x.inode.0,
+                name: x.name_bytes,
+                file_type: x.file_type.map_or(0, |t| match t {
+                    FileType::Regular => 1,
+                    FileType::Directory => 2,
+                    FileType::CharacterDevice => 3,
+                    FileType::BlockDevice => 4,
+                    FileType::Fifo => 5,
+                    FileType::Socket => 6,
+                    FileType::SymbolicLink => 7,
+                    FileType::Unknown => 0,
+                }),
+            })
+            .collect();
+        assert_eq!(parsed, entries, "case {seed}");
+        for entry in entries.iter().skip(2).filter(|_| rng.chance(10)) {
+            let path = format!("/{}", String::from_utf8_lossy(&entry.name));
+            let found = fs
+                .resolve(&path)
+                .unwrap_or_else(|e| panic!("case {seed}: {e}"));
+            assert_eq!(
+                found,
+                Some(InodeNumber(entry.inode)),
+                "case {seed} {path:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn inode_fields_round_trip() {
+    const TYPES: [u16; 7] = [0x1000, 0x2000, 0x4000, 0x6000, 0x8000, 0xA000, 0xC000];
+    for seed in 0..CASES {
+        let mut rng = Rng::new(seed);
+        let mut raw = [0u8; INODE_SIZE];
+        let mode = TYPES[rng.range(0, 6) as usize] | rng.range(0, 0o7777) as u16;
+        let uid = rng.next() as u32;
+        let gid = rng.next() as u32;
+        let size = rng.next();
+        let links = rng.next() as u16;
+        let generation = rng.next() as u32;
+        let projid = rng.next() as u32;
+        let (mtime, mtime_extra) = (rng.next() as u32, rng.next() as u32);
+        let (crtime, crtime_extra) = (rng.next() as u32, rng.next() as u32);
+        // fields past i_extra_isize aren't there
+        let extra_isize = rng.range(0, 8) as u16 * 4;
+        put16(&mut raw, 0x0, mode);
+        put16(&mut raw, 0x2, uid as u16);
+        put16(&mut raw, 0x78, (uid >> 16) as u16);
+        put16(&mut raw, 0x18, gid as u16);
+        put16(&mut raw, 0x7A, (gid >> 16) as u16);
+        put32(&mut raw, 0x4, size as u32);
+        put32(&mut raw, 0x6C, (size >> 32) as u32);
+        put16(&mut raw, 0x1A, links);
+        put32(&mut raw, 0x10, mtime);
+        put32(&mut raw, 0x64, generation);
+        put16(&mut raw, 0x80, extra_isize);
+        put32(&mut raw, 0x88, mtime_extra);
+        put32(&mut raw, 0x90, crtime);
+        put32(&mut raw, 0x94, crtime_extra);
+        put32(&mut raw, 0x9C, projid);
+        let inode = Inode::new(&raw[..]).unwrap_or_else(|e| panic!("case {seed}: {e}"));
+        let present = |offset: u16| 0x80 + extra_isize >= offset + 4;
+        let time = |seconds: u32, extra: u32, at: u16| {
+            let extra = present(at).then_some(extra);
+            Timestamp {
+                seconds: seconds as i32 as i64 + (((extra.unwrap_or(0) & 3) as i64) << 32),
+                nanos: extra.unwrap_or(0) >> 2,
+            }
+        };
+        assert_eq!(inode.mode, mode, "case {seed}");
+        assert_eq!(inode.file_type() as u16, mode & 0xF000, "case {seed}");
+        assert_eq!((inode.uid, inode.gid), (uid, gid), "case {seed}");
+        assert_eq!(inode.size, size, "case {seed}");
+        assert_eq!(inode.links_count, links, "case {seed}");
+        assert_eq!(inode.generation, generation, "case {seed}");
+        assert_eq!(inode.mtime, time(mtime, mtime_extra, 0x88), "case {seed}");
+        let expected_crtime = present(0x90).then(|| time(crtime, crtime_extra, 0x94));
+        assert_eq!(inode.crtime, expected_crtime, "case {seed}");
+        assert_eq!(inode.projid, present(0x9C).then_some(projid), "case {seed}");
+    }
+}
@@ -0,0 +1,113 @@
+//! Superblock fields out of range are refused when opening the image,
+//! rather than left to overflow or divide by zero later.
+
+use read_file_block_way::builder::ImageBuilder;
+use read_file_block_way::Ext4Fs;
+
+/// a 4 KiB block image with superblock fields at the given offsets
+/// replaced
+fn image(fields: &[(usize, &[u8])]) -> Vec<u8> {
+    let mut image = Vec::new();
+    ImageBuilder::new()
+        .block_size(4096)
+        .build(&mut image)
+        .unwrap();
+    for (at, bytes) in fields {
+        image[1024 + at..][..bytes.len()].copy_from_slice(bytes);
+    }
+    image
+}
+
+fn open(fields: &[(usize, &[u8])]) -> Result<Ext4Fs<Vec<u8>>, failure::Error> {
+    Ext4Fs::new(image(fields))
+}
+
+#[test]
+fn descriptor_size() {
+    // 64bit
+    let incompat = open(&[]).unwrap().sb.feature_incompat | 0x80;
+    let with_64bit =
+        |size: u16| open(&[(0x60, &incompat.to_le_bytes()), (0xFE, &size.to_le_bytes())]);
+    for size in [64, 128, 4096] {
+        assert_eq!(with_64bit(size).unwrap().sb.desc_size, size as u64);
+    }
+    for size in [0, 32, 48, 96, 8192] {
+        assert!(with_64bit(size).is_err(), "{size}");
+    }
+}
+
+#[test]
+fn flex_group_size() {
+    // flex_bg
+    let incompat = open(&[]).unwrap().sb.feature_incompat | 0x200;
+    let with_flex_bg = |log: u8| open(&[(0x60, &incompat.to_le_bytes()), (0x174, &[log])]);
+    assert_eq!(with_flex_bg(4).unwrap().sb.groups_per_flex(), 16);
+    assert_eq!(with_flex_bg(31).unwrap().sb.groups_per_flex(), 1 << 31);
+    assert!(with_flex_bg(32).is_err());
+    assert!(with_flex_bg(255).is_err());
+}
+
+#[test]
+fn block_size() {
+    assert!(open(&[(0x18, &7u32.to_le_bytes())]).is_err());
+    assert!(open(&[(0x18, &u32::MAX.to_le_bytes())]).is_err());
+    // unless the block size is given
+    let damaged = image(&[(0x18, &u32::MAX.to_le_bytes())]);
+    let fs = Ext4Fs::with_block_size(damaged, 4096).unwrap();
+    assert_eq!(fs.sb.block_size, 4096);
+    assert!(fs.stat("/lost+found").unwrap().is_some());
+}
+
+#[test]
+fn group_count() {
+    let fs = open(&[]).unwrap();
+    assert_eq!(fs.sb.group_count(), 1);
+    // blocks per group
+    assert!(open(&[(0x20, &0u32.to_le_bytes())]).is_err());
+    // blocks count, then first data block
+    assert!(open(&[(0x4, &0u32.to_le_bytes())]).is_err());
+    let blocks = fs.sb.blocks_count as u32;
+    assert!(open(&[(0x14, &blocks.to_le_bytes())]).is_err());
+    assert!(open(&[(0x14, &(blocks + 1).to_le_bytes())]).is_err());
+}
+
+#[test]
+fn block_to_cluster() {
+    let fs = open(&[]).unwrap();
+    assert_eq!(fs.sb.block_to_cluster(0), Some(0));
+    assert_eq!(fs.sb.block_to_cluster(7), Some(7));
+    // blocks before the first data block are in no cluster
+    let fs = open(&[(0x14, &1u32.to_le_bytes())]).unwrap();
+    assert_eq!(fs.sb.block_to_cluster(0), None);
+    assert_eq!(fs.sb.block_to_cluster(1), Some(0));
+    assert_eq!(fs.sb.cluster_to_block(5), 6);
+    assert_eq!(fs.sb.block_to_cluster(6), Some(5));
+}
+
+#[test]
+fn group_geometry() {
+    let fs = open(&[]).unwrap();
+    let blocks_per_group = fs.sb.block_per_group as u32;
+    assert!(blocks_per_group <= 4096 * 8);
+    assert!(open(&[(0x38, &[0, 0])]).is_err());
+    // inodes per group
+    assert!(open(&[(0x28, &0u32.to_le_bytes())]).is_err());
+    assert!(open(&[(0x28, &(4096u32 * 8 + 1).to_le_bytes())]).is_err());
+    assert!(open(&[(0x28, &(4096u32 * 8).to_le_bytes())]).is_ok());
+    // blocks per group, clusters too without bigalloc
+    assert!(open(&[(0x20, &(4096u32 * 8 + 1).to_le_bytes())]).is_err());
+    // clusters per group with bigalloc, 16 blocks each
+    let ro_compat = fs.sb.feature_ro_compat | 0x200;
+    let bigalloc = |cpg: u32, bpg: u32| {
+        open(&[
+            (0x64, &ro_compat.to_le_bytes()),
+            (0x1C, &6u32.to_le_bytes()),
+            (0x20, &bpg.to_le_bytes()),
+            (0x24, &cpg.to_le_bytes()),
+        ])
+    };
+    assert!(bigalloc(2048, 2048 * 16).is_ok());
+    assert!(bigalloc(0, 2048 * 16).is_err());
+    assert!(bigalloc(4096 * 8 + 1, 2048 * 16).is_err());
+    assert!(bigalloc(2048, 4096 * 8 * 16 + 1).is_err());
+}